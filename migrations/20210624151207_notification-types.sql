@@ -0,0 +1,11 @@
+ALTER TABLE notifications ADD COLUMN type varchar(64) DEFAULT 'unknown' NOT NULL;
+ALTER TABLE notifications ADD COLUMN body jsonb NULL;
+
+CREATE TABLE notification_preferences (
+    user_id bigint REFERENCES users NOT NULL,
+    type varchar(64) NOT NULL,
+    in_app boolean DEFAULT TRUE NOT NULL,
+    email boolean DEFAULT FALSE NOT NULL,
+    webhook boolean DEFAULT FALSE NOT NULL,
+    PRIMARY KEY (user_id, type)
+);
@@ -0,0 +1,13 @@
+CREATE TABLE email_queue (
+    id bigserial PRIMARY KEY,
+    user_id bigint REFERENCES users NOT NULL,
+    type varchar(64) NOT NULL,
+    title varchar(255) NOT NULL,
+    text varchar(2048) NOT NULL,
+    link varchar(2048) NOT NULL,
+    created timestamptz DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    attempts integer DEFAULT 0 NOT NULL
+);
+
+-- Existing users receive their first digest a week from now
+ALTER TABLE users ADD COLUMN last_digest timestamptz DEFAULT CURRENT_TIMESTAMP NOT NULL;
@@ -0,0 +1,29 @@
+CREATE TABLE webhooks (
+    id bigint PRIMARY KEY,
+    -- Exactly one of user_id and team_id is set, depending on who owns the webhook
+    user_id bigint REFERENCES users NULL,
+    team_id bigint REFERENCES teams NULL,
+    -- NULL subscribes to every mod of the owner
+    mod_id bigint REFERENCES mods NULL,
+    url varchar(2048) NOT NULL,
+    secret varchar(64) NOT NULL,
+    events varchar(64)[] NOT NULL,
+    created timestamptz DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    CHECK ((user_id IS NULL) <> (team_id IS NULL))
+);
+
+CREATE TABLE webhook_deliveries (
+    id bigserial PRIMARY KEY,
+    webhook_id bigint REFERENCES webhooks NOT NULL,
+    event varchar(64) NOT NULL,
+    payload jsonb NOT NULL,
+    status varchar(32) DEFAULT 'pending' NOT NULL,
+    attempts integer DEFAULT 0 NOT NULL,
+    next_attempt timestamptz DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    response_code integer NULL,
+    error varchar(2048) NULL,
+    created timestamptz DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    delivered timestamptz NULL
+);
+
+CREATE INDEX webhook_deliveries_pending ON webhook_deliveries (next_attempt) WHERE status = 'pending';
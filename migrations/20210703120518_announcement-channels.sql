@@ -0,0 +1,20 @@
+CREATE TABLE announcement_channels (
+    id serial PRIMARY KEY,
+    name varchar(255) NOT NULL,
+    -- A Discord (or Discord-compatible) webhook URL
+    url varchar(2048) NOT NULL,
+    -- Only mods in one of these categories are announced. Empty announces every mod.
+    categories varchar(255)[] DEFAULT '{}' NOT NULL,
+    allow_nsfw boolean DEFAULT FALSE NOT NULL,
+    announce_approvals boolean DEFAULT TRUE NOT NULL,
+    announce_versions boolean DEFAULT TRUE NOT NULL,
+    created timestamptz DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE TABLE announcement_queue (
+    id bigserial PRIMARY KEY,
+    channel_id integer REFERENCES announcement_channels NOT NULL,
+    payload jsonb NOT NULL,
+    attempts integer DEFAULT 0 NOT NULL,
+    created timestamptz DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
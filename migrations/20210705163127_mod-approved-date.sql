@@ -0,0 +1,7 @@
+-- When a mod was first approved, used to list newly approved mods
+ALTER TABLE mods ADD COLUMN approved timestamptz NULL;
+
+UPDATE mods SET approved = published
+WHERE status = (SELECT id FROM statuses WHERE status = 'approved');
+
+CREATE INDEX mods_approved ON mods (approved DESC) WHERE approved IS NOT NULL;
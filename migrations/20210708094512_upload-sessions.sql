@@ -0,0 +1,25 @@
+-- Resumable uploads. Their bytes are stored through the file host as they
+-- arrive, one object per chunk, and put together once the upload is used.
+CREATE TABLE upload_sessions (
+    id bigint PRIMARY KEY,
+    user_id bigint REFERENCES users ON DELETE CASCADE NOT NULL,
+    file_name varchar(256) NOT NULL,
+    size bigint NOT NULL,
+    -- The number of bytes received so far
+    received bigint DEFAULT 0 NOT NULL,
+    created timestamptz DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    updated timestamptz DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    -- Set once the upload was added to a version, after which it only waits
+    -- for its chunks to be removed
+    used boolean DEFAULT FALSE NOT NULL
+);
+
+CREATE TABLE upload_chunks (
+    session_id bigint REFERENCES upload_sessions ON DELETE CASCADE NOT NULL,
+    upload_offset bigint NOT NULL,
+    length bigint NOT NULL,
+    file_name varchar(2048) NOT NULL,
+    PRIMARY KEY (session_id, upload_offset)
+);
+
+CREATE INDEX upload_sessions_user ON upload_sessions (user_id);
@@ -0,0 +1,17 @@
+-- Version files are stored once per content, under their SHA-512 hash, and
+-- shared by all files with that content
+CREATE TABLE file_blobs (
+    sha512 varchar(128) PRIMARY KEY,
+    size bigint NOT NULL,
+    -- The number of files referring to the blob. Blobs nothing refers to
+    -- anymore are deleted from the file host along with their row
+    ref_count integer DEFAULT 0 NOT NULL,
+    created timestamptz DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX file_blobs_unreferenced ON file_blobs (sha512) WHERE ref_count = 0;
+
+-- Files uploaded before blobs existed keep their own path and have no blob
+ALTER TABLE files
+    ADD COLUMN blob_sha512 varchar(128) NULL REFERENCES file_blobs;
+CREATE INDEX files_blob ON files (blob_sha512);
@@ -0,0 +1,10 @@
+-- Limits set by moderators, in bytes.  NULL falls back to the defaults.
+ALTER TABLE users
+    ADD COLUMN file_size_limit bigint NULL,
+    ADD COLUMN storage_limit bigint NULL;
+
+ALTER TABLE teams
+    ADD COLUMN file_size_limit bigint NULL,
+    ADD COLUMN storage_limit bigint NULL;
+
+CREATE INDEX versions_author_id ON versions (author_id);
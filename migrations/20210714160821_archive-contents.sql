@@ -0,0 +1,4 @@
+-- The files in archives, listed when they're uploaded. NULL for blobs that
+-- aren't archives, or were stored before archives were inspected
+ALTER TABLE file_blobs
+    ADD COLUMN contents jsonb NULL;
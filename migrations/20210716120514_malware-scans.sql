@@ -0,0 +1,20 @@
+-- Blobs are scanned for malware once after they're uploaded, and their files
+-- can't be downloaded until the blob is clean. Blobs stored before scanning
+-- existed count as clean
+ALTER TABLE file_blobs
+    ADD COLUMN scan_status varchar(32) DEFAULT 'clean' NOT NULL,
+    -- What the scanner found in blobs that aren't clean
+    ADD COLUMN scan_result varchar(255) NULL;
+ALTER TABLE file_blobs
+    ALTER COLUMN scan_status SET DEFAULT 'pending_scan';
+
+CREATE INDEX file_blobs_pending_scan ON file_blobs (created) WHERE scan_status = 'pending_scan';
+
+-- Versions with a file the scanner flagged, which can't be downloaded until
+-- a moderator lifts the quarantine
+ALTER TABLE versions
+    ADD COLUMN quarantined boolean DEFAULT FALSE NOT NULL;
+
+-- Reports raised by the site itself, like for flagged files, have no reporter
+ALTER TABLE reports
+    ALTER COLUMN reporter DROP NOT NULL;
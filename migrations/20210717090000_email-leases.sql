@@ -0,0 +1,7 @@
+-- Emails and digests are claimed before they're sent and marked as sent
+-- afterwards, so no transaction stays open while talking to the SMTP server.
+-- Claims that are older than a lease were abandoned and can be taken again
+ALTER TABLE email_queue
+    ADD COLUMN sending_at timestamptz NULL;
+ALTER TABLE users
+    ADD COLUMN digest_sending_at timestamptz NULL;
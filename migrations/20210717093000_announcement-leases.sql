@@ -0,0 +1,4 @@
+-- Announcements are claimed before they're posted and removed afterwards, so
+-- no transaction stays open while waiting on Discord
+ALTER TABLE announcement_queue
+    ADD COLUMN sending_at timestamptz NULL;
@@ -0,0 +1,9 @@
+-- When a mod's page last changed other than by a new version. `updated` only
+-- moves when a version is added, which is what the search sorts by, so edits
+-- are tracked separately for `Last-Modified`
+ALTER TABLE mods
+    ADD COLUMN edited timestamptz NULL;
+UPDATE mods SET edited = updated;
+ALTER TABLE mods
+    ALTER COLUMN edited SET DEFAULT CURRENT_TIMESTAMP,
+    ALTER COLUMN edited SET NOT NULL;
@@ -0,0 +1,7 @@
+-- Browsers can't send headers when opening an event stream, so they
+-- authenticate with a ticket instead, which can only be used once
+CREATE TABLE notification_stream_tickets (
+    ticket varchar(64) PRIMARY KEY,
+    user_id bigint REFERENCES users ON DELETE CASCADE NOT NULL,
+    expires timestamptz NOT NULL
+);
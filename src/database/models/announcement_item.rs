@@ -0,0 +1,342 @@
+use super::ids::*;
+
+pub struct AnnouncementChannelBuilder {
+    pub name: String,
+    pub url: String,
+    pub categories: Vec<String>,
+    pub allow_nsfw: bool,
+    pub announce_approvals: bool,
+    pub announce_versions: bool,
+}
+
+pub struct AnnouncementChannel {
+    pub id: i32,
+    pub name: String,
+    pub url: String,
+    pub categories: Vec<String>,
+    pub allow_nsfw: bool,
+    pub announce_approvals: bool,
+    pub announce_versions: bool,
+    pub created: chrono::DateTime<chrono::Utc>,
+}
+
+/// An announcement waiting to be posted to a channel
+pub struct QueuedAnnouncement {
+    pub id: i64,
+    pub channel_id: i32,
+    pub url: String,
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+}
+
+/// The details of a mod shown in announcements
+pub struct AnnouncedMod {
+    pub id: ModId,
+    pub title: String,
+    pub description: String,
+    pub slug: Option<String>,
+    pub icon_url: Option<String>,
+    pub is_nsfw: bool,
+    pub status: String,
+    pub categories: Vec<String>,
+    pub owner_username: Option<String>,
+    pub owner_avatar_url: Option<String>,
+}
+
+impl AnnouncementChannelBuilder {
+    pub async fn insert<'a, E>(self, exec: E) -> Result<i32, sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            "
+            INSERT INTO announcement_channels (
+                name, url, categories, allow_nsfw, announce_approvals, announce_versions
+            )
+            VALUES (
+                $1, $2, $3, $4, $5, $6
+            )
+            RETURNING id
+            ",
+            self.name,
+            self.url,
+            &self.categories,
+            self.allow_nsfw,
+            self.announce_approvals,
+            self.announce_versions
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result.id)
+    }
+}
+
+impl AnnouncementChannel {
+    pub async fn get<'a, E>(
+        id: i32,
+        exec: E,
+    ) -> Result<Option<AnnouncementChannel>, sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            "
+            SELECT name, url, categories, allow_nsfw, announce_approvals, announce_versions, created
+            FROM announcement_channels
+            WHERE id = $1
+            ",
+            id
+        )
+        .fetch_optional(exec)
+        .await?;
+
+        Ok(result.map(|row| AnnouncementChannel {
+            id,
+            name: row.name,
+            url: row.url,
+            categories: row.categories,
+            allow_nsfw: row.allow_nsfw,
+            announce_approvals: row.announce_approvals,
+            announce_versions: row.announce_versions,
+            created: row.created,
+        }))
+    }
+
+    pub async fn list<'a, E>(exec: E) -> Result<Vec<AnnouncementChannel>, sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            SELECT id, name, url, categories, allow_nsfw, announce_approvals, announce_versions, created
+            FROM announcement_channels
+            ORDER BY id ASC
+            "
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async {
+            Ok(e.right().map(|row| AnnouncementChannel {
+                id: row.id,
+                name: row.name,
+                url: row.url,
+                categories: row.categories,
+                allow_nsfw: row.allow_nsfw,
+                announce_approvals: row.announce_approvals,
+                announce_versions: row.announce_versions,
+                created: row.created,
+            }))
+        })
+        .try_collect::<Vec<AnnouncementChannel>>()
+        .await
+    }
+
+    pub async fn edit<'a, E>(&self, exec: E) -> Result<(), sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            UPDATE announcement_channels
+            SET name = $1, url = $2, categories = $3, allow_nsfw = $4,
+                announce_approvals = $5, announce_versions = $6
+            WHERE id = $7
+            ",
+            self.name,
+            self.url,
+            &self.categories,
+            self.allow_nsfw,
+            self.announce_approvals,
+            self.announce_versions,
+            self.id
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove(
+        id: i32,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "
+            DELETE FROM announcement_queue
+            WHERE channel_id = $1
+            ",
+            id
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query!(
+            "
+            DELETE FROM announcement_channels
+            WHERE id = $1
+            ",
+            id
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Whether a mod may be announced in this channel. NSFW mods are only
+    /// announced in channels that allow them.
+    pub fn accepts(&self, is_nsfw: bool, categories: &[String]) -> bool {
+        (self.allow_nsfw || !is_nsfw)
+            && (self.categories.is_empty()
+                || self.categories.iter().any(|x| categories.contains(x)))
+    }
+}
+
+impl QueuedAnnouncement {
+    /// Queues a payload to be posted to each of the given channels
+    pub async fn insert_many(
+        channel_ids: &[i32],
+        payload: &serde_json::Value,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::error::Error> {
+        for channel_id in channel_ids {
+            sqlx::query!(
+                "
+                INSERT INTO announcement_queue (channel_id, payload)
+                VALUES ($1, $2)
+                ",
+                channel_id,
+                payload
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Claims the oldest queued announcements that nobody is posting, or
+    /// whose claim is older than `lease_expiry`.  Claimed announcements have
+    /// to be removed or released once they were tried.
+    pub async fn claim_batch<'a, E>(
+        count: i64,
+        lease_expiry: chrono::DateTime<chrono::Utc>,
+        exec: E,
+    ) -> Result<Vec<QueuedAnnouncement>, sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            UPDATE announcement_queue q
+            SET sending_at = CURRENT_TIMESTAMP
+            FROM announcement_channels c
+            WHERE c.id = q.channel_id AND q.id IN (
+                SELECT id FROM announcement_queue
+                WHERE sending_at IS NULL OR sending_at < $2
+                ORDER BY created ASC, id ASC
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING q.id, q.channel_id, c.url, q.payload, q.attempts
+            ",
+            count,
+            lease_expiry
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async {
+            Ok(e.right().map(|row| QueuedAnnouncement {
+                id: row.id,
+                channel_id: row.channel_id,
+                url: row.url,
+                payload: row.payload,
+                attempts: row.attempts,
+            }))
+        })
+        .try_collect::<Vec<QueuedAnnouncement>>()
+        .await
+    }
+
+    /// Gives up the claim on an announcement that failed to post, so it's
+    /// retried on the next run
+    pub async fn release_failed<'a, E>(id: i64, exec: E) -> Result<(), sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            UPDATE announcement_queue
+            SET attempts = attempts + 1, sending_at = NULL
+            WHERE id = $1
+            ",
+            id
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove<'a, E>(id: i64, exec: E) -> Result<(), sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            DELETE FROM announcement_queue
+            WHERE id = $1
+            ",
+            id
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl AnnouncedMod {
+    pub async fn get(
+        id: ModId,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<Option<AnnouncedMod>, sqlx::error::Error> {
+        let result = sqlx::query!(
+            r#"
+            SELECT m.title, m.description, m.slug, m.icon_url, m.is_nsfw, s.status status_name,
+                ARRAY(
+                    SELECT c.category FROM mods_categories mc
+                    INNER JOIN categories c ON c.id = mc.joining_category_id
+                    WHERE mc.joining_mod_id = m.id
+                ) "categories!",
+                u.username "owner_username?", u.avatar_url "owner_avatar_url?"
+            FROM mods m
+            INNER JOIN statuses s ON s.id = m.status
+            LEFT OUTER JOIN team_members tm ON tm.team_id = m.team_id AND tm.role = $2 AND tm.accepted = TRUE
+            LEFT OUTER JOIN users u ON u.id = tm.user_id
+            WHERE m.id = $1
+            LIMIT 1
+            "#,
+            id as ModId,
+            crate::models::teams::OWNER_ROLE
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+        Ok(result.map(|row| AnnouncedMod {
+            id,
+            title: row.title,
+            description: row.description,
+            slug: row.slug,
+            icon_url: row.icon_url,
+            is_nsfw: row.is_nsfw,
+            status: row.status_name,
+            categories: row.categories,
+            owner_username: row.owner_username,
+            owner_avatar_url: row.owner_avatar_url,
+        }))
+    }
+}
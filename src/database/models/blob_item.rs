@@ -0,0 +1,294 @@
+use super::DatabaseError;
+use crate::models::mods::{ArchiveEntry, ScanStatus};
+use std::collections::HashMap;
+
+/// The content of version files, stored once no matter how many files share it
+pub struct FileBlob {
+    pub sha512: String,
+    pub size: i64,
+    pub ref_count: i32,
+    pub created: chrono::DateTime<chrono::Utc>,
+}
+
+impl FileBlob {
+    /// Adds a reference to a blob, creating it if it doesn't exist.  Returns
+    /// true if it was created, in which case its content still has to be
+    /// stored, along with how far the blob's malware scan got.
+    pub async fn reference(
+        sha512: &str,
+        size: i64,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(bool, ScanStatus), sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO file_blobs (sha512, size, ref_count)
+            VALUES ($1, $2, 1)
+            ON CONFLICT (sha512) DO UPDATE
+            SET ref_count = file_blobs.ref_count + 1
+            RETURNING (xmax = 0) AS "created!", scan_status
+            "#,
+            sha512,
+            size
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        Ok((result.created, ScanStatus::from_str(&result.scan_status)))
+    }
+
+    /// Drops a reference to a blob, leaving it to `get_unreferenced` if
+    /// nothing refers to it anymore
+    pub async fn release(
+        sha512: &str,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "
+            UPDATE file_blobs
+            SET ref_count = ref_count - 1
+            WHERE sha512 = $1
+            ",
+            sha512
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Deletes the files of a version and drops their references to blobs,
+    /// leaving blobs that aren't referenced anymore to `get_unreferenced`.
+    /// Both happen in one statement, so a blob is never collected while a
+    /// file still refers to it.
+    pub async fn remove_version_files<'a, E>(
+        version_id: super::VersionId,
+        exec: E,
+    ) -> Result<(), sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            WITH deleted AS (
+                DELETE FROM files
+                WHERE version_id = $1
+                RETURNING blob_sha512
+            )
+            UPDATE file_blobs b
+            SET ref_count = b.ref_count - f.count
+            FROM (
+                SELECT blob_sha512, COUNT(*)::integer count FROM deleted
+                WHERE blob_sha512 IS NOT NULL
+                GROUP BY blob_sha512
+            ) f
+            WHERE b.sha512 = f.blob_sha512
+            ",
+            version_id as super::VersionId
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Locks up to `limit` blobs nothing refers to, skipping ones that are
+    /// being referenced again right now
+    pub async fn get_unreferenced(
+        limit: i64,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            SELECT sha512 FROM file_blobs
+            WHERE ref_count <= 0
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            ",
+            limit
+        )
+        .fetch_many(&mut *transaction)
+        .try_filter_map(|e| async { Ok(e.right().map(|row| row.sha512)) })
+        .try_collect::<Vec<String>>()
+        .await
+    }
+
+    /// Stores the files in a blob that is an archive
+    pub async fn set_contents(
+        sha512: &str,
+        contents: &[ArchiveEntry],
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), DatabaseError> {
+        sqlx::query!(
+            "
+            UPDATE file_blobs
+            SET contents = $1
+            WHERE sha512 = $2
+            ",
+            serde_json::to_value(contents)?,
+            sha512
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The archive contents and scan status of version files, by the file.
+    /// Files without a blob are left out.
+    pub async fn get_file_details<'a, E>(
+        file_ids: &[i64],
+        exec: E,
+    ) -> Result<HashMap<i64, BlobDetails>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let rows = sqlx::query!(
+            "
+            SELECT f.id, b.contents, b.scan_status FROM files f
+            INNER JOIN file_blobs b ON b.sha512 = f.blob_sha512
+            WHERE f.id = ANY($1)
+            ",
+            file_ids
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.id,
+                    BlobDetails {
+                        contents: row.contents.and_then(|x| serde_json::from_value(x).ok()),
+                        scan_status: ScanStatus::from_str(&row.scan_status),
+                    },
+                )
+            })
+            .collect())
+    }
+
+    /// Up to `limit` blobs that weren't scanned for malware yet, oldest first
+    pub async fn get_pending_scan<'a, E>(limit: i64, exec: E) -> Result<Vec<String>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            SELECT sha512 FROM file_blobs
+            WHERE scan_status = 'pending_scan' AND ref_count > 0
+            ORDER BY created
+            LIMIT $1
+            ",
+            limit
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async { Ok(e.right().map(|row| row.sha512)) })
+        .try_collect::<Vec<String>>()
+        .await
+    }
+
+    /// Records the result of a blob's malware scan.  Returns false if the
+    /// blob isn't pending anymore, because another scan finished first.
+    pub async fn finish_scan<'a, E>(
+        sha512: &str,
+        scan_status: ScanStatus,
+        scan_result: Option<&str>,
+        exec: E,
+    ) -> Result<bool, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use sqlx::Done;
+
+        let result = sqlx::query!(
+            "
+            UPDATE file_blobs
+            SET scan_status = $1, scan_result = $2
+            WHERE sha512 = $3 AND scan_status = 'pending_scan'
+            ",
+            scan_status.as_str(),
+            scan_result,
+            sha512
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Marks the flagged blobs of a version's files as clean, like when a
+    /// moderator found the scanner was wrong.  What the scanner found is
+    /// kept.  Returns the blobs that were flagged.
+    pub async fn clear_infected(
+        version_id: super::VersionId,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "
+            UPDATE file_blobs b
+            SET scan_status = 'clean'
+            FROM files f
+            WHERE f.version_id = $1 AND b.sha512 = f.blob_sha512 AND b.scan_status = 'infected'
+            RETURNING b.sha512
+            ",
+            version_id as super::VersionId
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.sha512).collect())
+    }
+
+    /// The names of the files referring to a blob, with their versions
+    pub async fn get_files<'a, E>(
+        sha512: &str,
+        exec: E,
+    ) -> Result<Vec<(super::VersionId, String)>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let rows = sqlx::query!(
+            "
+            SELECT version_id, filename FROM files
+            WHERE blob_sha512 = $1
+            ",
+            sha512
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (super::VersionId(row.version_id), row.filename))
+            .collect())
+    }
+
+    /// Removes a blob nothing refers to anymore
+    pub async fn remove(
+        sha512: &str,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "
+            DELETE FROM file_blobs
+            WHERE sha512 = $1 AND ref_count <= 0
+            ",
+            sha512
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// What's known about the content of a version file
+pub struct BlobDetails {
+    /// The files in the blob, if it's an archive
+    pub contents: Option<Vec<ArchiveEntry>>,
+    pub scan_status: ScanStatus,
+}
@@ -0,0 +1,278 @@
+//! An in-process read-through cache for data that is read far more often
+//! than it is written.  Entries expire after a fixed time, so data changed
+//! without an explicit invalidation (like download counters) is only stale
+//! for a short while.
+
+use super::categories::{DonationPlatform, License};
+use super::mod_item::QueryMod;
+use super::version_item::QueryVersion;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgListener, PgPool};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The Postgres channel cache invalidations are announced on
+pub const CACHE_CHANNEL: &str = "cache_invalidations";
+
+const MOD_TTL: Duration = Duration::from_secs(60);
+const MOD_CAPACITY: usize = 10_000;
+const VERSION_TTL: Duration = Duration::from_secs(60);
+const VERSION_CAPACITY: usize = 20_000;
+const TAG_TTL: Duration = Duration::from_secs(10 * 60);
+
+lazy_static::lazy_static! {
+    pub static ref MODS: Cache<i64, QueryMod> = Cache::new("mods", MOD_CAPACITY, MOD_TTL);
+    pub static ref VERSIONS: Cache<i64, QueryVersion> =
+        Cache::new("versions", VERSION_CAPACITY, VERSION_TTL);
+    pub static ref CATEGORIES: Cache<(), Vec<String>> = Cache::new("categories", 1, TAG_TTL);
+    pub static ref LOADERS: Cache<(), Vec<String>> = Cache::new("loaders", 1, TAG_TTL);
+    pub static ref GAME_VERSIONS: Cache<(), Vec<String>> = Cache::new("game_versions", 1, TAG_TTL);
+    pub static ref LICENSES: Cache<(), Vec<License>> = Cache::new("licenses", 1, TAG_TTL);
+    pub static ref DONATION_PLATFORMS: Cache<(), Vec<DonationPlatform>> =
+        Cache::new("donation_platforms", 1, TAG_TTL);
+    pub static ref REPORT_TYPES: Cache<(), Vec<String>> = Cache::new("report_types", 1, TAG_TTL);
+}
+
+/// Whether invalidations are sent to the other API instances
+static NOTIFY_INVALIDATIONS: AtomicBool = AtomicBool::new(false);
+
+struct CacheEntry<V> {
+    value: V,
+    inserted: Instant,
+}
+
+struct CacheState<K, V> {
+    entries: HashMap<K, CacheEntry<V>>,
+    /// Keys in the order they were inserted, used to evict the oldest entry
+    /// once the cache is full.  Keys that were removed or reinserted since
+    /// are skipped during eviction.
+    order: VecDeque<(K, Instant)>,
+}
+
+/// A bounded cache whose entries expire after a fixed time
+pub struct Cache<K, V> {
+    name: &'static str,
+    capacity: usize,
+    ttl: Duration,
+    state: Mutex<CacheState<K, V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// How well a cache is doing
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct CacheStats {
+    pub name: &'static str,
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// The share of lookups answered from the cache, between 0 and 1
+    pub hit_rate: f64,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Cache<K, V> {
+    pub fn new(name: &'static str, capacity: usize, ttl: Duration) -> Self {
+        Cache {
+            name,
+            capacity,
+            ttl,
+            state: Mutex::new(CacheState {
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        // Can only panic if mutex is poisoned
+        let mut state = self.state.lock().unwrap();
+
+        let value = match state.entries.get(key) {
+            Some(entry) if entry.inserted.elapsed() < self.ttl => Some(entry.value.clone()),
+            Some(_) => {
+                state.entries.remove(key);
+                None
+            }
+            None => None,
+        };
+
+        if value.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+
+        value
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        let mut state = self.state.lock().unwrap();
+        let inserted = Instant::now();
+
+        if !state.entries.contains_key(&key) {
+            while state.entries.len() >= self.capacity {
+                match state.order.pop_front() {
+                    Some((oldest, time)) => {
+                        if state.entries.get(&oldest).map(|x| x.inserted) == Some(time) {
+                            state.entries.remove(&oldest);
+                        }
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        state.order.push_back((key.clone(), inserted));
+        state.entries.insert(key, CacheEntry { value, inserted });
+
+        // Keeps the eviction queue from growing without bound when the same
+        // keys are invalidated and reinserted over and over
+        if state.order.len() > self.capacity * 2 {
+            let CacheState { entries, order } = &mut *state;
+            order.retain(|(key, time)| entries.get(key).map(|x| x.inserted) == Some(*time));
+        }
+    }
+
+    pub fn remove(&self, key: &K) {
+        self.state.lock().unwrap().entries.remove(key);
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        state.order.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+
+        CacheStats {
+            name: self.name,
+            entries: self.state.lock().unwrap().entries.len(),
+            capacity: self.capacity,
+            hits,
+            misses,
+            hit_rate: if hits + misses == 0 {
+                0.0
+            } else {
+                hits as f64 / (hits + misses) as f64
+            },
+        }
+    }
+}
+
+/// Something that changed and has to be dropped from the caches
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(tag = "type", content = "id", rename_all = "snake_case")]
+pub enum Invalidation {
+    Mod(i64),
+    Version(i64),
+    Categories,
+    Loaders,
+    GameVersions,
+    Licenses,
+    DonationPlatforms,
+    ReportTypes,
+}
+
+fn invalidate_local(invalidation: Invalidation) {
+    match invalidation {
+        Invalidation::Mod(id) => MODS.remove(&id),
+        Invalidation::Version(id) => VERSIONS.remove(&id),
+        Invalidation::Categories => CATEGORIES.clear(),
+        Invalidation::Loaders => LOADERS.clear(),
+        Invalidation::GameVersions => GAME_VERSIONS.clear(),
+        Invalidation::Licenses => LICENSES.clear(),
+        Invalidation::DonationPlatforms => DONATION_PLATFORMS.clear(),
+        Invalidation::ReportTypes => REPORT_TYPES.clear(),
+    }
+}
+
+/// Drops changed data from the cache of this instance and, if enabled, of
+/// every other instance.  Call this after the change was committed, so the
+/// old data can't be cached again in between.
+pub async fn invalidate<'a, E>(invalidation: Invalidation, exec: E)
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    invalidate_local(invalidation);
+
+    if NOTIFY_INVALIDATIONS.load(Ordering::Relaxed) {
+        let result = sqlx::query!(
+            "SELECT pg_notify($1, $2)",
+            CACHE_CHANNEL,
+            serde_json::to_string(&invalidation).unwrap_or_default()
+        )
+        .execute(exec)
+        .await;
+
+        // The other instances still drop the entry once it expires
+        if let Err(e) = result {
+            warn!("Sending cache invalidation failed: {:?}", e);
+        }
+    }
+}
+
+pub fn all_stats() -> Vec<CacheStats> {
+    vec![
+        MODS.stats(),
+        VERSIONS.stats(),
+        CATEGORIES.stats(),
+        LOADERS.stats(),
+        GAME_VERSIONS.stats(),
+        LICENSES.stats(),
+        DONATION_PLATFORMS.stats(),
+        REPORT_TYPES.stats(),
+    ]
+}
+
+fn clear_all() {
+    MODS.clear();
+    VERSIONS.clear();
+    CATEGORIES.clear();
+    LOADERS.clear();
+    GAME_VERSIONS.clear();
+    LICENSES.clear();
+    DONATION_PLATFORMS.clear();
+    REPORT_TYPES.clear();
+}
+
+/// Sends invalidations to the other API instances and applies theirs.
+/// Every instance has to run this for the caches to stay consistent.
+pub async fn listen(pool: PgPool) {
+    NOTIFY_INVALIDATIONS.store(true, Ordering::Relaxed);
+
+    loop {
+        if let Err(e) = apply_invalidations(&pool).await {
+            warn!("Cache invalidation listener failed, reconnecting: {:?}", e);
+        }
+
+        // Invalidations may have been missed while disconnected
+        clear_all();
+
+        futures_timer::Delay::new(Duration::from_secs(5)).await;
+    }
+}
+
+async fn apply_invalidations(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(CACHE_CHANNEL).await?;
+    info!("Listening for cache invalidations");
+
+    loop {
+        let notification = listener.recv().await?;
+
+        match serde_json::from_str::<Invalidation>(notification.payload()) {
+            Ok(invalidation) => invalidate_local(invalidation),
+            Err(e) => warn!("Received an invalid cache invalidation: {:?}", e),
+        }
+    }
+}
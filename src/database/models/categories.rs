@@ -1,3 +1,4 @@
+use super::cache;
 use super::ids::*;
 use super::DatabaseError;
 use futures::TryStreamExt;
@@ -22,12 +23,14 @@ pub struct ReportType {
     pub report_type: String,
 }
 
+#[derive(Clone)]
 pub struct License {
     pub id: LicenseId,
     pub short: String,
     pub name: String,
 }
 
+#[derive(Clone)]
 pub struct DonationPlatform {
     pub id: DonationPlatformId,
     pub short: String,
@@ -88,6 +91,10 @@ impl Category {
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
+        if let Some(cached) = cache::CATEGORIES.get(&()) {
+            return Ok(cached);
+        }
+
         let result = sqlx::query!(
             "
             SELECT category FROM categories
@@ -98,6 +105,8 @@ impl Category {
         .try_collect::<Vec<String>>()
         .await?;
 
+        cache::CATEGORIES.insert((), result.clone());
+
         Ok(result)
     }
 
@@ -213,6 +222,10 @@ impl Loader {
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
+        if let Some(cached) = cache::LOADERS.get(&()) {
+            return Ok(cached);
+        }
+
         let result = sqlx::query!(
             "
             SELECT loader FROM loaders
@@ -223,6 +236,8 @@ impl Loader {
         .try_collect::<Vec<String>>()
         .await?;
 
+        cache::LOADERS.insert((), result.clone());
+
         Ok(result)
     }
 
@@ -345,6 +360,10 @@ impl GameVersion {
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
+        if let Some(cached) = cache::GAME_VERSIONS.get(&()) {
+            return Ok(cached);
+        }
+
         let result = sqlx::query!(
             "
             SELECT version FROM game_versions
@@ -356,6 +375,8 @@ impl GameVersion {
         .try_collect::<Vec<String>>()
         .await?;
 
+        cache::GAME_VERSIONS.insert((), result.clone());
+
         Ok(result)
     }
 
@@ -563,6 +584,10 @@ impl License {
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
+        if let Some(cached) = cache::LICENSES.get(&()) {
+            return Ok(cached);
+        }
+
         let result = sqlx::query!(
             "
             SELECT id, short, name FROM licenses
@@ -579,6 +604,8 @@ impl License {
         .try_collect::<Vec<License>>()
         .await?;
 
+        cache::LICENSES.insert((), result.clone());
+
         Ok(result)
     }
 
@@ -711,6 +738,10 @@ impl DonationPlatform {
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
+        if let Some(cached) = cache::DONATION_PLATFORMS.get(&()) {
+            return Ok(cached);
+        }
+
         let result = sqlx::query!(
             "
             SELECT id, short, name FROM donation_platforms
@@ -727,6 +758,8 @@ impl DonationPlatform {
         .try_collect::<Vec<DonationPlatform>>()
         .await?;
 
+        cache::DONATION_PLATFORMS.insert((), result.clone());
+
         Ok(result)
     }
 
@@ -854,6 +887,10 @@ impl ReportType {
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
+        if let Some(cached) = cache::REPORT_TYPES.get(&()) {
+            return Ok(cached);
+        }
+
         let result = sqlx::query!(
             "
             SELECT name FROM report_types
@@ -864,6 +901,8 @@ impl ReportType {
         .try_collect::<Vec<String>>()
         .await?;
 
+        cache::REPORT_TYPES.insert((), result.clone());
+
         Ok(result)
     }
 
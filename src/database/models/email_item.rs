@@ -0,0 +1,247 @@
+use super::ids::*;
+
+/// An email waiting to be sent for a notification
+pub struct QueuedEmail {
+    pub id: i64,
+    pub user_id: UserId,
+    pub notification_type: String,
+    pub title: String,
+    pub text: String,
+    pub link: String,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub attempts: i32,
+}
+
+/// A followed mod that received new versions, listed in a user's digest
+pub struct DigestUpdate {
+    pub mod_id: ModId,
+    pub title: String,
+    pub versions: i64,
+}
+
+pub struct DigestRecipient {
+    pub id: UserId,
+    pub email: String,
+}
+
+impl QueuedEmail {
+    pub async fn insert(
+        user_id: UserId,
+        notification_type: &str,
+        title: &str,
+        text: &str,
+        link: &str,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "
+            INSERT INTO email_queue (
+                user_id, type, title, text, link
+            )
+            VALUES (
+                $1, $2, $3, $4, $5
+            )
+            ",
+            user_id as UserId,
+            notification_type,
+            title,
+            text,
+            link
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Claims the oldest queued emails that nobody is sending, or whose
+    /// claim is older than `lease_expiry`.  Claimed emails have to be removed
+    /// or released once they were tried.
+    pub async fn claim_batch<'a, E>(
+        count: i64,
+        lease_expiry: chrono::DateTime<chrono::Utc>,
+        exec: E,
+    ) -> Result<Vec<QueuedEmail>, sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            UPDATE email_queue
+            SET sending_at = CURRENT_TIMESTAMP
+            WHERE id IN (
+                SELECT id FROM email_queue
+                WHERE sending_at IS NULL OR sending_at < $2
+                ORDER BY created ASC
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, user_id, type notification_type, title, text, link, created, attempts
+            ",
+            count,
+            lease_expiry
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async {
+            Ok(e.right().map(|row| QueuedEmail {
+                id: row.id,
+                user_id: UserId(row.user_id),
+                notification_type: row.notification_type,
+                title: row.title,
+                text: row.text,
+                link: row.link,
+                created: row.created,
+                attempts: row.attempts,
+            }))
+        })
+        .try_collect::<Vec<QueuedEmail>>()
+        .await
+    }
+
+    /// Gives up the claim on an email that failed to send, so it's retried
+    /// on the next run
+    pub async fn release_failed<'a, E>(id: i64, exec: E) -> Result<(), sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            UPDATE email_queue
+            SET attempts = attempts + 1, sending_at = NULL
+            WHERE id = $1
+            ",
+            id
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove<'a, E>(id: i64, exec: E) -> Result<(), sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            DELETE FROM email_queue
+            WHERE id = $1
+            ",
+            id
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl DigestRecipient {
+    /// Claims users whose last digest was sent before `before`, who have not
+    /// unsubscribed from it and whose digest nobody is sending, or whose
+    /// claim is older than `lease_expiry`.  Digests that fail to send are
+    /// retried once their claim expires.
+    pub async fn claim_due<'a, E>(
+        before: chrono::DateTime<chrono::Utc>,
+        lease_expiry: chrono::DateTime<chrono::Utc>,
+        count: i64,
+        exec: E,
+    ) -> Result<Vec<DigestRecipient>, sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            UPDATE users
+            SET digest_sending_at = CURRENT_TIMESTAMP
+            WHERE id IN (
+                SELECT u.id FROM users u
+                WHERE u.last_digest < $1 AND u.email IS NOT NULL
+                AND (u.digest_sending_at IS NULL OR u.digest_sending_at < $2)
+                AND NOT EXISTS (
+                    SELECT 1 FROM notification_preferences np
+                    WHERE np.user_id = u.id AND np.type = $3 AND np.email = FALSE
+                )
+                LIMIT $4
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, email
+            ",
+            before,
+            lease_expiry,
+            crate::models::notifications::NotificationType::WeeklyDigest.as_str(),
+            count
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async {
+            Ok(e.right().and_then(|row| {
+                let id = UserId(row.id);
+                row.email.map(|email| DigestRecipient { id, email })
+            }))
+        })
+        .try_collect::<Vec<DigestRecipient>>()
+        .await
+    }
+
+    /// Marks the digest of a user as sent, ending the claim on it
+    pub async fn mark_sent<'a, E>(id: UserId, exec: E) -> Result<(), sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            UPDATE users
+            SET last_digest = CURRENT_TIMESTAMP, digest_sending_at = NULL
+            WHERE id = $1
+            ",
+            id as UserId
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+}
+impl DigestUpdate {
+    /// Lists the followed mods of a user that had versions published since `since`
+    pub async fn get_many_user<'a, E>(
+        user_id: UserId,
+        since: chrono::DateTime<chrono::Utc>,
+        exec: E,
+    ) -> Result<Vec<DigestUpdate>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            SELECT m.id, m.title, COUNT(v.id) versions
+            FROM mod_follows mf
+            INNER JOIN mods m ON m.id = mf.mod_id
+            INNER JOIN statuses s ON s.id = m.status
+            INNER JOIN versions v ON v.mod_id = m.id
+            WHERE mf.follower_id = $1 AND v.date_published > $2 AND s.status = $3
+            GROUP BY m.id, m.title
+            ORDER BY m.title ASC
+            ",
+            user_id as UserId,
+            since,
+            crate::models::mods::ModStatus::Approved.as_str()
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async {
+            Ok(e.right().map(|row| DigestUpdate {
+                mod_id: ModId(row.id),
+                title: row.title,
+                versions: row.versions.unwrap_or(0),
+            }))
+        })
+        .try_collect::<Vec<DigestUpdate>>()
+        .await
+    }
+}
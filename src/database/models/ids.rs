@@ -94,6 +94,22 @@ generate_ids!(
     ReportId
 );
 
+generate_ids!(
+    pub generate_webhook_id,
+    WebhookId,
+    8,
+    "SELECT EXISTS(SELECT 1 FROM webhooks WHERE id=$1)",
+    WebhookId
+);
+
+generate_ids!(
+    pub generate_upload_id,
+    UploadId,
+    8,
+    "SELECT EXISTS(SELECT 1 FROM upload_sessions WHERE id=$1)",
+    UploadId
+);
+
 generate_ids!(
     pub generate_notification_id,
     NotificationId,
@@ -102,7 +118,7 @@ generate_ids!(
     NotificationId
 );
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Type)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Type)]
 #[sqlx(transparent)]
 pub struct UserId(pub i64);
 
@@ -168,6 +184,14 @@ pub struct NotificationId(pub i64);
 #[sqlx(transparent)]
 pub struct NotificationActionId(pub i32);
 
+#[derive(Copy, Clone, Debug, Type)]
+#[sqlx(transparent)]
+pub struct WebhookId(pub i64);
+
+#[derive(Copy, Clone, Debug, Type)]
+#[sqlx(transparent)]
+pub struct UploadId(pub i64);
+
 use crate::models::ids;
 
 impl From<ids::ModId> for ModId {
@@ -230,3 +254,23 @@ impl From<NotificationId> for ids::NotificationId {
         ids::NotificationId(id.0 as u64)
     }
 }
+impl From<ids::WebhookId> for WebhookId {
+    fn from(id: ids::WebhookId) -> Self {
+        WebhookId(id.0 as i64)
+    }
+}
+impl From<WebhookId> for ids::WebhookId {
+    fn from(id: WebhookId) -> Self {
+        ids::WebhookId(id.0 as u64)
+    }
+}
+impl From<ids::UploadId> for UploadId {
+    fn from(id: ids::UploadId) -> Self {
+        UploadId(id.0 as i64)
+    }
+}
+impl From<UploadId> for ids::UploadId {
+    fn from(id: UploadId) -> Self {
+        ids::UploadId(id.0 as u64)
+    }
+}
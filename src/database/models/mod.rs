@@ -3,14 +3,21 @@
 
 use thiserror::Error;
 
+pub mod announcement_item;
+pub mod blob_item;
+pub mod cache;
 pub mod categories;
+pub mod email_item;
 pub mod ids;
 pub mod mod_item;
 pub mod notification_item;
+pub mod quota_item;
 pub mod report_item;
 pub mod team_item;
+pub mod upload_item;
 pub mod user_item;
 pub mod version_item;
+pub mod webhook_item;
 
 pub use ids::*;
 pub use mod_item::Mod;
@@ -34,6 +41,8 @@ pub enum DatabaseError {
     InvalidIdentifier(String),
     #[error("Invalid permissions bitflag!")]
     BitflagError,
+    #[error("Error while serializing data: {0}")]
+    JsonError(#[from] serde_json::Error),
     #[error("A database request failed")]
     Other(String),
 }
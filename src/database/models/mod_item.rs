@@ -1,6 +1,7 @@
+use super::cache;
 use super::ids::*;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DonationUrl {
     pub mod_id: ModId,
     pub platform_id: DonationPlatformId,
@@ -107,6 +108,7 @@ impl ModBuilder {
     }
 }
 
+#[derive(Clone)]
 pub struct Mod {
     pub id: ModId,
     pub team_id: TeamId,
@@ -352,6 +354,31 @@ impl Mod {
             super::Version::remove_full(version, exec).await?;
         }
 
+        sqlx::query!(
+            "
+            DELETE FROM webhook_deliveries
+            WHERE webhook_id IN (
+                SELECT id FROM webhooks
+                WHERE mod_id = $1 OR team_id = $2
+            )
+            ",
+            id as ModId,
+            team_id as TeamId,
+        )
+        .execute(exec)
+        .await?;
+
+        sqlx::query!(
+            "
+            DELETE FROM webhooks
+            WHERE mod_id = $1 OR team_id = $2
+            ",
+            id as ModId,
+            team_id as TeamId,
+        )
+        .execute(exec)
+        .await?;
+
         sqlx::query!(
             "
             DELETE FROM mods
@@ -409,18 +436,39 @@ impl Mod {
         }
     }
 
+    /// Gets a mod, from the cache if possible
     pub async fn get_full<'a, 'b, E>(
         id: ModId,
         executor: E,
     ) -> Result<Option<QueryMod>, sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres> + Copy,
+    {
+        if let Some(cached) = cache::MODS.get(&id.0) {
+            return Ok(Some(cached));
+        }
+
+        let result = Mod::get_full_uncached(id, executor).await?;
+
+        if let Some(data) = &result {
+            cache::MODS.insert(id.0, data.clone());
+        }
+
+        Ok(result)
+    }
+
+    async fn get_full_uncached<'a, 'b, E>(
+        id: ModId,
+        executor: E,
+    ) -> Result<Option<QueryMod>, sqlx::error::Error>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres> + Copy,
     {
         let result = sqlx::query!(
-            "
+            r#"
             SELECT m.id id, m.title title, m.description description, m.downloads downloads, m.follows follows,
             m.icon_url icon_url, m.body body, m.body_url body_url, m.published published, m.is_nsfw,
-            m.updated updated, m.status status,
+            m.updated updated, GREATEST(m.updated, m.edited) "last_modified!", m.status status,
             m.issues_url issues_url, m.source_url source_url, m.wiki_url wiki_url, m.discord_url discord_url,
             m.team_id team_id, m.slug slug,
             s.status status_name,
@@ -432,7 +480,7 @@ impl Mod {
             INNER JOIN statuses s ON s.id = m.status
             WHERE m.id = $1
             GROUP BY m.id, s.id;
-            ",
+            "#,
             id as ModId,
         )
             .fetch_optional(executor)
@@ -474,16 +522,89 @@ impl Mod {
                     .collect(),
                 donation_urls: vec![],
                 status: crate::models::mods::ModStatus::from_str(&m.status_name),
+                last_modified: m.last_modified,
             }))
         } else {
             Ok(None)
         }
     }
 
+    /// Gets the most recently approved mods along with when they were approved,
+    /// optionally limited to a category
+    pub async fn get_recently_approved<'a, E>(
+        category: Option<&str>,
+        allow_nsfw: bool,
+        count: i64,
+        exec: E,
+    ) -> Result<Vec<(ModId, chrono::DateTime<chrono::Utc>)>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::TryStreamExt;
+
+        sqlx::query!(
+            r#"
+            SELECT m.id, m.approved "approved!" FROM mods m
+            WHERE m.status = (SELECT s.id FROM statuses s WHERE s.status = $1)
+            AND m.approved IS NOT NULL
+            AND ($2 OR m.is_nsfw = FALSE)
+            AND ($3::varchar IS NULL OR EXISTS (
+                SELECT 1 FROM mods_categories mc
+                INNER JOIN categories c ON c.id = mc.joining_category_id
+                WHERE mc.joining_mod_id = m.id AND c.category = $3
+            ))
+            ORDER BY m.approved DESC
+            LIMIT $4
+            "#,
+            crate::models::mods::ModStatus::Approved.as_str(),
+            allow_nsfw,
+            category,
+            count
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async { Ok(e.right().map(|m| (ModId(m.id), m.approved))) })
+        .try_collect::<Vec<(ModId, chrono::DateTime<chrono::Utc>)>>()
+        .await
+    }
+
+    /// Gets many mods, taking the ones that are cached from the cache.  The
+    /// mods are returned in the order of `mod_ids`.
     pub async fn get_many_full<'a, E>(
         mod_ids: Vec<ModId>,
         exec: E,
     ) -> Result<Vec<QueryMod>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres> + Copy,
+    {
+        let mut mods = std::collections::HashMap::new();
+        let mut missing = Vec::new();
+
+        for id in &mod_ids {
+            match cache::MODS.get(&id.0) {
+                Some(cached) => {
+                    mods.insert(id.0, cached);
+                }
+                None => missing.push(*id),
+            }
+        }
+
+        if !missing.is_empty() {
+            for data in Mod::get_many_full_uncached(missing, exec).await? {
+                cache::MODS.insert(data.inner.id.0, data.clone());
+                mods.insert(data.inner.id.0, data);
+            }
+        }
+
+        Ok(mod_ids
+            .into_iter()
+            .filter_map(|id| mods.remove(&id.0))
+            .collect())
+    }
+
+    async fn get_many_full_uncached<'a, E>(
+        mod_ids: Vec<ModId>,
+        exec: E,
+    ) -> Result<Vec<QueryMod>, sqlx::Error>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres> + Copy,
     {
@@ -491,10 +612,10 @@ impl Mod {
 
         let mod_ids_parsed: Vec<i64> = mod_ids.into_iter().map(|x| x.0).collect();
         sqlx::query!(
-            "
+            r#"
             SELECT m.id id, m.title title, m.description description, m.downloads downloads, m.follows follows,
             m.icon_url icon_url, m.body body, m.body_url body_url, m.published published, m.is_nsfw,
-            m.updated updated, m.status status,
+            m.updated updated, GREATEST(m.updated, m.edited) "last_modified!", m.status status,
             m.issues_url issues_url, m.source_url source_url, m.wiki_url wiki_url, m.discord_url discord_url,
             m.team_id team_id, m.slug slug,
             s.status status_name,
@@ -506,7 +627,7 @@ impl Mod {
             INNER JOIN statuses s ON s.id = m.status
             WHERE m.id IN (SELECT * FROM UNNEST($1::bigint[]))
             GROUP BY m.id, s.id;
-            ",
+            "#,
             &mod_ids_parsed
         )
             .fetch_many(exec)
@@ -536,6 +657,7 @@ impl Mod {
                     versions: m.versions.unwrap_or_default().split(',').map(|x| VersionId(x.parse().unwrap_or_default())).collect(),
                     donation_urls: vec![],
                     status: crate::models::mods::ModStatus::from_str(&m.status_name),
+                    last_modified: m.last_modified,
                 }))
             })
             .try_collect::<Vec<QueryMod>>()
@@ -543,6 +665,7 @@ impl Mod {
     }
 }
 
+#[derive(Clone)]
 pub struct QueryMod {
     pub inner: Mod,
 
@@ -550,4 +673,6 @@ pub struct QueryMod {
     pub versions: Vec<VersionId>,
     pub donation_urls: Vec<DonationUrl>,
     pub status: crate::models::mods::ModStatus,
+    /// When the mod or its list of versions last changed
+    pub last_modified: chrono::DateTime<chrono::Utc>,
 }
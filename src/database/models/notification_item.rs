@@ -1,10 +1,12 @@
 use super::ids::*;
 use crate::database::models::DatabaseError;
+use crate::models::notifications::{NotificationBody, NotificationType};
 
 pub struct NotificationBuilder {
     pub title: String,
     pub text: String,
     pub link: String,
+    pub body: NotificationBody,
     pub actions: Vec<NotificationActionBuilder>,
 }
 
@@ -16,14 +18,25 @@ pub struct NotificationActionBuilder {
 pub struct Notification {
     pub id: NotificationId,
     pub user_id: UserId,
+    pub notification_type: String,
     pub title: String,
     pub text: String,
     pub link: String,
     pub read: bool,
     pub created: chrono::DateTime<chrono::Utc>,
+    pub body: Option<serde_json::Value>,
     pub actions: Vec<NotificationAction>,
 }
 
+#[derive(Clone)]
+pub struct NotificationPreference {
+    pub user_id: UserId,
+    pub notification_type: String,
+    pub in_app: bool,
+    pub email: bool,
+    pub webhook: bool,
+}
+
 pub struct NotificationAction {
     pub id: NotificationActionId,
     pub notification_id: NotificationId,
@@ -41,12 +54,59 @@ impl NotificationBuilder {
         self.insert_many(vec![user], transaction).await
     }
 
+    /// Inserts this notification for every user who receives this type in-app,
+    /// and queues an email or webhook delivery for those who receive it that way
     pub async fn insert_many(
         &self,
         users: Vec<UserId>,
         transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     ) -> Result<(), DatabaseError> {
+        let notification_type = self.body.notification_type().as_str();
+        let body = serde_json::to_value(&self.body)?;
+
+        let preferences =
+            NotificationPreference::get_many_type(&users, notification_type, &mut *transaction)
+                .await?;
+
         for user in users {
+            let preference = preferences
+                .iter()
+                .find(|x| x.user_id == user)
+                .cloned()
+                .unwrap_or_else(|| NotificationPreference::default_for(user, notification_type));
+
+            if preference.email && crate::notifications::email::is_enabled() {
+                super::email_item::QueuedEmail::insert(
+                    user,
+                    notification_type,
+                    &self.title,
+                    &self.text,
+                    &self.link,
+                    &mut *transaction,
+                )
+                .await?;
+            }
+
+            if preference.webhook {
+                crate::notifications::webhooks::dispatch_user_event(
+                    crate::models::webhooks::WebhookEvent::NotificationCreated,
+                    user,
+                    &serde_json::json!({
+                        "type": notification_type,
+                        "title": self.title,
+                        "text": self.text,
+                        "link": self.link,
+                        "body": body,
+                    }),
+                    &mut *transaction,
+                )
+                .await?;
+            }
+
+            if !preference.in_app {
+                continue;
+            }
+
             let id = generate_notification_id(&mut *transaction).await?;
 
             let mut actions = Vec::new();
@@ -64,11 +124,13 @@ impl NotificationBuilder {
             Notification {
                 id,
                 user_id: user,
+                notification_type: notification_type.to_string(),
                 title: self.title.clone(),
                 text: self.text.clone(),
                 link: self.link.clone(),
                 read: false,
                 created: chrono::Utc::now(),
+                body: Some(body.clone()),
                 actions,
             }
             .insert(&mut *transaction)
@@ -87,17 +149,19 @@ impl Notification {
         sqlx::query!(
             "
             INSERT INTO notifications (
-                id, user_id, title, text, link
+                id, user_id, type, title, text, link, body
             )
             VALUES (
-                $1, $2, $3, $4, $5
+                $1, $2, $3, $4, $5, $6, $7
             )
             ",
             self.id as NotificationId,
             self.user_id as UserId,
+            &self.notification_type,
             &self.title,
             &self.text,
-            &self.link
+            &self.link,
+            self.body
         )
         .execute(&mut *transaction)
         .await?;
@@ -106,6 +170,20 @@ impl Notification {
             action.insert(&mut *transaction).await?;
         }
 
+        // Postgres only delivers this once the transaction commits
+        let event = crate::notifications::stream::NotificationEvent {
+            user_id: self.user_id.0,
+            notification_id: self.id.0,
+        };
+
+        sqlx::query!(
+            "SELECT pg_notify($1, $2)",
+            crate::notifications::stream::NOTIFICATION_CHANNEL,
+            serde_json::to_string(&event).unwrap_or_default()
+        )
+        .execute(&mut *transaction)
+        .await?;
+
         Ok(())
     }
 
@@ -118,7 +196,7 @@ impl Notification {
     {
         let result = sqlx::query!(
             "
-            SELECT n.user_id, n.title, n.text, n.link, n.created, n.read,
+            SELECT n.user_id, n.type notification_type, n.title, n.text, n.link, n.created, n.read, n.body,
             STRING_AGG(DISTINCT na.id || ', ' || na.title || ', ' || na.action_route || ', ' || na.action_route_method,  ' ,') actions
             FROM notifications n
             LEFT OUTER JOIN notifications_actions na on n.id = na.notification_id
@@ -150,11 +228,13 @@ impl Notification {
             Ok(Some(Notification {
                 id,
                 user_id: UserId(row.user_id),
+                notification_type: row.notification_type,
                 title: row.title,
                 text: row.text,
                 link: row.link,
                 read: row.read,
                 created: row.created,
+                body: row.body,
                 actions,
             }))
         } else {
@@ -174,7 +254,7 @@ impl Notification {
         let notification_ids_parsed: Vec<i64> = notification_ids.into_iter().map(|x| x.0).collect();
         sqlx::query!(
             "
-            SELECT n.id, n.user_id, n.title, n.text, n.link, n.created, n.read,
+            SELECT n.id, n.user_id, n.type notification_type, n.title, n.text, n.link, n.created, n.read, n.body,
             STRING_AGG(DISTINCT na.id || ', ' || na.title || ', ' || na.action_route || ', ' || na.action_route_method,  ' ,') actions
             FROM notifications n
             LEFT OUTER JOIN notifications_actions na on n.id = na.notification_id
@@ -206,11 +286,13 @@ impl Notification {
                 Notification {
                     id,
                     user_id: UserId(row.user_id),
+                    notification_type: row.notification_type,
                     title: row.title,
                     text: row.text,
                     link: row.link,
                     read: row.read,
                     created: row.created,
+                    body: row.body,
                     actions,
                 }
             }))
@@ -230,7 +312,7 @@ impl Notification {
 
         sqlx::query!(
             "
-            SELECT n.id, n.user_id, n.title, n.text, n.link, n.created, n.read,
+            SELECT n.id, n.user_id, n.type notification_type, n.title, n.text, n.link, n.created, n.read, n.body,
             STRING_AGG(DISTINCT na.id || ', ' || na.title || ', ' || na.action_route || ', ' || na.action_route_method,  ' ,') actions
             FROM notifications n
             LEFT OUTER JOIN notifications_actions na on n.id = na.notification_id
@@ -262,11 +344,13 @@ impl Notification {
                 Notification {
                     id,
                     user_id: UserId(row.user_id),
+                    notification_type: row.notification_type,
                     title: row.title,
                     text: row.text,
                     link: row.link,
                     read: row.read,
                     created: row.created,
+                    body: row.body,
                     actions,
                 }
             }))
@@ -275,6 +359,110 @@ impl Notification {
         .await
     }
 
+    pub async fn get_unread_count_user<'a, E>(
+        user_id: UserId,
+        exec: E,
+    ) -> Result<i64, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            "
+            SELECT COUNT(id) count FROM notifications
+            WHERE user_id = $1 AND read = FALSE
+            ",
+            user_id as UserId
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result.count.unwrap_or(0))
+    }
+
+    pub async fn set_read_many(
+        notification_ids: Vec<NotificationId>,
+        read: bool,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::error::Error> {
+        let notification_ids_parsed: Vec<i64> = notification_ids.into_iter().map(|x| x.0).collect();
+
+        sqlx::query!(
+            "
+            UPDATE notifications
+            SET read = $1
+            WHERE id IN (SELECT * FROM UNNEST($2::bigint[]))
+            ",
+            read,
+            &notification_ids_parsed
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_many(
+        notification_ids: Vec<NotificationId>,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::error::Error> {
+        let notification_ids_parsed: Vec<i64> = notification_ids.into_iter().map(|x| x.0).collect();
+
+        sqlx::query!(
+            "
+            DELETE FROM notifications_actions
+            WHERE notification_id IN (SELECT * FROM UNNEST($1::bigint[]))
+            ",
+            &notification_ids_parsed
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query!(
+            "
+            DELETE FROM notifications
+            WHERE id IN (SELECT * FROM UNNEST($1::bigint[]))
+            ",
+            &notification_ids_parsed
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Deletes every read notification created before `before`, returning how many were removed
+    pub async fn remove_read_before(
+        before: chrono::DateTime<chrono::Utc>,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<u64, sqlx::error::Error> {
+        use sqlx::Done;
+
+        sqlx::query!(
+            "
+            DELETE FROM notifications_actions
+            WHERE notification_id IN (
+                SELECT id FROM notifications
+                WHERE read = TRUE AND created < $1
+            )
+            ",
+            before
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        let result = sqlx::query!(
+            "
+            DELETE FROM notifications
+            WHERE read = TRUE AND created < $1
+            ",
+            before
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
     pub async fn remove<'a, 'b, E>(
         id: NotificationId,
         exec: E,
@@ -331,3 +519,178 @@ impl NotificationAction {
         Ok(())
     }
 }
+
+impl NotificationPreference {
+    /// The preference used when a user has not configured a notification type
+    pub fn default_for(user_id: UserId, notification_type: &str) -> Self {
+        let notification_type = NotificationType::from_str(notification_type);
+
+        NotificationPreference {
+            user_id,
+            notification_type: notification_type.as_str().to_string(),
+            in_app: notification_type != NotificationType::WeeklyDigest,
+            email: matches!(
+                notification_type,
+                NotificationType::TeamInvite
+                    | NotificationType::ModerationDecision
+                    | NotificationType::WeeklyDigest
+            ),
+            webhook: false,
+        }
+    }
+
+    pub async fn upsert(
+        &self,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "
+            INSERT INTO notification_preferences (
+                user_id, type, in_app, email, webhook
+            )
+            VALUES (
+                $1, $2, $3, $4, $5
+            )
+            ON CONFLICT (user_id, type) DO UPDATE
+            SET in_app = EXCLUDED.in_app, email = EXCLUDED.email, webhook = EXCLUDED.webhook
+            ",
+            self.user_id as UserId,
+            &self.notification_type,
+            self.in_app,
+            self.email,
+            self.webhook
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lists the preferences a user has configured. Unconfigured types are not included.
+    pub async fn get_many_user<'a, E>(
+        user_id: UserId,
+        exec: E,
+    ) -> Result<Vec<NotificationPreference>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            SELECT np.type notification_type, np.in_app, np.email, np.webhook
+            FROM notification_preferences np
+            WHERE np.user_id = $1
+            ",
+            user_id as UserId
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async {
+            Ok(e.right().map(|row| NotificationPreference {
+                user_id,
+                notification_type: row.notification_type,
+                in_app: row.in_app,
+                email: row.email,
+                webhook: row.webhook,
+            }))
+        })
+        .try_collect::<Vec<NotificationPreference>>()
+        .await
+    }
+
+    /// Lists the configured preferences of the given users for one notification type
+    pub async fn get_many_type<'a, E>(
+        user_ids: &[UserId],
+        notification_type: &str,
+        exec: E,
+    ) -> Result<Vec<NotificationPreference>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        let user_ids_parsed: Vec<i64> = user_ids.iter().map(|x| x.0).collect();
+        sqlx::query!(
+            "
+            SELECT np.user_id, np.type notification_type, np.in_app, np.email, np.webhook
+            FROM notification_preferences np
+            WHERE np.user_id IN (SELECT * FROM UNNEST($1::bigint[])) AND np.type = $2
+            ",
+            &user_ids_parsed,
+            notification_type
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async {
+            Ok(e.right().map(|row| NotificationPreference {
+                user_id: UserId(row.user_id),
+                notification_type: row.notification_type,
+                in_app: row.in_app,
+                email: row.email,
+                webhook: row.webhook,
+            }))
+        })
+        .try_collect::<Vec<NotificationPreference>>()
+        .await
+    }
+}
+
+/// A ticket a user can open their notification stream with once, for
+/// clients that can't send an `Authorization` header, like browsers
+pub struct StreamTicket {
+    pub ticket: String,
+    pub user_id: UserId,
+    pub expires: chrono::DateTime<chrono::Utc>,
+}
+
+impl StreamTicket {
+    /// Stores the ticket, dropping tickets that expired
+    pub async fn insert(
+        &self,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "
+            DELETE FROM notification_stream_tickets
+            WHERE expires < NOW()
+            "
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query!(
+            "
+            INSERT INTO notification_stream_tickets (ticket, user_id, expires)
+            VALUES ($1, $2, $3)
+            ",
+            self.ticket,
+            self.user_id as UserId,
+            self.expires
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Uses up a ticket, returning the user it was issued to.  `None` if it
+    /// doesn't exist, was used already or expired.
+    pub async fn redeem<'a, E>(ticket: &str, exec: E) -> Result<Option<UserId>, sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            "
+            DELETE FROM notification_stream_tickets
+            WHERE ticket = $1
+            RETURNING user_id, expires
+            ",
+            ticket
+        )
+        .fetch_optional(exec)
+        .await?;
+
+        Ok(result
+            .filter(|row| row.expires > chrono::Utc::now())
+            .map(|row| UserId(row.user_id)))
+    }
+}
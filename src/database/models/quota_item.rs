@@ -0,0 +1,484 @@
+use super::ids::*;
+
+/// The largest file that can be uploaded, unless `FILE_SIZE_LIMIT` is set
+const DEFAULT_FILE_SIZE_LIMIT: u64 = 25 * (1 << 20);
+/// How much a user or team can store, unless `USER_STORAGE_LIMIT` or
+/// `TEAM_STORAGE_LIMIT` are set
+const DEFAULT_STORAGE_LIMIT: u64 = 1 << 30;
+
+fn limit_from_env(var: &str, default: u64) -> u64 {
+    dotenv::var(var)
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(default)
+}
+
+pub fn default_file_size_limit() -> u64 {
+    limit_from_env("FILE_SIZE_LIMIT", DEFAULT_FILE_SIZE_LIMIT)
+}
+
+pub fn default_user_storage_limit() -> u64 {
+    limit_from_env("USER_STORAGE_LIMIT", DEFAULT_STORAGE_LIMIT)
+}
+
+pub fn default_team_storage_limit() -> u64 {
+    limit_from_env("TEAM_STORAGE_LIMIT", DEFAULT_STORAGE_LIMIT)
+}
+
+/// Limits moderators set for a user or team, in bytes.  `None` uses the
+/// defaults.
+#[derive(Clone, Debug, Default)]
+pub struct StorageLimits {
+    pub file_size_limit: Option<i64>,
+    pub storage_limit: Option<i64>,
+}
+
+impl StorageLimits {
+    pub async fn get_user<'a, E>(id: UserId, exec: E) -> Result<Option<Self>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            "
+            SELECT file_size_limit, storage_limit FROM users
+            WHERE id = $1
+            ",
+            id as UserId
+        )
+        .fetch_optional(exec)
+        .await?;
+
+        Ok(result.map(|row| StorageLimits {
+            file_size_limit: row.file_size_limit,
+            storage_limit: row.storage_limit,
+        }))
+    }
+
+    pub async fn get_team<'a, E>(id: TeamId, exec: E) -> Result<Option<Self>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            "
+            SELECT file_size_limit, storage_limit FROM teams
+            WHERE id = $1
+            ",
+            id as TeamId
+        )
+        .fetch_optional(exec)
+        .await?;
+
+        Ok(result.map(|row| StorageLimits {
+            file_size_limit: row.file_size_limit,
+            storage_limit: row.storage_limit,
+        }))
+    }
+
+    /// Returns false if the user doesn't exist
+    pub async fn set_user<'a, E>(&self, id: UserId, exec: E) -> Result<bool, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use sqlx::Done;
+
+        let result = sqlx::query!(
+            "
+            UPDATE users
+            SET file_size_limit = $1, storage_limit = $2
+            WHERE id = $3
+            ",
+            self.file_size_limit,
+            self.storage_limit,
+            id as UserId
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns false if the team doesn't exist
+    pub async fn set_team<'a, E>(&self, id: TeamId, exec: E) -> Result<bool, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use sqlx::Done;
+
+        let result = sqlx::query!(
+            "
+            UPDATE teams
+            SET file_size_limit = $1, storage_limit = $2
+            WHERE id = $3
+            ",
+            self.file_size_limit,
+            self.storage_limit,
+            id as TeamId
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// The bytes stored in the files of a mod, by the mod
+pub struct ModUsage {
+    pub mod_id: ModId,
+    pub files: i64,
+    pub used: i64,
+}
+
+/// Counts the bytes stored in version files.  Files share their blob with
+/// identical files, but each of them counts.  Files stored before blobs were
+/// used have no known size, and don't count.
+pub struct StorageUsage;
+
+impl StorageUsage {
+    /// The bytes stored in the versions a user uploaded
+    pub async fn get_user<'a, E>(id: UserId, exec: E) -> Result<i64, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            r#"
+            SELECT COALESCE(SUM(b.size), 0)::bigint AS "used!" FROM versions v
+            INNER JOIN files f ON f.version_id = v.id
+            INNER JOIN file_blobs b ON b.sha512 = f.blob_sha512
+            WHERE v.author_id = $1
+            "#,
+            id as UserId
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result.used)
+    }
+
+    /// The bytes stored in the versions of a team's mods
+    pub async fn get_team<'a, E>(id: TeamId, exec: E) -> Result<i64, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            r#"
+            SELECT COALESCE(SUM(b.size), 0)::bigint AS "used!" FROM mods m
+            INNER JOIN versions v ON v.mod_id = m.id
+            INNER JOIN files f ON f.version_id = v.id
+            INNER JOIN file_blobs b ON b.sha512 = f.blob_sha512
+            WHERE m.team_id = $1
+            "#,
+            id as TeamId
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result.used)
+    }
+
+    /// The bytes stored in the versions a user uploaded, by the mod
+    pub async fn get_user_mods<'a, E>(id: UserId, exec: E) -> Result<Vec<ModUsage>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            r#"
+            SELECT v.mod_id, COUNT(f.id) AS "files!", COALESCE(SUM(b.size), 0)::bigint AS "used!"
+            FROM versions v
+            INNER JOIN files f ON f.version_id = v.id
+            LEFT JOIN file_blobs b ON b.sha512 = f.blob_sha512
+            WHERE v.author_id = $1
+            GROUP BY v.mod_id
+            ORDER BY 3 DESC
+            "#,
+            id as UserId
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result
+            .into_iter()
+            .map(|row| ModUsage {
+                mod_id: ModId(row.mod_id),
+                files: row.files,
+                used: row.used,
+            })
+            .collect())
+    }
+}
+
+/// A storage limit, and how much of it is used
+#[derive(Copy, Clone, Debug)]
+pub struct Quota {
+    pub limit: u64,
+    pub used: u64,
+}
+
+impl Quota {
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used)
+    }
+}
+
+/// What a user can upload to a team's mods
+#[derive(Copy, Clone, Debug)]
+pub struct StorageQuota {
+    /// The largest file that can be uploaded.  Limits raised for either the
+    /// user or the team apply.
+    pub file_size_limit: u64,
+    pub user: Quota,
+    pub team: Quota,
+}
+
+impl StorageQuota {
+    /// The quota of a user uploading to a team's mods.  `None` stands for
+    /// the team of a mod that is being created, which doesn't exist yet.
+    ///
+    /// The user and team rows stay locked until the transaction ends, so
+    /// concurrent uploads against the same quota wait for each other, and
+    /// count what the others stored.
+    pub async fn get(
+        user_id: UserId,
+        team_id: Option<TeamId>,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<Self, sqlx::Error> {
+        // Always the user first, so that uploads don't deadlock
+        sqlx::query!(
+            "
+            SELECT id FROM users
+            WHERE id = $1
+            FOR UPDATE
+            ",
+            user_id as UserId
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+        if let Some(team_id) = team_id {
+            sqlx::query!(
+                "
+                SELECT id FROM teams
+                WHERE id = $1
+                FOR UPDATE
+                ",
+                team_id as TeamId
+            )
+            .fetch_optional(&mut *transaction)
+            .await?;
+        }
+
+        let user_limits = StorageLimits::get_user(user_id, &mut *transaction)
+            .await?
+            .unwrap_or_default();
+
+        let (team_limits, team_used) = match team_id {
+            Some(team_id) => (
+                StorageLimits::get_team(team_id, &mut *transaction)
+                    .await?
+                    .unwrap_or_default(),
+                StorageUsage::get_team(team_id, &mut *transaction).await?,
+            ),
+            None => (StorageLimits::default(), 0),
+        };
+
+        let file_size_limit = std::cmp::max(
+            limit_or(user_limits.file_size_limit, default_file_size_limit),
+            limit_or(team_limits.file_size_limit, default_file_size_limit),
+        );
+
+        Ok(StorageQuota {
+            file_size_limit,
+            user: Quota {
+                limit: limit_or(user_limits.storage_limit, default_user_storage_limit),
+                used: StorageUsage::get_user(user_id, &mut *transaction).await? as u64,
+            },
+            team: Quota {
+                limit: limit_or(team_limits.storage_limit, default_team_storage_limit),
+                used: team_used as u64,
+            },
+        })
+    }
+
+    /// The largest file that fits into the quota
+    pub fn max_file_size(&self) -> u64 {
+        self.file_size_limit
+            .min(self.user.remaining())
+            .min(self.team.remaining())
+    }
+
+    /// Counts a file towards the quota
+    pub fn add(&mut self, size: u64) {
+        self.user.used += size;
+        self.team.used += size;
+    }
+
+    /// Why a file larger than `max_file_size` can't be uploaded
+    pub fn exceeded_error(&self) -> String {
+        let (limit, what) = if self.max_file_size() == self.file_size_limit {
+            (self.file_size_limit, "Mod file exceeds the maximum of")
+        } else if self.user.remaining() <= self.team.remaining() {
+            (
+                self.user.limit,
+                "The file would exceed your storage quota of",
+            )
+        } else {
+            (
+                self.team.limit,
+                "The file would exceed the team's storage quota of",
+            )
+        };
+
+        format!(
+            "{} {}. Contact a moderator or admin to request permission to upload larger files.",
+            what,
+            format_size(limit)
+        )
+    }
+}
+
+/// The largest file a user might be able to upload, taking the limits of all
+/// of their teams into account.  Used before it's known which mod a file is
+/// for.
+pub async fn get_largest_file_size_limit<'a, E>(
+    user_id: UserId,
+    exec: E,
+) -> Result<u64, sqlx::Error>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let result = sqlx::query!(
+        "
+        SELECT
+            u.file_size_limit,
+            (
+                SELECT MAX(t.file_size_limit) FROM team_members tm
+                INNER JOIN teams t ON t.id = tm.team_id
+                WHERE tm.user_id = u.id AND tm.accepted
+            ) team_file_size_limit
+        FROM users u
+        WHERE u.id = $1
+        ",
+        user_id as UserId
+    )
+    .fetch_optional(exec)
+    .await?;
+
+    Ok(match result {
+        Some(row) => std::cmp::max(
+            limit_or(row.file_size_limit, default_file_size_limit),
+            limit_or(row.team_file_size_limit, default_file_size_limit),
+        ),
+        None => default_file_size_limit(),
+    })
+}
+
+fn limit_or(limit: Option<i64>, default: fn() -> u64) -> u64 {
+    limit.map(|x| x.max(0) as u64).unwrap_or_else(default)
+}
+
+/// A size in bytes, in the largest binary unit it has at least one of
+pub fn format_size(size: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+    let mut unit = None;
+    for (i, name) in UNITS.iter().enumerate() {
+        if size >= 1 << (10 * (i + 1)) {
+            unit = Some((i + 1, name));
+        }
+    }
+
+    match unit {
+        Some((exponent, name)) => {
+            let value = size as f64 / (1u64 << (10 * exponent)) as f64;
+
+            if value.fract() == 0.0 {
+                format!("{}{}", value, name)
+            } else {
+                format!("{:.1}{}", value, name)
+            }
+        }
+        None => format!("{} bytes", size),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quota(file_size_limit: u64, user: (u64, u64), team: (u64, u64)) -> StorageQuota {
+        StorageQuota {
+            file_size_limit,
+            user: Quota {
+                limit: user.0,
+                used: user.1,
+            },
+            team: Quota {
+                limit: team.0,
+                used: team.1,
+            },
+        }
+    }
+
+    fn quota_with_team_full() -> StorageQuota {
+        quota(100, (1000, 0), (1000, 990))
+    }
+
+    fn quota_with_room() -> StorageQuota {
+        quota(100, (1000, 0), (1000, 0))
+    }
+
+    #[test]
+    fn sizes_are_formatted_in_the_largest_unit() {
+        assert_eq!(format_size(0), "0 bytes");
+        assert_eq!(format_size(1023), "1023 bytes");
+        assert_eq!(format_size(1024), "1KiB");
+        assert_eq!(format_size(1536), "1.5KiB");
+        assert_eq!(format_size(25 << 20), "25MiB");
+        assert_eq!(format_size(1 << 30), "1GiB");
+        assert_eq!(format_size(3 << 40), "3TiB");
+        assert_eq!(format_size(2048 << 40), "2048TiB");
+    }
+
+    #[test]
+    fn the_smallest_limit_applies() {
+        let quota = quota(100, (1000, 950), (1000, 0));
+        assert_eq!(quota.max_file_size(), 50);
+        assert!(quota
+            .exceeded_error()
+            .contains("your storage quota of 1000 bytes"));
+
+        let quota = quota_with_team_full();
+        assert_eq!(quota.max_file_size(), 10);
+        assert!(quota.exceeded_error().contains("the team's storage quota"));
+
+        let quota = quota_with_room();
+        assert_eq!(quota.max_file_size(), 100);
+        assert!(quota
+            .exceeded_error()
+            .starts_with("Mod file exceeds the maximum of 100 bytes"));
+    }
+
+    #[test]
+    fn used_storage_counts_against_both_quotas() {
+        let mut quota = quota_with_room();
+        quota.add(950);
+
+        assert_eq!(quota.user.remaining(), 50);
+        assert_eq!(quota.team.remaining(), 50);
+        assert_eq!(quota.max_file_size(), 50);
+
+        // Limits lowered below what's used leave nothing, instead of wrapping
+        quota.add(100);
+        assert_eq!(quota.user.remaining(), 0);
+        assert_eq!(quota.max_file_size(), 0);
+    }
+
+    #[test]
+    fn negative_limits_count_as_zero() {
+        assert_eq!(limit_or(Some(-5), default_file_size_limit), 0);
+        assert_eq!(limit_or(Some(5), default_file_size_limit), 5);
+        assert_eq!(
+            limit_or(None, default_file_size_limit),
+            default_file_size_limit()
+        );
+    }
+}
@@ -7,8 +7,9 @@ pub struct Report {
     pub version_id: Option<VersionId>,
     pub user_id: Option<UserId>,
     pub body: String,
-    pub reporter: UserId,
+    pub reporter: Option<UserId>,
     pub created: chrono::DateTime<chrono::Utc>,
+    pub snapshot: Option<serde_json::Value>,
 }
 
 pub struct QueryReport {
@@ -18,8 +19,9 @@ pub struct QueryReport {
     pub version_id: Option<VersionId>,
     pub user_id: Option<UserId>,
     pub body: String,
-    pub reporter: UserId,
+    pub reporter: Option<UserId>,
     pub created: chrono::DateTime<chrono::Utc>,
+    pub snapshot: Option<serde_json::Value>,
 }
 
 impl Report {
@@ -31,11 +33,11 @@ impl Report {
             "
             INSERT INTO reports (
                 id, report_type_id, mod_id, version_id, user_id,
-                body, reporter
+                body, reporter, snapshot
             )
             VALUES (
                 $1, $2, $3, $4, $5,
-                $6, $7
+                $6, $7, $8
             )
             ",
             self.id as ReportId,
@@ -44,7 +46,8 @@ impl Report {
             self.version_id.map(|x| x.0 as i64),
             self.user_id.map(|x| x.0 as i64),
             self.body,
-            self.reporter as UserId
+            self.reporter.map(|x| x.0 as i64),
+            self.snapshot
         )
         .execute(&mut *transaction)
         .await?;
@@ -58,7 +61,7 @@ impl Report {
     {
         let result = sqlx::query!(
             "
-            SELECT rt.name, r.mod_id, r.version_id, r.user_id, r.body, r.reporter, r.created
+            SELECT rt.name, r.mod_id, r.version_id, r.user_id, r.body, r.reporter, r.created, r.snapshot
             FROM reports r
             INNER JOIN report_types rt ON rt.id = r.report_type_id
             WHERE r.id = $1
@@ -76,8 +79,9 @@ impl Report {
                 version_id: row.version_id.map(VersionId),
                 user_id: row.user_id.map(UserId),
                 body: row.body,
-                reporter: UserId(row.reporter),
+                reporter: row.reporter.map(UserId),
                 created: row.created,
+                snapshot: row.snapshot,
             }))
         } else {
             Ok(None)
@@ -96,7 +100,7 @@ impl Report {
         let version_ids_parsed: Vec<i64> = version_ids.into_iter().map(|x| x.0).collect();
         let versions = sqlx::query!(
             "
-            SELECT r.id, rt.name, r.mod_id, r.version_id, r.user_id, r.body, r.reporter, r.created
+            SELECT r.id, rt.name, r.mod_id, r.version_id, r.user_id, r.body, r.reporter, r.created, r.snapshot
             FROM reports r
             INNER JOIN report_types rt ON rt.id = r.report_type_id
             WHERE r.id IN (SELECT * FROM UNNEST($1::bigint[]))
@@ -112,8 +116,9 @@ impl Report {
                 version_id: row.version_id.map(VersionId),
                 user_id: row.user_id.map(UserId),
                 body: row.body,
-                reporter: UserId(row.reporter),
+                reporter: row.reporter.map(UserId),
                 created: row.created,
+                snapshot: row.snapshot,
             }))
         })
         .try_collect::<Vec<QueryReport>>()
@@ -0,0 +1,247 @@
+use super::ids::*;
+
+pub struct UploadSession {
+    pub id: UploadId,
+    pub user_id: UserId,
+    pub file_name: String,
+    pub size: i64,
+    pub received: i64,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub updated: chrono::DateTime<chrono::Utc>,
+    pub used: bool,
+}
+
+/// A piece of an upload, stored as its own file
+pub struct UploadChunk {
+    pub upload_offset: i64,
+    pub length: i64,
+    pub file_name: String,
+}
+
+impl UploadSession {
+    pub async fn insert(
+        &self,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "
+            INSERT INTO upload_sessions (id, user_id, file_name, size)
+            VALUES ($1, $2, $3, $4)
+            ",
+            self.id as UploadId,
+            self.user_id as UserId,
+            self.file_name,
+            self.size
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get<'a, E>(id: UploadId, exec: E) -> Result<Option<UploadSession>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            "
+            SELECT user_id, file_name, size, received, created, updated, used
+            FROM upload_sessions
+            WHERE id = $1
+            ",
+            id as UploadId
+        )
+        .fetch_optional(exec)
+        .await?;
+
+        Ok(result.map(|row| UploadSession {
+            id,
+            user_id: UserId(row.user_id),
+            file_name: row.file_name,
+            size: row.size,
+            received: row.received,
+            created: row.created,
+            updated: row.updated,
+            used: row.used,
+        }))
+    }
+
+    /// The number of uploads a user has started and not used yet
+    pub async fn count_unused<'a, E>(user_id: UserId, exec: E) -> Result<i64, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            "
+            SELECT COUNT(*) FROM upload_sessions
+            WHERE user_id = $1 AND NOT used
+            ",
+            user_id as UserId
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result.count.unwrap_or(0))
+    }
+
+    /// Records a stored chunk.  Returns false without recording it if the
+    /// upload doesn't continue at `upload_offset` anymore, like when another
+    /// chunk for the same offset was stored first.
+    pub async fn add_chunk(
+        id: UploadId,
+        chunk: &UploadChunk,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<bool, sqlx::Error> {
+        use sqlx::Done;
+
+        let result = sqlx::query!(
+            "
+            UPDATE upload_sessions
+            SET received = received + $3, updated = NOW()
+            WHERE id = $1 AND received = $2 AND NOT used
+            ",
+            id as UploadId,
+            chunk.upload_offset,
+            chunk.length
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query!(
+            "
+            INSERT INTO upload_chunks (session_id, upload_offset, length, file_name)
+            VALUES ($1, $2, $3, $4)
+            ",
+            id as UploadId,
+            chunk.upload_offset,
+            chunk.length,
+            chunk.file_name
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(true)
+    }
+
+    pub async fn get_chunks<'a, E>(id: UploadId, exec: E) -> Result<Vec<UploadChunk>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            SELECT upload_offset, length, file_name FROM upload_chunks
+            WHERE session_id = $1
+            ORDER BY upload_offset ASC
+            ",
+            id as UploadId
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async {
+            Ok(e.right().map(|row| UploadChunk {
+                upload_offset: row.upload_offset,
+                length: row.length,
+                file_name: row.file_name,
+            }))
+        })
+        .try_collect::<Vec<UploadChunk>>()
+        .await
+    }
+
+    /// Marks a complete upload of a user as used, so it can't be added to
+    /// another version.  Returns `None` if the upload doesn't exist, belongs
+    /// to someone else, isn't complete or was used already.
+    pub async fn take(
+        id: UploadId,
+        user_id: UserId,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<Option<UploadSession>, sqlx::Error> {
+        let result = sqlx::query!(
+            "
+            UPDATE upload_sessions
+            SET used = TRUE, updated = NOW()
+            WHERE id = $1 AND user_id = $2 AND received = size AND NOT used
+            RETURNING file_name, size, received, created, updated
+            ",
+            id as UploadId,
+            user_id as UserId
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+        Ok(result.map(|row| UploadSession {
+            id,
+            user_id,
+            file_name: row.file_name,
+            size: row.size,
+            received: row.received,
+            created: row.created,
+            updated: row.updated,
+            used: true,
+        }))
+    }
+
+    /// Removes an upload, returning the files of its chunks
+    pub async fn remove<'a, E>(id: UploadId, exec: E) -> Result<Vec<String>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            WITH removed AS (
+                DELETE FROM upload_sessions
+                WHERE id = $1
+                RETURNING id
+            )
+            SELECT c.file_name FROM upload_chunks c
+            INNER JOIN removed r ON r.id = c.session_id
+            ",
+            id as UploadId
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async { Ok(e.right().map(|row| row.file_name)) })
+        .try_collect::<Vec<String>>()
+        .await
+    }
+
+    /// Removes the uploads that were used, or haven't received anything since
+    /// `before`, returning the files of their chunks.  Uploads that are being
+    /// added to a version right now are skipped.
+    pub async fn remove_abandoned<'a, E>(
+        before: chrono::DateTime<chrono::Utc>,
+        exec: E,
+    ) -> Result<Vec<String>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            WITH removed AS (
+                DELETE FROM upload_sessions
+                WHERE id IN (
+                    SELECT id FROM upload_sessions
+                    WHERE used OR updated < $1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id
+            )
+            SELECT c.file_name FROM upload_chunks c
+            INNER JOIN removed r ON r.id = c.session_id
+            ",
+            before
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async { Ok(e.right().map(|row| row.file_name)) })
+        .try_collect::<Vec<String>>()
+        .await
+    }
+}
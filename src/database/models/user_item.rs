@@ -317,6 +317,46 @@ impl User {
         .execute(exec)
         .await?;
 
+        sqlx::query!(
+            "
+            DELETE FROM notification_preferences
+            WHERE user_id = $1
+            ",
+            id as UserId,
+        )
+        .execute(exec)
+        .await?;
+
+        sqlx::query!(
+            "
+            DELETE FROM email_queue
+            WHERE user_id = $1
+            ",
+            id as UserId,
+        )
+        .execute(exec)
+        .await?;
+
+        sqlx::query!(
+            "
+            DELETE FROM webhook_deliveries
+            WHERE webhook_id IN (SELECT id FROM webhooks WHERE user_id = $1)
+            ",
+            id as UserId,
+        )
+        .execute(exec)
+        .await?;
+
+        sqlx::query!(
+            "
+            DELETE FROM webhooks
+            WHERE user_id = $1
+            ",
+            id as UserId,
+        )
+        .execute(exec)
+        .await?;
+
         sqlx::query!(
             "
             DELETE FROM notifications_actions
@@ -422,6 +462,46 @@ impl User {
         .execute(exec)
         .await?;
 
+        sqlx::query!(
+            "
+            DELETE FROM notification_preferences
+            WHERE user_id = $1
+            ",
+            id as UserId,
+        )
+        .execute(exec)
+        .await?;
+
+        sqlx::query!(
+            "
+            DELETE FROM email_queue
+            WHERE user_id = $1
+            ",
+            id as UserId,
+        )
+        .execute(exec)
+        .await?;
+
+        sqlx::query!(
+            "
+            DELETE FROM webhook_deliveries
+            WHERE webhook_id IN (SELECT id FROM webhooks WHERE user_id = $1)
+            ",
+            id as UserId,
+        )
+        .execute(exec)
+        .await?;
+
+        sqlx::query!(
+            "
+            DELETE FROM webhooks
+            WHERE user_id = $1
+            ",
+            id as UserId,
+        )
+        .execute(exec)
+        .await?;
+
         sqlx::query!(
             "
             DELETE FROM team_members
@@ -1,3 +1,4 @@
+use super::cache;
 use super::ids::*;
 use super::DatabaseError;
 use std::collections::HashMap;
@@ -23,6 +24,12 @@ pub struct VersionFileBuilder {
     pub filename: String,
     pub hashes: Vec<HashBuilder>,
     pub primary: bool,
+    /// The hash of the blob holding the file's content
+    pub blob_sha512: Option<String>,
+    /// The files in the archive, which are kept with its blob
+    pub contents: Option<Vec<crate::models::mods::ArchiveEntry>>,
+    /// Whether the blob was scanned for malware yet
+    pub scan_status: crate::models::mods::ScanStatus,
 }
 
 impl VersionFileBuilder {
@@ -35,17 +42,23 @@ impl VersionFileBuilder {
 
         sqlx::query!(
             "
-            INSERT INTO files (id, version_id, url, filename)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO files (id, version_id, url, filename, blob_sha512)
+            VALUES ($1, $2, $3, $4, $5)
             ",
             file_id as FileId,
             version_id as VersionId,
             self.url,
             self.filename,
+            self.blob_sha512,
         )
         .execute(&mut *transaction)
         .await?;
 
+        if let (Some(blob_sha512), Some(contents)) = (&self.blob_sha512, &self.contents) {
+            super::blob_item::FileBlob::set_contents(blob_sha512, contents, &mut *transaction)
+                .await?;
+        }
+
         for hash in self.hashes {
             sqlx::query!(
                 "
@@ -243,7 +256,7 @@ impl Version {
         let files = sqlx::query!(
             "
             SELECT files.id, files.url, files.filename, files.is_primary FROM files
-            WHERE files.version_id = $1
+            WHERE files.version_id = $1 AND files.blob_sha512 IS NULL
             ",
             id as VersionId,
         )
@@ -285,10 +298,16 @@ impl Version {
         .execute(exec)
         .await?;
 
+        // Blobs are deleted by `remove_unreferenced` once nothing refers to
+        // them anymore
+        super::blob_item::FileBlob::remove_version_files(id, exec).await?;
+
+        // The mod's list of versions changes
         sqlx::query!(
             "
-            DELETE FROM files
-            WHERE files.version_id = $1
+            UPDATE mods
+            SET edited = NOW()
+            WHERE id = (SELECT mod_id FROM versions WHERE id = $1)
             ",
             id as VersionId,
         )
@@ -452,10 +471,56 @@ impl Version {
         Ok(versions)
     }
 
+    /// Quarantines a version, or lifts its quarantine.  The cached version
+    /// has to be invalidated afterwards.
+    pub async fn set_quarantined<'a, E>(
+        id: VersionId,
+        quarantined: bool,
+        exec: E,
+    ) -> Result<(), sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            UPDATE versions
+            SET quarantined = $1
+            WHERE id = $2
+            ",
+            quarantined,
+            id as VersionId,
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Gets a version, from the cache if possible
     pub async fn get_full<'a, 'b, E>(
         id: VersionId,
         executor: E,
     ) -> Result<Option<QueryVersion>, sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres> + Copy,
+    {
+        if let Some(cached) = cache::VERSIONS.get(&id.0) {
+            return Ok(Some(cached));
+        }
+
+        let result = Version::get_full_uncached(id, executor).await?;
+
+        if let Some(data) = &result {
+            cache::VERSIONS.insert(id.0, data.clone());
+        }
+
+        Ok(result)
+    }
+
+    async fn get_full_uncached<'a, 'b, E>(
+        id: VersionId,
+        executor: E,
+    ) -> Result<Option<QueryVersion>, sqlx::error::Error>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres> + Copy,
     {
@@ -463,7 +528,7 @@ impl Version {
             "
             SELECT v.id id, v.mod_id mod_id, v.author_id author_id, v.name version_name, v.version_number version_number,
             v.changelog changelog, v.changelog_url changelog_url, v.date_published date_published, v.downloads downloads,
-            rc.channel release_channel, v.featured featured, v.external_url, v.hosting_location,
+            rc.channel release_channel, v.featured featured, v.quarantined, v.external_url, v.hosting_location,
             STRING_AGG(DISTINCT f.id || ', ' || f.filename || ', ' || f.is_primary || ', ' || f.url, ' ,') files,
             STRING_AGG(DISTINCT h.algorithm || ', ' || encode(h.hash, 'escape') || ', ' || h.file_id,  ' ,') hashes,
             STRING_AGG(DISTINCT d.dependency_id || ', ' || d.dependency_type,  ' ,') dependencies
@@ -517,11 +582,15 @@ impl Version {
                             filename: file[1].to_string(),
                             hashes: file_hashes,
                             primary: file[2].parse().unwrap_or(false),
+                            contents: None,
+                            scan_status: crate::models::mods::ScanStatus::Clean,
                         })
                     }
                 });
             }
 
+            add_file_details(files.iter_mut().collect(), executor).await?;
+
             let mut dependencies = Vec::new();
 
             v.dependencies
@@ -553,6 +622,7 @@ impl Version {
                 hosting_location: v.hosting_location,
                 files,
                 featured: v.featured,
+                quarantined: v.quarantined,
                 dependencies,
             }))
         } else {
@@ -560,21 +630,77 @@ impl Version {
         }
     }
 
+    /// Gets the most recently published versions of the given mods, newest first
+    pub async fn get_recent_ids<'a, E>(
+        mod_ids: Vec<ModId>,
+        count: i64,
+        exec: E,
+    ) -> Result<Vec<VersionId>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        let mod_ids_parsed: Vec<i64> = mod_ids.into_iter().map(|x| x.0).collect();
+        sqlx::query!(
+            "
+            SELECT id FROM versions
+            WHERE mod_id IN (SELECT * FROM UNNEST($1::bigint[]))
+            ORDER BY date_published DESC
+            LIMIT $2
+            ",
+            &mod_ids_parsed,
+            count
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async { Ok(e.right().map(|v| VersionId(v.id))) })
+        .try_collect::<Vec<VersionId>>()
+        .await
+    }
+
+    /// Gets many versions, taking the ones that are cached from the cache
     pub async fn get_many_full<'a, E>(
         version_ids: Vec<VersionId>,
         exec: E,
     ) -> Result<Vec<QueryVersion>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres> + Copy,
+    {
+        let mut versions = Vec::new();
+        let mut missing = Vec::new();
+
+        for id in version_ids {
+            match cache::VERSIONS.get(&id.0) {
+                Some(cached) => versions.push(cached),
+                None => missing.push(id),
+            }
+        }
+
+        if !missing.is_empty() {
+            for data in Version::get_many_full_uncached(missing, exec).await? {
+                cache::VERSIONS.insert(data.id.0, data.clone());
+                versions.push(data);
+            }
+        }
+
+        Ok(versions)
+    }
+
+    async fn get_many_full_uncached<'a, E>(
+        version_ids: Vec<VersionId>,
+        exec: E,
+    ) -> Result<Vec<QueryVersion>, sqlx::Error>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres> + Copy,
     {
         use futures::stream::TryStreamExt;
 
         let version_ids_parsed: Vec<i64> = version_ids.into_iter().map(|x| x.0).collect();
-        sqlx::query!(
+        let mut versions = sqlx::query!(
             "
             SELECT v.id id, v.mod_id mod_id, v.author_id author_id, v.name version_name, v.version_number version_number,
             v.changelog changelog, v.changelog_url changelog_url, v.date_published date_published, v.downloads downloads,
-            rc.channel release_channel, v.featured featured, v.external_url, v.hosting_location,
+            rc.channel release_channel, v.featured featured, v.quarantined, v.external_url, v.hosting_location,
             STRING_AGG(DISTINCT f.id || ', ' || f.filename || ', ' || f.is_primary || ', ' || f.url, ' ,') files,
             STRING_AGG(DISTINCT h.algorithm || ', ' || encode(h.hash, 'escape') || ', ' || h.file_id,  ' ,') hashes,
             STRING_AGG(DISTINCT d.dependency_id || ', ' || d.dependency_type,  ' ,') dependencies
@@ -625,7 +751,9 @@ impl Version {
                                 url: file[3].to_string(),
                                 filename: file[1].to_string(),
                                 hashes: file_hashes,
-                                primary: file[2].parse().unwrap_or(false)
+                                primary: file[2].parse().unwrap_or(false),
+                                contents: None,
+                                scan_status: crate::models::mods::ScanStatus::Clean,
                             })
                         }
                     });
@@ -655,13 +783,48 @@ impl Version {
                         release_channel: v.release_channel,
                         files,
                         featured: v.featured,
+                        quarantined: v.quarantined,
                         dependencies,
                     }
                 }))
             })
             .try_collect::<Vec<QueryVersion>>()
-            .await
+            .await?;
+
+        add_file_details(
+            versions
+                .iter_mut()
+                .flat_map(|x| x.files.iter_mut())
+                .collect(),
+            exec,
+        )
+        .await?;
+
+        Ok(versions)
+    }
+}
+
+/// Adds the files in archives and the scan status of their blobs to the
+/// files of versions.  Files stored before blobs existed count as clean.
+async fn add_file_details<'a, E>(files: Vec<&mut QueryFile>, exec: E) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    if files.is_empty() {
+        return Ok(());
     }
+
+    let file_ids = files.iter().map(|x| x.id.0).collect::<Vec<_>>();
+    let mut details = super::blob_item::FileBlob::get_file_details(&file_ids, exec).await?;
+
+    for file in files {
+        if let Some(details) = details.remove(&file.id.0) {
+            file.contents = details.contents;
+            file.scan_status = details.scan_status;
+        }
+    }
+
+    Ok(())
 }
 
 pub struct ReleaseChannel {
@@ -699,6 +862,7 @@ pub struct QueryVersion {
     pub release_channel: String,
     pub files: Vec<QueryFile>,
     pub featured: bool,
+    pub quarantined: bool,
     pub dependencies: Vec<(VersionId, String)>,
 }
 
@@ -709,4 +873,6 @@ pub struct QueryFile {
     pub filename: String,
     pub hashes: HashMap<String, Vec<u8>>,
     pub primary: bool,
+    pub contents: Option<Vec<crate::models::mods::ArchiveEntry>>,
+    pub scan_status: crate::models::mods::ScanStatus,
 }
@@ -0,0 +1,410 @@
+use super::ids::*;
+
+pub struct Webhook {
+    pub id: WebhookId,
+    pub user_id: Option<UserId>,
+    pub team_id: Option<TeamId>,
+    pub mod_id: Option<ModId>,
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+    pub created: chrono::DateTime<chrono::Utc>,
+}
+
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: WebhookId,
+    pub event: String,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt: chrono::DateTime<chrono::Utc>,
+    pub response_code: Option<i32>,
+    pub error: Option<String>,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub delivered: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A delivery that is due to be sent, along with where to send it
+pub struct DueDelivery {
+    pub id: i64,
+    pub webhook_id: WebhookId,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+impl Webhook {
+    pub async fn insert(
+        &self,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "
+            INSERT INTO webhooks (
+                id, user_id, team_id, mod_id, url, secret, events
+            )
+            VALUES (
+                $1, $2, $3, $4, $5, $6, $7
+            )
+            ",
+            self.id as WebhookId,
+            self.user_id.map(|x| x.0),
+            self.team_id.map(|x| x.0),
+            self.mod_id.map(|x| x.0),
+            self.url,
+            self.secret,
+            &self.events
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get<'a, E>(id: WebhookId, exec: E) -> Result<Option<Webhook>, sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            "
+            SELECT user_id, team_id, mod_id, url, secret, events, created
+            FROM webhooks
+            WHERE id = $1
+            ",
+            id as WebhookId
+        )
+        .fetch_optional(exec)
+        .await?;
+
+        Ok(result.map(|row| Webhook {
+            id,
+            user_id: row.user_id.map(UserId),
+            team_id: row.team_id.map(TeamId),
+            mod_id: row.mod_id.map(ModId),
+            url: row.url,
+            secret: row.secret,
+            events: row.events,
+            created: row.created,
+        }))
+    }
+
+    /// Lists the webhooks owned by a user, and by the teams they are an accepted member of
+    pub async fn get_many_user<'a, E>(
+        user_id: UserId,
+        exec: E,
+    ) -> Result<Vec<Webhook>, sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            SELECT w.id, w.user_id, w.team_id, w.mod_id, w.url, w.secret, w.events, w.created
+            FROM webhooks w
+            WHERE w.user_id = $1 OR w.team_id IN (
+                SELECT tm.team_id FROM team_members tm
+                WHERE tm.user_id = $1 AND tm.accepted = TRUE
+            )
+            ORDER BY w.created ASC
+            ",
+            user_id as UserId
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async {
+            Ok(e.right().map(|row| Webhook {
+                id: WebhookId(row.id),
+                user_id: row.user_id.map(UserId),
+                team_id: row.team_id.map(TeamId),
+                mod_id: row.mod_id.map(ModId),
+                url: row.url,
+                secret: row.secret,
+                events: row.events,
+                created: row.created,
+            }))
+        })
+        .try_collect::<Vec<Webhook>>()
+        .await
+    }
+
+    pub async fn edit(
+        &self,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "
+            UPDATE webhooks
+            SET url = $1, events = $2
+            WHERE id = $3
+            ",
+            self.url,
+            &self.events,
+            self.id as WebhookId
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove(
+        id: WebhookId,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "
+            DELETE FROM webhook_deliveries
+            WHERE webhook_id = $1
+            ",
+            id as WebhookId
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query!(
+            "
+            DELETE FROM webhooks
+            WHERE id = $1
+            ",
+            id as WebhookId
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Finds the webhooks subscribed to an event on a mod: the webhooks of the
+    /// mod's team, account webhooks of its members, and webhooks registered
+    /// for this mod specifically. Outsiders only receive events of public mods.
+    pub async fn get_matching_mod(
+        event: &str,
+        mod_id: ModId,
+        team_id: TeamId,
+        public: bool,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<Vec<WebhookId>, sqlx::error::Error> {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            SELECT w.id FROM webhooks w
+            WHERE $1 = ANY(w.events) AND (
+                w.team_id = $3
+                OR (w.user_id IS NOT NULL AND (w.mod_id IS NULL OR w.mod_id = $2) AND EXISTS (
+                    SELECT 1 FROM team_members tm
+                    WHERE tm.team_id = $3 AND tm.user_id = w.user_id AND tm.accepted = TRUE
+                ))
+                OR (w.user_id IS NOT NULL AND w.mod_id = $2 AND $4)
+            )
+            ",
+            event,
+            mod_id as ModId,
+            team_id as TeamId,
+            public
+        )
+        .fetch_many(&mut *transaction)
+        .try_filter_map(|e| async { Ok(e.right().map(|row| WebhookId(row.id))) })
+        .try_collect::<Vec<WebhookId>>()
+        .await
+    }
+
+    /// Finds the account webhooks of a user that are subscribed to an event
+    pub async fn get_matching_user(
+        event: &str,
+        user_id: UserId,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<Vec<WebhookId>, sqlx::error::Error> {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            SELECT id FROM webhooks
+            WHERE user_id = $2 AND mod_id IS NULL AND $1 = ANY(events)
+            ",
+            event,
+            user_id as UserId
+        )
+        .fetch_many(&mut *transaction)
+        .try_filter_map(|e| async { Ok(e.right().map(|row| WebhookId(row.id))) })
+        .try_collect::<Vec<WebhookId>>()
+        .await
+    }
+}
+
+impl WebhookDelivery {
+    /// Queues an event for delivery to each of the given webhooks
+    pub async fn enqueue(
+        webhook_ids: &[WebhookId],
+        event: &str,
+        payload: &serde_json::Value,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), sqlx::error::Error> {
+        for webhook_id in webhook_ids {
+            sqlx::query!(
+                "
+                INSERT INTO webhook_deliveries (webhook_id, event, payload)
+                VALUES ($1, $2, $3)
+                ",
+                *webhook_id as WebhookId,
+                event,
+                payload
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Claims up to `count` pending deliveries that are due, by pushing their next
+    /// attempt back by `lease`. Deliveries claimed by another instance are skipped,
+    /// and deliveries whose sender died are picked up again once the lease expires.
+    pub async fn claim_due<'a, E>(
+        count: i64,
+        lease: chrono::Duration,
+        exec: E,
+    ) -> Result<Vec<DueDelivery>, sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        let lease_until = chrono::Utc::now() + lease;
+
+        sqlx::query!(
+            r#"
+            WITH due AS (
+                UPDATE webhook_deliveries
+                SET next_attempt = $2
+                WHERE id IN (
+                    SELECT id FROM webhook_deliveries
+                    WHERE status = 'pending' AND next_attempt <= NOW()
+                    ORDER BY next_attempt ASC
+                    LIMIT $1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id, webhook_id, event, payload, attempts
+            )
+            SELECT due.id "id!", due.webhook_id "webhook_id!", due.event "event!",
+                   due.payload "payload!", due.attempts "attempts!", w.url, w.secret
+            FROM due
+            INNER JOIN webhooks w ON w.id = due.webhook_id
+            "#,
+            count,
+            lease_until
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async {
+            Ok(e.right().map(|row| DueDelivery {
+                id: row.id,
+                webhook_id: WebhookId(row.webhook_id),
+                event: row.event,
+                payload: row.payload,
+                attempts: row.attempts,
+                url: row.url,
+                secret: row.secret,
+            }))
+        })
+        .try_collect::<Vec<DueDelivery>>()
+        .await
+    }
+
+    pub async fn mark_delivered<'a, E>(
+        id: i64,
+        response_code: i32,
+        exec: E,
+    ) -> Result<(), sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            UPDATE webhook_deliveries
+            SET status = 'delivered', attempts = attempts + 1, response_code = $2,
+                error = NULL, delivered = NOW()
+            WHERE id = $1
+            ",
+            id,
+            response_code
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed attempt. The delivery is retried at `retry_at`, or marked
+    /// as failed if there are no attempts left.
+    pub async fn mark_attempt_failed<'a, E>(
+        id: i64,
+        response_code: Option<i32>,
+        error: &str,
+        retry_at: Option<chrono::DateTime<chrono::Utc>>,
+        exec: E,
+    ) -> Result<(), sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            UPDATE webhook_deliveries
+            SET status = CASE WHEN $4::timestamptz IS NULL THEN 'failed' ELSE 'pending' END,
+                attempts = attempts + 1, response_code = $2, error = $3,
+                next_attempt = COALESCE($4, next_attempt)
+            WHERE id = $1
+            ",
+            id,
+            response_code,
+            error,
+            retry_at
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lists the most recent deliveries of a webhook, newest first
+    pub async fn get_many_webhook<'a, E>(
+        webhook_id: WebhookId,
+        count: i64,
+        exec: E,
+    ) -> Result<Vec<WebhookDelivery>, sqlx::error::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        use futures::stream::TryStreamExt;
+
+        sqlx::query!(
+            "
+            SELECT id, event, status, attempts, next_attempt, response_code, error, created, delivered
+            FROM webhook_deliveries
+            WHERE webhook_id = $1
+            ORDER BY created DESC, id DESC
+            LIMIT $2
+            ",
+            webhook_id as WebhookId,
+            count
+        )
+        .fetch_many(exec)
+        .try_filter_map(|e| async {
+            Ok(e.right().map(|row| WebhookDelivery {
+                id: row.id,
+                webhook_id,
+                event: row.event,
+                status: row.status,
+                attempts: row.attempts,
+                next_attempt: row.next_attempt,
+                response_code: row.response_code,
+                error: row.error,
+                created: row.created,
+                delivered: row.delivered,
+            }))
+        })
+        .try_collect::<Vec<WebhookDelivery>>()
+        .await
+    }
+}
@@ -0,0 +1,731 @@
+//! Opens uploaded archives to list the files in them.  Archives are rejected
+//! if they contain executables or scripts, archives nested too deeply, or
+//! unpack to far more than their own size, like zip bombs do.
+//!
+//! Everything in an archive is unpacked while it's inspected, so what is
+//! checked is the actual content, not what the archive claims it contains.
+//! Archives are recognized by their content too, whatever they're named, and
+//! uploads that are archives but aren't named like one are rejected.  A file
+//! compressed with gzip on its own is listed as the file in it.
+
+use super::{blocking, FileHost, FileHostingError};
+use crate::database::models::quota_item::format_size;
+use crate::models::mods::ArchiveEntry;
+use futures::StreamExt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+/// How deep archives can be nested, counting the uploaded one.  Modpacks
+/// are often shared in a zip file, so one level of nesting is allowed.
+const MAX_DEPTH: usize = 2;
+/// The most files an archive can contain, counting those in nested archives
+const MAX_ENTRIES: usize = 10_000;
+/// How many times its own size an archive can unpack to
+const MAX_COMPRESSION_RATIO: u64 = 100;
+/// What any archive can unpack to, however small it is
+const MIN_UNPACKED_LIMIT: u64 = 64 << 20;
+/// What no archive can unpack to more than
+const MAX_UNPACKED_LIMIT: u64 = 8 << 30;
+
+/// Extensions of executables and scripts, which are rejected in archives
+const BLOCKED_EXTENSIONS: &[&str] = &[
+    "exe", "dll", "com", "scr", "msi", "sys", "cpl", "lnk", "bat", "cmd", "ps1", "psm1", "vbs",
+    "vbe", "js", "jse", "wsf", "wsh", "hta", "reg", "jar", "sh", "py", "pyw",
+];
+
+/// What executables start with: DOS and Windows, ELF, Mach-O and scripts
+/// with an interpreter line
+const EXECUTABLE_SIGNATURES: &[&[u8]] = &[
+    b"MZ",
+    b"\x7fELF",
+    b"\xfe\xed\xfa\xce",
+    b"\xfe\xed\xfa\xcf",
+    b"\xce\xfa\xed\xfe",
+    b"\xcf\xfa\xed\xfe",
+    b"#!",
+];
+
+/// How much of a file is read to tell what it is.  The signature of tar
+/// archives starts 257 bytes in.
+const HEADER_SIZE: usize = 512;
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+    /// The file isn't allowed, for the given reason
+    #[error("{0}")]
+    Rejected(String),
+    #[error("Error while reading the file: {0}")]
+    FileHostingError(#[from] FileHostingError),
+}
+
+impl From<std::io::Error> for ArchiveError {
+    fn from(error: std::io::Error) -> Self {
+        ArchiveError::FileHostingError(error.into())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ArchiveKind {
+    Zip,
+    SevenZip,
+    TarGz,
+    Tar,
+    Rar,
+}
+
+impl ArchiveKind {
+    /// The kind of an uploaded file, going by its extension
+    fn from_name(file_name: &str) -> Option<Self> {
+        let file_name = file_name.to_lowercase();
+
+        [
+            (".zip", ArchiveKind::Zip),
+            (".ttmp2", ArchiveKind::Zip),
+            (".7z", ArchiveKind::SevenZip),
+            (".tar.gz", ArchiveKind::TarGz),
+        ]
+        .iter()
+        .find(|(extension, _)| file_name.ends_with(extension))
+        .map(|(_, kind)| *kind)
+    }
+
+    /// The kind of a file, going by how it starts
+    fn from_header(header: &[u8]) -> Option<Self> {
+        if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(ArchiveKind::Zip)
+        } else if header.starts_with(b"7z\xbc\xaf\x27\x1c") {
+            Some(ArchiveKind::SevenZip)
+        } else if header.starts_with(b"\x1f\x8b") {
+            Some(ArchiveKind::TarGz)
+        } else if header.get(257..262) == Some(&b"ustar"[..]) {
+            Some(ArchiveKind::Tar)
+        } else if header.starts_with(b"Rar!\x1a\x07") {
+            Some(ArchiveKind::Rar)
+        } else {
+            None
+        }
+    }
+}
+
+/// A file in the temporary directory, which is removed once it's dropped
+struct TempFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl TempFile {
+    fn create() -> std::io::Result<Self> {
+        let path = std::env::temp_dir().join(format!("xivrepo-{:016x}", rand::random::<u64>()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+
+        Ok(TempFile { path, file })
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+struct Inspector {
+    entries: Vec<ArchiveEntry>,
+    /// The bytes unpacked so far, counting nested archives and their content
+    unpacked: u64,
+    unpacked_limit: u64,
+}
+
+impl Inspector {
+    fn archive<R: Read + Seek>(
+        &mut self,
+        kind: ArchiveKind,
+        reader: R,
+        name: &str,
+        prefix: &str,
+        depth: usize,
+    ) -> Result<(), ArchiveError> {
+        match kind {
+            ArchiveKind::Zip => self.zip(reader, name, prefix, depth),
+            ArchiveKind::SevenZip => self.seven_zip(reader, name, prefix, depth),
+            ArchiveKind::TarGz => self.gzip(reader, name, prefix, depth),
+            ArchiveKind::Tar => self.tar(reader, name, prefix, depth),
+            ArchiveKind::Rar => Err(ArchiveError::Rejected(format!(
+                "{} is a RAR archive, which can't be checked. Please use zip or 7z archives instead.",
+                name
+            ))),
+        }
+    }
+
+    fn zip<R: Read + Seek>(
+        &mut self,
+        reader: R,
+        name: &str,
+        prefix: &str,
+        depth: usize,
+    ) -> Result<(), ArchiveError> {
+        let mut archive = zip::ZipArchive::new(reader).map_err(|e| unreadable(name, e))?;
+
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).map_err(|e| unreadable(name, e))?;
+
+            if file.is_dir() {
+                continue;
+            }
+
+            let path = format!("{}{}", prefix, file.name());
+
+            // Only the type bits of the mode are checked, since files are
+            // marked as executable by mistake all the time
+            if file.unix_mode().map(|x| x & 0o170000) == Some(0o120000) {
+                return Err(link_error(&path));
+            }
+
+            self.entry(path, &mut file, depth)?;
+        }
+
+        Ok(())
+    }
+
+    fn seven_zip<R: Read + Seek>(
+        &mut self,
+        mut reader: R,
+        name: &str,
+        prefix: &str,
+        depth: usize,
+    ) -> Result<(), ArchiveError> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut archive =
+            sevenz_rust::SevenZReader::new(reader, len, sevenz_rust::Password::empty())
+                .map_err(|e| unreadable(name, e))?;
+
+        // The callback can only fail with the library's errors, so the
+        // reason an entry was rejected is kept here
+        let mut rejection = None;
+
+        let result = archive.for_each_entries(|entry, reader| {
+            if entry.is_directory() {
+                return Ok(true);
+            }
+
+            match self.entry(format!("{}{}", prefix, entry.name()), reader, depth) {
+                Ok(()) => Ok(true),
+                Err(e) => {
+                    rejection = Some(e);
+                    Err(sevenz_rust::Error::other("The archive was rejected"))
+                }
+            }
+        });
+
+        match (rejection, result) {
+            (Some(e), _) => Err(e),
+            (None, Err(e)) => Err(unreadable(name, e)),
+            (None, Ok(())) => Ok(()),
+        }
+    }
+
+    /// Usually a tar archive, but files can be compressed with gzip on their
+    /// own too
+    fn gzip<R: Read>(
+        &mut self,
+        reader: R,
+        name: &str,
+        prefix: &str,
+        depth: usize,
+    ) -> Result<(), ArchiveError> {
+        let mut decoder = flate2::read::GzDecoder::new(reader);
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        (&mut decoder)
+            .take(HEADER_SIZE as u64)
+            .read_to_end(&mut header)
+            .map_err(|e| unreadable(name, e))?;
+
+        let is_tar = ArchiveKind::from_header(&header) == Some(ArchiveKind::Tar);
+        let mut reader = std::io::Cursor::new(header).chain(decoder);
+
+        if is_tar {
+            self.tar(reader, name, prefix, depth)
+        } else {
+            let file_name = Path::new(name)
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy();
+
+            self.entry(format!("{}{}", prefix, file_name), &mut reader, depth)
+        }
+    }
+
+    fn tar<R: Read>(
+        &mut self,
+        reader: R,
+        name: &str,
+        prefix: &str,
+        depth: usize,
+    ) -> Result<(), ArchiveError> {
+        let mut archive = tar::Archive::new(reader);
+
+        for entry in archive.entries().map_err(|e| unreadable(name, e))? {
+            let mut entry = entry.map_err(|e| unreadable(name, e))?;
+            let path = format!(
+                "{}{}",
+                prefix,
+                entry
+                    .path()
+                    .map_err(|e| unreadable(name, e))?
+                    .to_string_lossy()
+            );
+
+            match entry.header().entry_type() {
+                tar::EntryType::Regular | tar::EntryType::Continuous => {
+                    self.entry(path, &mut entry, depth)?
+                }
+                tar::EntryType::Symlink | tar::EntryType::Link => return Err(link_error(&path)),
+                // Directories, and devices and the like, which aren't
+                // created when unpacking anyway
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks and lists a file in an archive, inspecting it as well if it's
+    /// an archive itself
+    fn entry(
+        &mut self,
+        path: String,
+        reader: &mut dyn Read,
+        depth: usize,
+    ) -> Result<(), ArchiveError> {
+        check_path(&path)?;
+
+        if self.entries.len() >= MAX_ENTRIES {
+            return Err(ArchiveError::Rejected(format!(
+                "Archives can't contain more than {} files",
+                MAX_ENTRIES
+            )));
+        }
+
+        let extension = Path::new(&path)
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase();
+
+        if BLOCKED_EXTENSIONS.contains(&&*extension) {
+            return Err(executable_error(&path));
+        }
+
+        let mut header = vec![0; HEADER_SIZE];
+        let mut header_len = 0;
+        while header_len < HEADER_SIZE {
+            match self.read(reader, &mut header[header_len..], &path)? {
+                0 => break,
+                read => header_len += read,
+            }
+        }
+        header.truncate(header_len);
+
+        if is_executable(&header) {
+            return Err(executable_error(&path));
+        }
+
+        let index = self.entries.len();
+        self.entries.push(ArchiveEntry {
+            path: path.clone(),
+            size: 0,
+        });
+
+        let kind = ArchiveKind::from_header(&header);
+        let mut nested = match kind {
+            Some(_) if depth >= MAX_DEPTH => {
+                return Err(ArchiveError::Rejected(format!(
+                    "{} is nested too deeply, since archives can only be nested {} levels deep",
+                    path, MAX_DEPTH
+                )))
+            }
+            // Archives are read from the end, so they're stored to be
+            // opened once all of them was read
+            Some(_) => {
+                let mut temp_file = TempFile::create()?;
+                temp_file.file.write_all(&header)?;
+                Some(temp_file)
+            }
+            None => None,
+        };
+
+        let mut size = header.len() as u64;
+        let mut buffer = vec![0; 64 * 1024];
+
+        loop {
+            let read = self.read(reader, &mut buffer, &path)?;
+            if read == 0 {
+                break;
+            }
+
+            if let Some(temp_file) = &mut nested {
+                temp_file.file.write_all(&buffer[..read])?;
+            }
+            size += read as u64;
+        }
+
+        self.entries[index].size = size;
+
+        if let (Some(kind), Some(mut temp_file)) = (kind, nested) {
+            temp_file.file.seek(SeekFrom::Start(0))?;
+            self.archive(
+                kind,
+                &mut temp_file.file,
+                &path,
+                &format!("{}/", path),
+                depth + 1,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads unpacked bytes, making sure the archive doesn't unpack to more
+    /// than it's allowed to
+    fn read(
+        &mut self,
+        reader: &mut dyn Read,
+        buffer: &mut [u8],
+        path: &str,
+    ) -> Result<usize, ArchiveError> {
+        let read = loop {
+            match reader.read(buffer) {
+                Ok(read) => break read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(unreadable(path, e)),
+            }
+        };
+
+        self.unpacked += read as u64;
+
+        if self.unpacked > self.unpacked_limit {
+            return Err(ArchiveError::Rejected(format!(
+                "The archive unpacks to more than {}, which is too much for its size",
+                format_size(self.unpacked_limit)
+            )));
+        }
+
+        Ok(read)
+    }
+}
+
+/// Rejects paths that would end up outside of the directory the archive is
+/// unpacked in
+fn check_path(path: &str) -> Result<(), ArchiveError> {
+    let path = path.replace('\\', "/");
+
+    let valid = Path::new(&path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ArchiveError::Rejected(format!(
+            "{} would be unpacked outside of the archive's folder",
+            path
+        )))
+    }
+}
+
+fn is_executable(header: &[u8]) -> bool {
+    EXECUTABLE_SIGNATURES.iter().any(|x| header.starts_with(x))
+}
+
+fn executable_error(path: &str) -> ArchiveError {
+    ArchiveError::Rejected(format!(
+        "{} is an executable or script, which aren't allowed in mod files",
+        path
+    ))
+}
+
+fn link_error(path: &str) -> ArchiveError {
+    ArchiveError::Rejected(format!(
+        "{} is a link, which aren't allowed in archives",
+        path
+    ))
+}
+
+fn unreadable(name: &str, error: impl std::fmt::Display) -> ArchiveError {
+    ArchiveError::Rejected(format!(
+        "{} couldn't be unpacked: {}. Encrypted and damaged archives aren't accepted.",
+        name, error
+    ))
+}
+
+/// Checks a file that was stored as `file_name`, uploaded under the name
+/// `original_name`.  Returns the files in it if it's an archive.
+pub async fn inspect(
+    file_host: &dyn FileHost,
+    file_name: &str,
+    original_name: &str,
+) -> Result<Option<Vec<ArchiveEntry>>, ArchiveError> {
+    let mut temp_file = blocking(|| Ok(TempFile::create()?)).await?;
+    let mut file_stream = file_host.download_file(file_name).await?;
+
+    while let Some(chunk) = file_stream.next().await {
+        let chunk = chunk?;
+
+        temp_file = blocking(move || {
+            temp_file.file.write_all(&chunk)?;
+            Ok(temp_file)
+        })
+        .await?;
+    }
+
+    // Rejections aren't errors of the thread pool, so they're passed through
+    let original_name = original_name.to_string();
+    blocking(move || Ok(inspect_file(temp_file, &original_name))).await?
+}
+
+fn inspect_file(
+    mut temp_file: TempFile,
+    original_name: &str,
+) -> Result<Option<Vec<ArchiveEntry>>, ArchiveError> {
+    let len = temp_file.file.seek(SeekFrom::End(0))?;
+    temp_file.file.seek(SeekFrom::Start(0))?;
+
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    (&mut temp_file.file)
+        .take(HEADER_SIZE as u64)
+        .read_to_end(&mut header)?;
+
+    if is_executable(&header) {
+        return Err(executable_error(original_name));
+    }
+
+    // What the file contains decides how it's opened, so that archives can't
+    // slip by under another extension
+    let kind = match (
+        ArchiveKind::from_header(&header),
+        ArchiveKind::from_name(original_name),
+    ) {
+        (Some(_), None) => {
+            return Err(ArchiveError::Rejected(format!(
+                "{} is an archive, but isn't named like one",
+                original_name
+            )))
+        }
+        (Some(kind), Some(_)) | (None, Some(kind)) => kind,
+        (None, None) => return Ok(None),
+    };
+
+    temp_file.file.seek(SeekFrom::Start(0))?;
+
+    let mut inspector = Inspector {
+        entries: Vec::new(),
+        unpacked: 0,
+        unpacked_limit: len
+            .saturating_mul(MAX_COMPRESSION_RATIO)
+            .clamp(MIN_UNPACKED_LIMIT, MAX_UNPACKED_LIMIT),
+    };
+    inspector.archive(kind, &mut temp_file.file, original_name, "", 1)?;
+
+    Ok(Some(inspector.entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+
+        for (name, content) in files {
+            writer
+                .start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(content).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// A zip archive with a single symbolic link in it.  The writer only
+    /// stores permissions, so the type is set in the central directory.
+    fn zip_with_link() -> Vec<u8> {
+        let mut archive = zip(&[("link", b"/etc/passwd")]);
+        let central = archive.windows(4).position(|x| x == b"PK\x01\x02").unwrap();
+        archive[central + 38..central + 42].copy_from_slice(&(0o120777u32 << 16).to_le_bytes());
+        archive
+    }
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, *content).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn inspect_bytes(
+        original_name: &str,
+        content: &[u8],
+    ) -> Result<Option<Vec<ArchiveEntry>>, ArchiveError> {
+        let mut temp_file = TempFile::create().unwrap();
+        temp_file.file.write_all(content).unwrap();
+        inspect_file(temp_file, original_name)
+    }
+
+    fn paths(result: Result<Option<Vec<ArchiveEntry>>, ArchiveError>) -> Vec<String> {
+        result
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect()
+    }
+
+    fn rejection(result: Result<Option<Vec<ArchiveEntry>>, ArchiveError>) -> String {
+        match result {
+            Err(ArchiveError::Rejected(reason)) => reason,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("the file was accepted"),
+        }
+    }
+
+    #[test]
+    fn paths_outside_the_archive_are_rejected() {
+        assert!(check_path("mods/file.ttmp2").is_ok());
+        assert!(check_path("./mods/file.ttmp2").is_ok());
+
+        assert!(check_path("../file").is_err());
+        assert!(check_path("mods/../../file").is_err());
+        assert!(check_path("/etc/passwd").is_err());
+        assert!(check_path("..\\file").is_err());
+        assert!(check_path("\\file").is_err());
+
+        let archive = zip(&[("../file", b"")]);
+        assert!(rejection(inspect_bytes("mod.zip", &archive)).contains("outside"));
+    }
+
+    #[test]
+    fn links_are_rejected() {
+        let reason = rejection(inspect_bytes("mod.zip", &zip_with_link()));
+        assert!(reason.contains("is a link"));
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "link", "/etc/passwd")
+            .unwrap();
+        let archive = gzip(&builder.into_inner().unwrap());
+
+        let reason = rejection(inspect_bytes("mod.tar.gz", &archive));
+        assert!(reason.contains("is a link"));
+    }
+
+    #[test]
+    fn executables_are_rejected() {
+        let archive = zip(&[("install.exe", b"")]);
+        assert!(rejection(inspect_bytes("mod.zip", &archive)).contains("install.exe"));
+
+        let archive = zip(&[("readme.txt", b"MZ\x90\x00")]);
+        assert!(rejection(inspect_bytes("mod.zip", &archive)).contains("readme.txt"));
+
+        let archive = tar(&[("model.mdl", b"\x7fELF\x02\x01")]);
+        assert!(rejection(inspect_bytes("mod.tar.gz", &gzip(&archive))).contains("model.mdl"));
+
+        assert!(rejection(inspect_bytes("pose.pose", b"#!/bin/sh")).contains("pose.pose"));
+    }
+
+    #[test]
+    fn one_level_of_nesting_is_allowed() {
+        let inner = zip(&[("meta.json", b"{}")]);
+        let archive = zip(&[("pack/inner.ttmp2", &inner), ("renamed.bin", &inner)]);
+
+        assert_eq!(
+            paths(inspect_bytes("mod.zip", &archive)),
+            vec![
+                "pack/inner.ttmp2",
+                "pack/inner.ttmp2/meta.json",
+                "renamed.bin",
+                "renamed.bin/meta.json",
+            ]
+        );
+
+        let innermost = zip(&[("meta.json", b"{}")]);
+        let inner = zip(&[("inner.zip", &innermost)]);
+        let archive = zip(&[("outer.zip", &inner)]);
+
+        let reason = rejection(inspect_bytes("mod.zip", &archive));
+        assert!(reason.contains("nested too deeply"));
+    }
+
+    #[test]
+    fn compressed_files_are_listed() {
+        let archive = zip(&[("pose.pose.gz", &gzip(b"pose"))]);
+        assert_eq!(
+            paths(inspect_bytes("mod.zip", &archive)),
+            vec!["pose.pose.gz", "pose.pose.gz/pose.pose"]
+        );
+
+        let archive = gzip(&tar(&[("meta.json", b"{}")]));
+        assert_eq!(
+            paths(inspect_bytes("mod.tar.gz", &archive)),
+            vec!["meta.json"]
+        );
+    }
+
+    #[test]
+    fn archives_have_to_be_named_like_one() {
+        assert!(inspect_bytes("pose.pose", b"pose").unwrap().is_none());
+
+        let archive = zip(&[("meta.json", b"{}")]);
+        let reason = rejection(inspect_bytes("pose.pose", &archive));
+        assert!(reason.contains("isn't named like one"));
+    }
+
+    #[test]
+    fn archives_unpacking_to_too_much_are_rejected() {
+        let archive = zip(&[("model.mdl", &[0; 4096])]);
+        let mut inspector = Inspector {
+            entries: Vec::new(),
+            unpacked: 0,
+            unpacked_limit: 1024,
+        };
+
+        let reason =
+            match inspector.archive(ArchiveKind::Zip, Cursor::new(archive), "mod.zip", "", 1) {
+                Err(ArchiveError::Rejected(reason)) => reason,
+                _ => panic!("the archive was accepted"),
+            };
+        assert!(reason.contains("unpacks to more than 1KiB"));
+    }
+
+    #[test]
+    fn archives_with_too_many_files_are_rejected() {
+        let names: Vec<String> = (0..=MAX_ENTRIES).map(|i| format!("{}.json", i)).collect();
+        let files: Vec<(&str, &[u8])> = names.iter().map(|x| (&**x, &b""[..])).collect();
+
+        let reason = rejection(inspect_bytes("mod.zip", &zip(&files)));
+        assert!(reason.contains("more than 10000 files"));
+    }
+}
@@ -0,0 +1,180 @@
+//! Version files are stored once per content, under their SHA-512 hash.
+//! Postgres counts the files referring to each blob, and a blob is deleted
+//! once the last of them is gone.
+//!
+//! Adding and dropping references lock the blob's row until the transaction
+//! ends, and blobs are only deleted while their row is locked, so a blob
+//! can't be deleted while a new file starts referring to it.
+//!
+//! New blobs are kept private until the malware scanner found them clean,
+//! and are only published under their public path then.
+
+use super::{ByteStream, FileHost, FileHostingError, UploadFileData};
+use crate::database::models::blob_item::FileBlob;
+use crate::models::mods::ScanStatus;
+use log::warn;
+use sqlx::PgPool;
+
+/// Where the blob with a hash is served from once it's published
+pub fn blob_path(sha512: &str) -> String {
+    format!("blobs/{}/{}", &sha512[..2], sha512)
+}
+
+/// Where the blob with a hash is kept until it's published, and where
+/// flagged blobs are kept for moderators to look at
+pub fn pending_blob_path(sha512: &str) -> String {
+    format!("blobs/pending/{}", sha512)
+}
+
+pub struct StoredBlob {
+    /// The file as it was uploaded, with `file_name` set to where the blob
+    /// is stored for now
+    pub data: UploadFileData,
+    /// Whether the blob didn't exist before.  New blobs have to be deleted
+    /// again if the transaction doesn't go through.
+    pub created: bool,
+    /// Whether the blob was scanned for malware yet.  New blobs are always
+    /// pending.
+    pub scan_status: ScanStatus,
+}
+
+/// Uploads a file to where blobs are kept until their hash is known,
+/// returning the name it was stored under.  It becomes a blob once it's
+/// passed to `adopt`.
+pub async fn upload(
+    file_host: &dyn FileHost,
+    content_type: &str,
+    file_stream: ByteStream<'_>,
+    size_cap: u64,
+) -> Result<(String, UploadFileData), FileHostingError> {
+    let incoming = format!("blobs/incoming/{:016x}", rand::random::<u64>());
+    let data = file_host
+        .upload_file(content_type, &incoming, file_stream, size_cap)
+        .await?;
+
+    Ok((incoming, data))
+}
+
+/// Turns a file that was stored already into a blob, or deletes it if the
+/// blob with its content exists already
+pub async fn adopt(
+    file_host: &dyn FileHost,
+    file_name: &str,
+    data: UploadFileData,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<StoredBlob, FileHostingError> {
+    let (created, scan_status) = match FileBlob::reference(
+        &data.content_sha512,
+        data.content_length as i64,
+        transaction,
+    )
+    .await
+    {
+        Ok(result) => result,
+        Err(e) => {
+            let _ = file_host.delete_file_version("", file_name).await;
+            return Err(e.into());
+        }
+    };
+
+    let path = if created {
+        let path = pending_blob_path(&data.content_sha512);
+        file_host.move_file(file_name, &path).await?;
+        path
+    } else {
+        file_host.delete_file_version("", file_name).await?;
+        blob_path(&data.content_sha512)
+    };
+
+    Ok(StoredBlob {
+        data: UploadFileData {
+            file_name: path,
+            ..data
+        },
+        created,
+        scan_status,
+    })
+}
+
+/// Moves a blob to where it's served from.  Blobs that were published
+/// already are left alone.
+pub async fn publish(file_host: &dyn FileHost, sha512: &str) -> Result<(), FileHostingError> {
+    match file_host
+        .move_file(&pending_blob_path(sha512), &blob_path(sha512))
+        .await
+    {
+        Err(FileHostingError::NotFound(_)) => Ok(()),
+        result => result,
+    }
+}
+
+/// Takes a published blob back out of reach, like when it was flagged after
+/// all.  Blobs that weren't published are left alone.
+pub async fn unpublish(file_host: &dyn FileHost, sha512: &str) -> Result<(), FileHostingError> {
+    match file_host
+        .move_file(&blob_path(sha512), &pending_blob_path(sha512))
+        .await
+    {
+        Err(FileHostingError::NotFound(_)) => Ok(()),
+        result => result,
+    }
+}
+
+/// Drops a reference to a blob.  The blob isn't deleted right away, since
+/// the transaction could still be rolled back; `remove_unreferenced` deletes
+/// it once the release is committed and nothing refers to it anymore.
+pub async fn release(
+    sha512: &str,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), FileHostingError> {
+    FileBlob::release(sha512, transaction).await?;
+
+    Ok(())
+}
+
+/// Deletes blobs nothing refers to anymore, like the ones of removed
+/// versions.  Returns the number of blobs deleted.
+pub async fn remove_unreferenced(
+    pool: &PgPool,
+    file_host: &dyn FileHost,
+) -> Result<usize, FileHostingError> {
+    const BATCH_SIZE: i64 = 100;
+    let mut count = 0;
+
+    loop {
+        let mut transaction = pool.begin().await?;
+        let blobs = FileBlob::get_unreferenced(BATCH_SIZE, &mut transaction).await?;
+        let mut deleted = 0;
+
+        for sha512 in &blobs {
+            // Blobs that can't be deleted keep their row, so they're tried
+            // again next time.  Whether the blob was published doesn't
+            // matter, since deleting a file that doesn't exist succeeds.
+            let result = match file_host.delete_file_version("", &blob_path(sha512)).await {
+                Ok(_) => {
+                    file_host
+                        .delete_file_version("", &pending_blob_path(sha512))
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(_) => {
+                    FileBlob::remove(sha512, &mut transaction).await?;
+                    deleted += 1;
+                }
+                Err(e) => warn!("Deleting blob {} failed: {:?}", sha512, e),
+            }
+        }
+
+        transaction.commit().await?;
+        count += deleted;
+
+        // Stopping after a failure keeps the next batch from picking the
+        // failed blobs up again
+        if deleted < blobs.len() || (blobs.len() as i64) < BATCH_SIZE {
+            return Ok(count);
+        }
+    }
+}
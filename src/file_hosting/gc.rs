@@ -0,0 +1,391 @@
+//! Compares the stored files with the rows referring to them, finding files
+//! nothing refers to and rows referring to files that aren't stored.
+
+use super::{blobs, cdn_file_name, FileHost, FileHostingError};
+use crate::database::models::cache::{self, Invalidation};
+use crate::database::models::ids::{FileId, ModId, UserId, VersionId};
+use futures::TryStreamExt;
+use log::{info, warn};
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+
+/// How old a file has to be before it counts as orphaned.  Newer files may
+/// belong to uploads that aren't done yet, like presigned uploads waiting to
+/// be finalized.
+fn orphan_grace_period() -> chrono::Duration {
+    chrono::Duration::hours(24)
+}
+
+#[derive(Debug)]
+pub enum DanglingReference {
+    VersionFile {
+        file_id: FileId,
+        version_id: VersionId,
+        url: String,
+    },
+    ModIcon {
+        mod_id: ModId,
+        url: String,
+    },
+    UserAvatar {
+        user_id: UserId,
+        url: String,
+    },
+}
+
+impl std::fmt::Display for DanglingReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DanglingReference::VersionFile { file_id, url, .. } => {
+                write!(f, "file {} ({})", file_id.0, url)
+            }
+            DanglingReference::ModIcon { mod_id, url } => {
+                write!(f, "icon of mod {} ({})", mod_id.0, url)
+            }
+            DanglingReference::UserAvatar { user_id, url } => {
+                write!(f, "avatar of user {} ({})", user_id.0, url)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StorageReport {
+    /// Stored files nothing refers to
+    pub orphaned_files: Vec<String>,
+    /// Rows referring to files that aren't stored
+    pub dangling_references: Vec<DanglingReference>,
+}
+
+impl StorageReport {
+    pub fn is_empty(&self) -> bool {
+        self.orphaned_files.is_empty() && self.dangling_references.is_empty()
+    }
+
+    pub fn log(&self, dry_run: bool) {
+        let action = if dry_run { "Found" } else { "Removed" };
+
+        for file_name in &self.orphaned_files {
+            info!("{} orphaned file {}", action, file_name);
+        }
+        for reference in &self.dangling_references {
+            info!("{} dangling reference to the {}", action, reference);
+        }
+
+        info!(
+            "{} {} orphaned files and {} dangling references",
+            action,
+            self.orphaned_files.len(),
+            self.dangling_references.len()
+        );
+    }
+}
+
+/// The CDN URLs stored in the database, by the file they point to.  Files
+/// stored as blobs are left out: their blobs are kept by reference counting,
+/// and aren't at the URL yet while they wait for their scan.
+async fn get_references(
+    pool: &PgPool,
+    cdn_url: &str,
+) -> Result<HashMap<String, Vec<DanglingReference>>, sqlx::Error> {
+    fn add(
+        references: &mut HashMap<String, Vec<DanglingReference>>,
+        file_name: String,
+        reference: DanglingReference,
+    ) {
+        references.entry(file_name).or_default().push(reference);
+    }
+
+    let mut references = HashMap::new();
+
+    let mut files =
+        sqlx::query!("SELECT id, version_id, url FROM files WHERE blob_sha512 IS NULL").fetch(pool);
+    while let Some(row) = files.try_next().await? {
+        if let Some(file_name) = cdn_file_name(&row.url, cdn_url) {
+            add(
+                &mut references,
+                file_name.to_string(),
+                DanglingReference::VersionFile {
+                    file_id: FileId(row.id),
+                    version_id: VersionId(row.version_id),
+                    url: row.url,
+                },
+            );
+        }
+    }
+
+    let mut icons =
+        sqlx::query!("SELECT id, icon_url FROM mods WHERE icon_url IS NOT NULL").fetch(pool);
+    while let Some(row) = icons.try_next().await? {
+        let url = row.icon_url.unwrap_or_default();
+        if let Some(file_name) = cdn_file_name(&url, cdn_url) {
+            add(
+                &mut references,
+                file_name.to_string(),
+                DanglingReference::ModIcon {
+                    mod_id: ModId(row.id),
+                    url,
+                },
+            );
+        }
+    }
+
+    let mut avatars =
+        sqlx::query!("SELECT id, avatar_url FROM users WHERE avatar_url IS NOT NULL").fetch(pool);
+    while let Some(row) = avatars.try_next().await? {
+        let url = row.avatar_url.unwrap_or_default();
+        if let Some(file_name) = cdn_file_name(&url, cdn_url) {
+            add(
+                &mut references,
+                file_name.to_string(),
+                DanglingReference::UserAvatar {
+                    user_id: UserId(row.id),
+                    url,
+                },
+            );
+        }
+    }
+
+    Ok(references)
+}
+
+/// Files that are kept without a URL referring to them
+async fn get_unlisted_files(pool: &PgPool) -> Result<HashSet<String>, sqlx::Error> {
+    let mut files = HashSet::new();
+
+    // Blobs nothing refers to anymore are deleted by `remove_unreferenced`
+    let mut blob_rows = sqlx::query!("SELECT sha512 FROM file_blobs").fetch(pool);
+    while let Some(row) = blob_rows.try_next().await? {
+        files.insert(blobs::blob_path(&row.sha512));
+        files.insert(blobs::pending_blob_path(&row.sha512));
+    }
+
+    let mut chunks = sqlx::query!("SELECT file_name FROM upload_chunks").fetch(pool);
+    while let Some(row) = chunks.try_next().await? {
+        files.insert(row.file_name);
+    }
+
+    Ok(files)
+}
+
+/// Compares the stored files with the database
+pub async fn check_storage(
+    pool: &PgPool,
+    file_host: &dyn FileHost,
+    cdn_url: &str,
+) -> Result<StorageReport, FileHostingError> {
+    // The database is read first, so files stored in the meantime are too
+    // new to count as orphaned instead of being missed by the references
+    let mut references = get_references(pool, cdn_url).await?;
+    let unlisted = get_unlisted_files(pool).await?;
+    let stored = file_host.list_files("").await?;
+
+    let orphaned_before = chrono::Utc::now() - orphan_grace_period();
+    let mut report = StorageReport::default();
+
+    for file in stored {
+        let referenced =
+            references.remove(&file.file_name).is_some() || unlisted.contains(&file.file_name);
+
+        if !referenced && file.modified < orphaned_before {
+            report.orphaned_files.push(file.file_name);
+        }
+    }
+
+    // Whatever is left refers to files that weren't found
+    report.dangling_references = references.into_values().flatten().collect();
+
+    Ok(report)
+}
+
+/// Deletes the orphaned files of a report and the rows referring to files
+/// that aren't stored.  Rows that changed since the check are kept.
+pub async fn repair_storage(
+    report: &StorageReport,
+    pool: &PgPool,
+    file_host: &dyn FileHost,
+) -> Result<(), FileHostingError> {
+    for file_name in &report.orphaned_files {
+        if let Err(e) = file_host.delete_file_version("", file_name).await {
+            warn!("Deleting orphaned file {} failed: {:?}", file_name, e);
+        }
+    }
+
+    for reference in &report.dangling_references {
+        match reference {
+            DanglingReference::VersionFile {
+                file_id,
+                version_id,
+                url,
+            } => {
+                let mut transaction = pool.begin().await?;
+
+                sqlx::query!(
+                    "
+                    DELETE FROM hashes
+                    WHERE file_id = $1 AND EXISTS(SELECT 1 FROM files WHERE id = $1 AND url = $2)
+                    ",
+                    *file_id as FileId,
+                    url
+                )
+                .execute(&mut transaction)
+                .await?;
+
+                sqlx::query!(
+                    "
+                    DELETE FROM files
+                    WHERE id = $1 AND url = $2 AND blob_sha512 IS NULL
+                    ",
+                    *file_id as FileId,
+                    url
+                )
+                .execute(&mut transaction)
+                .await?;
+
+                transaction.commit().await?;
+
+                cache::invalidate(Invalidation::Version(version_id.0), pool).await;
+            }
+            DanglingReference::ModIcon { mod_id, url } => {
+                sqlx::query!(
+                    "
+                    UPDATE mods
+                    SET icon_url = NULL, edited = NOW()
+                    WHERE id = $1 AND icon_url = $2
+                    ",
+                    *mod_id as ModId,
+                    url
+                )
+                .execute(pool)
+                .await?;
+
+                cache::invalidate(Invalidation::Mod(mod_id.0), pool).await;
+            }
+            DanglingReference::UserAvatar { user_id, url } => {
+                sqlx::query!(
+                    "
+                    UPDATE users
+                    SET avatar_url = NULL
+                    WHERE id = $1 AND avatar_url = $2
+                    ",
+                    *user_id as UserId,
+                    url
+                )
+                .execute(pool)
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the storage, repairing it unless `dry_run` is set
+pub async fn collect_garbage(
+    pool: &PgPool,
+    file_host: &dyn FileHost,
+    cdn_url: &str,
+    dry_run: bool,
+) -> Result<StorageReport, FileHostingError> {
+    let report = check_storage(pool, file_host, cdn_url).await?;
+
+    if !dry_run {
+        repair_storage(&report, pool, file_host).await?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_hosting::LocalHost;
+
+    // Runs against the database configured in the environment
+    #[actix_rt::test]
+    async fn pending_blobs_are_kept() {
+        use rand::Rng;
+
+        let pool = crate::database::connect().await.unwrap();
+        let id = rand::thread_rng().gen_range(1..i64::MAX);
+        let sha512 = format!("{:0128x}", id);
+        let cdn_url = "https://cdn.example.com";
+
+        let root = std::env::temp_dir().join(format!("xivrepo-gc-{}", id));
+        let pending = root.join(blobs::pending_blob_path(&sha512));
+        std::fs::create_dir_all(pending.parent().unwrap()).unwrap();
+        std::fs::write(&pending, b"waiting for its scan").unwrap();
+        let file_host = LocalHost::new(&root).unwrap();
+
+        let url = format!("{}/{}", cdn_url, blobs::blob_path(&sha512));
+        let setup = async {
+            sqlx::query("INSERT INTO users (id, username) VALUES ($1, $1::varchar)")
+                .bind(id)
+                .execute(&pool)
+                .await?;
+            sqlx::query("INSERT INTO teams (id) VALUES ($1)")
+                .bind(id)
+                .execute(&pool)
+                .await?;
+            sqlx::query(
+                "
+                INSERT INTO mods (id, team_id, title, description, status)
+                VALUES ($1, $1, 'GC test', 'GC test', (SELECT id FROM statuses LIMIT 1))
+                ",
+            )
+            .bind(id)
+            .execute(&pool)
+            .await?;
+            sqlx::query(
+                "
+                INSERT INTO versions (id, mod_id, author_id, name, version_number, release_channel)
+                VALUES ($1, $1, $1, 'GC test', '1.0.0', (SELECT id FROM release_channels LIMIT 1))
+                ",
+            )
+            .bind(id)
+            .execute(&pool)
+            .await?;
+            sqlx::query("INSERT INTO file_blobs (sha512, size, ref_count) VALUES ($1, 20, 1)")
+                .bind(&sha512)
+                .execute(&pool)
+                .await?;
+            sqlx::query(
+                "
+                INSERT INTO files (id, version_id, url, filename, blob_sha512)
+                VALUES ($1, $1, $2, 'test.zip', $3)
+                ",
+            )
+            .bind(id)
+            .bind(&url)
+            .bind(&sha512)
+            .execute(&pool)
+            .await
+        };
+        let setup = setup.await;
+
+        let report = check_storage(&pool, &file_host, cdn_url).await;
+
+        for table in &["files", "versions", "mods", "teams", "users"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE id = $1", table))
+                .bind(id)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        sqlx::query("DELETE FROM file_blobs WHERE sha512 = $1")
+            .bind(&sha512)
+            .execute(&pool)
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        setup.unwrap();
+        let report = report.unwrap();
+        assert!(!report.dangling_references.iter().any(
+            |x| matches!(x, DanglingReference::VersionFile { file_id, .. } if file_id.0 == id)
+        ));
+        assert!(!report
+            .orphaned_files
+            .contains(&blobs::pending_blob_path(&sha512)));
+    }
+}
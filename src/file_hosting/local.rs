@@ -0,0 +1,353 @@
+//! Stores files in a directory on the server, which serves them itself
+//! through the `files` routes.
+
+use super::{
+    blocking, is_private, ByteStream, DeleteFileData, FileHost, FileHostingError, StoredFile,
+    UploadFileData, UploadHasher,
+};
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+pub struct LocalHost {
+    root: PathBuf,
+}
+
+impl LocalHost {
+    /// Stores files in `root`, which is created if it doesn't exist
+    pub fn new(root: impl AsRef<Path>) -> Result<Self, FileHostingError> {
+        std::fs::create_dir_all(&root)?;
+
+        Ok(LocalHost {
+            root: root.as_ref().canonicalize()?,
+        })
+    }
+
+    /// Where a file is stored.  Only plain relative names are accepted, and
+    /// none of their parts may start with a dot, which keeps temporary files
+    /// and content types out of reach.
+    fn path(&self, file_name: &str) -> Result<PathBuf, FileHostingError> {
+        let relative = Path::new(file_name);
+
+        let valid = !file_name.is_empty()
+            && relative.components().all(|component| match component {
+                Component::Normal(part) => !part.to_string_lossy().starts_with('.'),
+                _ => false,
+            });
+
+        if valid {
+            Ok(self.root.join(relative))
+        } else {
+            Err(FileHostingError::InvalidFilename)
+        }
+    }
+
+    /// Makes sure a path that exists doesn't lead out of the root, like
+    /// through a symlink
+    fn confine(&self, path: &Path) -> Result<PathBuf, FileHostingError> {
+        let path = path.canonicalize()?;
+
+        if path.starts_with(&self.root) {
+            Ok(path)
+        } else {
+            Err(FileHostingError::InvalidFilename)
+        }
+    }
+
+    /// Creates the directory a file goes in, returning it
+    fn create_parent(&self, path: &Path) -> Result<PathBuf, FileHostingError> {
+        let parent = path.parent().ok_or(FileHostingError::InvalidFilename)?;
+        std::fs::create_dir_all(parent)?;
+
+        self.confine(parent)
+    }
+
+    /// Opens a file to be served, or `None` if it doesn't exist or isn't
+    /// public.  Returns the content type the file was uploaded with.
+    pub fn open(&self, file_name: &str) -> Result<Option<(File, String)>, FileHostingError> {
+        if is_private(file_name) {
+            return Ok(None);
+        }
+
+        let path = match self.path(file_name) {
+            Ok(path) => path,
+            Err(FileHostingError::InvalidFilename) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let path = match self.confine(&path) {
+            Ok(path) if path.is_file() => path,
+            Ok(_) | Err(FileHostingError::InvalidFilename) => return Ok(None),
+            Err(FileHostingError::FileSystemError(e))
+                if e.kind() == std::io::ErrorKind::NotFound =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let content_type = match std::fs::read_to_string(content_type_path(&path)) {
+            Ok(content_type) => content_type,
+            // Files stored before content types were kept
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let extension = path.extension().unwrap_or_default().to_string_lossy();
+                actix_files::file_extension_to_mime(&extension).to_string()
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Some((File::open(path)?, content_type)))
+    }
+}
+
+/// Where the content type of a file is kept, next to the file itself
+fn content_type_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    path.with_file_name(format!(".{}.type", file_name))
+}
+
+/// Writes a file under a temporary name, moving it into place once all of
+/// it was written, so readers never see a partial file
+fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let temp_path = temp_path(path);
+
+    let result = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&temp_path, path));
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!(".upload-{:016x}", rand::random::<u64>()))
+}
+
+/// Makes a rename in a directory survive a crash
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn not_found(file_name: &str) -> impl FnOnce(std::io::Error) -> FileHostingError + '_ {
+    move |e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            FileHostingError::NotFound(file_name.to_string())
+        } else {
+            e.into()
+        }
+    }
+}
+
+fn list_dir(root: &Path, dir: &Path, files: &mut Vec<StoredFile>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+
+        // Temporary files and content types
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+
+        // Symlinks aren't created by the host, so they aren't followed
+        if metadata.is_dir() {
+            list_dir(root, &entry.path(), files)?;
+        } else if metadata.is_file() {
+            if let Ok(path) = entry.path().strip_prefix(root) {
+                files.push(StoredFile {
+                    file_name: path.to_string_lossy().replace('\\', "/"),
+                    modified: metadata.modified()?.into(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[async_trait(?Send)]
+impl FileHost for LocalHost {
+    async fn upload_file(
+        &self,
+        content_type: &str,
+        file_name: &str,
+        mut file_stream: ByteStream<'_>,
+        size_cap: u64,
+    ) -> Result<UploadFileData, FileHostingError> {
+        let path = self.path(file_name)?;
+        let parent = self.create_parent(&path)?;
+        let path = parent.join(path.file_name().ok_or(FileHostingError::InvalidFilename)?);
+
+        // The file is written next to its destination and only moved there
+        // once complete, so a failed upload never replaces an existing file
+        let temp_path = temp_path(&path);
+        let mut temp_file = File::create(&temp_path)?;
+        let mut hasher = UploadHasher::new(size_cap);
+
+        let result: Result<(), FileHostingError> = async {
+            while let Some(chunk) = file_stream.next().await {
+                let chunk = chunk?;
+                hasher.update(&chunk)?;
+
+                temp_file = blocking(move || {
+                    temp_file.write_all(&chunk)?;
+                    Ok(temp_file)
+                })
+                .await?;
+            }
+
+            let (temp_path, path) = (temp_path.clone(), path.clone());
+            let content_type = content_type.to_string();
+
+            blocking(move || {
+                temp_file.sync_all()?;
+                write_atomically(&content_type_path(&path), content_type.as_bytes())?;
+                std::fs::rename(&temp_path, &path)?;
+                sync_dir(&parent)?;
+
+                Ok(())
+            })
+            .await
+        }
+        .await;
+
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(e);
+        }
+
+        Ok(hasher.finish("LOCAL_FILE_ID", file_name, content_type))
+    }
+
+    async fn download_file(
+        &self,
+        file_name: &str,
+    ) -> Result<ByteStream<'static>, FileHostingError> {
+        let path = self.path(file_name)?;
+        let path = self.confine(&path).map_err(|e| match e {
+            FileHostingError::FileSystemError(e) => not_found(file_name)(e),
+            e => e,
+        })?;
+        let file = File::open(path).map_err(not_found(file_name))?;
+
+        Ok(Box::pin(futures::stream::unfold(
+            Some(file),
+            |file| async move {
+                let mut file = file?;
+
+                let result = blocking(move || {
+                    let mut buffer = vec![0; 64 * 1024];
+                    let length = file.read(&mut buffer)?;
+                    buffer.truncate(length);
+
+                    Ok((buffer, file))
+                })
+                .await;
+
+                match result {
+                    Ok((buffer, _)) if buffer.is_empty() => None,
+                    Ok((buffer, file)) => Some((Ok(buffer.into()), Some(file))),
+                    Err(e) => Some((Err(e), None)),
+                }
+            },
+        )))
+    }
+
+    async fn list_files(&self, prefix: &str) -> Result<Vec<StoredFile>, FileHostingError> {
+        let root = self.root.clone();
+
+        let mut files = blocking(move || {
+            let mut files = Vec::new();
+            list_dir(&root, &root, &mut files)?;
+
+            Ok(files)
+        })
+        .await?;
+
+        files.retain(|x| x.file_name.starts_with(prefix));
+
+        Ok(files)
+    }
+
+    async fn move_file(&self, from: &str, to: &str) -> Result<(), FileHostingError> {
+        let from = self.confine(&self.path(from)?).map_err(|e| match e {
+            FileHostingError::FileSystemError(e) => not_found(from)(e),
+            e => e,
+        })?;
+        let to = self.path(to)?;
+        let parent = self.create_parent(&to)?;
+        let to = parent.join(to.file_name().ok_or(FileHostingError::InvalidFilename)?);
+
+        blocking(move || {
+            // The content type is moved first, so the file never shows up
+            // without it
+            match std::fs::rename(content_type_path(&from), content_type_path(&to)) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    remove_if_exists(&content_type_path(&to))?
+                }
+                result => result?,
+            }
+
+            std::fs::rename(&from, &to)?;
+            sync_dir(&parent)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_file_version(
+        &self,
+        file_id: &str,
+        file_name: &str,
+    ) -> Result<DeleteFileData, FileHostingError> {
+        let path = self.path(file_name)?;
+
+        // Files that are gone already count as deleted, like they do on S3
+        let path = match self.confine(&path) {
+            Ok(path) => Some(path),
+            Err(FileHostingError::FileSystemError(e))
+                if e.kind() == std::io::ErrorKind::NotFound =>
+            {
+                None
+            }
+            Err(e) => return Err(e),
+        };
+
+        if let Some(path) = path {
+            blocking(move || {
+                remove_if_exists(&path)?;
+                remove_if_exists(&content_type_path(&path))?;
+
+                Ok(())
+            })
+            .await?;
+        }
+
+        Ok(DeleteFileData {
+            file_id: file_id.to_string(),
+            file_name: file_name.to_string(),
+        })
+    }
+}
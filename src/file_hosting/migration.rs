@@ -0,0 +1,351 @@
+//! Copies the stored files from one file host to another, pointing the URLs
+//! in the database at the new host.
+//!
+//! Rows are moved over in batches.  The files of a batch are copied first,
+//! and then its rows are updated in a short transaction.  Rows that
+//! point at the new host already are skipped, so an interrupted migration
+//! carries on where it stopped when run again.  Nothing is deleted from the
+//! old host.  Unfinished uploads aren't copied, so uploads should be paused
+//! while migrating.
+
+use super::{cdn_file_name, FileHost, FileHostingError};
+use crate::database::models::cache::{self, Invalidation};
+use log::{info, warn};
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+
+const BATCH_SIZE: i64 = 100;
+
+/// A file host, along with the URL its files are served under
+#[derive(Copy, Clone)]
+pub struct Storage<'a> {
+    pub file_host: &'a dyn FileHost,
+    pub cdn_url: &'a str,
+}
+
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    /// Rows pointing at the new host now
+    pub migrated: usize,
+    /// Rows left pointing at the old host, since their file couldn't be
+    /// copied or didn't match its hashes
+    pub failed: usize,
+}
+
+struct Migration<'a> {
+    from: Storage<'a>,
+    to: Storage<'a>,
+    /// Files copied during this run.  Blobs are shared by several rows, but
+    /// only copied once.
+    copied: HashSet<String>,
+    report: MigrationReport,
+}
+
+/// The content type of a file, going by its extension
+fn guess_content_type(file_name: &str) -> String {
+    let extension = std::path::Path::new(file_name)
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy();
+
+    actix_files::file_extension_to_mime(&extension).to_string()
+}
+
+impl<'a> Migration<'a> {
+    /// Copies the file a URL points to, returning its URL on the new host.
+    /// `hashes` are the hex hashes the file should have, by algorithm.
+    /// Failures are logged and give `None`.
+    async fn copy(
+        &mut self,
+        url: &str,
+        content_type: &str,
+        hashes: &HashMap<String, String>,
+    ) -> Option<String> {
+        // When one of the URLs starts with the other, the rows of the longer
+        // one match both
+        if self.to.cdn_url.len() > self.from.cdn_url.len()
+            && cdn_file_name(url, self.to.cdn_url).is_some()
+        {
+            return None;
+        }
+
+        let file_name = cdn_file_name(url, self.from.cdn_url)?;
+        let new_url = format!("{}/{}", self.to.cdn_url, file_name);
+
+        if self.copied.contains(file_name) {
+            return Some(new_url);
+        }
+
+        match self.copy_file(file_name, content_type, hashes).await {
+            Ok(()) => {
+                self.copied.insert(file_name.to_string());
+                Some(new_url)
+            }
+            Err(e) => {
+                warn!("Copying {} failed: {}", file_name, e);
+                self.report.failed += 1;
+                None
+            }
+        }
+    }
+
+    async fn copy_file(
+        &self,
+        file_name: &str,
+        content_type: &str,
+        hashes: &HashMap<String, String>,
+    ) -> Result<(), FileHostingError> {
+        let file_stream = self.from.file_host.download_file(file_name).await?;
+        let data = self
+            .to
+            .file_host
+            .upload_file(content_type, file_name, file_stream, u64::MAX)
+            .await?;
+
+        let matches = hashes.iter().all(|(algorithm, hash)| match &**algorithm {
+            "sha1" => *hash == data.content_sha1,
+            "sha512" => *hash == data.content_sha512,
+            _ => true,
+        });
+
+        if !matches {
+            self.to
+                .file_host
+                .delete_file_version(&data.file_id, file_name)
+                .await?;
+
+            return Err(FileHostingError::HashMismatch(file_name.to_string()));
+        }
+
+        Ok(())
+    }
+
+    async fn migrate_files(&mut self, pool: &PgPool) -> Result<(), FileHostingError> {
+        let mut last_id = i64::MIN;
+
+        loop {
+            let files = sqlx::query!(
+                "
+                SELECT id, version_id, url, filename FROM files
+                WHERE id > $1 AND left(url, length($2)) = $2
+                ORDER BY id
+                LIMIT $3
+                ",
+                last_id,
+                self.from.cdn_url,
+                BATCH_SIZE
+            )
+            .fetch_all(pool)
+            .await?;
+
+            let last = match files.last() {
+                Some(last) => last.id,
+                None => return Ok(()),
+            };
+
+            let file_ids = files.iter().map(|x| x.id).collect::<Vec<_>>();
+            let mut hashes: HashMap<i64, HashMap<String, String>> = HashMap::new();
+
+            for row in sqlx::query!(
+                "
+                SELECT file_id, algorithm, hash FROM hashes
+                WHERE file_id = ANY($1)
+                ",
+                &file_ids
+            )
+            .fetch_all(pool)
+            .await?
+            {
+                hashes.entry(row.file_id).or_default().insert(
+                    row.algorithm,
+                    String::from_utf8_lossy(&row.hash).into_owned(),
+                );
+            }
+
+            let mut copied = Vec::new();
+
+            for file in files {
+                let content_type = guess_content_type(&file.filename);
+                let file_hashes = hashes.remove(&file.id).unwrap_or_default();
+
+                if let Some(new_url) = self.copy(&file.url, &content_type, &file_hashes).await {
+                    copied.push((file, new_url));
+                }
+            }
+
+            let mut transaction = pool.begin().await?;
+
+            for (file, new_url) in &copied {
+                sqlx::query!(
+                    "
+                    UPDATE files
+                    SET url = $1
+                    WHERE id = $2 AND url = $3
+                    ",
+                    new_url,
+                    file.id,
+                    file.url
+                )
+                .execute(&mut transaction)
+                .await?;
+            }
+
+            transaction.commit().await?;
+            self.report.migrated += copied.len();
+
+            for (file, _) in copied {
+                cache::invalidate(Invalidation::Version(file.version_id), pool).await;
+            }
+
+            info!("Migrated files up to {}", last);
+            last_id = last;
+        }
+    }
+
+    async fn migrate_icons(&mut self, pool: &PgPool) -> Result<(), FileHostingError> {
+        let mut last_id = i64::MIN;
+
+        loop {
+            let icons = sqlx::query!(
+                "
+                SELECT id, icon_url AS \"icon_url!\" FROM mods
+                WHERE id > $1 AND left(icon_url, length($2)) = $2
+                ORDER BY id
+                LIMIT $3
+                ",
+                last_id,
+                self.from.cdn_url,
+                BATCH_SIZE
+            )
+            .fetch_all(pool)
+            .await?;
+
+            let last = match icons.last() {
+                Some(last) => last.id,
+                None => return Ok(()),
+            };
+
+            let mut copied = Vec::new();
+
+            for icon in icons {
+                let content_type = guess_content_type(&icon.icon_url);
+
+                if let Some(new_url) = self
+                    .copy(&icon.icon_url, &content_type, &HashMap::new())
+                    .await
+                {
+                    copied.push((icon, new_url));
+                }
+            }
+
+            let mut transaction = pool.begin().await?;
+
+            for (icon, new_url) in &copied {
+                sqlx::query!(
+                    "
+                    UPDATE mods
+                    SET icon_url = $1, edited = NOW()
+                    WHERE id = $2 AND icon_url = $3
+                    ",
+                    new_url,
+                    icon.id,
+                    icon.icon_url
+                )
+                .execute(&mut transaction)
+                .await?;
+            }
+
+            transaction.commit().await?;
+            self.report.migrated += copied.len();
+
+            for (icon, _) in copied {
+                cache::invalidate(Invalidation::Mod(icon.id), pool).await;
+            }
+
+            info!("Migrated mod icons up to {}", last);
+            last_id = last;
+        }
+    }
+
+    async fn migrate_avatars(&mut self, pool: &PgPool) -> Result<(), FileHostingError> {
+        let mut last_id = i64::MIN;
+
+        loop {
+            let avatars = sqlx::query!(
+                "
+                SELECT id, avatar_url AS \"avatar_url!\" FROM users
+                WHERE id > $1 AND left(avatar_url, length($2)) = $2
+                ORDER BY id
+                LIMIT $3
+                ",
+                last_id,
+                self.from.cdn_url,
+                BATCH_SIZE
+            )
+            .fetch_all(pool)
+            .await?;
+
+            let last = match avatars.last() {
+                Some(last) => last.id,
+                None => return Ok(()),
+            };
+
+            let mut copied = Vec::new();
+
+            for avatar in avatars {
+                let content_type = guess_content_type(&avatar.avatar_url);
+
+                if let Some(new_url) = self
+                    .copy(&avatar.avatar_url, &content_type, &HashMap::new())
+                    .await
+                {
+                    copied.push((avatar, new_url));
+                }
+            }
+
+            let mut transaction = pool.begin().await?;
+
+            for (avatar, new_url) in &copied {
+                sqlx::query!(
+                    "
+                    UPDATE users
+                    SET avatar_url = $1
+                    WHERE id = $2 AND avatar_url = $3
+                    ",
+                    new_url,
+                    avatar.id,
+                    avatar.avatar_url
+                )
+                .execute(&mut transaction)
+                .await?;
+            }
+
+            transaction.commit().await?;
+            self.report.migrated += copied.len();
+
+            info!("Migrated user avatars up to {}", last);
+            last_id = last;
+        }
+    }
+}
+
+/// Copies the files the database refers to from one host to another, and
+/// points their URLs at the new host
+pub async fn migrate_storage(
+    pool: &PgPool,
+    from: Storage<'_>,
+    to: Storage<'_>,
+) -> Result<MigrationReport, FileHostingError> {
+    let mut migration = Migration {
+        from,
+        to,
+        copied: HashSet::new(),
+        report: MigrationReport::default(),
+    };
+
+    migration.migrate_files(pool).await?;
+    migration.migrate_icons(pool).await?;
+    migration.migrate_avatars(pool).await?;
+
+    Ok(migration.report)
+}
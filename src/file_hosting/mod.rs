@@ -1,33 +1,76 @@
+use actix_web::web::Bytes;
 use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use sha2::Digest;
+use std::pin::Pin;
 use thiserror::Error;
 
-mod mock;
+pub mod archives;
+pub mod blobs;
+pub mod gc;
+mod local;
+pub mod migration;
 mod s3_host;
+pub mod scanning;
+pub mod uploads;
 
-pub use mock::MockHost;
+pub use local::LocalHost;
 use s3::creds::AwsCredsError;
 use s3::S3Error;
 pub use s3_host::S3Host;
 
+/// Files under these prefixes are kept private, since they haven't been
+/// checked yet or are only parts of a file
+const PRIVATE_PREFIXES: &[&str] = &["blobs/incoming/", "blobs/pending/", "uploads/"];
+
+/// Whether a file is kept from being served
+fn is_private(file_name: &str) -> bool {
+    PRIVATE_PREFIXES.iter().any(|x| file_name.starts_with(x))
+}
+
 #[derive(Error, Debug)]
 pub enum FileHostingError {
     #[error("Error while accessing the data from backblaze")]
     HttpError(#[from] reqwest::Error),
     #[error("S3 error: {0}")]
-    S3Error(#[from] S3Error),
+    S3Error(Box<S3Error>),
     #[error("S3 Authentication error: {0}")]
-    S3CredentialsError(#[from] AwsCredsError),
+    S3CredentialsError(Box<AwsCredsError>),
     #[error("File system error in file hosting: {0}")]
     FileSystemError(#[from] std::io::Error),
     #[error("Invalid Filename")]
     InvalidFilename,
+    #[error("Error while reading the uploaded file: {0}")]
+    PayloadError(String),
+    #[error("The file must be smaller than {0} bytes")]
+    FileTooLarge(u64),
+    #[error("File not found: {0}")]
+    NotFound(String),
+    #[error("The file doesn't match its hashes: {0}")]
+    HashMismatch(String),
+    #[error("Database error while storing file: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+// The S3 errors are boxed, since they're much larger than the others and
+// every result carrying a `FileHostingError` would be as large
+impl From<S3Error> for FileHostingError {
+    fn from(error: S3Error) -> Self {
+        FileHostingError::S3Error(Box::new(error))
+    }
+}
+
+impl From<AwsCredsError> for FileHostingError {
+    fn from(error: AwsCredsError) -> Self {
+        FileHostingError::S3CredentialsError(Box::new(error))
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct UploadFileData {
     pub file_id: String,
     pub file_name: String,
-    pub content_length: u32,
+    pub content_length: u64,
     pub content_sha512: String,
     pub content_sha1: String,
     pub content_md5: Option<String>,
@@ -41,18 +84,170 @@ pub struct DeleteFileData {
     pub file_name: String,
 }
 
-#[async_trait]
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    pub file_name: String,
+    pub modified: chrono::DateTime<chrono::Utc>,
+}
+
+/// Where and how a file can be uploaded to directly
+#[derive(Debug, Clone)]
+pub struct PresignedUpload {
+    pub url: String,
+    /// The headers the `PUT` request has to be sent with
+    pub headers: Vec<(String, String)>,
+}
+
+/// The bytes of a file, in the chunks they arrive in
+pub type ByteStream<'a> = Pin<Box<dyn Stream<Item = Result<Bytes, FileHostingError>> + 'a>>;
+
+// Multipart payloads can't be sent between threads, so neither can the
+// futures reading them
+#[async_trait(?Send)]
 pub trait FileHost {
+    /// Uploads a file as it arrives, without holding all of it in memory.
+    /// Fails with `FileTooLarge` as soon as `size_cap` bytes were read, in
+    /// which case nothing is stored.
     async fn upload_file(
         &self,
         content_type: &str,
         file_name: &str,
-        file_bytes: Vec<u8>,
+        file_stream: ByteStream<'_>,
+        size_cap: u64,
     ) -> Result<UploadFileData, FileHostingError>;
 
+    /// Reads a file back as it arrives
+    async fn download_file(&self, file_name: &str) -> Result<ByteStream<'static>, FileHostingError>;
+
+    /// A URL `file_name` can be uploaded to with a `PUT` request for the next
+    /// `expires_in` seconds, without passing through this server.  The file
+    /// isn't public until it's moved with `move_file`.  `None` if the host
+    /// can't take direct uploads.
+    fn presign_upload(
+        &self,
+        _content_type: &str,
+        _file_name: &str,
+        _expires_in: u32,
+    ) -> Result<Option<PresignedUpload>, FileHostingError> {
+        Ok(None)
+    }
+
+    /// Reads back a file that was uploaded directly and hashes it, like
+    /// `upload_file` does.  Fails with `FileTooLarge` if it has `size_cap`
+    /// bytes or more.
+    async fn inspect_file(
+        &self,
+        content_type: &str,
+        file_name: &str,
+        size_cap: u64,
+    ) -> Result<UploadFileData, FileHostingError> {
+        let file_stream = self.download_file(file_name).await?;
+
+        hash_file(content_type, file_name, file_stream, size_cap).await
+    }
+
+    /// Lists the stored files whose names start with `prefix`
+    async fn list_files(&self, prefix: &str) -> Result<Vec<StoredFile>, FileHostingError>;
+
+    /// Moves a file that was stored already to another name, which makes it
+    /// public unless the name is private.  Fails with `NotFound` if there's
+    /// no file to move.
+    async fn move_file(&self, from: &str, to: &str) -> Result<(), FileHostingError>;
+
     async fn delete_file_version(
         &self,
         file_id: &str,
         file_name: &str,
     ) -> Result<DeleteFileData, FileHostingError>;
 }
+
+/// The name a file on the CDN is stored under, or `None` for URLs that
+/// aren't on the CDN, like avatars from Discord
+pub fn cdn_file_name<'a>(url: &'a str, cdn_url: &str) -> Option<&'a str> {
+    url.strip_prefix(cdn_url)?.strip_prefix('/')
+}
+
+/// Deletes the file a URL points to if it's on the CDN, logging failures.
+/// Files this misses are found by the storage check in `gc`.
+pub async fn delete_cdn_file(file_host: &dyn FileHost, url: &str, cdn_url: &str) {
+    if let Some(file_name) = cdn_file_name(url, cdn_url) {
+        if let Err(e) = file_host.delete_file_version("", file_name).await {
+            log::warn!("Deleting {} failed: {:?}", file_name, e);
+        }
+    }
+}
+
+/// Hashes a file without storing it
+pub async fn hash_file(
+    content_type: &str,
+    file_name: &str,
+    mut file_stream: ByteStream<'_>,
+    size_cap: u64,
+) -> Result<UploadFileData, FileHostingError> {
+    let mut hasher = UploadHasher::new(size_cap);
+
+    while let Some(chunk) = file_stream.next().await {
+        hasher.update(&chunk?)?;
+    }
+
+    Ok(hasher.finish(file_name, file_name, content_type))
+}
+
+/// Runs file system calls on the thread pool, so they don't hold up requests
+async fn blocking<T, F>(f: F) -> Result<T, FileHostingError>
+where
+    F: FnOnce() -> Result<T, FileHostingError> + Send + 'static,
+    T: Send + 'static,
+{
+    actix_web::web::block(f).await.map_err(|e| match e {
+        actix_web::error::BlockingError::Error(e) => e,
+        actix_web::error::BlockingError::Canceled => {
+            std::io::Error::other("The thread pool was shut down").into()
+        }
+    })
+}
+
+/// Hashes and counts the bytes of a streamed upload
+pub struct UploadHasher {
+    sha1: sha1::Sha1,
+    sha512: sha2::Sha512,
+    length: u64,
+    size_cap: u64,
+}
+
+impl UploadHasher {
+    pub fn new(size_cap: u64) -> Self {
+        UploadHasher {
+            sha1: sha1::Sha1::new(),
+            sha512: sha2::Sha512::new(),
+            length: 0,
+            size_cap,
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) -> Result<(), FileHostingError> {
+        self.length += chunk.len() as u64;
+
+        if self.length >= self.size_cap {
+            return Err(FileHostingError::FileTooLarge(self.size_cap));
+        }
+
+        self.sha1.update(chunk);
+        self.sha512.update(chunk);
+
+        Ok(())
+    }
+
+    pub fn finish(self, file_id: &str, file_name: &str, content_type: &str) -> UploadFileData {
+        UploadFileData {
+            file_id: file_id.to_string(),
+            file_name: file_name.to_string(),
+            content_length: self.length,
+            content_sha512: format!("{:x}", self.sha512.finalize()),
+            content_sha1: self.sha1.hexdigest(),
+            content_md5: None,
+            content_type: content_type.to_string(),
+            upload_timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        }
+    }
+}
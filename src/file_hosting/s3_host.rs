@@ -1,12 +1,22 @@
-use crate::file_hosting::{DeleteFileData, FileHost, FileHostingError, UploadFileData};
+use crate::file_hosting::{
+    hash_file, is_private, ByteStream, DeleteFileData, FileHost, FileHostingError, PresignedUpload,
+    StoredFile, UploadFileData, UploadHasher,
+};
 use async_trait::async_trait;
-use s3::bucket::Bucket;
+use futures::StreamExt;
+use s3::bucket::{Bucket, CHUNK_SIZE};
+use s3::command::Command;
 use s3::creds::Credentials;
 use s3::region::Region;
-use sha2::Digest;
+use s3::request::Request;
+use s3::serde_types::{CompleteMultipartUploadData, Part};
+use s3::S3Error;
+use xml::reader::XmlEvent;
 
 pub struct S3Host {
     bucket: Bucket,
+    /// The same bucket, for files that aren't uploaded with a public ACL
+    private_bucket: Bucket,
 }
 
 impl S3Host {
@@ -26,41 +36,397 @@ impl S3Host {
             Credentials::new(Some(access_token), Some(secret), None, None, None)?,
         )?;
 
+        let private_bucket = bucket.clone();
         bucket.add_header("x-amz-acl", "public-read");
 
-        Ok(S3Host { bucket })
+        Ok(S3Host {
+            bucket,
+            private_bucket,
+        })
+    }
+
+    /// Addresses the bucket as part of the path instead of the host name,
+    /// which servers like MinIO need unless they have a domain set up
+    pub fn with_path_style(mut self) -> Self {
+        self.bucket.set_path_style();
+        self.private_bucket.set_path_style();
+        self
+    }
+
+    /// The bucket to write a file with, which makes it public unless its
+    /// name is private
+    fn bucket_for(&self, file_name: &str) -> &Bucket {
+        if is_private(file_name) {
+            &self.private_bucket
+        } else {
+            &self.bucket
+        }
+    }
+
+    /// Sends a request, failing on error responses.  Returns the body, or
+    /// the ETag header if `etag` is set.
+    async fn send(
+        &self,
+        path: &str,
+        command: Command<'_>,
+        etag: bool,
+    ) -> Result<Vec<u8>, FileHostingError> {
+        let bucket = self.bucket_for(path.trim_start_matches('/'));
+        let (data, code) = Request::new(bucket, path, command)
+            .response_data_future(etag)
+            .await?;
+
+        if !(200..300).contains(&code) {
+            return Err(S3Error::from(
+                format!(
+                    "Request failed with code {}: {}",
+                    code,
+                    String::from_utf8_lossy(&data)
+                )
+                .as_str(),
+            )
+            .into());
+        }
+
+        Ok(data)
+    }
+
+    async fn upload_part(
+        &self,
+        file_name: &str,
+        upload_id: &str,
+        part_number: u32,
+        content: &[u8],
+    ) -> Result<Part, FileHostingError> {
+        // Parts are sent like whole objects, as rust-s3 doesn't sign the
+        // body of `UploadPart` commands
+        let etag = self
+            .send(
+                &format!(
+                    "/{}?partNumber={}&uploadId={}",
+                    file_name, part_number, upload_id
+                ),
+                Command::PutObject {
+                    content,
+                    content_type: "application/octet-stream",
+                },
+                true,
+            )
+            .await?;
+
+        Ok(Part {
+            part_number,
+            etag: String::from_utf8_lossy(&etag).into_owned(),
+        })
+    }
+
+    /// Uploads the rest of a stream as a multipart upload, starting with
+    /// `first_part`.  Returns the parts that were uploaded.
+    async fn upload_parts(
+        &self,
+        file_name: &str,
+        upload_id: &str,
+        first_part: Vec<u8>,
+        file_stream: &mut ByteStream<'_>,
+        hasher: &mut UploadHasher,
+    ) -> Result<Vec<Part>, FileHostingError> {
+        let mut parts = vec![
+            self.upload_part(file_name, upload_id, 1, &first_part)
+                .await?,
+        ];
+        let mut buffer = first_part;
+        buffer.clear();
+
+        loop {
+            let chunk = file_stream.next().await.transpose()?;
+
+            if let Some(chunk) = &chunk {
+                hasher.update(chunk)?;
+                buffer.extend_from_slice(chunk);
+            }
+
+            // Every part but the last has to be at least 5MiB
+            if buffer.len() >= CHUNK_SIZE || (chunk.is_none() && !buffer.is_empty()) {
+                let part_number = parts.len() as u32 + 1;
+                parts.push(
+                    self.upload_part(file_name, upload_id, part_number, &buffer)
+                        .await?,
+                );
+                buffer.clear();
+            }
+
+            if chunk.is_none() {
+                return Ok(parts);
+            }
+        }
     }
 }
 
-#[async_trait]
+/// Reads the upload ID from the response to `InitiateMultipartUpload`
+fn parse_upload_id(response: &[u8]) -> Option<String> {
+    let mut in_upload_id = false;
+
+    for event in xml::EventReader::new(response) {
+        match event.ok()? {
+            XmlEvent::StartElement { name, .. } => in_upload_id = name.local_name == "UploadId",
+            XmlEvent::Characters(id) if in_upload_id => return Some(id),
+            XmlEvent::EndElement { .. } => in_upload_id = false,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[async_trait(?Send)]
 impl FileHost for S3Host {
     async fn upload_file(
         &self,
         content_type: &str,
         file_name: &str,
-        file_bytes: Vec<u8>,
+        mut file_stream: ByteStream<'_>,
+        size_cap: u64,
     ) -> Result<UploadFileData, FileHostingError> {
-        let content_sha1 = sha1::Sha1::from(&file_bytes).hexdigest();
-        let content_sha512 = format!("{:x}", sha2::Sha512::digest(&file_bytes));
-
-        self.bucket
-            .put_object_with_content_type(
-                format!("/{}", file_name),
-                file_bytes.as_slice(),
-                content_type,
+        let mut hasher = UploadHasher::new(size_cap);
+
+        // One part is buffered at a time
+        let mut buffer = Vec::new();
+        while buffer.len() < CHUNK_SIZE {
+            match file_stream.next().await {
+                Some(chunk) => {
+                    let chunk = chunk?;
+                    hasher.update(&chunk)?;
+                    buffer.extend_from_slice(&chunk);
+                }
+                None => break,
+            }
+        }
+
+        // Files that fit in one part are uploaded as a whole
+        if buffer.len() < CHUNK_SIZE {
+            self.bucket_for(file_name)
+                .put_object_with_content_type(format!("/{}", file_name), &buffer, content_type)
+                .await?;
+
+            return Ok(hasher.finish(file_name, file_name, content_type));
+        }
+
+        // rust-s3 always starts multipart uploads with `text/plain` as the
+        // content type, which only matters for files opened in a browser
+        let response = self
+            .send(
+                &format!("/{}?uploads", file_name),
+                Command::InitiateMultipartUpload,
+                false,
             )
             .await?;
+        let upload_id = parse_upload_id(&response).ok_or_else(|| {
+            S3Error::from("The response to starting a multipart upload has no upload ID")
+        })?;
 
-        Ok(UploadFileData {
-            file_id: file_name.to_string(),
-            file_name: file_name.to_string(),
-            content_length: file_bytes.len() as u32,
-            content_sha512,
-            content_sha1,
-            content_md5: None,
-            content_type: content_type.to_string(),
-            upload_timestamp: chrono::Utc::now().timestamp_millis() as u64,
-        })
+        let parts = self
+            .upload_parts(file_name, &upload_id, buffer, &mut file_stream, &mut hasher)
+            .await;
+
+        let result = match parts {
+            Ok(parts) => {
+                self.send(
+                    &format!("/{}?uploadId={}", file_name, upload_id),
+                    Command::CompleteMultipartUpload {
+                        upload_id: &upload_id,
+                        data: CompleteMultipartUploadData { parts },
+                    },
+                    false,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            // Otherwise the uploaded parts are kept, and paid for, until the
+            // bucket's lifecycle rules remove them
+            let _ = self
+                .send(
+                    &format!("/{}?uploadId={}", file_name, upload_id),
+                    Command::AbortMultipartUpload {
+                        upload_id: &upload_id,
+                    },
+                    false,
+                )
+                .await;
+
+            return Err(e);
+        }
+
+        Ok(hasher.finish(file_name, file_name, content_type))
+    }
+
+    async fn download_file(
+        &self,
+        file_name: &str,
+    ) -> Result<ByteStream<'static>, FileHostingError> {
+        let path = format!("/{}", file_name);
+        let response = Request::new(&self.bucket, &path, Command::GetObject)
+            .response_future()
+            .await?;
+
+        if response.status().as_u16() == 404 {
+            return Err(FileHostingError::NotFound(file_name.to_string()));
+        }
+
+        if response.status().as_u16() != 200 {
+            return Err(S3Error::from(
+                format!(
+                    "Reading {} failed with code {}",
+                    file_name,
+                    response.status().as_u16()
+                )
+                .as_str(),
+            )
+            .into());
+        }
+
+        Ok(Box::pin(futures::stream::unfold(
+            Some(response),
+            |response| async move {
+                let mut response = response?;
+
+                match response.chunk().await {
+                    Ok(Some(chunk)) => Some((Ok(chunk), Some(response))),
+                    Ok(None) => None,
+                    Err(e) => Some((Err(e.into()), None)),
+                }
+            },
+        )))
+    }
+
+    fn presign_upload(
+        &self,
+        content_type: &str,
+        file_name: &str,
+        expires_in: u32,
+    ) -> Result<Option<PresignedUpload>, FileHostingError> {
+        // Presigned requests don't include the bucket's own headers, so the
+        // upload stays private until `move_file` copies it into place, once
+        // it's been checked
+        let headers = vec![("content-type".to_string(), content_type.to_string())];
+
+        let mut header_map = reqwest::header::HeaderMap::new();
+        for (name, value) in &headers {
+            header_map.insert(
+                reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| FileHostingError::InvalidFilename)?,
+                reqwest::header::HeaderValue::from_str(value)
+                    .map_err(|_| FileHostingError::InvalidFilename)?,
+            );
+        }
+
+        let url =
+            self.bucket
+                .presign_put(format!("/{}", file_name), expires_in, Some(header_map))?;
+
+        Ok(Some(PresignedUpload { url, headers }))
+    }
+
+    async fn inspect_file(
+        &self,
+        content_type: &str,
+        file_name: &str,
+        size_cap: u64,
+    ) -> Result<UploadFileData, FileHostingError> {
+        // Files that are too large are turned down without reading them
+        let (head, code) = self.bucket.head_object(format!("/{}", file_name)).await?;
+
+        if code == 404 {
+            return Err(FileHostingError::NotFound(file_name.to_string()));
+        }
+
+        if code != 200 {
+            return Err(S3Error::from(
+                format!("Reading {} failed with code {}", file_name, code).as_str(),
+            )
+            .into());
+        }
+
+        if head.content_length.unwrap_or(0) as u64 >= size_cap {
+            return Err(FileHostingError::FileTooLarge(size_cap));
+        }
+
+        let file_stream = self.download_file(file_name).await?;
+
+        hash_file(content_type, file_name, file_stream, size_cap).await
+    }
+
+    async fn list_files(&self, prefix: &str) -> Result<Vec<StoredFile>, FileHostingError> {
+        let pages = self.bucket.list(prefix.to_string(), None).await?;
+
+        pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| {
+                let modified = chrono::DateTime::parse_from_rfc3339(&object.last_modified)
+                    .map_err(|_| {
+                        S3Error::from(
+                            format!("Invalid modification time for {}", object.key).as_str(),
+                        )
+                    })?;
+
+                Ok(StoredFile {
+                    file_name: object.key,
+                    modified: modified.into(),
+                })
+            })
+            .collect()
+    }
+
+    async fn move_file(&self, from: &str, to: &str) -> Result<(), FileHostingError> {
+        // rust-s3 has no command for copying objects, but a `PUT` with a
+        // copy source header is what S3 expects for one
+        let mut bucket = self.bucket_for(to).clone();
+        bucket.add_header(
+            "x-amz-copy-source",
+            &format!(
+                "/{}/{}",
+                bucket.name(),
+                s3::signing::uri_encode(from, false)
+            ),
+        );
+
+        let (data, code) = Request::new(
+            &bucket,
+            &format!("/{}", to),
+            Command::PutObject {
+                content: &[],
+                content_type: "application/octet-stream",
+            },
+        )
+        .response_data_future(false)
+        .await?;
+
+        if code == 404 {
+            return Err(FileHostingError::NotFound(from.to_string()));
+        }
+
+        // Copies can fail after the response started, with the error in
+        // the body of a 200 response
+        if code != 200 || String::from_utf8_lossy(&data).contains("<Error>") {
+            return Err(S3Error::from(
+                format!(
+                    "Copying {} to {} failed with code {}: {}",
+                    from,
+                    to,
+                    code,
+                    String::from_utf8_lossy(&data)
+                )
+                .as_str(),
+            )
+            .into());
+        }
+
+        self.bucket.delete_object(format!("/{}", from)).await?;
+
+        Ok(())
     }
 
     async fn delete_file_version(
@@ -82,8 +448,9 @@ mod tests {
     use crate::file_hosting::s3_host::S3Host;
     use crate::file_hosting::FileHost;
 
-    #[actix_rt::test]
-    async fn test_file_management() {
+    // Runs against the bucket configured in the environment, like the
+    // MinIO container from docker-compose
+    fn s3_host() -> S3Host {
         let s3_host = S3Host::new(
             &*dotenv::var("S3_BUCKET_NAME").unwrap(),
             &*dotenv::var("S3_REGION").unwrap(),
@@ -93,15 +460,66 @@ mod tests {
         )
         .unwrap();
 
+        if dotenv::var("S3_PATH_STYLE").ok().as_deref() == Some("true") {
+            s3_host.with_path_style()
+        } else {
+            s3_host
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_file_management() {
+        let s3_host = s3_host();
+
         s3_host
             .upload_file(
                 "text/plain",
                 "test.txt",
-                "test file".to_string().into_bytes(),
+                Box::pin(futures::stream::once(async { Ok("test file".into()) })),
+                1024,
             )
             .await
             .unwrap();
 
         s3_host.delete_file_version("", "test.txt").await.unwrap();
     }
+
+    #[actix_rt::test]
+    async fn test_presigned_upload() {
+        let s3_host = s3_host();
+
+        let presigned = s3_host
+            .presign_upload("text/plain", "presigned.txt", 60)
+            .unwrap()
+            .unwrap();
+
+        assert!(presigned
+            .headers
+            .iter()
+            .all(|(name, _)| name != "x-amz-acl"));
+
+        let mut request = reqwest::Client::new().put(&presigned.url);
+        for (name, value) in &presigned.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let response = request.body("test file").send().await.unwrap();
+        assert!(response.status().is_success());
+
+        let data = s3_host
+            .inspect_file("text/plain", "presigned.txt", 1024)
+            .await
+            .unwrap();
+        assert_eq!(data.content_length, 9);
+        assert_eq!(data.content_sha1, sha1::Sha1::from("test file").hexdigest());
+
+        assert!(s3_host
+            .inspect_file("text/plain", "presigned.txt", 9)
+            .await
+            .is_err());
+
+        s3_host
+            .delete_file_version("", "presigned.txt")
+            .await
+            .unwrap();
+    }
 }
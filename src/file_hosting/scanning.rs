@@ -0,0 +1,301 @@
+//! Version files are scanned for malware once their blob is stored.  Blobs
+//! start out pending and private, and are only published once a scanner
+//! found them clean.  Flagged blobs stay private, quarantine the versions of
+//! the files referring to them, and a report is raised for moderators to
+//! look at.
+
+use super::blobs::{self, blob_path, pending_blob_path};
+use super::{ByteStream, FileHost, FileHostingError};
+use crate::database::models::blob_item::FileBlob;
+use crate::database::models::cache::{self, Invalidation};
+use crate::database::models::categories::ReportType;
+use crate::database::models::report_item::Report;
+use crate::database::models::{generate_report_id, DatabaseError, Version, VersionId};
+use crate::models::mods::ScanStatus;
+use async_trait::async_trait;
+use futures::StreamExt;
+use log::{info, warn};
+use sqlx::PgPool;
+use std::path::PathBuf;
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// How long a scan can take before it's given up on and tried again later
+const SCAN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+#[derive(Error, Debug)]
+pub enum ScanError {
+    #[error("The scanner couldn't be reached: {0}")]
+    Connection(#[from] std::io::Error),
+    #[error("The scanner couldn't scan the file: {0}")]
+    Scanner(String),
+    #[error("Error while reading the file to scan: {0}")]
+    FileHosting(#[from] FileHostingError),
+    #[error("Database error while scanning files: {0}")]
+    Database(#[from] DatabaseError),
+}
+
+impl From<sqlx::Error> for ScanError {
+    fn from(e: sqlx::Error) -> Self {
+        ScanError::Database(e.into())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScanResult {
+    Clean,
+    /// The file has malware in it, with the name of what was found
+    Infected(String),
+}
+
+// File host streams can't be sent between threads, so neither can scans
+#[async_trait(?Send)]
+pub trait Scanner {
+    /// Scans a file as it arrives
+    async fn scan(&self, file_stream: ByteStream<'_>) -> Result<ScanResult, ScanError>;
+}
+
+/// Scans files with a ClamAV daemon listening on a local socket.  Files are
+/// streamed to it, so its `StreamMaxLength` has to be at least as large as
+/// the largest file that can be uploaded.
+pub struct ClamAvScanner {
+    socket: PathBuf,
+}
+
+impl ClamAvScanner {
+    pub fn new(socket: PathBuf) -> Self {
+        ClamAvScanner { socket }
+    }
+
+    /// The scanner for the socket in `CLAMD_SOCKET`, or `None` if it isn't
+    /// set
+    pub fn from_env() -> Option<Self> {
+        let socket = dotenv::var("CLAMD_SOCKET").ok().filter(|x| !x.is_empty())?;
+
+        Some(ClamAvScanner::new(socket.into()))
+    }
+}
+
+#[async_trait(?Send)]
+impl Scanner for ClamAvScanner {
+    async fn scan(&self, mut file_stream: ByteStream<'_>) -> Result<ScanResult, ScanError> {
+        let mut socket = actix_rt::net::UnixStream::connect(&self.socket).await?;
+
+        // Every chunk is sent with its length in front, and an empty chunk
+        // ends the file
+        socket.write_all(b"zINSTREAM\0").await?;
+        while let Some(chunk) = file_stream.next().await {
+            let chunk = chunk?;
+            if chunk.is_empty() {
+                continue;
+            }
+
+            socket
+                .write_all(&(chunk.len() as u32).to_be_bytes())
+                .await?;
+            socket.write_all(&chunk).await?;
+        }
+        socket.write_all(&0u32.to_be_bytes()).await?;
+
+        let mut reply = Vec::new();
+        socket.read_to_end(&mut reply).await?;
+
+        parse_clamd_reply(&reply)
+    }
+}
+
+/// Reads replies like `stream: OK` or `stream: Win.Test.EICAR_HDB-1 FOUND`
+fn parse_clamd_reply(reply: &[u8]) -> Result<ScanResult, ScanError> {
+    let reply = String::from_utf8_lossy(reply);
+    let reply = reply.trim_end_matches(|c: char| c == '\0' || c.is_whitespace());
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+
+    if result == "OK" {
+        Ok(ScanResult::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(ScanResult::Infected(signature.to_string()))
+    } else {
+        Err(ScanError::Scanner(reply.to_string()))
+    }
+}
+
+/// Opens a pending blob to scan it.  Pending blobs are published already if
+/// recording their scan failed after publishing them, or if they were stored
+/// before blobs were kept private until they're scanned.
+async fn open_pending(
+    file_host: &dyn FileHost,
+    sha512: &str,
+) -> Result<ByteStream<'static>, FileHostingError> {
+    match file_host.download_file(&pending_blob_path(sha512)).await {
+        Err(FileHostingError::NotFound(_)) => file_host.download_file(&blob_path(sha512)).await,
+        result => result,
+    }
+}
+
+/// Scans blobs that are pending, oldest first.  Without a scanner, pending
+/// blobs are taken to be clean.  Returns the number of blobs scanned.
+pub async fn scan_pending(
+    pool: &PgPool,
+    file_host: &dyn FileHost,
+    scanner: Option<&dyn Scanner>,
+) -> Result<usize, ScanError> {
+    const BATCH_SIZE: i64 = 20;
+
+    let pending = FileBlob::get_pending_scan(BATCH_SIZE, pool).await?;
+    let mut count = 0;
+
+    for sha512 in &pending {
+        let result = match scanner {
+            Some(scanner) => {
+                let file_stream = open_pending(file_host, sha512).await?;
+
+                match actix_rt::time::timeout(SCAN_TIMEOUT, scanner.scan(file_stream)).await {
+                    Ok(Ok(result)) => result,
+                    // The scanner is down, so the rest of the batch would
+                    // fail as well
+                    Ok(Err(e @ ScanError::Connection(_))) => return Err(e),
+                    // Blobs that can't be scanned stay pending and are tried
+                    // again next time
+                    Ok(Err(e)) => {
+                        warn!("Scanning blob {} failed: {:?}", sha512, e);
+                        continue;
+                    }
+                    Err(_) => {
+                        warn!("Scanning blob {} timed out", sha512);
+                        continue;
+                    }
+                }
+            }
+            None => ScanResult::Clean,
+        };
+
+        // Blobs are only marked as scanned once they're where they belong,
+        // so they're scanned again if moving them fails
+        match result {
+            ScanResult::Clean => {
+                blobs::publish(file_host, sha512).await?;
+
+                if FileBlob::finish_scan(sha512, ScanStatus::Clean, None, pool).await? {
+                    // The files were left out of their cached versions until now
+                    for (version_id, _) in FileBlob::get_files(sha512, pool).await? {
+                        cache::invalidate(Invalidation::Version(version_id.0), pool).await;
+                    }
+                }
+            }
+            ScanResult::Infected(signature) => {
+                blobs::unpublish(file_host, sha512).await?;
+                flag_blob(pool, sha512, &signature).await?
+            }
+        }
+
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Marks a blob as infected, quarantining the versions of its files and
+/// reporting them to moderators
+async fn flag_blob(pool: &PgPool, sha512: &str, signature: &str) -> Result<(), ScanError> {
+    let mut transaction = pool.begin().await?;
+
+    // Scans can overlap, and only the first one to finish raises reports
+    if !FileBlob::finish_scan(
+        sha512,
+        ScanStatus::Infected,
+        Some(signature),
+        &mut transaction,
+    )
+    .await?
+    {
+        return Ok(());
+    }
+
+    let report_type = ReportType::get_id("malicious", &mut transaction)
+        .await?
+        .ok_or_else(|| DatabaseError::Other("The malicious report type is missing".to_string()))?;
+
+    let mut versions: Vec<(VersionId, Vec<String>)> = Vec::new();
+    for (version_id, file_name) in FileBlob::get_files(sha512, &mut transaction).await? {
+        match versions.iter_mut().find(|(id, _)| id.0 == version_id.0) {
+            Some((_, file_names)) => file_names.push(file_name),
+            None => versions.push((version_id, vec![file_name])),
+        }
+    }
+
+    for (version_id, file_names) in &versions {
+        warn!(
+            "Quarantining version {} for {} in {}",
+            version_id.0,
+            signature,
+            file_names.join(", ")
+        );
+
+        Version::set_quarantined(*version_id, true, &mut transaction).await?;
+
+        Report {
+            id: generate_report_id(&mut transaction).await?,
+            report_type_id: report_type,
+            mod_id: None,
+            version_id: Some(*version_id),
+            user_id: None,
+            body: format!(
+                "The malware scanner found {} in {}. The version was quarantined, \
+                 so its files can't be downloaded until the quarantine is lifted.",
+                signature,
+                file_names.join(", ")
+            ),
+            reporter: None,
+            created: chrono::Utc::now(),
+            snapshot: None,
+        }
+        .insert(&mut transaction)
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    for (version_id, _) in &versions {
+        cache::invalidate(Invalidation::Version(version_id.0), pool).await;
+    }
+
+    info!("Flagged blob {} for {}", sha512, signature);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamd_replies_are_parsed() {
+        assert_eq!(
+            parse_clamd_reply(b"stream: OK\0").unwrap(),
+            ScanResult::Clean
+        );
+        assert_eq!(
+            parse_clamd_reply(b"stream: OK\n").unwrap(),
+            ScanResult::Clean
+        );
+        assert_eq!(
+            parse_clamd_reply(b"stream: Win.Test.EICAR_HDB-1 FOUND\0").unwrap(),
+            ScanResult::Infected("Win.Test.EICAR_HDB-1".to_string())
+        );
+    }
+
+    #[test]
+    fn clamd_errors_are_not_clean() {
+        for reply in &[
+            &b"INSTREAM size limit exceeded. ERROR\0"[..],
+            b"stream: Can't allocate memory ERROR\0",
+            b"",
+            b"stream: OKAY\0",
+        ] {
+            assert!(matches!(
+                parse_clamd_reply(reply),
+                Err(ScanError::Scanner(_))
+            ));
+        }
+    }
+}
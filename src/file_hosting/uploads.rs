@@ -0,0 +1,108 @@
+//! Resumable uploads, whose bytes are stored through the file host in
+//! pieces as they arrive and put back together once the upload is used.
+
+use super::{ByteStream, FileHost, FileHostingError};
+use crate::database::models::upload_item::{UploadChunk, UploadSession};
+use actix_web::web::Bytes;
+use futures::{StreamExt, TryStreamExt};
+use log::warn;
+use sqlx::PgPool;
+
+/// The largest piece an upload is stored in.  Pieces are read back into
+/// memory as a whole when the upload is used.
+pub const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// How long an upload is kept after the last bytes of it arrived
+pub fn abandoned_after() -> chrono::Duration {
+    chrono::Duration::hours(24)
+}
+
+/// Splits a request body into pieces of at most `CHUNK_SIZE` bytes, so each
+/// piece counts towards the upload as soon as it was stored
+pub struct Pieces<'a> {
+    body: ByteStream<'a>,
+    leftover: Option<Bytes>,
+}
+
+impl<'a> Pieces<'a> {
+    pub fn new(body: ByteStream<'a>) -> Self {
+        Pieces {
+            body,
+            leftover: None,
+        }
+    }
+
+    /// The next piece of the body, or `None` once all of it was read
+    pub async fn next(&mut self) -> Result<Option<ByteStream<'_>>, FileHostingError> {
+        // Bodies that end right at the end of a piece don't start an empty one
+        while self.leftover.is_none() {
+            match self.body.next().await {
+                Some(chunk) => {
+                    let chunk = chunk?;
+
+                    if !chunk.is_empty() {
+                        self.leftover = Some(chunk);
+                    }
+                }
+                None => return Ok(None),
+            }
+        }
+
+        Ok(Some(Box::pin(futures::stream::unfold(
+            (self, CHUNK_SIZE),
+            |(pieces, remaining)| async move {
+                if remaining == 0 {
+                    return None;
+                }
+
+                let mut chunk = match pieces.leftover.take() {
+                    Some(chunk) => chunk,
+                    None => match pieces.body.next().await {
+                        Some(Ok(chunk)) => chunk,
+                        Some(Err(e)) => return Some((Err(e), (pieces, 0))),
+                        None => return None,
+                    },
+                };
+
+                if chunk.len() > remaining {
+                    pieces.leftover = Some(chunk.split_off(remaining));
+                }
+
+                let remaining = remaining - chunk.len();
+                Some((Ok(chunk), (pieces, remaining)))
+            },
+        ))))
+    }
+}
+
+/// The bytes of an upload, read back from its pieces in order
+pub fn assemble(file_host: &dyn FileHost, chunks: Vec<UploadChunk>) -> ByteStream<'_> {
+    Box::pin(
+        futures::stream::iter(chunks)
+            .then(move |chunk| async move { file_host.download_file(&chunk.file_name).await })
+            .try_flatten(),
+    )
+}
+
+/// Deletes the pieces of uploads, logging the ones that couldn't be deleted
+pub async fn delete_chunks(file_host: &dyn FileHost, file_names: &[String]) {
+    for file_name in file_names {
+        if let Err(e) = file_host.delete_file_version("", file_name).await {
+            warn!("Deleting upload chunk {} failed: {:?}", file_name, e);
+        }
+    }
+}
+
+/// Removes uploads that were used or abandoned, along with their pieces.
+/// Returns the number of pieces deleted.
+pub async fn remove_abandoned(
+    pool: &PgPool,
+    file_host: &dyn FileHost,
+) -> Result<usize, sqlx::Error> {
+    let chunks =
+        UploadSession::remove_abandoned(chrono::Utc::now() - abandoned_after(), pool).await?;
+
+    delete_chunks(file_host, &chunks).await;
+
+    Ok(chunks.len())
+}
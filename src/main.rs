@@ -15,9 +15,11 @@ mod auth;
 mod database;
 mod file_hosting;
 mod models;
+mod notifications;
 mod routes;
 mod scheduler;
 mod search;
+mod sitemap;
 
 #[derive(Debug, Options)]
 struct Config {
@@ -31,6 +33,22 @@ struct Config {
     #[options(no_short, help = "Reset the documents in the indices")]
     reset_indices: bool,
 
+    #[options(
+        no_short,
+        help = "Report stored files nothing refers to and references to missing files, then exit"
+    )]
+    check_storage: bool,
+    #[options(
+        no_short,
+        help = "Delete stored files nothing refers to and references to missing files, then exit"
+    )]
+    clean_storage: bool,
+    #[options(
+        no_short,
+        help = "Copy the stored files to the storage set up by the MIGRATE_ variables and point their URLs at MIGRATE_CDN_URL, then exit"
+    )]
+    migrate_storage: bool,
+
     #[options(
         no_short,
         help = "Allow missing environment variables on startup. This is a bad idea, but it may work in some cases."
@@ -103,24 +121,54 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Database connection failed");
 
-    let storage_backend = dotenv::var("STORAGE_BACKEND").unwrap_or_else(|_| "local".to_string());
+    // Files stored on this server are served by it as well
+    let (file_host, local_host) = file_host_from_env("");
 
-    let file_host: Arc<dyn file_hosting::FileHost + Send + Sync> = if storage_backend == "s3" {
-        Arc::new(
-            S3Host::new(
-                &*dotenv::var("S3_BUCKET_NAME").unwrap(),
-                &*dotenv::var("S3_REGION").unwrap(),
-                &*dotenv::var("S3_URL").unwrap(),
-                &*dotenv::var("S3_ACCESS_TOKEN").unwrap(),
-                &*dotenv::var("S3_SECRET").unwrap(),
-            )
-            .unwrap(),
+    if config.check_storage || config.clean_storage {
+        let dry_run = !config.clean_storage;
+        let cdn_url = dotenv::var("CDN_URL").unwrap();
+
+        info!("Checking storage");
+        let report = file_hosting::gc::collect_garbage(&pool, &*file_host, &cdn_url, dry_run)
+            .await
+            .unwrap();
+        report.log(dry_run);
+        return Ok(());
+    }
+
+    if config.migrate_storage {
+        let (target_host, _) = file_host_from_env("MIGRATE_");
+        let cdn_url = dotenv::var("CDN_URL").unwrap();
+        let target_cdn_url = dotenv::var("MIGRATE_CDN_URL").unwrap();
+
+        // Rows would be pointed at the files they already point at, and
+        // the files copied onto themselves
+        if cdn_url.trim_end_matches('/') == target_cdn_url.trim_end_matches('/') {
+            return Err(std::io::Error::other(
+                "MIGRATE_CDN_URL has to differ from CDN_URL",
+            ));
+        }
+
+        info!("Migrating storage to {}", target_cdn_url);
+        let report = file_hosting::migration::migrate_storage(
+            &pool,
+            file_hosting::migration::Storage {
+                file_host: &*file_host,
+                cdn_url: &cdn_url,
+            },
+            file_hosting::migration::Storage {
+                file_host: &*target_host,
+                cdn_url: &target_cdn_url,
+            },
         )
-    } else if storage_backend == "local" {
-        Arc::new(file_hosting::MockHost::new())
-    } else {
-        panic!("Invalid storage backend specified. Aborting startup!")
-    };
+        .await
+        .unwrap();
+        info!(
+            "Migrated {} files, {} couldn't be copied",
+            report.migrated, report.failed
+        );
+        return Ok(());
+    }
 
     let mut scheduler = scheduler::Scheduler::new();
 
@@ -203,6 +251,251 @@ async fn main() -> std::io::Result<()> {
         }
     });
 
+    // The age in seconds after which notifications that have been read are
+    // deleted.  Defaults to 30 days if unset.
+    let read_notification_max_age = chrono::Duration::seconds(
+        dotenv::var("READ_NOTIFICATION_MAX_AGE")
+            .ok()
+            .map(|i| i.parse().unwrap())
+            .unwrap_or(30 * 24 * 60 * 60),
+    );
+
+    let pool_ref = pool.clone();
+    scheduler.run(std::time::Duration::from_secs(60 * 60), move || {
+        let pool_ref = pool_ref.clone();
+        info!("Pruning old read notifications");
+
+        async move {
+            let before = chrono::Utc::now() - read_notification_max_age;
+
+            let result = async {
+                let mut transaction = pool_ref.begin().await?;
+                let count = database::models::notification_item::Notification::remove_read_before(
+                    before,
+                    &mut transaction,
+                )
+                .await?;
+                transaction.commit().await?;
+
+                Ok::<u64, sqlx::Error>(count)
+            }
+            .await;
+
+            match result {
+                Ok(count) => info!("Pruned {} old read notifications", count),
+                Err(e) => warn!("Pruning old read notifications failed: {:?}", e),
+            }
+        }
+    });
+
+    match notifications::email::Mailer::from_env() {
+        Ok(Some(mailer)) => {
+            let mailer = Arc::new(mailer);
+
+            let pool_ref = pool.clone();
+            let mailer_ref = mailer.clone();
+            scheduler.run(std::time::Duration::from_secs(60), move || {
+                let pool_ref = pool_ref.clone();
+                let mailer_ref = mailer_ref.clone();
+
+                async move {
+                    let result =
+                        notifications::email::send_queued_emails(&pool_ref, &mailer_ref).await;
+
+                    match result {
+                        Ok(0) => {}
+                        Ok(count) => info!("Sent {} notification emails", count),
+                        Err(e) => warn!("Sending notification emails failed: {:?}", e),
+                    }
+                }
+            });
+
+            // Digests are sent in batches to users whose last one is over a week old
+            let pool_ref = pool.clone();
+            let mailer_ref = mailer.clone();
+            scheduler.run(std::time::Duration::from_secs(15 * 60), move || {
+                let pool_ref = pool_ref.clone();
+                let mailer_ref = mailer_ref.clone();
+
+                async move {
+                    let result = notifications::email::send_digests(&pool_ref, &mailer_ref).await;
+
+                    match result {
+                        Ok(0) => {}
+                        Ok(count) => info!("Sent {} weekly digests", count),
+                        Err(e) => warn!("Sending weekly digests failed: {:?}", e),
+                    }
+                }
+            });
+        }
+        Ok(None) => info!("SMTP_HOST is not set, email delivery is disabled"),
+        Err(e) => warn!(
+            "Email delivery is disabled, the mailer could not be created: {:?}",
+            e
+        ),
+    }
+
+    let webhook_client = Arc::new(notifications::webhooks::client());
+
+    let pool_ref = pool.clone();
+    let client_ref = webhook_client.clone();
+    scheduler.run(std::time::Duration::from_secs(30), move || {
+        let pool_ref = pool_ref.clone();
+        let client_ref = client_ref.clone();
+
+        async move {
+            let result = notifications::webhooks::deliver_pending(&pool_ref, &client_ref).await;
+
+            match result {
+                Ok(0) => {}
+                Ok(count) => info!("Delivered {} webhook events", count),
+                Err(e) => warn!("Delivering webhook events failed: {:?}", e),
+            }
+        }
+    });
+
+    let pool_ref = pool.clone();
+    let client_ref = webhook_client.clone();
+    scheduler.run(std::time::Duration::from_secs(30), move || {
+        let pool_ref = pool_ref.clone();
+        let client_ref = client_ref.clone();
+
+        async move {
+            let result =
+                notifications::discord::send_queued_announcements(&pool_ref, &client_ref).await;
+
+            match result {
+                Ok(0) => {}
+                Ok(count) => info!("Posted {} announcements", count),
+                Err(e) => warn!("Posting announcements failed: {:?}", e),
+            }
+        }
+    });
+
+    // Only needed when several instances share the database
+    if dotenv::var("CACHE_INVALIDATION_NOTIFY")
+        .ok()
+        .and_then(|x| x.parse::<bool>().ok())
+        .unwrap_or(false)
+    {
+        actix_rt::spawn(database::models::cache::listen(pool.clone()));
+    }
+
+    let notification_streams = Arc::new(notifications::stream::NotificationStreams::new());
+
+    actix_rt::spawn(notifications::stream::listen(
+        pool.clone(),
+        notification_streams.clone(),
+    ));
+
+    // Keeps idle notification streams from being closed by proxies
+    let streams_ref = notification_streams.clone();
+    scheduler.run(std::time::Duration::from_secs(30), move || {
+        let streams_ref = streams_ref.clone();
+
+        async move {
+            streams_ref.keep_alive();
+        }
+    });
+
+    let pool_ref = pool.clone();
+    let file_host_ref = file_host.clone();
+    scheduler.run_local(std::time::Duration::from_secs(60 * 60), move || {
+        let pool_ref = pool_ref.clone();
+        let file_host_ref = file_host_ref.clone();
+
+        async move {
+            info!("Removing abandoned uploads");
+            match file_hosting::uploads::remove_abandoned(&pool_ref, &*file_host_ref).await {
+                Ok(count) => info!("Removed {} pieces of abandoned uploads", count),
+                Err(e) => warn!("Removing abandoned uploads failed: {:?}", e),
+            }
+        }
+    });
+
+    let pool_ref = pool.clone();
+    let file_host_ref = file_host.clone();
+    scheduler.run_local(std::time::Duration::from_secs(15 * 60), move || {
+        let pool_ref = pool_ref.clone();
+        let file_host_ref = file_host_ref.clone();
+
+        async move {
+            match file_hosting::blobs::remove_unreferenced(&pool_ref, &*file_host_ref).await {
+                Ok(0) => {}
+                Ok(count) => info!("Deleted {} unreferenced file blobs", count),
+                Err(e) => warn!("Deleting unreferenced file blobs failed: {:?}", e),
+            }
+        }
+    });
+
+    // Without a scanner, new files can be downloaded as soon as this job
+    // picks them up
+    let scanner = file_hosting::scanning::ClamAvScanner::from_env().map(Arc::new);
+    if scanner.is_none() {
+        warn!("CLAMD_SOCKET is not set, uploaded files are published without a malware scan");
+    }
+
+    let pool_ref = pool.clone();
+    let file_host_ref = file_host.clone();
+    scheduler.run_local(std::time::Duration::from_secs(30), move || {
+        let pool_ref = pool_ref.clone();
+        let file_host_ref = file_host_ref.clone();
+        let scanner_ref = scanner.clone();
+
+        async move {
+            let scanner = scanner_ref
+                .as_deref()
+                .map(|x| x as &dyn file_hosting::scanning::Scanner);
+            match file_hosting::scanning::scan_pending(&pool_ref, &*file_host_ref, scanner).await {
+                Ok(0) => {}
+                Ok(count) => info!("Scanned {} uploaded files", count),
+                Err(e) => warn!("Scanning uploaded files failed: {:?}", e),
+            }
+        }
+    });
+
+    // Files are only deleted if STORAGE_GC_DELETE is set, otherwise what
+    // would be deleted is logged
+    let gc_dry_run = dotenv::var("STORAGE_GC_DELETE").ok().as_deref() != Some("true");
+    let cdn_url = dotenv::var("CDN_URL").unwrap_or_default();
+    let pool_ref = pool.clone();
+    let file_host_ref = file_host.clone();
+    scheduler.run_local(std::time::Duration::from_secs(24 * 60 * 60), move || {
+        let pool_ref = pool_ref.clone();
+        let file_host_ref = file_host_ref.clone();
+        let cdn_url = cdn_url.clone();
+
+        async move {
+            info!("Checking storage");
+            let result =
+                file_hosting::gc::collect_garbage(&pool_ref, &*file_host_ref, &cdn_url, gc_dry_run)
+                    .await;
+
+            match result {
+                Ok(report) if report.is_empty() => {}
+                Ok(report) => report.log(gc_dry_run),
+                Err(e) => warn!("Checking storage failed: {:?}", e),
+            }
+        }
+    });
+
+    let sitemaps = Arc::new(sitemap::Sitemaps::new());
+
+    let pool_ref = pool.clone();
+    let sitemaps_ref = sitemaps.clone();
+    scheduler.run(std::time::Duration::from_secs(60 * 60), move || {
+        let pool_ref = pool_ref.clone();
+        let sitemaps_ref = sitemaps_ref.clone();
+
+        async move {
+            info!("Generating sitemap");
+            match sitemaps_ref.refresh(&pool_ref).await {
+                Ok(pages) => info!("Done generating sitemap with {} pages", pages),
+                Err(e) => warn!("Generating sitemap failed: {:?}", e),
+            }
+        }
+    });
+
     let indexing_queue = Arc::new(search::indexing::queue::CreationQueue::new());
 
     let queue_ref = indexing_queue.clone();
@@ -244,6 +537,13 @@ async fn main() -> std::io::Result<()> {
                     .allowed_methods(vec!["GET", "POST", "DELETE", "PATCH", "PUT"])
                     .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
                     .allowed_header(http::header::CONTENT_TYPE)
+                    .allowed_header("Upload-Offset")
+                    .expose_headers(vec![
+                        "X-Unread-Count",
+                        "Location",
+                        "Upload-Offset",
+                        "Upload-Length",
+                    ])
                     .allow_any_origin()
                     .max_age(3600),
             )
@@ -282,9 +582,20 @@ async fn main() -> std::io::Result<()> {
             .data(pool.clone())
             .data(file_host.clone())
             .data(indexing_queue.clone())
+            .data(notification_streams.clone())
+            .data(sitemaps.clone())
             .data(search_config.clone())
             .data(ip_salt.clone())
             .service(routes::index_get)
+            .configure(routes::feeds_config)
+            .configure(routes::sitemap_config)
+            .configure(routes::badges_config)
+            .configure(|cfg| {
+                if let Some(local_host) = &local_host {
+                    cfg.data(local_host.clone());
+                    routes::files_config(cfg);
+                }
+            })
             .service(
                 web::scope("/api/v1/")
                     .configure(routes::auth_config)
@@ -294,8 +605,14 @@ async fn main() -> std::io::Result<()> {
                     .configure(routes::teams_config)
                     .configure(routes::users_config)
                     .configure(routes::moderation_config)
+                    .configure(routes::admin_config)
                     .configure(routes::reports_config)
-                    .configure(routes::notifications_config),
+                    .configure(routes::notifications_config)
+                    .configure(routes::webhooks_config)
+                    .configure(routes::uploads_config)
+                    .configure(routes::announcements_config)
+                    .configure(routes::embeds_config)
+                    .configure(routes::openapi_config),
             )
             .default_service(web::get().to(routes::not_found))
     })
@@ -304,6 +621,50 @@ async fn main() -> std::io::Result<()> {
     .await
 }
 
+/// The file host set up by the storage variables whose names start with
+/// `prefix`.  Hosts that store files locally are returned a second time, since
+/// the server serves their files.
+fn file_host_from_env(
+    prefix: &str,
+) -> (
+    Arc<dyn file_hosting::FileHost + Send + Sync>,
+    Option<Arc<file_hosting::LocalHost>>,
+) {
+    let var = |name: &str| dotenv::var(format!("{}{}", prefix, name));
+
+    let storage_backend = var("STORAGE_BACKEND").unwrap_or_else(|_| "local".to_string());
+
+    if storage_backend == "s3" {
+        let s3_host = S3Host::new(
+            &var("S3_BUCKET_NAME").unwrap(),
+            &var("S3_REGION").unwrap(),
+            &var("S3_URL").unwrap(),
+            &var("S3_ACCESS_TOKEN").unwrap(),
+            &var("S3_SECRET").unwrap(),
+        )
+        .unwrap();
+
+        if var("S3_PATH_STYLE").ok().as_deref() == Some("true") {
+            (Arc::new(s3_host.with_path_style()), None)
+        } else {
+            (Arc::new(s3_host), None)
+        }
+    } else if storage_backend == "local" {
+        let local_host = Arc::new(
+            file_hosting::LocalHost::new(
+                var("LOCAL_FILE_PATH")
+                    .or_else(|_| var("MOCK_FILE_PATH"))
+                    .unwrap(),
+            )
+            .expect("The directory files are stored in couldn't be opened"),
+        );
+
+        (local_host.clone(), Some(local_host))
+    } else {
+        panic!("Invalid storage backend specified. Aborting startup!")
+    }
+}
+
 // This is so that env vars not used immediately don't panic at runtime
 fn check_env_vars() -> bool {
     let mut failed = false;
@@ -351,7 +712,11 @@ fn check_env_vars() -> bool {
         failed |= check_var::<String>("S3_REGION");
         failed |= check_var::<String>("S3_BUCKET_NAME");
     } else if storage_backend.as_deref() == Some("local") {
-        failed |= check_var::<String>("MOCK_FILE_PATH");
+        if dotenv::var("LOCAL_FILE_PATH").is_err() && dotenv::var("MOCK_FILE_PATH").is_ok() {
+            warn!("Variable `MOCK_FILE_PATH` is deprecated, rename it to `LOCAL_FILE_PATH`");
+        } else {
+            failed |= check_var::<String>("LOCAL_FILE_PATH");
+        }
     } else if let Some(backend) = storage_backend {
         warn!("Variable `STORAGE_BACKEND` contains an invalid value: {}. Expected \"backblaze\", \"s3\", or \"local\".", backend);
         failed |= true;
@@ -364,5 +729,18 @@ fn check_env_vars() -> bool {
     failed |= check_var::<String>("DISCORD_CLIENT_ID");
     failed |= check_var::<String>("DISCORD_CLIENT_SECRET");
 
+    failed |= check_var::<String>("SITE_URL");
+    failed |= check_var::<String>("API_URL");
+
+    if notifications::email::is_enabled() {
+        failed |= check_var::<String>("SMTP_FROM");
+        failed |= check_var::<String>("UNSUBSCRIBE_SECRET");
+
+        if let Err(e) = notifications::email::unsubscribe_secret() {
+            warn!("Variable `UNSUBSCRIBE_SECRET` is not usable: {}", e);
+            failed |= true;
+        }
+    }
+
     failed
 }
@@ -0,0 +1,20 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A Discord channel that newly approved mods and releases are announced in
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AnnouncementChannel {
+    pub id: i32,
+    pub name: String,
+    /// The Discord webhook URL messages are posted to
+    pub url: String,
+    /// Only mods in one of these categories are announced. Empty announces every mod.
+    pub categories: Vec<String>,
+    /// Whether NSFW mods are announced in this channel
+    pub allow_nsfw: bool,
+    pub announce_approvals: bool,
+    /// Whether new releases of approved mods are announced in this channel
+    pub announce_versions: bool,
+    pub created: DateTime<Utc>,
+}
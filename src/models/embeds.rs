@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A response in the format of the oEmbed spec, version 1.0.
+/// Pages are embedded as links; scrapers use the OpenGraph tags for images.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct OEmbed {
+    pub version: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author_url: Option<String>,
+    pub provider_name: String,
+    pub provider_url: String,
+    /// How long consumers may cache this response, in seconds
+    pub cache_age: u32,
+}
+
+/// The metadata shown in previews of a mod page
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct OpenGraph {
+    pub title: String,
+    pub description: String,
+    /// The canonical URL of the mod page
+    pub url: String,
+    /// The featured image of the mod.  Mods have no gallery, so this is the icon.
+    pub image: Option<String>,
+    pub author: Option<OpenGraphAuthor>,
+    pub site_name: String,
+    pub is_nsfw: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct OpenGraphAuthor {
+    pub username: String,
+    pub url: String,
+    pub avatar_url: Option<String>,
+}
@@ -1,8 +1,11 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 /// An error returned by the API
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct ApiError<'a> {
+    /// A machine readable identifier of the kind of error, such as `invalid_input`
     pub error: &'a str,
+    /// A human readable description of what went wrong
     pub description: &'a str,
 }
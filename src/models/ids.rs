@@ -4,7 +4,9 @@ pub use super::mods::{ModId, VersionId};
 pub use super::notifications::NotificationId;
 pub use super::reports::ReportId;
 pub use super::teams::TeamId;
+pub use super::uploads::UploadId;
 pub use super::users::UserId;
+pub use super::webhooks::WebhookId;
 
 /// Generates a random 64 bit integer that is exactly `n` characters
 /// long when encoded as base62.
@@ -103,6 +105,16 @@ macro_rules! base62_id_impl {
     ($struct:ty, $cons:expr) => {
         from_base62id!($struct, $cons;);
         impl_base62_display!($struct);
+
+        impl utoipa::PartialSchema for $struct {
+            fn schema() -> utoipa::openapi::RefOr<utoipa::openapi::schema::Schema> {
+                utoipa::openapi::ObjectBuilder::new()
+                    .schema_type(utoipa::openapi::schema::Type::String)
+                    .description(Some("An ID, encoded as a base62 string"))
+                    .into()
+            }
+        }
+        impl utoipa::ToSchema for $struct {}
     }
 }
 base62_id_impl!(ModId, ModId);
@@ -111,6 +123,8 @@ base62_id_impl!(VersionId, VersionId);
 base62_id_impl!(TeamId, TeamId);
 base62_id_impl!(ReportId, ReportId);
 base62_id_impl!(NotificationId, NotificationId);
+base62_id_impl!(WebhookId, WebhookId);
+base62_id_impl!(UploadId, UploadId);
 
 pub mod base62_impl {
     use serde::de::{self, Deserializer, Visitor};
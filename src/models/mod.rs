@@ -1,7 +1,13 @@
+pub mod announcements;
+pub mod embeds;
 pub mod error;
 pub mod ids;
 pub mod mods;
 pub mod notifications;
+pub mod pagination;
 pub mod reports;
+pub mod storage;
 pub mod teams;
+pub mod uploads;
 pub mod users;
+pub mod webhooks;
@@ -3,6 +3,7 @@ use super::teams::TeamId;
 use super::users::UserId;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
 /// The ID of a specific mod, encoded as base62 for usage in the API
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -17,7 +18,7 @@ pub struct ModId(pub u64);
 pub struct VersionId(pub u64);
 
 /// A mod returned from the API
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct Mod {
     /// The ID of the mod, encoded as a base62 string.
     pub id: ModId,
@@ -66,7 +67,7 @@ pub struct Mod {
 }
 
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct DonationLink {
     pub id: String,
     pub platform: String,
@@ -79,7 +80,7 @@ pub struct DonationLink {
 /// Draft - Mod is not displayed on search, and not accessible by URL
 /// Unlisted - Mod is not displayed on search, but accessible by URL
 /// Processing - Mod is not displayed on search, and not accessible by URL (Temporary state, mod under review)
-#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum ModStatus {
     Approved,
@@ -135,7 +136,7 @@ impl ModStatus {
 }
 
 /// A specific version of a mod
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct Version {
     /// The ID of the version, encoded as a base62 string.
     pub id: VersionId,
@@ -145,6 +146,10 @@ pub struct Version {
     pub author_id: UserId,
     /// Whether the version is featured or not
     pub featured: bool,
+    /// Whether a file of the version was flagged by the malware scanner.
+    /// Quarantined versions list no files until a moderator lifts the
+    /// quarantine.
+    pub quarantined: bool,
 
     /// The name of this version
     pub name: String,
@@ -172,7 +177,7 @@ pub struct Version {
 }
 
 /// A single mod file, with a url for the file and the file's hash
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct VersionFile {
     /// A map of hashes of the file.  The key is the hashing algorithm
     /// and the value is the string version of the hash.
@@ -183,11 +188,55 @@ pub struct VersionFile {
     pub filename: String,
     /// Whether the file is the primary file of a version
     pub primary: bool,
+    /// The files in the archive, for archives that were opened when they
+    /// were uploaded
+    pub contents: Option<Vec<ArchiveEntry>>,
+    /// Whether the file was scanned for malware yet.  Only clean files are
+    /// listed on versions, so other states are only seen right after
+    /// uploading.
+    pub scan_status: ScanStatus,
+}
+
+/// Files are scanned for malware after they're uploaded, and can't be
+/// downloaded until they're clean
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanStatus {
+    PendingScan,
+    Clean,
+    Infected,
+}
+
+impl ScanStatus {
+    pub fn from_str(string: &str) -> ScanStatus {
+        match string {
+            "clean" => ScanStatus::Clean,
+            "infected" => ScanStatus::Infected,
+            _ => ScanStatus::PendingScan,
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScanStatus::PendingScan => "pending_scan",
+            ScanStatus::Clean => "clean",
+            ScanStatus::Infected => "infected",
+        }
+    }
+}
+
+/// A file in an archive
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ArchiveEntry {
+    /// The path of the file in the archive.  Files in nested archives start
+    /// with the path of the nested archive.
+    pub path: String,
+    /// The size of the file once unpacked, in bytes
+    pub size: u64,
 }
 
 /// A dependency which describes what versions are required, break support, or are optional to the
 /// version's functionality
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct Dependency {
     /// The filename of the file.
     pub version_id: VersionId,
@@ -195,7 +244,7 @@ pub struct Dependency {
     pub dependency_type: DependencyType,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum VersionType {
     Release,
@@ -225,7 +274,7 @@ impl VersionType {
 }
 
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum DependencyType {
     Required,
@@ -264,18 +313,19 @@ impl DependencyType {
 }
 
 /// A specific version of Minecraft
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(transparent)]
 pub struct GameVersion(pub String);
 
 /// A mod loader
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(transparent)]
 pub struct ModLoader(pub String);
 
 // These fields must always succeed parsing; deserialize errors aren't
 // processed correctly (don't return JSON errors)
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct SearchRequest {
     pub query: Option<String>,
     /// Must match a json 2 deep array of strings `[["categories:misc"]]`
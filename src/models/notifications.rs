@@ -1,28 +1,136 @@
 use super::ids::Base62Id;
+use super::mods::{ModId, ModStatus, VersionId};
+use super::reports::{ItemType, ReportId};
+use super::teams::TeamId;
 use super::users::UserId;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 #[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(from = "Base62Id")]
 #[serde(into = "Base62Id")]
 pub struct NotificationId(pub u64);
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct Notification {
     pub id: NotificationId,
     pub user_id: UserId,
+    #[serde(rename = "type")]
+    pub notification_type: NotificationType,
     pub title: String,
     pub text: String,
     pub link: String,
     pub read: bool,
     pub created: DateTime<Utc>,
+    /// Structured data describing what this notification is about.
+    /// Notifications created before types were introduced have no body.
+    pub body: Option<NotificationBody>,
     pub actions: Vec<NotificationAction>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct NotificationAction {
     pub title: String,
     /// The route to call when this notification action is called. Formatted HTTP Method, route
     pub action_route: (String, String),
 }
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationType {
+    VersionReleased,
+    TeamInvite,
+    ModerationDecision,
+    ReportResolved,
+    /// A weekly email summarising updates to followed mods. Only delivered by email.
+    WeeklyDigest,
+    Unknown,
+}
+
+impl std::fmt::Display for NotificationType {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "{}", self.as_str())
+    }
+}
+
+impl NotificationType {
+    /// Every notification type a user can set preferences for
+    pub const ALL: [NotificationType; 5] = [
+        NotificationType::VersionReleased,
+        NotificationType::TeamInvite,
+        NotificationType::ModerationDecision,
+        NotificationType::ReportResolved,
+        NotificationType::WeeklyDigest,
+    ];
+
+    pub fn from_str(string: &str) -> NotificationType {
+        match string {
+            "version_released" => NotificationType::VersionReleased,
+            "team_invite" => NotificationType::TeamInvite,
+            "moderation_decision" => NotificationType::ModerationDecision,
+            "report_resolved" => NotificationType::ReportResolved,
+            "weekly_digest" => NotificationType::WeeklyDigest,
+            _ => NotificationType::Unknown,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationType::VersionReleased => "version_released",
+            NotificationType::TeamInvite => "team_invite",
+            NotificationType::ModerationDecision => "moderation_decision",
+            NotificationType::ReportResolved => "report_resolved",
+            NotificationType::WeeklyDigest => "weekly_digest",
+            NotificationType::Unknown => "unknown",
+        }
+    }
+}
+
+/// The payload of a notification, tagged with its type
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotificationBody {
+    VersionReleased {
+        mod_id: ModId,
+        version_id: VersionId,
+    },
+    TeamInvite {
+        mod_id: ModId,
+        team_id: TeamId,
+        invited_by: UserId,
+        role: String,
+    },
+    ModerationDecision {
+        mod_id: ModId,
+        old_status: ModStatus,
+        new_status: ModStatus,
+    },
+    ReportResolved {
+        report_id: ReportId,
+        item_type: ItemType,
+        item_id: String,
+    },
+}
+
+impl NotificationBody {
+    pub fn notification_type(&self) -> NotificationType {
+        match self {
+            NotificationBody::VersionReleased { .. } => NotificationType::VersionReleased,
+            NotificationBody::TeamInvite { .. } => NotificationType::TeamInvite,
+            NotificationBody::ModerationDecision { .. } => NotificationType::ModerationDecision,
+            NotificationBody::ReportResolved { .. } => NotificationType::ReportResolved,
+        }
+    }
+}
+
+/// How a user wants to receive notifications of a given type.
+/// Disabling every channel mutes the type entirely.
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct NotificationPreference {
+    #[serde(rename = "type")]
+    pub notification_type: NotificationType,
+    pub in_app: bool,
+    pub email: bool,
+    pub webhook: bool,
+}
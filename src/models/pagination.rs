@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+/// A page of a list.  Pass `next` as the `cursor` of the next request to
+/// get the following page; it is absent on the last page.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<String>,
+}
+
+/// A list as it's returned: a `Page` when paging was asked for with `cursor`
+/// or `limit`, and a plain array of every item otherwise
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum Listing<T> {
+    Page(Page<T>),
+    All(Vec<T>),
+}
+
+/// The query parameters shared by every paginated list.  Lists are only
+/// paged when `cursor` or `limit` is given.
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    /// The `next` cursor of the previous page.  Cursors are only valid with
+    /// the sort they were created with.
+    pub cursor: Option<String>,
+    /// The maximum number of items on a page, between 1 and 100.  Defaults
+    /// to 50 when paging with `cursor`.
+    pub limit: Option<u32>,
+    /// The maximum number of items of a list that isn't paged.  Superseded
+    /// by `limit`.
+    pub count: Option<u32>,
+    /// The order of the items.  Each list documents the keys it supports;
+    /// the first one is the default.
+    pub sort: Option<String>,
+}
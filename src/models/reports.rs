@@ -2,24 +2,31 @@ use super::ids::Base62Id;
 use crate::models::ids::UserId;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 #[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(from = "Base62Id")]
 #[serde(into = "Base62Id")]
 pub struct ReportId(pub u64);
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct Report {
     pub id: ReportId,
     pub report_type: String,
     pub item_id: String,
     pub item_type: ItemType,
-    pub reporter: UserId,
+    /// The user who created the report.  `null` for reports the site raised
+    /// itself, like for files flagged by the malware scanner.  Reporters
+    /// used to always be set, so clients have to handle `null` now.
+    pub reporter: Option<UserId>,
     pub body: String,
     pub created: DateTime<Utc>,
+    /// The state of the reported item at the time the report was created.
+    /// Only returned to moderators.
+    pub snapshot: Option<serde_json::Value>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 #[serde(rename_all = "kebab-case")]
 pub enum ItemType {
     Mod,
@@ -0,0 +1,46 @@
+use super::ids::ModId;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// The storage used by the files of a user's versions, and the limits on it.
+/// Sizes are in bytes.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UserStorage {
+    /// The bytes stored in the files of versions the user uploaded
+    pub used: u64,
+    /// How much the user can store in total
+    pub storage_limit: u64,
+    /// The largest file the user can upload.  Teams can have a larger limit.
+    pub file_size_limit: u64,
+    /// Whether moderators changed the limits of the user
+    pub limits_overridden: bool,
+    /// The storage used, by the mod, largest first
+    pub mods: Vec<ModStorage>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ModStorage {
+    pub mod_id: ModId,
+    /// The number of files the user uploaded to the mod
+    pub files: u64,
+    pub used: u64,
+}
+
+/// Limits a moderator sets for a user or team, in bytes.  Limits that are
+/// set to `null` go back to the defaults, and limits that are left out stay
+/// the same.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct EditStorageLimits {
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::double_option"
+    )]
+    pub file_size_limit: Option<Option<u64>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::double_option"
+    )]
+    pub storage_limit: Option<Option<u64>>,
+}
@@ -1,6 +1,7 @@
 use super::ids::Base62Id;
 use crate::models::users::UserId;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 /// The ID of a team
 #[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -12,7 +13,7 @@ pub const OWNER_ROLE: &str = "Owner";
 
 // TODO: permissions, role names, etc
 /// A team of users who control a mod
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct Team {
     /// The id of the team
     pub id: TeamId,
@@ -36,6 +37,23 @@ bitflags::bitflags! {
     }
 }
 
+// The bitflags macro can't derive the schema, so it is described by hand
+impl utoipa::PartialSchema for Permissions {
+    fn schema() -> utoipa::openapi::RefOr<utoipa::openapi::schema::Schema> {
+        utoipa::openapi::ObjectBuilder::new()
+            .schema_type(utoipa::openapi::schema::Type::Integer)
+            .format(Some(utoipa::openapi::SchemaFormat::KnownFormat(
+                utoipa::openapi::KnownFormat::Int64,
+            )))
+            .description(Some(
+                "A bitset of permissions: 1 upload version, 2 delete version, 4 edit details, \
+                 8 edit body, 16 manage invites, 32 remove member, 64 edit member, 128 delete mod",
+            ))
+            .into()
+    }
+}
+impl ToSchema for Permissions {}
+
 impl Default for Permissions {
     fn default() -> Permissions {
         Permissions::UPLOAD_VERSION | Permissions::DELETE_VERSION
@@ -43,7 +61,7 @@ impl Default for Permissions {
 }
 
 /// A member of a team
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct TeamMember {
     /// The ID of the team this team member is a member of
     pub team_id: TeamId,
@@ -0,0 +1,26 @@
+use super::ids::Base62Id;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// The ID of a resumable upload
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Base62Id")]
+#[serde(into = "Base62Id")]
+pub struct UploadId(pub u64);
+
+/// A file that is uploaded in pieces, so an interrupted upload can be
+/// continued where it stopped.  Once all of it arrived, it can be added to a
+/// version by listing its ID in `uploads`.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Upload {
+    pub id: UploadId,
+    pub file_name: String,
+    /// The size of the whole file, in bytes
+    pub size: u64,
+    /// The number of bytes received so far, which is where the next piece starts
+    pub offset: u64,
+    pub created: DateTime<Utc>,
+    /// When the upload is removed, unless more of it arrives before
+    pub expires: DateTime<Utc>,
+}
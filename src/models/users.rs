@@ -1,5 +1,6 @@
 use super::ids::Base62Id;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(from = "Base62Id")]
@@ -8,7 +9,7 @@ pub struct UserId(pub u64);
 
 pub const DELETED_USER: UserId = UserId(127155982985829);
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct User {
     pub id: UserId,
     pub discord_id: Option<String>,
@@ -22,7 +23,7 @@ pub struct User {
     pub show_nsfw: bool
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum Role {
     Developer,
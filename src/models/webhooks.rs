@@ -0,0 +1,122 @@
+use super::ids::Base62Id;
+use super::mods::ModId;
+use super::teams::TeamId;
+use super::users::UserId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// The ID of a webhook
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Base62Id")]
+#[serde(into = "Base62Id")]
+pub struct WebhookId(pub u64);
+
+/// An HTTP endpoint that receives events for a mod, or for every mod of its owner
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Webhook {
+    pub id: WebhookId,
+    /// The user who owns this webhook, if it is not owned by a team
+    pub user_id: Option<UserId>,
+    /// The team that owns this webhook, if it is not owned by a user
+    pub team_id: Option<TeamId>,
+    /// The mod this webhook is limited to. Account webhooks receive events
+    /// for every mod their owner is a member of.
+    pub mod_id: Option<ModId>,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub created: DateTime<Utc>,
+    /// The key used to sign deliveries. Only returned when the webhook is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug, ToSchema)]
+pub enum WebhookEvent {
+    #[serde(rename = "version.created")]
+    VersionCreated,
+    #[serde(rename = "mod.approved")]
+    ModApproved,
+    #[serde(rename = "mod.updated")]
+    ModUpdated,
+    /// A notification was sent to the owner of an account webhook.
+    /// Only delivered if the user enabled webhooks for that notification type.
+    #[serde(rename = "notification.created")]
+    NotificationCreated,
+    /// Sent when testing a webhook. Every webhook receives it, regardless of its events.
+    #[serde(rename = "ping")]
+    Ping,
+    #[serde(rename = "unknown")]
+    Unknown,
+}
+
+impl std::fmt::Display for WebhookEvent {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "{}", self.as_str())
+    }
+}
+
+impl WebhookEvent {
+    pub fn from_str(string: &str) -> WebhookEvent {
+        match string {
+            "version.created" => WebhookEvent::VersionCreated,
+            "mod.approved" => WebhookEvent::ModApproved,
+            "mod.updated" => WebhookEvent::ModUpdated,
+            "notification.created" => WebhookEvent::NotificationCreated,
+            "ping" => WebhookEvent::Ping,
+            _ => WebhookEvent::Unknown,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::VersionCreated => "version.created",
+            WebhookEvent::ModApproved => "mod.approved",
+            WebhookEvent::ModUpdated => "mod.updated",
+            WebhookEvent::NotificationCreated => "notification.created",
+            WebhookEvent::Ping => "ping",
+            WebhookEvent::Unknown => "unknown",
+        }
+    }
+
+    /// Whether a webhook can subscribe to this event
+    pub fn is_subscribable(&self) -> bool {
+        !matches!(self, WebhookEvent::Ping | WebhookEvent::Unknown)
+    }
+}
+
+/// An attempt to deliver an event to a webhook
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: u64,
+    pub event: WebhookEvent,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    /// The HTTP status returned by the receiver on the last attempt
+    pub response_code: Option<u16>,
+    /// Why the last attempt failed, if it did
+    pub error: Option<String>,
+    pub created: DateTime<Utc>,
+    pub next_attempt: Option<DateTime<Utc>>,
+    pub delivered: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    /// Waiting to be sent, or to be retried
+    Pending,
+    Delivered,
+    /// Every attempt failed; the delivery won't be retried
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn from_str(string: &str) -> DeliveryStatus {
+        match string {
+            "delivered" => DeliveryStatus::Delivered,
+            "failed" => DeliveryStatus::Failed,
+            _ => DeliveryStatus::Pending,
+        }
+    }
+}
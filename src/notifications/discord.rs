@@ -0,0 +1,249 @@
+use crate::database::models::announcement_item::{
+    AnnouncedMod, AnnouncementChannel, QueuedAnnouncement,
+};
+use crate::database::models::ids::ModId;
+use crate::database::models::DatabaseError;
+use crate::models::mods::{ModStatus, Version, VersionType};
+use crate::notifications::webhooks::{post_json, Client};
+use log::warn;
+use serde::Serialize;
+use sqlx::PgPool;
+
+/// Queued announcements are dropped after failing to post this many times
+const MAX_ATTEMPTS: i32 = 5;
+/// How long a claimed announcement is held before another run may pick it up again
+const SENDING_LEASE: i64 = 5 * 60;
+
+const APPROVED_COLOR: u32 = 0x1b_d9_6a;
+const VERSION_COLOR: u32 = 0x4f_9c_f6;
+
+/// A message in the format of Discord's "Execute Webhook" endpoint
+#[derive(Serialize)]
+pub struct DiscordMessage {
+    pub username: String,
+    pub embeds: Vec<Embed>,
+}
+
+#[derive(Serialize)]
+pub struct Embed {
+    pub title: String,
+    pub url: String,
+    pub description: String,
+    pub color: u32,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<EmbedAuthor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<EmbedImage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<EmbedImage>,
+    pub fields: Vec<EmbedField>,
+}
+
+#[derive(Serialize)]
+pub struct EmbedAuthor {
+    pub name: String,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_url: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct EmbedImage {
+    pub url: String,
+}
+
+#[derive(Serialize)]
+pub struct EmbedField {
+    pub name: String,
+    pub value: String,
+    pub inline: bool,
+}
+
+fn site_url() -> String {
+    dotenv::var("SITE_URL").unwrap_or_default()
+}
+
+/// Shortens text to a length Discord accepts, marking it as cut off
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        text.to_string()
+    } else {
+        let mut truncated: String = text.chars().take(max - 1).collect();
+        truncated.push('…');
+        truncated
+    }
+}
+
+fn mod_embed(data: &AnnouncedMod, title: String, description: &str, color: u32) -> Embed {
+    let api_id: crate::models::ids::ModId = data.id.into();
+    let mod_path = data.slug.clone().unwrap_or_else(|| api_id.to_string());
+
+    let mut fields = Vec::new();
+
+    if !data.categories.is_empty() {
+        fields.push(EmbedField {
+            name: "Categories".to_string(),
+            value: data.categories.join(", "),
+            inline: true,
+        });
+    }
+
+    Embed {
+        title: truncate(&title, 256),
+        url: format!("{}/mod/{}", site_url(), mod_path),
+        description: truncate(description, 2048),
+        color,
+        timestamp: chrono::Utc::now(),
+        author: data.owner_username.as_ref().map(|username| EmbedAuthor {
+            name: username.clone(),
+            url: format!("{}/user/{}", site_url(), username),
+            icon_url: data.owner_avatar_url.clone(),
+        }),
+        // Mods have no gallery yet, so the icon is the only image there is
+        thumbnail: data.icon_url.clone().map(|url| EmbedImage { url }),
+        image: None,
+        fields,
+    }
+}
+
+/// Queues a message for every channel that accepts the mod and this kind of announcement
+async fn enqueue(
+    data: &AnnouncedMod,
+    is_version: bool,
+    embed: Embed,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), DatabaseError> {
+    let channels: Vec<i32> = AnnouncementChannel::list(&mut *transaction)
+        .await?
+        .into_iter()
+        .filter(|x| {
+            if is_version {
+                x.announce_versions
+            } else {
+                x.announce_approvals
+            }
+        })
+        .filter(|x| x.accepts(data.is_nsfw, &data.categories))
+        .map(|x| x.id)
+        .collect();
+
+    if channels.is_empty() {
+        return Ok(());
+    }
+
+    let payload = serde_json::to_value(DiscordMessage {
+        username: "XivRepo".to_string(),
+        embeds: vec![embed],
+    })?;
+
+    QueuedAnnouncement::insert_many(&channels, &payload, &mut *transaction).await?;
+
+    Ok(())
+}
+
+/// Announces a mod that was just approved
+pub async fn announce_approval(
+    mod_id: ModId,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), DatabaseError> {
+    let data = match AnnouncedMod::get(mod_id, &mut *transaction).await? {
+        Some(data) => data,
+        None => return Ok(()),
+    };
+
+    let embed = mod_embed(
+        &data,
+        format!("New mod: {}", data.title),
+        &data.description,
+        APPROVED_COLOR,
+    );
+
+    enqueue(&data, false, embed, transaction).await
+}
+
+/// Announces a new version of an approved mod. Only releases are announced;
+/// betas and alphas are too frequent for a community feed.
+pub async fn announce_version(
+    version: &Version,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), DatabaseError> {
+    if !matches!(version.version_type, VersionType::Release) {
+        return Ok(());
+    }
+
+    let data = match AnnouncedMod::get(version.mod_id.into(), &mut *transaction).await? {
+        Some(data) => data,
+        None => return Ok(()),
+    };
+
+    if ModStatus::from_str(&data.status) != ModStatus::Approved {
+        return Ok(());
+    }
+
+    let mut embed = mod_embed(
+        &data,
+        format!("{} {}", data.title, version.version_number),
+        &version.changelog,
+        VERSION_COLOR,
+    );
+    embed.url = format!("{}/version/{}", embed.url, version.id);
+    embed.fields.insert(
+        0,
+        EmbedField {
+            name: "Version".to_string(),
+            value: truncate(&version.name, 1024),
+            inline: true,
+        },
+    );
+
+    enqueue(&data, true, embed, transaction).await
+}
+
+/// Posts a batch of queued announcements. Failed posts are retried on the
+/// next run, up to `MAX_ATTEMPTS` times.
+pub async fn send_queued_announcements(
+    pool: &PgPool,
+    client: &Client,
+) -> Result<usize, sqlx::error::Error> {
+    let lease_expiry = chrono::Utc::now() - chrono::Duration::seconds(SENDING_LEASE);
+    let announcements = QueuedAnnouncement::claim_batch(25, lease_expiry, pool).await?;
+    let mut sent = 0;
+
+    for announcement in announcements {
+        match post(client, &announcement).await {
+            Ok(()) => {
+                QueuedAnnouncement::remove(announcement.id, pool).await?;
+                sent += 1;
+            }
+            Err(e) => {
+                warn!(
+                    "Posting announcement {} to channel {} failed: {}",
+                    announcement.id, announcement.channel_id, e
+                );
+
+                if announcement.attempts + 1 >= MAX_ATTEMPTS {
+                    QueuedAnnouncement::remove(announcement.id, pool).await?;
+                } else {
+                    QueuedAnnouncement::release_failed(announcement.id, pool).await?;
+                }
+            }
+        }
+    }
+
+    Ok(sent)
+}
+
+/// Posts an announcement through the webhook client, which refuses to
+/// connect to internal addresses
+async fn post(client: &Client, announcement: &QueuedAnnouncement) -> Result<(), String> {
+    let url = reqwest::Url::parse(&announcement.url).map_err(|e| e.to_string())?;
+    let body = serde_json::to_vec(&announcement.payload).map_err(|e| e.to_string())?;
+
+    let status = post_json(client, &url, &[], body).await?;
+    if !status.is_success() {
+        return Err(format!("Discord responded with {}", status));
+    }
+
+    Ok(())
+}
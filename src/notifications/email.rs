@@ -0,0 +1,398 @@
+use crate::database::models::email_item::{DigestRecipient, DigestUpdate, QueuedEmail};
+use crate::database::models::ids::UserId;
+use crate::models::notifications::NotificationType;
+use askama::Template;
+use hmac::{Hmac, Mac, NewMac};
+use lettre::message::header::{ContentType, Header, HeaderName};
+use lettre::message::{Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio02Executor};
+use log::warn;
+use sha2::Sha256;
+use sqlx::PgPool;
+use thiserror::Error;
+
+/// Queued emails are dropped after failing to send this many times
+const MAX_ATTEMPTS: i32 = 5;
+
+/// How long an email or digest stays claimed by the run sending it.  Claims
+/// of runs that stopped halfway are taken over once they're this old.
+fn sending_lease() -> chrono::Duration {
+    chrono::Duration::minutes(30)
+}
+
+/// Unsubscribe links can be forged by anyone who guesses the secret, so
+/// placeholders and short secrets are refused
+const MIN_SECRET_LENGTH: usize = 32;
+
+#[derive(Error, Debug)]
+pub enum EmailError {
+    #[error("Environment Error")]
+    EnvError(#[from] dotenv::Error),
+    #[error("Invalid email address: {0}")]
+    AddressError(#[from] lettre::address::AddressError),
+    #[error("Error while building email: {0}")]
+    MessageError(#[from] lettre::error::Error),
+    #[error("Error while sending email: {0}")]
+    SmtpError(#[from] lettre::transport::smtp::Error),
+    #[error("Error while rendering email template: {0}")]
+    TemplateError(#[from] askama::Error),
+    #[error("Database Error: {0}")]
+    DatabaseError(#[from] sqlx::error::Error),
+    #[error("UNSUBSCRIBE_SECRET must be a random string of at least {0} characters")]
+    InsecureSecret(usize),
+}
+
+/// Whether email delivery is configured. Emails are only queued when it is.
+pub fn is_enabled() -> bool {
+    dotenv::var("SMTP_HOST")
+        .map(|x| !x.is_empty())
+        .unwrap_or(false)
+}
+
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio02Executor>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    /// Creates a mailer from the `SMTP_*` environment variables, or returns
+    /// `None` if email delivery is not configured.
+    pub fn from_env() -> Result<Option<Self>, EmailError> {
+        if !is_enabled() {
+            return Ok(None);
+        }
+
+        let host = dotenv::var("SMTP_HOST")?;
+        let port = dotenv::var("SMTP_PORT")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(587);
+
+        // `none` allows testing against a local SMTP catcher, which usually
+        // doesn't support TLS
+        let mut builder = match &*dotenv::var("SMTP_TLS").unwrap_or_default() {
+            "none" => AsyncSmtpTransport::<Tokio02Executor>::builder_dangerous(host),
+            "tls" => AsyncSmtpTransport::<Tokio02Executor>::relay(&host)?,
+            _ => AsyncSmtpTransport::<Tokio02Executor>::starttls_relay(&host)?,
+        }
+        .port(port);
+
+        let username = dotenv::var("SMTP_USERNAME").unwrap_or_default();
+        let password = dotenv::var("SMTP_PASSWORD").unwrap_or_default();
+
+        if !username.is_empty() {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Some(Mailer {
+            transport: builder.build(),
+            from: dotenv::var("SMTP_FROM")?.parse()?,
+        }))
+    }
+
+    async fn send(
+        &self,
+        to: &str,
+        subject: &str,
+        text: String,
+        html: String,
+        unsubscribe_url: &str,
+    ) -> Result<(), EmailError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse()?)
+            .subject(subject)
+            .header(ListUnsubscribe(unsubscribe_url.to_string()))
+            .header(ListUnsubscribePost)
+            .multipart(
+                MultiPart::alternative()
+                    .singlepart(
+                        SinglePart::builder()
+                            .content_type(ContentType::TEXT_PLAIN)
+                            .body(text),
+                    )
+                    .singlepart(
+                        SinglePart::builder()
+                            .content_type(ContentType::TEXT_HTML)
+                            .body(html),
+                    ),
+            )?;
+
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Template)]
+#[template(path = "email/notification.html")]
+struct NotificationHtml<'a> {
+    title: &'a str,
+    text: &'a str,
+    link: &'a str,
+    unsubscribe_url: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "email/notification.txt")]
+struct NotificationText<'a> {
+    title: &'a str,
+    text: &'a str,
+    link: &'a str,
+    unsubscribe_url: &'a str,
+}
+
+struct DigestEntry {
+    title: String,
+    link: String,
+    versions: i64,
+}
+
+#[derive(Template)]
+#[template(path = "email/digest.html")]
+struct DigestHtml<'a> {
+    updates: &'a [DigestEntry],
+    unsubscribe_url: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "email/digest.txt")]
+struct DigestText<'a> {
+    updates: &'a [DigestEntry],
+    unsubscribe_url: &'a str,
+}
+
+/// The `List-Unsubscribe` header, so mail clients can offer their own unsubscribe button
+#[derive(Clone)]
+struct ListUnsubscribe(String);
+
+impl Header for ListUnsubscribe {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe")
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(ListUnsubscribe(
+            s.trim_start_matches('<').trim_end_matches('>').to_string(),
+        ))
+    }
+
+    fn display(&self) -> String {
+        format!("<{}>", self.0)
+    }
+}
+
+/// Marks the `List-Unsubscribe` link as supporting one-click unsubscription (RFC 8058)
+#[derive(Clone)]
+struct ListUnsubscribePost;
+
+impl Header for ListUnsubscribePost {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe-Post")
+    }
+
+    fn parse(_: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(ListUnsubscribePost)
+    }
+
+    fn display(&self) -> String {
+        "List-Unsubscribe=One-Click".to_string()
+    }
+}
+
+/// The key unsubscribe links are signed with, if it's long and random enough
+pub fn unsubscribe_secret() -> Result<String, EmailError> {
+    let secret = dotenv::var("UNSUBSCRIBE_SECRET")?;
+    let distinct = secret.chars().collect::<std::collections::HashSet<_>>().len();
+
+    if secret.len() < MIN_SECRET_LENGTH || distinct < 8 {
+        return Err(EmailError::InsecureSecret(MIN_SECRET_LENGTH));
+    }
+
+    Ok(secret)
+}
+
+fn unsubscribe_mac(
+    user_id: UserId,
+    notification_type: NotificationType,
+) -> Result<Hmac<Sha256>, EmailError> {
+    let mut mac = Hmac::<Sha256>::new_varkey(unsubscribe_secret()?.as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(format!("{}:{}", user_id.0, notification_type.as_str()).as_bytes());
+
+    Ok(mac)
+}
+
+/// Signs an unsubscribe request, so the link works without logging in
+pub fn sign_unsubscribe(
+    user_id: UserId,
+    notification_type: NotificationType,
+) -> Result<String, EmailError> {
+    let signature = unsubscribe_mac(user_id, notification_type)?
+        .finalize()
+        .into_bytes();
+
+    Ok(base64::encode_config(signature, base64::URL_SAFE_NO_PAD))
+}
+
+pub fn verify_unsubscribe(
+    user_id: UserId,
+    notification_type: NotificationType,
+    signature: &str,
+) -> Result<bool, EmailError> {
+    let signature = match base64::decode_config(signature, base64::URL_SAFE_NO_PAD) {
+        Ok(signature) => signature,
+        Err(_) => return Ok(false),
+    };
+
+    Ok(unsubscribe_mac(user_id, notification_type)?
+        .verify(&signature)
+        .is_ok())
+}
+
+fn unsubscribe_url(
+    user_id: UserId,
+    notification_type: NotificationType,
+) -> Result<String, EmailError> {
+    let api_user_id: crate::models::users::UserId = user_id.into();
+
+    Ok(format!(
+        "{}/api/v1/email/unsubscribe?user={}&type={}&signature={}",
+        dotenv::var("API_URL")?,
+        api_user_id,
+        notification_type,
+        sign_unsubscribe(user_id, notification_type)?
+    ))
+}
+
+fn site_link(link: &str) -> Result<String, EmailError> {
+    if link.is_empty() {
+        Ok(String::new())
+    } else {
+        Ok(format!("{}/{}", dotenv::var("SITE_URL")?, link))
+    }
+}
+
+/// Sends a batch of queued notification emails. Emails that fail to send are
+/// retried on the next run, up to `MAX_ATTEMPTS` times.
+pub async fn send_queued_emails(pool: &PgPool, mailer: &Mailer) -> Result<usize, EmailError> {
+    let lease_expiry = chrono::Utc::now() - sending_lease();
+    let emails = QueuedEmail::claim_batch(50, lease_expiry, pool).await?;
+    let mut sent = 0;
+
+    for email in emails {
+        let user = crate::database::models::User::get(email.user_id, pool).await?;
+
+        let address = match user.and_then(|x| x.email) {
+            Some(address) => address,
+            None => {
+                QueuedEmail::remove(email.id, pool).await?;
+                continue;
+            }
+        };
+
+        let notification_type = NotificationType::from_str(&email.notification_type);
+        let unsubscribe_url = unsubscribe_url(email.user_id, notification_type)?;
+        let link = site_link(&email.link)?;
+
+        let text = NotificationText {
+            title: &email.title,
+            text: &email.text,
+            link: &link,
+            unsubscribe_url: &unsubscribe_url,
+        }
+        .render()?;
+        let html = NotificationHtml {
+            title: &email.title,
+            text: &email.text,
+            link: &link,
+            unsubscribe_url: &unsubscribe_url,
+        }
+        .render()?;
+
+        match mailer
+            .send(&address, &email.title, text, html, &unsubscribe_url)
+            .await
+        {
+            Ok(()) => {
+                QueuedEmail::remove(email.id, pool).await?;
+                sent += 1;
+            }
+            Err(e) => {
+                warn!("Sending notification email {} failed: {:?}", email.id, e);
+
+                if email.attempts + 1 >= MAX_ATTEMPTS {
+                    QueuedEmail::remove(email.id, pool).await?;
+                } else {
+                    QueuedEmail::release_failed(email.id, pool).await?;
+                }
+            }
+        }
+    }
+
+    Ok(sent)
+}
+
+/// Sends the weekly digest to a batch of users who are due one. Users whose
+/// followed mods had no updates are skipped until next week, and digests
+/// that fail to send are retried once their claim expires.
+pub async fn send_digests(pool: &PgPool, mailer: &Mailer) -> Result<usize, EmailError> {
+    let now = chrono::Utc::now();
+    let since = now - chrono::Duration::weeks(1);
+    let recipients = DigestRecipient::claim_due(since, now - sending_lease(), 100, pool).await?;
+    let mut sent = 0;
+
+    for recipient in recipients {
+        let updates = DigestUpdate::get_many_user(recipient.id, since, pool).await?;
+
+        if updates.is_empty() {
+            DigestRecipient::mark_sent(recipient.id, pool).await?;
+            continue;
+        }
+
+        let mut entries = Vec::new();
+
+        for update in updates {
+            let mod_id: crate::models::ids::ModId = update.mod_id.into();
+
+            entries.push(DigestEntry {
+                title: update.title,
+                link: site_link(&format!("mod/{}", mod_id))?,
+                versions: update.versions,
+            });
+        }
+
+        let unsubscribe_url = unsubscribe_url(recipient.id, NotificationType::WeeklyDigest)?;
+
+        let text = DigestText {
+            updates: &entries,
+            unsubscribe_url: &unsubscribe_url,
+        }
+        .render()?;
+        let html = DigestHtml {
+            updates: &entries,
+            unsubscribe_url: &unsubscribe_url,
+        }
+        .render()?;
+
+        match mailer
+            .send(
+                &recipient.email,
+                "Your weekly XivRepo digest",
+                text,
+                html,
+                &unsubscribe_url,
+            )
+            .await
+        {
+            Ok(()) => {
+                DigestRecipient::mark_sent(recipient.id, pool).await?;
+                sent += 1;
+            }
+            Err(e) => warn!("Sending digest to user {:?} failed: {:?}", recipient.id, e),
+        }
+    }
+
+    Ok(sent)
+}
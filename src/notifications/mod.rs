@@ -0,0 +1,4 @@
+pub mod discord;
+pub mod email;
+pub mod stream;
+pub mod webhooks;
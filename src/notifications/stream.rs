@@ -0,0 +1,115 @@
+use crate::database::models::ids::{NotificationId, UserId};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgListener, PgPool};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// The Postgres channel new notifications are announced on
+pub const NOTIFICATION_CHANNEL: &str = "notifications";
+
+/// The payload sent with `pg_notify` when a notification is inserted
+#[derive(Serialize, Deserialize)]
+pub struct NotificationEvent {
+    pub user_id: i64,
+    pub notification_id: i64,
+}
+
+pub enum StreamEvent {
+    Notification(NotificationId),
+    KeepAlive,
+}
+
+/// The open notification streams of this API instance, keyed by user
+pub struct NotificationStreams {
+    // Senders are pruned whenever a send to them fails, which happens
+    // once the client has disconnected and the receiver was dropped.
+    clients: Mutex<HashMap<UserId, Vec<UnboundedSender<StreamEvent>>>>,
+}
+
+impl NotificationStreams {
+    pub fn new() -> Self {
+        NotificationStreams {
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn subscribe(&self, user_id: UserId) -> UnboundedReceiver<StreamEvent> {
+        let (sender, receiver) = unbounded();
+
+        // Sending an event straight away makes sure the response headers are flushed
+        sender.unbounded_send(StreamEvent::KeepAlive).ok();
+
+        // Can only panic if mutex is poisoned
+        self.clients
+            .lock()
+            .unwrap()
+            .entry(user_id)
+            .or_default()
+            .push(sender);
+
+        receiver
+    }
+
+    pub fn send(&self, user_id: UserId, notification_id: NotificationId) {
+        let mut clients = self.clients.lock().unwrap();
+
+        if let Some(senders) = clients.get_mut(&user_id) {
+            senders.retain(|sender| {
+                sender
+                    .unbounded_send(StreamEvent::Notification(notification_id))
+                    .is_ok()
+            });
+
+            if senders.is_empty() {
+                clients.remove(&user_id);
+            }
+        }
+    }
+
+    /// Sends a keep-alive to every open stream and drops the closed ones
+    pub fn keep_alive(&self) {
+        let mut clients = self.clients.lock().unwrap();
+
+        clients.retain(|_, senders| {
+            senders.retain(|sender| sender.unbounded_send(StreamEvent::KeepAlive).is_ok());
+            !senders.is_empty()
+        });
+    }
+}
+
+/// Listens for new notifications on the database and forwards them to the
+/// matching open streams. Every API instance runs its own listener, so
+/// clients receive their notifications regardless of which instance
+/// created them.
+pub async fn listen(pool: PgPool, streams: Arc<NotificationStreams>) {
+    loop {
+        if let Err(e) = forward_notifications(&pool, &streams).await {
+            warn!("Notification listener failed, reconnecting: {:?}", e);
+        }
+
+        futures_timer::Delay::new(std::time::Duration::from_secs(5)).await;
+    }
+}
+
+async fn forward_notifications(
+    pool: &PgPool,
+    streams: &NotificationStreams,
+) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(NOTIFICATION_CHANNEL).await?;
+    info!("Listening for new notifications");
+
+    loop {
+        let notification = listener.recv().await?;
+
+        match serde_json::from_str::<NotificationEvent>(notification.payload()) {
+            Ok(event) => streams.send(
+                UserId(event.user_id),
+                NotificationId(event.notification_id),
+            ),
+            Err(e) => warn!("Received an invalid notification event: {:?}", e),
+        }
+    }
+}
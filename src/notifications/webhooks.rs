@@ -0,0 +1,462 @@
+use crate::database::models::ids::{ModId, TeamId, UserId, WebhookId};
+use crate::database::models::webhook_item::{DueDelivery, Webhook, WebhookDelivery};
+use crate::database::models::DatabaseError;
+use crate::models::webhooks::WebhookEvent;
+use futures::Future;
+use hmac::{Hmac, Mac, NewMac};
+use hyper::client::connect::dns::Name;
+use hyper::client::HttpConnector;
+use hyper::{header, Body, Request, StatusCode};
+use hyper_tls::HttpsConnector;
+use log::warn;
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower_service::Service;
+
+/// Deliveries are marked as failed after this many unsuccessful attempts
+const MAX_ATTEMPTS: i32 = 8;
+/// The delay before the first retry, doubled after every failed attempt
+const BASE_RETRY_DELAY: i64 = 30;
+/// How long a claimed delivery is held before another run may pick it up again
+const DELIVERY_LEASE: i64 = 5 * 60;
+/// How long sending a delivery may take, including connecting
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const SIGNATURE_HEADER: &str = "X-XivRepo-Signature";
+pub const EVENT_HEADER: &str = "X-XivRepo-Event";
+pub const DELIVERY_HEADER: &str = "X-XivRepo-Delivery";
+
+#[derive(Serialize)]
+struct Payload<'a, T: Serialize> {
+    event: WebhookEvent,
+    created: chrono::DateTime<chrono::Utc>,
+    data: &'a T,
+}
+
+fn build_payload<T: Serialize>(
+    event: WebhookEvent,
+    data: &T,
+) -> Result<serde_json::Value, serde_json::Error> {
+    serde_json::to_value(Payload {
+        event,
+        created: chrono::Utc::now(),
+        data,
+    })
+}
+
+/// Whether webhooks may be registered for plain HTTP endpoints and internal
+/// addresses. Only meant for testing against a local receiver.
+fn allow_insecure() -> bool {
+    dotenv::var("WEBHOOK_ALLOW_INSECURE")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(false)
+}
+
+/// Checks that a webhook URL can be delivered to, returning why it can't
+/// otherwise. URLs have to point at public addresses, so that webhooks can't
+/// be used to reach services on the site's own network.
+pub async fn validate_url(url: &str) -> Result<(), String> {
+    if url.len() > 2048 {
+        return Err("Webhook URLs may be at most 2048 characters long".to_string());
+    }
+
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid webhook URL: {}", e))?;
+
+    match parsed.scheme() {
+        "https" => {}
+        "http" if allow_insecure() => {}
+        _ => return Err("Webhook URLs must use HTTPS".to_string()),
+    }
+
+    if parsed.host_str().is_none() {
+        return Err("Webhook URLs must have a host".to_string());
+    }
+
+    check_destination(&parsed).await
+}
+
+/// Resolves the host of a URL and checks that all of its addresses are
+/// public. Deliveries don't rely on this, since the host can resolve to
+/// something else by then; their client checks the addresses it connects to.
+async fn check_destination(url: &reqwest::Url) -> Result<(), String> {
+    if allow_insecure() {
+        return Ok(());
+    }
+
+    let host = match url.host_str() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => return Err("Webhook URLs must have a host".to_string()),
+    };
+
+    resolve_public(host).await?;
+
+    Ok(())
+}
+
+/// Resolves a host, failing if any of its addresses is internal
+async fn resolve_public(host: &str) -> Result<Vec<IpAddr>, String> {
+    let addresses = tokio::net::lookup_host((host, 0))
+        .await
+        .map_err(|e| format!("The webhook host couldn't be resolved: {}", e))?
+        .map(|address| address.ip())
+        .collect::<Vec<_>>();
+
+    if addresses.is_empty() {
+        return Err("The webhook host doesn't resolve to any address".to_string());
+    }
+
+    if !allow_insecure() {
+        for address in &addresses {
+            check_address(*address)?;
+        }
+    }
+
+    Ok(addresses)
+}
+
+fn check_address(ip: IpAddr) -> Result<(), String> {
+    let internal = match ip {
+        IpAddr::V4(ip) => is_internal_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4() {
+            // IPv4-mapped and -compatible addresses reach the IPv4 host
+            Some(mapped) if ip.segments()[..5] == [0; 5] => is_internal_v4(mapped),
+            _ => is_internal_v6(ip),
+        },
+    };
+
+    if internal {
+        return Err(format!(
+            "Webhook URLs can't point at internal addresses like {}",
+            ip
+        ));
+    }
+
+    Ok(())
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+
+    ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        // Also covers cloud metadata services at 169.254.169.254
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // Shared address space used by carrier-grade NAT
+        || (a == 100 && (64..128).contains(&b))
+        // Reserved for future use
+        || a >= 240
+        || a == 0
+}
+
+fn is_internal_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+
+    ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local addresses
+        || (first & 0xfe00) == 0xfc00
+        // Link-local addresses
+        || (first & 0xffc0) == 0xfe80
+        // 6to4, which can wrap any IPv4 address
+        || first == 0x2002
+        // NAT64, which translates to the IPv4 address in the last 32 bits
+        || ip.segments()[..6] == [0x64, 0xff9b, 0, 0, 0, 0]
+}
+
+/// Generates the key used to sign a webhook's deliveries
+pub fn generate_secret() -> String {
+    use rand::Rng;
+
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect()
+}
+
+/// Signs a payload, formatted as the value of the signature header
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(body);
+
+    let signature = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<String>();
+
+    format!("sha256={}", signature)
+}
+
+/// Queues an event about a mod for every webhook subscribed to it
+pub async fn dispatch_mod_event<T: Serialize>(
+    event: WebhookEvent,
+    mod_id: ModId,
+    team_id: TeamId,
+    public: bool,
+    data: &T,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), DatabaseError> {
+    let webhooks =
+        Webhook::get_matching_mod(event.as_str(), mod_id, team_id, public, &mut *transaction)
+            .await?;
+
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+
+    let payload = build_payload(event, data)?;
+    WebhookDelivery::enqueue(&webhooks, event.as_str(), &payload, &mut *transaction).await?;
+
+    Ok(())
+}
+
+/// Queues an event for the account webhooks of a user that are subscribed to it
+pub async fn dispatch_user_event<T: Serialize>(
+    event: WebhookEvent,
+    user_id: UserId,
+    data: &T,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), DatabaseError> {
+    let webhooks = Webhook::get_matching_user(event.as_str(), user_id, &mut *transaction).await?;
+
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+
+    let payload = build_payload(event, data)?;
+    WebhookDelivery::enqueue(&webhooks, event.as_str(), &payload, &mut *transaction).await?;
+
+    Ok(())
+}
+
+/// Queues a `ping` event, used to check that a webhook is set up correctly
+pub async fn ping(
+    webhook_id: WebhookId,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), DatabaseError> {
+    let api_id: crate::models::ids::WebhookId = webhook_id.into();
+    let payload = build_payload(
+        WebhookEvent::Ping,
+        &serde_json::json!({ "webhook_id": api_id }),
+    )?;
+
+    WebhookDelivery::enqueue(
+        &[webhook_id],
+        WebhookEvent::Ping.as_str(),
+        &payload,
+        &mut *transaction,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Resolves hosts for the webhook client. The addresses are checked here, as
+/// they're connected to, so a host can't switch to an internal address after
+/// it has been checked.
+#[derive(Clone)]
+pub struct PublicResolver;
+
+impl Service<Name> for PublicResolver {
+    type Response = std::vec::IntoIter<IpAddr>;
+    type Error = std::io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        Box::pin(async move {
+            resolve_public(name.as_str())
+                .await
+                .map(Vec::into_iter)
+                .map_err(std::io::Error::other)
+        })
+    }
+}
+
+/// The client webhooks and announcements are sent with. It doesn't follow
+/// redirects, and only connects to public addresses.
+pub type Client = hyper::Client<HttpsConnector<HttpConnector<PublicResolver>>>;
+
+pub fn client() -> Client {
+    let mut http = HttpConnector::new_with_resolver(PublicResolver);
+    http.enforce_http(false);
+    http.set_connect_timeout(Some(REQUEST_TIMEOUT));
+
+    hyper::Client::builder().build(HttpsConnector::new_with_connector(http))
+}
+
+/// Posts a JSON body, returning the status code of the response. Hosts that
+/// are IP addresses aren't resolved, so they're checked here.
+pub async fn post_json(
+    client: &Client,
+    url: &reqwest::Url,
+    headers: &[(&str, String)],
+    body: Vec<u8>,
+) -> Result<StatusCode, String> {
+    let literal = url.host_str().and_then(|host| {
+        host.trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .ok()
+    });
+    if let (Some(ip), false) = (literal, allow_insecure()) {
+        check_address(ip)?;
+    }
+
+    let mut request = Request::post(url.as_str())
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::USER_AGENT, "XivRepo-Webhooks");
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    let request = request.body(Body::from(body)).map_err(|e| e.to_string())?;
+
+    match tokio::time::timeout(REQUEST_TIMEOUT, client.request(request)).await {
+        Ok(Ok(response)) => Ok(response.status()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("The request timed out".to_string()),
+    }
+}
+
+/// Sends the result of an attempt: the response code, if any, and an error if it failed
+async fn send(client: &Client, delivery: &DueDelivery) -> (Option<i32>, Option<String>) {
+    let body = match serde_json::to_vec(&delivery.payload) {
+        Ok(body) => body,
+        Err(e) => return (None, Some(e.to_string())),
+    };
+
+    let url = match reqwest::Url::parse(&delivery.url) {
+        Ok(url) => url,
+        Err(e) => return (None, Some(e.to_string())),
+    };
+
+    let headers = [
+        (SIGNATURE_HEADER, sign(&delivery.secret, &body)),
+        (EVENT_HEADER, delivery.event.clone()),
+        (DELIVERY_HEADER, delivery.id.to_string()),
+    ];
+
+    match post_json(client, &url, &headers, body).await {
+        Ok(status) if status.is_success() => (Some(status.as_u16() as i32), None),
+        Ok(status) => (
+            Some(status.as_u16() as i32),
+            Some(format!("Receiver responded with {}", status)),
+        ),
+        Err(e) => (None, Some(e)),
+    }
+}
+
+/// Sends a batch of due webhook deliveries. Failed deliveries are retried with
+/// exponential backoff, up to `MAX_ATTEMPTS` times.
+pub async fn deliver_pending(pool: &PgPool, client: &Client) -> Result<usize, sqlx::error::Error> {
+    let deliveries =
+        WebhookDelivery::claim_due(50, chrono::Duration::seconds(DELIVERY_LEASE), pool).await?;
+    let mut delivered = 0;
+
+    for delivery in deliveries {
+        match send(client, &delivery).await {
+            (Some(code), None) => {
+                WebhookDelivery::mark_delivered(delivery.id, code, pool).await?;
+                delivered += 1;
+            }
+            (code, error) => {
+                let error: String = error.unwrap_or_default().chars().take(2048).collect();
+                warn!(
+                    "Webhook delivery {} to webhook {:?} failed: {}",
+                    delivery.id, delivery.webhook_id, error
+                );
+
+                let attempts = delivery.attempts + 1;
+                let retry_at = if attempts >= MAX_ATTEMPTS {
+                    None
+                } else {
+                    Some(
+                        chrono::Utc::now()
+                            + chrono::Duration::seconds(BASE_RETRY_DELAY << delivery.attempts),
+                    )
+                };
+
+                WebhookDelivery::mark_attempt_failed(delivery.id, code, &error, retry_at, pool)
+                    .await?;
+            }
+        }
+    }
+
+    Ok(delivered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_rejected() {
+        for ip in &[
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00:ec2::254",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "2002:7f00:1::1",
+            "2002:a9fe:a9fe::",
+            "64:ff9b::7f00:1",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(check_address(ip.parse().unwrap()).is_err(), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn public_addresses_are_allowed() {
+        for ip in &["1.1.1.1", "162.159.135.232", "2606:4700:4700::1111"] {
+            assert!(check_address(ip.parse().unwrap()).is_ok(), "{}", ip);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn literal_hosts_are_checked() {
+        assert!(validate_url("https://127.0.0.1/hook").await.is_err());
+        assert!(validate_url("https://[::1]:8443/hook").await.is_err());
+        assert!(validate_url("https://169.254.169.254/latest/meta-data")
+            .await
+            .is_err());
+        assert!(validate_url("https://1.1.1.1/hook").await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn deliveries_to_internal_hosts_are_refused() {
+        let client = client();
+
+        for url in &["http://localhost:1/hook", "http://127.0.0.1:1/hook"] {
+            let url = reqwest::Url::parse(url).unwrap();
+            let error = post_json(&client, &url, &[], vec![]).await.unwrap_err();
+
+            assert!(error.contains("internal addresses"), "{}: {}", url, error);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use super::ApiError;
+use crate::auth::check_is_admin_from_headers;
+use crate::database::models::cache::{self, CacheStats};
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+/// The size and hit rate of every cache of this instance, counted since it
+/// was started
+#[utoipa::path(
+    context_path = "/api/v1/admin/",
+    tag = "admin",
+    security(("token" = [])),
+    responses((status = 200, description = "The statistics of every cache", body = Vec<CacheStats>)),
+)]
+#[get("cache")]
+pub async fn cache_stats(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    check_is_admin_from_headers(req.headers(), &**pool).await?;
+
+    Ok(HttpResponse::Ok().json(cache::all_stats()))
+}
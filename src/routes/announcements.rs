@@ -0,0 +1,236 @@
+use super::ApiError;
+use crate::auth::check_is_admin_from_headers;
+use crate::database::models;
+use crate::database::models::announcement_item::{
+    AnnouncementChannel as DBChannel, AnnouncementChannelBuilder,
+};
+use crate::database::models::categories::Category;
+use crate::models::announcements::AnnouncementChannel;
+use crate::notifications::webhooks::validate_url;
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateChannel {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub allow_nsfw: bool,
+    #[serde(default = "default_true")]
+    pub announce_approvals: bool,
+    #[serde(default = "default_true")]
+    pub announce_versions: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct EditChannel {
+    pub name: Option<String>,
+    pub url: Option<String>,
+    pub categories: Option<Vec<String>>,
+    pub allow_nsfw: Option<bool>,
+    pub announce_approvals: Option<bool>,
+    pub announce_versions: Option<bool>,
+}
+
+async fn check_categories(categories: &[String], pool: &PgPool) -> Result<(), ApiError> {
+    for category in categories {
+        if Category::get_id(category, pool).await?.is_none() {
+            return Err(ApiError::InvalidInputError(format!(
+                "Invalid category: {}",
+                category
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "announcements",
+    security(("token" = [])),
+    responses((status = 200, description = "Every announcement channel. Only available to admins.", body = Vec<AnnouncementChannel>)),
+)]
+#[get("announcement_channels")]
+pub async fn channel_list(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    check_is_admin_from_headers(req.headers(), &**pool).await?;
+
+    let channels = DBChannel::list(&**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(HttpResponse::Ok().json(
+        channels
+            .into_iter()
+            .map(convert_channel)
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "announcements",
+    request_body = CreateChannel,
+    security(("token" = [])),
+    responses((status = 200, description = "The channel was created", body = AnnouncementChannel)),
+)]
+#[post("announcement_channel")]
+pub async fn channel_create(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    new_channel: web::Json<CreateChannel>,
+) -> Result<HttpResponse, ApiError> {
+    check_is_admin_from_headers(req.headers(), &**pool).await?;
+
+    let new_channel = new_channel.into_inner();
+
+    validate_url(&new_channel.url)
+        .await
+        .map_err(ApiError::InvalidInputError)?;
+    check_categories(&new_channel.categories, &pool).await?;
+
+    let id = AnnouncementChannelBuilder {
+        name: new_channel.name,
+        url: new_channel.url,
+        categories: new_channel.categories,
+        allow_nsfw: new_channel.allow_nsfw,
+        announce_approvals: new_channel.announce_approvals,
+        announce_versions: new_channel.announce_versions,
+    }
+    .insert(&**pool)
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let channel = DBChannel::get(id, &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    match channel {
+        Some(channel) => Ok(HttpResponse::Ok().json(convert_channel(channel))),
+        None => Ok(HttpResponse::NotFound().body("")),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "announcements",
+    request_body = EditChannel,
+    security(("token" = [])),
+    responses((status = 200, description = "The channel was edited"), (status = 404, description = "The channel doesn't exist")),
+)]
+#[patch("announcement_channel/{id}")]
+pub async fn channel_edit(
+    req: HttpRequest,
+    info: web::Path<(i32,)>,
+    pool: web::Data<PgPool>,
+    edit_channel: web::Json<EditChannel>,
+) -> Result<HttpResponse, ApiError> {
+    check_is_admin_from_headers(req.headers(), &**pool).await?;
+
+    let mut channel = match DBChannel::get(info.into_inner().0, &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?
+    {
+        Some(channel) => channel,
+        None => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    let edit_channel = edit_channel.into_inner();
+
+    if let Some(name) = edit_channel.name {
+        channel.name = name;
+    }
+
+    if let Some(url) = edit_channel.url {
+        validate_url(&url)
+            .await
+            .map_err(ApiError::InvalidInputError)?;
+        channel.url = url;
+    }
+
+    if let Some(categories) = edit_channel.categories {
+        check_categories(&categories, &pool).await?;
+        channel.categories = categories;
+    }
+
+    if let Some(allow_nsfw) = edit_channel.allow_nsfw {
+        channel.allow_nsfw = allow_nsfw;
+    }
+
+    if let Some(announce_approvals) = edit_channel.announce_approvals {
+        channel.announce_approvals = announce_approvals;
+    }
+
+    if let Some(announce_versions) = edit_channel.announce_versions {
+        channel.announce_versions = announce_versions;
+    }
+
+    channel
+        .edit(&**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "announcements",
+    security(("token" = [])),
+    responses((status = 200, description = "The channel was deleted"), (status = 404, description = "The channel doesn't exist")),
+)]
+#[delete("announcement_channel/{id}")]
+pub async fn channel_delete(
+    req: HttpRequest,
+    info: web::Path<(i32,)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    check_is_admin_from_headers(req.headers(), &**pool).await?;
+
+    let id = info.into_inner().0;
+
+    let channel = DBChannel::get(id, &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    if channel.is_none() {
+        return Ok(HttpResponse::NotFound().body(""));
+    }
+
+    let mut transaction = pool.begin().await.map_err(models::DatabaseError::from)?;
+
+    DBChannel::remove(id, &mut transaction)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(models::DatabaseError::from)?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
+
+fn convert_channel(data: DBChannel) -> AnnouncementChannel {
+    AnnouncementChannel {
+        id: data.id,
+        name: data.name,
+        url: data.url,
+        categories: data.categories,
+        allow_nsfw: data.allow_nsfw,
+        announce_approvals: data.announce_approvals,
+        announce_versions: data.announce_versions,
+        created: data.created,
+    }
+}
@@ -14,6 +14,7 @@ use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgPool;
 use thiserror::Error;
+use utoipa::{IntoParams, ToSchema};
 
 pub fn config(cfg: &mut ServiceConfig) {
     cfg.service(scope("/auth/").service(auth_callback).service(init));
@@ -69,12 +70,14 @@ impl actix_web::ResponseError for AuthorizationError {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, IntoParams, ToSchema)]
+#[into_params(parameter_in = Query)]
 pub struct AuthorizationInit {
     pub url: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Authorization {
     pub code: Option<String>,
     pub state: Option<String>,
@@ -89,6 +92,12 @@ pub struct AccessToken {
 }
 
 //http://localhost:8000/api/v1/auth/init?url=siteurl
+#[utoipa::path(
+    context_path = "/api/v1/auth/",
+    tag = "auth",
+    params(AuthorizationInit),
+    responses((status = 307, description = "Redirects to Discord to authorize the login", body = AuthorizationInit)),
+)]
 #[get("init")]
 pub async fn init(
     Query(info): Query<AuthorizationInit>,
@@ -126,6 +135,12 @@ pub async fn init(
         .json(AuthorizationInit { url }))
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/auth/",
+    tag = "auth",
+    params(Authorization),
+    responses((status = 307, description = "Redirects back to the site with the new access token"), (status = 401, description = "The login state is invalid or expired")),
+)]
 #[get("callback")]
 pub async fn auth_callback(
     Query(info): Query<Authorization>,
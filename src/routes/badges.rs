@@ -0,0 +1,248 @@
+use super::embeds::get_public_mod;
+use crate::database;
+use crate::routes::ApiError;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{get, web, HttpResponse};
+use askama::Template;
+use serde::Deserialize;
+use sqlx::PgPool;
+use utoipa::IntoParams;
+
+/// How long badges may be cached, in seconds.  Kept short so counters
+/// embedded in READMEs stay reasonably fresh.
+const BADGE_MAX_AGE: u32 = 5 * 60;
+
+/// Labels longer than this are cut off
+const MAX_LABEL_LENGTH: usize = 64;
+
+const COLOR_GREEN: &str = "#1bd96a";
+const COLOR_BLUE: &str = "#4f9cf6";
+const COLOR_ORANGE: &str = "#fe7d37";
+const COLOR_RED: &str = "#e05d44";
+const COLOR_GREY: &str = "#9f9f9f";
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BadgeQuery {
+    /// One of `flat`, `flat-square` or `plastic`
+    pub style: Option<String>,
+    /// Replaces the text on the left of the badge
+    pub label: Option<String>,
+}
+
+#[derive(Copy, Clone)]
+enum BadgeStyle {
+    Flat,
+    FlatSquare,
+    Plastic,
+}
+
+impl BadgeStyle {
+    fn from_str(string: &str) -> BadgeStyle {
+        match string {
+            "flat-square" => BadgeStyle::FlatSquare,
+            "plastic" => BadgeStyle::Plastic,
+            _ => BadgeStyle::Flat,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "badge.svg", escape = "html")]
+struct BadgeSvg<'a> {
+    label: &'a str,
+    message: &'a str,
+    color: &'a str,
+    width: u32,
+    height: u32,
+    radius: u32,
+    gradient: bool,
+    label_width: u32,
+    message_width: u32,
+    label_x: f32,
+    message_x: f32,
+    text_y: u32,
+}
+
+/// Approximates the width of text in 11px Verdana, the font badges are drawn in
+fn text_width(text: &str) -> u32 {
+    let width: f32 = text
+        .chars()
+        .map(|c| match c {
+            'i' | 'j' | 'l' | '.' | ',' | ':' | ';' | '\'' | '!' | '|' => 3.5,
+            'f' | 'r' | 't' | 'I' | ' ' | '(' | ')' | '[' | ']' | '-' => 4.5,
+            'm' | 'w' | 'M' | 'W' => 10.0,
+            c if c.is_ascii_uppercase() => 7.5,
+            _ => 7.0,
+        })
+        .sum();
+
+    width.ceil() as u32
+}
+
+fn render_badge(label: &str, message: &str, color: &str, style: BadgeStyle) -> HttpResponse {
+    let (height, radius, gradient, text_y) = match style {
+        BadgeStyle::Flat => (20, 3, true, 14),
+        BadgeStyle::FlatSquare => (20, 0, false, 14),
+        BadgeStyle::Plastic => (18, 4, true, 13),
+    };
+
+    let label_width = text_width(label) + 10;
+    let message_width = text_width(message) + 10;
+
+    let svg = BadgeSvg {
+        label,
+        message,
+        color,
+        width: label_width + message_width,
+        height,
+        radius,
+        gradient,
+        label_width,
+        message_width,
+        label_x: label_width as f32 / 2.0,
+        message_x: label_width as f32 + message_width as f32 / 2.0,
+        text_y,
+    };
+
+    match svg.render() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("image/svg+xml; charset=utf-8")
+            .set(CacheControl(vec![
+                CacheDirective::Public,
+                CacheDirective::MaxAge(BADGE_MAX_AGE),
+            ]))
+            .body(body),
+        Err(_) => HttpResponse::InternalServerError().body(""),
+    }
+}
+
+/// Formats a counter the way shields do, e.g. `12.3k`
+fn format_count(count: i32) -> String {
+    const UNITS: &[&str] = &["", "k", "M", "B"];
+
+    let mut value = count as f64;
+    let mut unit = 0;
+
+    // Checked after rounding, so 999,950 becomes `1M` rather than `1000k`
+    while (value * 10.0).round() >= 10_000.0 && unit + 1 < UNITS.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        return count.to_string();
+    }
+
+    let formatted = format!("{:.1}", value);
+
+    format!(
+        "{}{}",
+        formatted.strip_suffix(".0").unwrap_or(&formatted),
+        UNITS[unit]
+    )
+}
+
+/// Renders a badge for a mod.  Badges for hidden and unknown mods look the
+/// same, so they don't reveal that a hidden mod exists.
+#[utoipa::path(
+    context_path = "/badge/",
+    tag = "embeds",
+    params(("id" = String, Path, description = "The ID or slug of the mod"), ("badge" = String, Path, description = "One of `downloads`, `followers` or `version`"), BadgeQuery),
+    responses((status = 200, description = "The badge", content_type = "image/svg+xml", body = String), (status = 404, description = "The badge type doesn't exist")),
+)]
+#[get("mod/{id}/{badge}.svg")]
+pub async fn mod_badge(
+    info: web::Path<(String, String)>,
+    web::Query(query): web::Query<BadgeQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, badge) = info.into_inner();
+
+    let default_label = match &*badge {
+        "downloads" => "downloads",
+        "followers" => "followers",
+        "version" => "version",
+        _ => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    let label: String = query
+        .label
+        .as_deref()
+        .unwrap_or(default_label)
+        .chars()
+        .take(MAX_LABEL_LENGTH)
+        .collect();
+    let style = BadgeStyle::from_str(query.style.as_deref().unwrap_or_default());
+
+    let mod_data = match get_public_mod(&id, &pool).await? {
+        Some(data) => data,
+        None => return Ok(render_badge(&label, "not found", COLOR_GREY, style)),
+    };
+
+    let response = match default_label {
+        "downloads" => render_badge(
+            &label,
+            &format_count(mod_data.inner.downloads),
+            COLOR_GREEN,
+            style,
+        ),
+        "followers" => render_badge(
+            &label,
+            &format_count(mod_data.inner.follows),
+            COLOR_BLUE,
+            style,
+        ),
+        _ => {
+            let version_ids =
+                database::models::Version::get_recent_ids(vec![mod_data.inner.id], 1, &**pool)
+                    .await
+                    .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+            let version = database::models::Version::get_many_full(version_ids, &**pool)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?
+                .into_iter()
+                .next();
+
+            match version {
+                Some(version) => {
+                    let color = match &*version.release_channel {
+                        "alpha" => COLOR_RED,
+                        "beta" => COLOR_ORANGE,
+                        _ => COLOR_GREEN,
+                    };
+
+                    render_badge(&label, &version.version_number, color, style)
+                }
+                None => render_badge(&label, "none", COLOR_GREY, style),
+            }
+        }
+    };
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_are_abbreviated() {
+        assert_eq!(format_count(0), "0");
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(1000), "1k");
+        assert_eq!(format_count(1234), "1.2k");
+        assert_eq!(format_count(10_000), "10k");
+        assert_eq!(format_count(100_040), "100k");
+        assert_eq!(format_count(1_500_000), "1.5M");
+        assert_eq!(format_count(i32::MAX), "2.1B");
+    }
+
+    #[test]
+    fn rounding_moves_to_the_next_unit() {
+        assert_eq!(format_count(999_949), "999.9k");
+        assert_eq!(format_count(999_950), "1M");
+        assert_eq!(format_count(999_999_999), "1B");
+    }
+}
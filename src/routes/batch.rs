@@ -0,0 +1,32 @@
+use super::ApiError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// The maximum number of IDs a batch lookup accepts
+const MAX_BATCH_SIZE: usize = 500;
+
+/// The body of the batch lookup endpoints
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct BatchIds<T> {
+    pub ids: Vec<T>,
+}
+
+impl<T> BatchIds<T> {
+    pub fn validate(&self) -> Result<(), ApiError> {
+        if self.ids.len() > MAX_BATCH_SIZE {
+            return Err(ApiError::InvalidInputError(format!(
+                "At most {} IDs can be looked up at once",
+                MAX_BATCH_SIZE
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Lines the found items up with the requested IDs, leaving `None` for IDs
+/// that don't exist or may not be seen
+pub fn in_request_order<'a, T>(ids: &[u64], items: &'a HashMap<u64, T>) -> Vec<Option<&'a T>> {
+    ids.iter().map(|id| items.get(id)).collect()
+}
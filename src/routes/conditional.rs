@@ -0,0 +1,115 @@
+use super::ApiError;
+use actix_web::http::header::{
+    self, CacheControl, CacheDirective, ETag, EntityTag, HttpDate, IfModifiedSince, IfNoneMatch,
+    LastModified,
+};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::{Duration, SystemTime};
+
+/// Who may keep a copy of a response
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum CacheScope {
+    /// The response is the same for everyone
+    Public,
+    /// The response depends on who asked for it, like hidden mods shown to
+    /// their team
+    Private,
+}
+
+/// Serializes `data` into a JSON response carrying an `ETag`.  Answers with
+/// `304 Not Modified` instead when the client's copy is still current.
+///
+/// The ETag is a hash of the body, so it is strong and changes with anything
+/// in the response; the response has to be built to compute it, which saves
+/// bandwidth but not the queries behind it.
+pub fn json_response<T: Serialize>(
+    req: &HttpRequest,
+    data: &T,
+    scope: CacheScope,
+) -> Result<HttpResponse, ApiError> {
+    respond(req, data, None, scope)
+}
+
+/// Like `json_response`, with a `Last-Modified` header as well.  Clients that
+/// only send `If-Modified-Since` are told their copy is current as long as
+/// `last_modified` hasn't moved, so it has to change whenever the response
+/// does, apart from counters like downloads.
+pub fn json_response_modified<T: Serialize>(
+    req: &HttpRequest,
+    data: &T,
+    last_modified: DateTime<Utc>,
+    scope: CacheScope,
+) -> Result<HttpResponse, ApiError> {
+    respond(req, data, Some(last_modified), scope)
+}
+
+fn respond<T: Serialize>(
+    req: &HttpRequest,
+    data: &T,
+    last_modified: Option<DateTime<Utc>>,
+    scope: CacheScope,
+) -> Result<HttpResponse, ApiError> {
+    let body = serde_json::to_vec(data)?;
+
+    let mut hasher = sha1::Sha1::new();
+    if let Some(last_modified) = last_modified {
+        hasher.update(last_modified.to_rfc3339().as_bytes());
+    }
+    hasher.update(&body);
+    let etag = EntityTag::strong(hasher.hexdigest());
+
+    // HTTP dates only have a precision of one second
+    let last_modified = last_modified.map(|x| {
+        HttpDate::from(SystemTime::UNIX_EPOCH + Duration::from_secs(x.timestamp().max(0) as u64))
+    });
+
+    // Clients may keep a copy, but have to check that it's current before using it
+    let cache_control = CacheControl(vec![
+        match scope {
+            CacheScope::Public => CacheDirective::Public,
+            CacheScope::Private => CacheDirective::Private,
+        },
+        CacheDirective::NoCache,
+    ]);
+
+    let fresh = is_fresh(req, &etag, last_modified);
+
+    let mut response = if fresh {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+
+    response.set(cache_control).set(ETag(etag));
+
+    if let Some(last_modified) = last_modified {
+        response.set(LastModified(last_modified));
+    }
+
+    if fresh {
+        Ok(response.finish())
+    } else {
+        Ok(response.content_type("application/json").body(body))
+    }
+}
+
+/// Whether the client's copy matches the current one.  `If-None-Match` takes
+/// precedence over `If-Modified-Since`, as RFC 7232 requires.
+fn is_fresh(req: &HttpRequest, etag: &EntityTag, last_modified: Option<HttpDate>) -> bool {
+    if req.headers().contains_key(header::IF_NONE_MATCH) {
+        return match req.get_header::<IfNoneMatch>() {
+            Some(IfNoneMatch::Any) => true,
+            Some(IfNoneMatch::Items(items)) => items.iter().any(|x| x.weak_eq(etag)),
+            None => false,
+        };
+    }
+
+    match (req.get_header::<IfModifiedSince>(), last_modified) {
+        (Some(IfModifiedSince(since)), Some(last_modified)) => {
+            SystemTime::from(since) >= SystemTime::from(last_modified)
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,212 @@
+use crate::database;
+use crate::database::models::mod_item::QueryMod;
+use crate::database::models::{TeamMember, User};
+use crate::models::embeds::{OEmbed, OpenGraph, OpenGraphAuthor};
+use crate::models::ids::{ModId, UserId};
+use crate::models::teams::OWNER_ROLE;
+use crate::routes::ApiError;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{get, web, HttpResponse};
+use serde::Deserialize;
+use sqlx::PgPool;
+use utoipa::IntoParams;
+
+/// How long previews may be cached, in seconds
+const EMBED_MAX_AGE: u32 = 60 * 60;
+
+const PROVIDER_NAME: &str = "XivRepo";
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OEmbedQuery {
+    /// The URL of the page to embed
+    pub url: String,
+    /// Only `json` is supported
+    pub format: Option<String>,
+}
+
+fn site_url() -> String {
+    dotenv::var("SITE_URL").unwrap_or_default()
+}
+
+fn mod_url(data: &QueryMod) -> String {
+    let id: ModId = data.inner.id.into();
+
+    format!(
+        "{}/mod/{}",
+        site_url(),
+        data.inner.slug.clone().unwrap_or_else(|| id.to_string())
+    )
+}
+
+fn embed_response<T: serde::Serialize>(data: &T) -> HttpResponse {
+    HttpResponse::Ok()
+        .set(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(EMBED_MAX_AGE),
+        ]))
+        .json(data)
+}
+
+/// Gets a mod by its ID or slug, if it is publicly visible
+pub async fn get_public_mod(string: &str, pool: &PgPool) -> Result<Option<QueryMod>, ApiError> {
+    let id_option: Option<ModId> = serde_json::from_str(&format!("\"{}\"", string)).ok();
+
+    let mut mod_data = None;
+
+    if let Some(id) = id_option {
+        mod_data = database::models::Mod::get_full(id.into(), pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    }
+
+    if mod_data.is_none() {
+        mod_data = database::models::Mod::get_full_from_slug(string, pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    }
+
+    // Previews are public, so hidden mods are never shown, even to their team
+    Ok(mod_data.filter(|x| !x.status.is_hidden()))
+}
+
+async fn get_user(string: &str, pool: &PgPool) -> Result<Option<User>, ApiError> {
+    let id_option: Option<UserId> = serde_json::from_str(&format!("\"{}\"", string)).ok();
+
+    let mut user_data = None;
+
+    if let Some(id) = id_option {
+        user_data = User::get(id.into(), pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    }
+
+    if user_data.is_none() {
+        user_data = User::get_from_username(string.to_string(), pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    }
+
+    Ok(user_data)
+}
+
+/// The owner of a mod's team
+async fn get_owner(data: &QueryMod, pool: &PgPool) -> Result<Option<User>, ApiError> {
+    let owner = TeamMember::get_from_team(data.inner.team_id, pool)
+        .await?
+        .into_iter()
+        .find(|x| x.accepted && x.role == OWNER_ROLE);
+
+    match owner {
+        Some(owner) => Ok(User::get(owner.user_id, pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?),
+        None => Ok(None),
+    }
+}
+
+/// oEmbed provider for mod and user pages of the site
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "embeds",
+    params(OEmbedQuery),
+    responses((status = 200, description = "The oEmbed response for the page", body = OEmbed), (status = 404, description = "The URL isn't a mod or user page"), (status = 501, description = "The format isn't supported")),
+)]
+#[get("oembed")]
+pub async fn oembed(
+    web::Query(query): web::Query<OEmbedQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    // The spec requires unsupported formats to be answered with 501
+    if query.format.as_deref().unwrap_or("json") != "json" {
+        return Ok(HttpResponse::NotImplemented().body(""));
+    }
+
+    let site_url = site_url();
+
+    let path = match query.url.strip_prefix(site_url.trim_end_matches('/')) {
+        Some(path) => path.split(['?', '#']).next().unwrap_or_default(),
+        None => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    let segments: Vec<&str> = path.split('/').filter(|x| !x.is_empty()).collect();
+
+    let embed = match segments.as_slice() {
+        ["mod", id, ..] => {
+            let data = match get_public_mod(id, &pool).await? {
+                Some(data) => data,
+                None => return Ok(HttpResponse::NotFound().body("")),
+            };
+
+            let owner = get_owner(&data, &pool).await?;
+
+            OEmbed {
+                version: "1.0".to_string(),
+                type_: "link".to_string(),
+                title: data.inner.title,
+                author_url: owner
+                    .as_ref()
+                    .map(|x| format!("{}/user/{}", site_url, x.username)),
+                author_name: owner.map(|x| x.username),
+                provider_name: PROVIDER_NAME.to_string(),
+                provider_url: site_url,
+                cache_age: EMBED_MAX_AGE,
+            }
+        }
+        ["user", id, ..] => {
+            let user = match get_user(id, &pool).await? {
+                Some(user) => user,
+                None => return Ok(HttpResponse::NotFound().body("")),
+            };
+
+            OEmbed {
+                version: "1.0".to_string(),
+                type_: "link".to_string(),
+                title: user.name.clone().unwrap_or_else(|| user.username.clone()),
+                author_url: Some(format!("{}/user/{}", site_url, user.username)),
+                author_name: Some(user.username),
+                provider_name: PROVIDER_NAME.to_string(),
+                provider_url: site_url,
+                cache_age: EMBED_MAX_AGE,
+            }
+        }
+        _ => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    Ok(embed_response(&embed))
+}
+
+/// The metadata used for previews of a mod page
+#[utoipa::path(
+    context_path = "/api/v1/mod/",
+    tag = "embeds",
+    params(("id" = String, Path, description = "The ID or slug of the mod")),
+    responses((status = 200, description = "The preview metadata of the mod", body = OpenGraph), (status = 404, description = "The mod doesn't exist")),
+)]
+#[get("{id}/opengraph")]
+pub async fn mod_opengraph(
+    info: web::Path<(String,)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let data = match get_public_mod(&info.into_inner().0, &pool).await? {
+        Some(data) => data,
+        None => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    let owner = get_owner(&data, &pool).await?;
+    let url = mod_url(&data);
+
+    Ok(embed_response(&OpenGraph {
+        title: data.inner.title,
+        description: data.inner.description,
+        url,
+        image: data.inner.icon_url,
+        author: owner.map(|x| OpenGraphAuthor {
+            url: format!("{}/user/{}", site_url(), x.username),
+            username: x.username,
+            avatar_url: x.avatar_url,
+        }),
+        site_name: PROVIDER_NAME.to_string(),
+        is_nsfw: data.inner.is_nsfw,
+    }))
+}
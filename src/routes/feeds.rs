@@ -0,0 +1,479 @@
+use crate::database;
+use crate::database::models::categories::Category;
+use crate::database::models::mod_item::QueryMod;
+use crate::database::models::version_item::QueryVersion;
+use crate::database::models::User;
+use crate::models::ids::{ModId, UserId, VersionId};
+use crate::models::mods::ModStatus;
+use crate::routes::ApiError;
+use actix_web::http::header::{
+    CacheControl, CacheDirective, HttpDate, IfModifiedSince, LastModified,
+};
+use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use utoipa::IntoParams;
+use xml::writer::{EmitterConfig, XmlEvent};
+
+/// The maximum number of entries in a feed
+const FEED_SIZE: i64 = 50;
+/// How long feed readers and proxies may cache a feed, in seconds
+const FEED_MAX_AGE: u32 = 15 * 60;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FeedQuery {
+    /// Whether NSFW mods are included. The feed of an NSFW mod is only found
+    /// with this set.
+    #[serde(default)]
+    pub nsfw: bool,
+}
+
+struct Feed {
+    id: String,
+    title: String,
+    link: String,
+    self_link: String,
+    updated: DateTime<Utc>,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    id: String,
+    title: String,
+    link: String,
+    published: DateTime<Utc>,
+    author: Option<String>,
+    summary: String,
+    categories: Vec<String>,
+}
+
+fn site_url() -> String {
+    dotenv::var("SITE_URL").unwrap_or_default()
+}
+
+fn mod_link(data: &QueryMod) -> String {
+    let id: ModId = data.inner.id.into();
+
+    format!(
+        "{}/mod/{}",
+        site_url(),
+        data.inner.slug.clone().unwrap_or_else(|| id.to_string())
+    )
+}
+
+fn mod_entry(data: &QueryMod, published: DateTime<Utc>) -> Entry {
+    let id: ModId = data.inner.id.into();
+
+    Entry {
+        id: format!("{}/mod/{}", site_url(), id),
+        title: data.inner.title.clone(),
+        link: mod_link(data),
+        published,
+        author: None,
+        summary: data.inner.description.clone(),
+        categories: data.categories.clone(),
+    }
+}
+
+fn version_entry(data: &QueryVersion, mod_data: &QueryMod) -> Entry {
+    let id: VersionId = data.id.into();
+
+    Entry {
+        id: format!("{}/version/{}", site_url(), id),
+        title: format!("{} {}", mod_data.inner.title, data.version_number),
+        link: format!("{}/version/{}", mod_link(mod_data), id),
+        published: data.date_published,
+        author: None,
+        summary: if data.changelog.is_empty() {
+            data.name.clone()
+        } else {
+            data.changelog.clone()
+        },
+        categories: vec![data.release_channel.clone()],
+    }
+}
+
+fn write_element<W: std::io::Write>(
+    writer: &mut xml::EventWriter<W>,
+    name: &str,
+    text: &str,
+) -> xml::writer::Result<()> {
+    writer.write(XmlEvent::start_element(name))?;
+    writer.write(XmlEvent::characters(text))?;
+    writer.write(XmlEvent::end_element())
+}
+
+fn write_feed(feed: &Feed) -> xml::writer::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .create_writer(&mut buffer);
+
+    writer.write(XmlEvent::start_element("feed").default_ns("http://www.w3.org/2005/Atom"))?;
+    write_element(&mut writer, "id", &feed.id)?;
+    write_element(&mut writer, "title", &feed.title)?;
+    write_element(&mut writer, "updated", &feed.updated.to_rfc3339())?;
+    writer.write(
+        XmlEvent::start_element("link")
+            .attr("rel", "alternate")
+            .attr("href", &feed.link),
+    )?;
+    writer.write(XmlEvent::end_element())?;
+    writer.write(
+        XmlEvent::start_element("link")
+            .attr("rel", "self")
+            .attr("href", &feed.self_link),
+    )?;
+    writer.write(XmlEvent::end_element())?;
+
+    for entry in &feed.entries {
+        writer.write(XmlEvent::start_element("entry"))?;
+        write_element(&mut writer, "id", &entry.id)?;
+        write_element(&mut writer, "title", &entry.title)?;
+        write_element(&mut writer, "published", &entry.published.to_rfc3339())?;
+        write_element(&mut writer, "updated", &entry.published.to_rfc3339())?;
+        writer.write(XmlEvent::start_element("link").attr("href", &entry.link))?;
+        writer.write(XmlEvent::end_element())?;
+
+        if let Some(author) = &entry.author {
+            writer.write(XmlEvent::start_element("author"))?;
+            write_element(&mut writer, "name", author)?;
+            writer.write(XmlEvent::end_element())?;
+        }
+
+        for category in &entry.categories {
+            writer.write(XmlEvent::start_element("category").attr("term", category))?;
+            writer.write(XmlEvent::end_element())?;
+        }
+
+        writer.write(XmlEvent::start_element("summary").attr("type", "text"))?;
+        writer.write(XmlEvent::characters(&entry.summary))?;
+        writer.write(XmlEvent::end_element())?;
+
+        writer.write(XmlEvent::end_element())?;
+    }
+
+    writer.write(XmlEvent::end_element())?;
+
+    Ok(buffer)
+}
+
+/// Renders a feed, answering with `304 Not Modified` if the client's copy is still current
+fn feed_response(req: &HttpRequest, mut feed: Feed) -> Result<HttpResponse, ApiError> {
+    feed.entries.sort_by_key(|x| std::cmp::Reverse(x.published));
+    feed.entries.truncate(FEED_SIZE as usize);
+
+    if let Some(newest) = feed.entries.first() {
+        if newest.published > feed.updated {
+            feed.updated = newest.published;
+        }
+    }
+
+    // HTTP dates only have a precision of one second
+    let last_modified =
+        SystemTime::UNIX_EPOCH + Duration::from_secs(feed.updated.timestamp().max(0) as u64);
+    let cache_control = CacheControl(vec![
+        CacheDirective::Public,
+        CacheDirective::MaxAge(FEED_MAX_AGE),
+    ]);
+
+    if let Some(IfModifiedSince(since)) = req.get_header::<IfModifiedSince>() {
+        if SystemTime::from(since) >= last_modified {
+            return Ok(HttpResponse::NotModified()
+                .set(cache_control)
+                .set(LastModified(HttpDate::from(last_modified)))
+                .finish());
+        }
+    }
+
+    let body = write_feed(&feed).map_err(|e| ApiError::XmlError(e.to_string()))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .set(cache_control)
+        .set(LastModified(HttpDate::from(last_modified)))
+        .body(body))
+}
+
+/// The canonical URL of a feed, also used as its ID. It's built from
+/// `API_URL` rather than the request, so it doesn't change with the host or
+/// the query the feed was requested with.
+fn feed_url(path: &str, nsfw: bool) -> String {
+    format!(
+        "{}/feed/{}{}",
+        dotenv::var("API_URL").unwrap_or_default(),
+        path,
+        if nsfw { "?nsfw=true" } else { "" }
+    )
+}
+
+/// Gets versions along with the mods they belong to, skipping versions of unknown mods
+async fn get_version_entries(
+    version_ids: Vec<database::models::VersionId>,
+    mods: &HashMap<i64, QueryMod>,
+    pool: &PgPool,
+) -> Result<Vec<Entry>, ApiError> {
+    let versions = database::models::Version::get_many_full(version_ids, pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(versions
+        .iter()
+        .filter_map(|version| {
+            mods.get(&version.mod_id.0)
+                .map(|mod_data| version_entry(version, mod_data))
+        })
+        .collect())
+}
+
+/// The changelog of a mod
+#[utoipa::path(
+    context_path = "/feed/",
+    tag = "feeds",
+    params(("id" = String, Path, description = "The ID or slug of the mod"), FeedQuery),
+    responses((status = 200, description = "An Atom feed of the mod's versions", content_type = "application/atom+xml", body = String), (status = 304, description = "The feed hasn't changed"), (status = 404, description = "The mod doesn't exist, or is NSFW and `nsfw` isn't set")),
+)]
+#[get("mod/{id}.atom")]
+pub async fn mod_feed(
+    req: HttpRequest,
+    info: web::Path<(String,)>,
+    web::Query(query): web::Query<FeedQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let string = info.into_inner().0;
+    let id_option: Option<ModId> = serde_json::from_str(&format!("\"{}\"", string)).ok();
+
+    let mut mod_data = None;
+
+    if let Some(id) = id_option {
+        mod_data = database::models::Mod::get_full(id.into(), &**pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    }
+
+    if mod_data.is_none() {
+        mod_data = database::models::Mod::get_full_from_slug(&string, &**pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    }
+
+    let mod_data = match mod_data {
+        // Feeds are public, so hidden mods are never served, even to their team
+        Some(data) if !data.status.is_hidden() && (query.nsfw || !data.inner.is_nsfw) => data,
+        _ => return Ok(HttpResponse::NotFound().body("")),
+    };
+    let mod_id: ModId = mod_data.inner.id.into();
+    let url = feed_url(&format!("mod/{}.atom", mod_id), query.nsfw);
+
+    let version_ids =
+        database::models::Version::get_recent_ids(vec![mod_data.inner.id], FEED_SIZE, &**pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let title = format!("{} versions", mod_data.inner.title);
+    let link = mod_link(&mod_data);
+    let updated = mod_data.inner.updated;
+
+    let mut mods = HashMap::new();
+    mods.insert(mod_data.inner.id.0, mod_data);
+
+    let entries = get_version_entries(version_ids, &mods, &pool).await?;
+
+    feed_response(
+        &req,
+        Feed {
+            id: url.clone(),
+            title,
+            link,
+            self_link: url,
+            updated,
+            entries,
+        },
+    )
+}
+
+/// New mods and versions by a user
+#[utoipa::path(
+    context_path = "/feed/",
+    tag = "feeds",
+    params(("id" = String, Path, description = "The ID or username of the user"), FeedQuery),
+    responses((status = 200, description = "An Atom feed of versions of the user's mods", content_type = "application/atom+xml", body = String), (status = 304, description = "The feed hasn't changed"), (status = 404, description = "The user doesn't exist")),
+)]
+#[get("user/{id}.atom")]
+pub async fn user_feed(
+    req: HttpRequest,
+    info: web::Path<(String,)>,
+    web::Query(query): web::Query<FeedQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let string = info.into_inner().0;
+    let id_option: Option<UserId> = serde_json::from_str(&format!("\"{}\"", string)).ok();
+
+    let mut user_data = None;
+
+    if let Some(id) = id_option {
+        user_data = User::get(id.into(), &**pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    }
+
+    if user_data.is_none() {
+        user_data = User::get_from_username(string, &**pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    }
+
+    let user = match user_data {
+        Some(user) => user,
+        None => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    let mod_ids = User::get_mods(user.id, ModStatus::Approved.as_str(), &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let mods: HashMap<i64, QueryMod> = database::models::Mod::get_many_full(mod_ids, &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?
+        .into_iter()
+        .filter(|x| query.nsfw || !x.inner.is_nsfw)
+        .map(|x| (x.inner.id.0, x))
+        .collect();
+
+    let version_ids = database::models::Version::get_recent_ids(
+        mods.values().map(|x| x.inner.id).collect(),
+        FEED_SIZE,
+        &**pool,
+    )
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let mut entries = get_version_entries(version_ids, &mods, &pool).await?;
+    entries.extend(mods.values().map(|x| mod_entry(x, x.inner.published)));
+
+    for entry in &mut entries {
+        entry.author = Some(user.username.clone());
+    }
+
+    let user_id: UserId = user.id.into();
+    let url = feed_url(&format!("user/{}.atom", user_id), query.nsfw);
+
+    feed_response(
+        &req,
+        Feed {
+            id: url.clone(),
+            title: format!("Mods by {}", user.username),
+            link: format!("{}/user/{}", site_url(), user.username),
+            self_link: url,
+            updated: user.created,
+            entries,
+        },
+    )
+}
+
+async fn approved_feed(
+    req: &HttpRequest,
+    category: Option<&str>,
+    allow_nsfw: bool,
+    title: String,
+    link: String,
+    url: String,
+    pool: &PgPool,
+) -> Result<HttpResponse, ApiError> {
+    let approved =
+        database::models::Mod::get_recently_approved(category, allow_nsfw, FEED_SIZE, pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let dates: HashMap<i64, DateTime<Utc>> = approved.iter().map(|x| ((x.0).0, x.1)).collect();
+
+    let mods =
+        database::models::Mod::get_many_full(approved.into_iter().map(|x| x.0).collect(), pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let entries = mods
+        .iter()
+        .map(|x| {
+            let approved = dates
+                .get(&x.inner.id.0)
+                .cloned()
+                .unwrap_or(x.inner.published);
+
+            mod_entry(x, approved)
+        })
+        .collect();
+
+    feed_response(
+        req,
+        Feed {
+            id: url.clone(),
+            title,
+            link,
+            self_link: url,
+            updated: DateTime::<Utc>::from(SystemTime::UNIX_EPOCH),
+            entries,
+        },
+    )
+}
+
+/// Newly approved mods in a category
+#[utoipa::path(
+    context_path = "/feed/",
+    tag = "feeds",
+    params(("name" = String, Path, description = "The name of the category"), FeedQuery),
+    responses((status = 200, description = "An Atom feed of versions of mods in the category", content_type = "application/atom+xml", body = String), (status = 304, description = "The feed hasn't changed"), (status = 404, description = "The category doesn't exist")),
+)]
+#[get("category/{name}.atom")]
+pub async fn category_feed(
+    req: HttpRequest,
+    info: web::Path<(String,)>,
+    web::Query(query): web::Query<FeedQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let category = info.into_inner().0;
+
+    if Category::get_id(&category, &**pool).await?.is_none() {
+        return Ok(HttpResponse::NotFound().body(""));
+    }
+
+    approved_feed(
+        &req,
+        Some(&category),
+        query.nsfw,
+        format!("New {} mods", category),
+        format!("{}/mods?f=categories:{}", site_url(), category),
+        feed_url(&format!("category/{}.atom", category), query.nsfw),
+        &pool,
+    )
+    .await
+}
+
+/// Newly approved mods across the whole site
+#[utoipa::path(
+    context_path = "/feed/",
+    tag = "feeds",
+    params(FeedQuery),
+    responses((status = 200, description = "An Atom feed of newly approved mods", content_type = "application/atom+xml", body = String), (status = 304, description = "The feed hasn't changed")),
+)]
+#[get("approved.atom")]
+pub async fn approved_mods_feed(
+    req: HttpRequest,
+    web::Query(query): web::Query<FeedQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    approved_feed(
+        &req,
+        None,
+        query.nsfw,
+        "Newly approved mods".to_string(),
+        format!("{}/mods", site_url()),
+        feed_url("approved.atom", query.nsfw),
+        &pool,
+    )
+    .await
+}
@@ -0,0 +1,77 @@
+use crate::file_hosting::LocalHost;
+use crate::routes::ApiError;
+use actix_files::NamedFile;
+use actix_web::http::header::{self, CacheControl, CacheDirective, HeaderValue};
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use std::sync::Arc;
+
+/// How long blobs may be cached, in seconds.  Their name is the hash of
+/// their content, so they never change.
+const BLOB_MAX_AGE: u32 = 365 * 24 * 60 * 60;
+
+/// How long other files may be cached, in seconds.  Icons uploaded before
+/// they got unique names were replaced under the same name, so they're
+/// revalidated after a day.
+const FILE_MAX_AGE: u32 = 24 * 60 * 60;
+
+/// Only served when files are stored with `STORAGE_BACKEND=local`, in which
+/// case `CDN_URL` points here.  Supports range and conditional requests.
+#[utoipa::path(
+    context_path = "/files/",
+    tag = "files",
+    responses((status = 200, description = "The file"), (status = 206, description = "The requested range of the file"), (status = 404, description = "The file doesn't exist")),
+)]
+#[get("{file_name:.*}")]
+pub async fn file_get(
+    req: HttpRequest,
+    info: web::Path<(String,)>,
+    local_host: web::Data<Arc<LocalHost>>,
+) -> Result<HttpResponse, ApiError> {
+    let file_name = info.into_inner().0;
+
+    let (file, content_type) = match local_host.open(&file_name)? {
+        Some(file) => file,
+        None => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    let max_age = if file_name.starts_with("blobs/") {
+        BLOB_MAX_AGE
+    } else {
+        FILE_MAX_AGE
+    };
+
+    let mut response = NamedFile::from_file(file, &file_name)
+        .and_then(|file| {
+            file.disable_content_disposition()
+                .into_response(&req)
+                .map_err(|e| std::io::Error::other(e.to_string()))
+        })
+        .map_err(|e| ApiError::FileHostingError(e.into()))?;
+
+    let mut directives = vec![CacheDirective::Public, CacheDirective::MaxAge(max_age)];
+    if max_age == BLOB_MAX_AGE {
+        directives.push(CacheDirective::Extension("immutable".to_string(), None));
+    }
+
+    let headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&CacheControl(directives).to_string()) {
+        headers.insert(header::CACHE_CONTROL, value);
+    }
+    // Unknown content types are left to the guess from the file's extension
+    if let Ok(value) = HeaderValue::from_str(&content_type) {
+        headers.insert(header::CONTENT_TYPE, value);
+    }
+    // Files are uploaded by users and served from the API's origin, so
+    // browsers must not run scripts in them, like in SVG icons, or guess a
+    // more dangerous type than the one given
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("sandbox"),
+    );
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+
+    Ok(response)
+}
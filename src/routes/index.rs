@@ -1,6 +1,10 @@
 use actix_web::{get, HttpResponse};
 use serde_json::json;
 
+#[utoipa::path(
+    tag = "misc",
+    responses((status = 200, description = "Information about the API")),
+)]
 #[get("/")]
 pub async fn index_get() -> HttpResponse {
     let data = json!({
@@ -1,18 +1,31 @@
 use actix_web::web;
 
+mod admin;
+mod announcements;
 mod auth;
+mod badges;
+mod batch;
+mod conditional;
+mod embeds;
+mod feeds;
+mod files;
 mod index;
 mod mod_creation;
 mod moderation;
 mod mods;
 mod not_found;
 mod notifications;
+mod openapi;
+mod pagination;
 mod reports;
+mod sitemap;
 mod tags;
 mod teams;
+mod uploads;
 mod users;
 mod version_creation;
 mod versions;
+mod webhooks;
 
 pub use auth::config as auth_config;
 pub use tags::config as tags_config;
@@ -24,6 +37,7 @@ use crate::file_hosting::FileHostingError;
 pub fn mods_config(cfg: &mut web::ServiceConfig) {
     cfg.service(mods::mod_search);
     cfg.service(mods::mods_get);
+    cfg.service(mods::mods_batch);
     cfg.service(mod_creation::mod_create);
     cfg.service(mods::mod_get_random);
 
@@ -36,18 +50,22 @@ pub fn mods_config(cfg: &mut web::ServiceConfig) {
             .service(mods::mod_icon_edit)
             .service(mods::mod_follow)
             .service(mods::mod_unfollow)
+            .service(embeds::mod_opengraph)
             .service(web::scope("{mod_id}").service(versions::version_list)),
     );
 }
 
 pub fn versions_config(cfg: &mut web::ServiceConfig) {
     cfg.service(versions::versions_get);
+    cfg.service(versions::versions_batch);
     cfg.service(version_creation::version_create);
     cfg.service(
         web::scope("version")
             .service(versions::version_get)
             .service(versions::version_delete)
             .service(version_creation::upload_file_to_version)
+            .service(version_creation::version_file_presign)
+            .service(version_creation::version_file_finalize)
             .service(versions::version_edit),
     );
     cfg.service(
@@ -62,6 +80,7 @@ pub fn users_config(cfg: &mut web::ServiceConfig) {
     cfg.service(users::user_auth_get);
 
     cfg.service(users::users_get);
+    cfg.service(users::users_batch);
     cfg.service(
         web::scope("user")
             .service(users::user_username_get)
@@ -71,6 +90,10 @@ pub fn users_config(cfg: &mut web::ServiceConfig) {
             .service(users::user_edit)
             .service(users::user_icon_edit)
             .service(users::user_notifications)
+            .service(users::user_notification_preferences_get)
+            .service(users::user_notification_preferences_edit)
+            .service(users::user_storage_get)
+            .service(users::user_storage_edit)
             .service(users::user_follows),
     );
 }
@@ -82,24 +105,105 @@ pub fn teams_config(cfg: &mut web::ServiceConfig) {
             .service(teams::edit_team_member)
             .service(teams::add_team_member)
             .service(teams::join_team)
-            .service(teams::remove_team_member),
+            .service(teams::remove_team_member)
+            .service(teams::team_storage_edit),
     );
 }
 
 pub fn notifications_config(cfg: &mut web::ServiceConfig) {
     cfg.service(notifications::notifications_get);
+    cfg.service(notifications::notifications_batch);
+    cfg.service(notifications::notifications_stream);
+    cfg.service(notifications::notifications_stream_ticket);
+    cfg.service(notifications::notifications_edit);
+    cfg.service(notifications::notifications_delete);
 
     cfg.service(
         web::scope("notification")
             .service(notifications::notification_get)
+            .service(notifications::notification_edit)
             .service(notifications::notification_delete),
     );
+
+    cfg.service(
+        web::scope("email")
+            .service(notifications::email_unsubscribe_get)
+            .service(notifications::email_unsubscribe_post),
+    );
+}
+
+pub fn webhooks_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(webhooks::webhook_create);
+    cfg.service(webhooks::webhooks_get);
+
+    cfg.service(
+        web::scope("webhook")
+            .service(webhooks::webhook_get)
+            .service(webhooks::webhook_edit)
+            .service(webhooks::webhook_delete)
+            .service(webhooks::webhook_ping)
+            .service(webhooks::webhook_deliveries),
+    );
+}
+
+pub fn announcements_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(announcements::channel_list);
+    cfg.service(announcements::channel_create);
+    cfg.service(announcements::channel_edit);
+    cfg.service(announcements::channel_delete);
+}
+
+pub fn feeds_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("feed")
+            .service(feeds::mod_feed)
+            .service(feeds::user_feed)
+            .service(feeds::category_feed)
+            .service(feeds::approved_mods_feed),
+    );
+}
+
+pub fn badges_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("badge").service(badges::mod_badge));
+}
+
+pub fn files_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("files").service(files::file_get));
+}
+
+pub fn openapi_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(openapi::openapi_json);
+    cfg.service(openapi::docs_redirect);
+    cfg.service(openapi::docs);
+}
+
+pub fn sitemap_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(sitemap::sitemap_index);
+    cfg.service(sitemap::sitemap_page);
+}
+
+pub fn embeds_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(embeds::oembed);
 }
 
 pub fn moderation_config(cfg: &mut web::ServiceConfig) {
     cfg.service(web::scope("moderation").service(moderation::mods));
 }
 
+pub fn admin_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("admin").service(admin::cache_stats));
+}
+
+pub fn uploads_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(uploads::upload_create);
+    cfg.service(
+        web::scope("upload")
+            .service(uploads::upload_get)
+            .service(uploads::upload_append)
+            .service(uploads::upload_delete),
+    );
+}
+
 pub fn reports_config(cfg: &mut web::ServiceConfig) {
     cfg.service(reports::reports);
     cfg.service(reports::report_create);
@@ -115,7 +219,7 @@ pub enum ApiError {
     #[error("Internal server error: {0}")]
     DatabaseError(#[from] crate::database::models::DatabaseError),
     #[error("Internal server error: {0}")]
-    _XmlError(String),
+    XmlError(String),
     #[error("Deserialization error: {0}")]
     JsonError(#[from] serde_json::Error),
     #[error("Authentication Error: {0}")]
@@ -128,6 +232,8 @@ pub enum ApiError {
     SearchError(#[from] meilisearch_sdk::errors::Error),
     #[error("Indexing Error: {0}")]
     IndexingError(#[from] crate::search::indexing::IndexingError),
+    #[error("Email Error: {0}")]
+    EmailError(#[from] crate::notifications::email::EmailError),
 }
 
 impl actix_web::ResponseError for ApiError {
@@ -137,12 +243,13 @@ impl actix_web::ResponseError for ApiError {
             ApiError::DatabaseError(..) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::AuthenticationError(..) => actix_web::http::StatusCode::UNAUTHORIZED,
             ApiError::CustomAuthenticationError(..) => actix_web::http::StatusCode::UNAUTHORIZED,
-            ApiError::_XmlError(..) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::XmlError(..) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::JsonError(..) => actix_web::http::StatusCode::BAD_REQUEST,
             ApiError::SearchError(..) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::IndexingError(..) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::FileHostingError(..) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::InvalidInputError(..) => actix_web::http::StatusCode::BAD_REQUEST,
+            ApiError::EmailError(..) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
@@ -154,12 +261,13 @@ impl actix_web::ResponseError for ApiError {
                     ApiError::DatabaseError(..) => "database_error",
                     ApiError::AuthenticationError(..) => "unauthorized",
                     ApiError::CustomAuthenticationError(..) => "unauthorized",
-                    ApiError::_XmlError(..) => "xml_error",
+                    ApiError::XmlError(..) => "xml_error",
                     ApiError::JsonError(..) => "json_error",
                     ApiError::SearchError(..) => "search_error",
                     ApiError::IndexingError(..) => "indexing_error",
                     ApiError::FileHostingError(..) => "file_hosting_error",
                     ApiError::InvalidInputError(..) => "invalid_input",
+                    ApiError::EmailError(..) => "email_error",
                 },
                 description: &self.to_string(),
             },
@@ -1,6 +1,7 @@
 use crate::auth::{get_user_from_headers, AuthenticationError};
 use crate::database::models;
-use crate::file_hosting::{FileHost, FileHostingError};
+use crate::database::models::quota_item::StorageQuota;
+use crate::file_hosting::{ByteStream, FileHost, FileHostingError};
 use crate::models::error::ApiError;
 use crate::models::mods::{DonationLink, ModId, ModStatus, VersionId};
 use crate::models::users::UserId;
@@ -128,6 +129,23 @@ pub struct UploadedFile {
     pub file_name: String,
 }
 
+/// The bytes of a multipart field, to be streamed to the file host
+pub fn field_stream(field: &mut Field) -> ByteStream<'_> {
+    Box::pin(field.map(|chunk| {
+        chunk.map_err(|e| FileHostingError::PayloadError(e.to_string()))
+    }))
+}
+
+/// Reports problems with a streamed upload that the client caused as invalid
+/// input, with `too_large` as the message for files over the size cap
+pub fn upload_error(error: FileHostingError, too_large: &str) -> CreateError {
+    match error {
+        FileHostingError::FileTooLarge(_) => CreateError::InvalidInput(too_large.to_string()),
+        FileHostingError::PayloadError(e) => CreateError::InvalidInput(e),
+        e => CreateError::FileHostingError(e),
+    }
+}
+
 pub async fn undo_uploads(
     file_host: &dyn FileHost,
     uploaded_files: &[UploadedFile],
@@ -140,6 +158,13 @@ pub async fn undo_uploads(
     Ok(())
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "mods",
+    request_body(content_type = "multipart/form-data", description = "A `data` field holding the mod as JSON, followed by the icon and the files of the initial versions"),
+    security(("token" = [])),
+    responses((status = 200, description = "The mod was created", body = crate::models::mods::Mod)),
+)]
 #[post("mod")]
 pub async fn mod_create(
     req: HttpRequest,
@@ -199,7 +224,7 @@ Get logged in user
         - Check for matching version
         - File size limits?
         - Check file type
-            - Eventually, malware scan
+            - Malware scan runs after storing, see file_hosting::scanning
         - Upload to backblaze & create VersionFileBuilder
     -
 
@@ -224,6 +249,10 @@ async fn mod_create_inner(
 
     let mod_id: ModId = models::generate_mod_id(transaction).await?.into();
 
+    // The mod's team is only created along with the mod, so its quota can't
+    // have been used up yet
+    let mut quota = StorageQuota::get(current_user.id.into(), None, transaction).await?;
+
     let mod_create_data;
     let mut versions;
     let mut versions_map = std::collections::HashMap::new();
@@ -413,19 +442,19 @@ async fn mod_create_inner(
 
         // `index` is always valid for these lists
         let created_version = versions.get_mut(index).unwrap();
-        let version_data = mod_create_data.initial_versions.get(index).unwrap();
 
         // Upload the new jar file
-        let file_builder = super::version_creation::upload_file(
+        let mut file_builder = super::version_creation::upload_file(
             &mut field,
             file_host,
+            &mut *transaction,
             uploaded_files,
             &cdn_url,
             &content_disposition,
-            mod_id,
-            &version_data.version_number,
+            &mut quota,
         )
         .await?;
+        file_builder.primary = created_version.files.is_empty();
 
         // Add the newly uploaded file to the existing or new version
         created_version.files.push(file_builder);
@@ -574,6 +603,12 @@ async fn create_initial_version(
         )));
     }
 
+    if !version_data.uploads.is_empty() {
+        return Err(CreateError::InvalidInput(String::from(
+            "Resumable uploads can only be added to versions of existing mods",
+        )));
+    }
+
     check_length(3..=256, "version name", &version_data.version_title)?;
     check_length(1..=32, "version number", &version_data.version_number)?;
 
@@ -621,24 +656,15 @@ async fn process_icon_upload(
     cdn_url: &str,
 ) -> Result<String, CreateError> {
     if let Some(content_type) = get_image_content_type(file_extension) {
-        let mut data = Vec::new();
-        while let Some(chunk) = field.next().await {
-            data.extend_from_slice(&chunk.map_err(CreateError::MultipartError)?);
-        }
-
-        if data.len() >= 1048576 {
-            return Err(CreateError::InvalidInput(String::from(
-                "Icons can not be larger than 1MiB",
-            )));
-        }
-
         let upload_data = file_host
             .upload_file(
                 content_type,
                 &format!("data/{}/icon.{}", mod_id, file_extension),
-                data,
+                field_stream(&mut field),
+                1048576,
             )
-            .await?;
+            .await
+            .map_err(|e| upload_error(e, "Icons can not be larger than 1MiB"))?;
 
         uploaded_files.push(UploadedFile {
             file_id: upload_data.file_id,
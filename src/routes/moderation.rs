@@ -1,55 +1,80 @@
+use super::pagination::{Pagination, SortKey, NEWEST, OLDEST};
 use super::ApiError;
 use crate::auth::check_is_moderator_from_headers;
 use crate::database;
 use crate::models::mods::{Mod, ModStatus};
+use crate::models::pagination::{Listing, PageQuery};
 use actix_web::{get, web, HttpRequest, HttpResponse};
-use serde::Deserialize;
 use sqlx::PgPool;
 
-#[derive(Deserialize)]
-pub struct ResultCount {
-    #[serde(default = "default_count")]
-    count: i16,
-}
-
-fn default_count() -> i16 {
-    100
-}
+const MODERATION_SORTS: &[SortKey] = &[OLDEST, NEWEST];
 
+/// Sorted by the last update, with the keys `oldest` and `newest`
+#[utoipa::path(
+    context_path = "/api/v1/moderation/",
+    tag = "moderation",
+    security(("token" = [])),
+    params(PageQuery),
+    responses((status = 200, description = "The mods waiting for review", body = Listing<Mod>)),
+)]
 #[get("mods")]
 pub async fn mods(
     req: HttpRequest,
     pool: web::Data<PgPool>,
-    count: web::Query<ResultCount>,
+    web::Query(page): web::Query<PageQuery>,
 ) -> Result<HttpResponse, ApiError> {
     check_is_moderator_from_headers(req.headers(), &**pool).await?;
 
+    let pagination = Pagination::from_query(&page, MODERATION_SORTS)?.unpaged_limit(100);
+
     use futures::stream::TryStreamExt;
 
-    let mod_ids = sqlx::query!(
+    let rows = sqlx::query!(
         "
-        SELECT id FROM mods
+        SELECT id, updated FROM mods
         WHERE status = (
             SELECT id FROM statuses WHERE status = $1
         )
-        ORDER BY updated ASC
-        LIMIT $2;
+        AND ($2::timestamptz IS NULL OR CASE WHEN $4
+            THEN (updated, id) < ($2::timestamptz, $3::bigint)
+            ELSE (updated, id) > ($2::timestamptz, $3::bigint)
+        END)
+        ORDER BY
+            CASE WHEN $4 THEN updated END DESC,
+            CASE WHEN $4 THEN id END DESC,
+            updated ASC, id ASC
+        LIMIT $5;
         ",
         ModStatus::Processing.as_str(),
-        count.count as i64
+        pagination.after_key(),
+        pagination.after_id(),
+        pagination.sort.descending,
+        pagination.fetch_limit(),
     )
     .fetch_many(&**pool)
-    .try_filter_map(|e| async { Ok(e.right().map(|m| database::models::ids::ModId(m.id))) })
-    .try_collect::<Vec<database::models::ModId>>()
+    .try_filter_map(|e| async { Ok(e.right().map(|m| (m.updated, m.id))) })
+    .try_collect::<Vec<_>>()
     .await
     .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
-    let mods: Vec<Mod> = database::models::mod_item::Mod::get_many_full(mod_ids, &**pool)
-        .await
-        .map_err(|e| ApiError::DatabaseError(e.into()))?
-        .into_iter()
-        .map(super::mods::convert_mod)
-        .collect();
+    let (ids, next) = pagination.split(rows);
+
+    let mods: Vec<Mod> = database::models::mod_item::Mod::get_many_full(
+        ids.iter()
+            .map(|x| database::models::ids::ModId(*x))
+            .collect(),
+        &**pool,
+    )
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.into()))?
+    .into_iter()
+    .map(super::mods::convert_mod)
+    .collect();
 
-    Ok(HttpResponse::Ok().json(mods))
+    Ok(HttpResponse::Ok().json(pagination.page(
+        &ids,
+        mods,
+        |x| database::models::ids::ModId::from(x.id).0,
+        next,
+    )))
 }
@@ -1,9 +1,19 @@
 use crate::auth::get_user_from_headers;
 use crate::database;
-use crate::file_hosting::FileHost;
+use crate::database::models::cache::{self, Invalidation};
+use crate::database::models::notification_item::NotificationBuilder;
+use crate::file_hosting::{delete_cdn_file, FileHost, FileHostingError};
 use crate::models;
+use crate::models::ids::base62_impl::to_base62;
+use crate::models::ids::random_base62;
 use crate::models::mods::{DonationLink, ModId, ModStatus, SearchRequest};
+use crate::models::notifications::NotificationBody;
 use crate::models::teams::Permissions;
+use crate::models::users::User;
+use crate::models::webhooks::WebhookEvent;
+use crate::notifications::webhooks::dispatch_mod_event;
+use crate::routes::batch::{in_request_order, BatchIds};
+use crate::routes::conditional::{json_response_modified, CacheScope};
 use crate::routes::ApiError;
 use crate::search::indexing::queue::CreationQueue;
 use crate::search::{search_for_mod, SearchConfig, SearchError};
@@ -13,8 +23,16 @@ use futures::StreamExt;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use std::collections::HashMap;
 use std::sync::Arc;
-
+use utoipa::{IntoParams, ToSchema};
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "mods",
+    params(SearchRequest),
+    responses((status = 200, description = "The mods matching the search", body = crate::search::SearchResults)),
+)]
 #[get("mod")]
 pub async fn mod_search(
     web::Query(info): web::Query<SearchRequest>,
@@ -27,12 +45,19 @@ pub async fn mod_search(
     Ok(HttpResponse::Ok().json(results))
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ModIds {
     pub ids: String,
 }
 
 // TODO: Make this return the full mod struct
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "mods",
+    params(ModIds),
+    responses((status = 200, description = "The mods that exist and are visible to the user", body = Vec<models::mods::Mod>)),
+)]
 #[get("mods")]
 pub async fn mods_get(
     req: HttpRequest,
@@ -44,47 +69,117 @@ pub async fn mods_get(
         .map(|x| x.into())
         .collect();
 
-    let mods_data = database::models::Mod::get_many_full(mod_ids, &**pool)
-        .await
-        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    let user_option = get_user_from_headers(req.headers(), &**pool).await.ok();
+    let mods = get_visible_mods(mod_ids, user_option.as_ref(), &pool).await?;
+
+    Ok(HttpResponse::Ok().json(mods))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "mods",
+    request_body = BatchIds<models::ids::ModId>,
+    responses((status = 200, description = "The mods in the order they were requested, with `null` for mods that don't exist or are hidden", body = Vec<Option<models::mods::Mod>>)),
+)]
+#[post("mods")]
+pub async fn mods_batch(
+    req: HttpRequest,
+    web::Json(batch): web::Json<BatchIds<models::ids::ModId>>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    batch.validate()?;
+
+    let mod_ids = batch.ids.iter().map(|x| (*x).into()).collect();
 
     let user_option = get_user_from_headers(req.headers(), &**pool).await.ok();
+    let mods: HashMap<u64, models::mods::Mod> =
+        get_visible_mods(mod_ids, user_option.as_ref(), &pool)
+            .await?
+            .into_iter()
+            .map(|x| (x.id.0, x))
+            .collect();
 
-    let mut mods = Vec::new();
+    let ids: Vec<u64> = batch.ids.iter().map(|x| x.0).collect();
 
-    for mod_data in mods_data {
-        let mut authorized = !mod_data.status.is_hidden();
+    Ok(HttpResponse::Ok().json(in_request_order(&ids, &mods)))
+}
 
-        if let Some(user) = &user_option {
-            if !authorized {
-                if user.role.is_mod() {
-                    authorized = true;
-                } else {
-                    let user_id: database::models::ids::UserId = user.id.into();
+/// Responds with a mod, allowing clients to revalidate their copy of it.
+/// Hidden mods are only shown to some users, so they may not be cached by
+/// shared caches.
+fn mod_response(
+    req: &HttpRequest,
+    data: database::models::mod_item::QueryMod,
+) -> Result<HttpResponse, ApiError> {
+    let scope = if data.status.is_hidden() {
+        CacheScope::Private
+    } else {
+        CacheScope::Public
+    };
+    let last_modified = data.last_modified;
 
-                    let mod_exists = sqlx::query!(
-                            "SELECT EXISTS(SELECT 1 FROM team_members WHERE team_id = $1 AND user_id = $2)",
-                            mod_data.inner.team_id as database::models::ids::TeamId,
-                            user_id as database::models::ids::UserId,
-                        )
-                        .fetch_one(&**pool)
-                        .await
-                        .map_err(|e| ApiError::DatabaseError(e.into()))?
-                        .exists;
+    json_response_modified(req, &convert_mod(data), last_modified, scope)
+}
 
-                    authorized = mod_exists.unwrap_or(false);
-                }
-            }
-        }
+/// Gets the mods that exist and that the user may see
+async fn get_visible_mods(
+    mod_ids: Vec<database::models::ModId>,
+    user_option: Option<&User>,
+    pool: &PgPool,
+) -> Result<Vec<models::mods::Mod>, ApiError> {
+    let mods_data = database::models::Mod::get_many_full(mod_ids, pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
-        if authorized {
+    let mut mods = Vec::new();
+
+    for mod_data in mods_data {
+        if is_visible(&mod_data.status, mod_data.inner.team_id, user_option, pool).await? {
             mods.push(convert_mod(mod_data));
         }
     }
 
-    Ok(HttpResponse::Ok().json(mods))
+    Ok(mods)
 }
 
+/// Whether a user may see a mod.  Hidden mods can only be seen by their
+/// team and moderators.
+pub async fn is_visible(
+    status: &ModStatus,
+    team_id: database::models::TeamId,
+    user_option: Option<&User>,
+    pool: &PgPool,
+) -> Result<bool, ApiError> {
+    if !status.is_hidden() {
+        return Ok(true);
+    }
+
+    match user_option {
+        Some(user) if user.role.is_mod() => Ok(true),
+        Some(user) => {
+            let user_id: database::models::ids::UserId = user.id.into();
+
+            let member_exists = sqlx::query!(
+                "SELECT EXISTS(SELECT 1 FROM team_members WHERE team_id = $1 AND user_id = $2)",
+                team_id as database::models::ids::TeamId,
+                user_id as database::models::ids::UserId,
+            )
+            .fetch_one(pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?
+            .exists;
+
+            Ok(member_exists.unwrap_or(false))
+        }
+        None => Ok(false),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/mod/",
+    tag = "mods",
+    responses((status = 200, body = models::mods::Mod), (status = 404, description = "The mod doesn't exist or is hidden"), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("@{id}")]
 pub async fn mod_slug_get(
     req: HttpRequest,
@@ -98,40 +193,22 @@ pub async fn mod_slug_get(
     let user_option = get_user_from_headers(req.headers(), &**pool).await.ok();
 
     if let Some(data) = mod_data {
-        let mut authorized = !data.status.is_hidden();
-
-        if let Some(user) = user_option {
-            if !authorized {
-                if user.role.is_mod() {
-                    authorized = true;
-                } else {
-                    let user_id: database::models::ids::UserId = user.id.into();
-
-                    let mod_exists = sqlx::query!(
-                        "SELECT EXISTS(SELECT 1 FROM team_members WHERE team_id = $1 AND user_id = $2)",
-                        data.inner.team_id as database::models::ids::TeamId,
-                        user_id as database::models::ids::UserId,
-                    )
-                    .fetch_one(&**pool)
-                    .await
-                    .map_err(|e| ApiError::DatabaseError(e.into()))?
-                    .exists;
-
-                    authorized = mod_exists.unwrap_or(false);
-                }
-            }
+        if !is_visible(&data.status, data.inner.team_id, user_option.as_ref(), &pool).await? {
+            return Ok(HttpResponse::NotFound().body(""));
         }
 
-        if authorized {
-            return Ok(HttpResponse::Ok().json(convert_mod(data)));
-        }
-
-        Ok(HttpResponse::NotFound().body(""))
+        mod_response(&req, data)
     } else {
         Ok(HttpResponse::NotFound().body(""))
     }
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/mod/",
+    tag = "mods",
+    params(("id" = String, Path, description = "The ID or slug of the mod")),
+    responses((status = 200, body = models::mods::Mod), (status = 404, description = "The mod doesn't exist or is hidden"), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("{id}")]
 pub async fn mod_get(
     req: HttpRequest,
@@ -162,40 +239,21 @@ pub async fn mod_get(
     let user_option = get_user_from_headers(req.headers(), &**pool).await.ok();
 
     if let Some(data) = mod_data {
-        let mut authorized = !data.status.is_hidden();
-
-        if let Some(user) = user_option {
-            if !authorized {
-                if user.role.is_mod() {
-                    authorized = true;
-                } else {
-                    let user_id: database::models::ids::UserId = user.id.into();
-
-                    let mod_exists = sqlx::query!(
-                        "SELECT EXISTS(SELECT 1 FROM team_members WHERE team_id = $1 AND user_id = $2)",
-                        data.inner.team_id as database::models::ids::TeamId,
-                        user_id as database::models::ids::UserId,
-                    )
-                    .fetch_one(&**pool)
-                    .await
-                    .map_err(|e| ApiError::DatabaseError(e.into()))?
-                    .exists;
-
-                    authorized = mod_exists.unwrap_or(false);
-                }
-            }
-        }
-
-        if authorized {
-            return Ok(HttpResponse::Ok().json(convert_mod(data)));
+        if !is_visible(&data.status, data.inner.team_id, user_option.as_ref(), &pool).await? {
+            return Ok(HttpResponse::NotFound().body(""));
         }
 
-        Ok(HttpResponse::NotFound().body(""))
+        mod_response(&req, data)
     } else {
         Ok(HttpResponse::NotFound().body(""))
     }
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "mods",
+    responses((status = 200, body = models::mods::Mod), (status = 404, description = "There are no approved mods")),
+)]
 #[get("random_mod")]
 pub async fn mod_get_random(
     req: HttpRequest,
@@ -360,7 +418,7 @@ pub fn convert_mod(data: database::models::mod_item::QueryMod) -> models::mods::
 }
 
 /// A mod returned from the API
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct EditMod {
     pub title: Option<String>,
     pub description: Option<String>,
@@ -401,6 +459,13 @@ pub struct EditMod {
     pub is_nsfw: Option<bool>,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/mod/",
+    tag = "mods",
+    request_body = EditMod,
+    security(("token" = [])),
+    responses((status = 200, description = "The mod was edited"), (status = 404, description = "The mod doesn't exist or is hidden")),
+)]
 #[patch("{id}")]
 pub async fn mod_edit(
     req: HttpRequest,
@@ -442,6 +507,22 @@ pub async fn mod_edit(
                 .await
                 .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
+            // The cached mod can be out of date, and concurrent edits have to
+            // wait for this one, or they'd both act on the same old status
+            let current_status = sqlx::query!(
+                "
+                SELECT s.status FROM mods m
+                INNER JOIN statuses s ON s.id = m.status
+                WHERE m.id = $1
+                FOR UPDATE OF m
+                ",
+                id as database::models::ids::ModId,
+            )
+            .fetch_one(&mut *transaction)
+            .await
+            .map(|x| ModStatus::from_str(&x.status))
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
             if let Some(title) = &new_mod.title {
                 if !perms.contains(Permissions::EDIT_DETAILS) {
                     return Err(ApiError::CustomAuthenticationError(
@@ -535,9 +616,9 @@ pub async fn mod_edit(
                 .await
                 .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
-                if mod_item.status.is_searchable() && !status.is_searchable() {
+                if current_status.is_searchable() && !status.is_searchable() {
                     delete_from_index(mod_id, config).await?;
-                } else if !mod_item.status.is_searchable() && status.is_searchable() {
+                } else if !current_status.is_searchable() && status.is_searchable() {
                     let index_mod = crate::search::indexing::local_import::query_one(
                         mod_id.into(),
                         &mut *transaction,
@@ -546,6 +627,51 @@ pub async fn mod_edit(
 
                     indexing_queue.add(index_mod);
                 }
+
+                if status == &ModStatus::Approved {
+                    sqlx::query!(
+                        "
+                        UPDATE mods
+                        SET approved = NOW()
+                        WHERE (id = $1 AND approved IS NULL)
+                        ",
+                        id as database::models::ids::ModId,
+                    )
+                    .execute(&mut *transaction)
+                    .await
+                    .map_err(|e| ApiError::DatabaseError(e.into()))?;
+                }
+
+                if (status == &ModStatus::Rejected || status == &ModStatus::Approved)
+                    && &current_status != status
+                {
+                    let members = database::models::TeamMember::get_from_team(
+                        mod_item.inner.team_id,
+                        &mut *transaction,
+                    )
+                    .await?
+                    .into_iter()
+                    .filter(|x| x.accepted)
+                    .map(|x| x.user_id)
+                    .collect();
+
+                    NotificationBuilder {
+                        title: format!("Your mod has been {}", status),
+                        text: format!(
+                            "A moderator has set the status of mod {} to {}",
+                            mod_item.inner.title, status
+                        ),
+                        link: format!("mod/{}", mod_id),
+                        body: NotificationBody::ModerationDecision {
+                            mod_id,
+                            old_status: current_status.clone(),
+                            new_status: status.clone(),
+                        },
+                        actions: vec![],
+                    }
+                    .insert_many(members, &mut transaction)
+                    .await?;
+                }
             }
 
             if let Some(categories) = &new_mod.categories {
@@ -884,10 +1010,74 @@ pub async fn mod_edit(
                 .map_err(|e| ApiError::DatabaseError(e.into()))?;
             }
 
+            // Keeps `Last-Modified` of the mod current. `updated` is left
+            // alone, since it's when the last version was added.
+            sqlx::query!(
+                "
+                UPDATE mods
+                SET edited = NOW()
+                WHERE (id = $1)
+                ",
+                id as database::models::ids::ModId,
+            )
+            .execute(&mut *transaction)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
             transaction
                 .commit()
                 .await
                 .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+            cache::invalidate(Invalidation::Mod(id.0), &**pool).await;
+
+            let updated_mod = database::models::Mod::get_full(id, &**pool)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+            if let Some(updated_mod) = updated_mod {
+                let public = !updated_mod.status.is_hidden();
+                let approved = updated_mod.status == ModStatus::Approved
+                    && current_status != ModStatus::Approved;
+                let team_id = updated_mod.inner.team_id;
+                let data = convert_mod(updated_mod);
+
+                let mut transaction = pool
+                    .begin()
+                    .await
+                    .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+                dispatch_mod_event(
+                    WebhookEvent::ModUpdated,
+                    id,
+                    team_id,
+                    public,
+                    &data,
+                    &mut transaction,
+                )
+                .await?;
+
+                if approved {
+                    dispatch_mod_event(
+                        WebhookEvent::ModApproved,
+                        id,
+                        team_id,
+                        public,
+                        &data,
+                        &mut transaction,
+                    )
+                    .await?;
+
+                    crate::notifications::discord::announce_approval(id, &mut transaction)
+                        .await?;
+                }
+
+                transaction
+                    .commit()
+                    .await
+                    .map_err(|e| ApiError::DatabaseError(e.into()))?;
+            }
+
             Ok(HttpResponse::Ok().body(""))
         } else {
             Err(ApiError::CustomAuthenticationError(
@@ -899,11 +1089,20 @@ pub async fn mod_edit(
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Extension {
     pub ext: String,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/mod/",
+    tag = "mods",
+    security(("token" = [])),
+    request_body(content = Vec<u8>, description = "The image data", content_type = "image/*"),
+    params(Extension),
+    responses((status = 200, description = "The icon was changed"), (status = 404, description = "The mod doesn't exist or is hidden")),
+)]
 #[patch("{id}/icon")]
 pub async fn mod_icon_edit(
     web::Query(ext): web::Query<Extension>,
@@ -911,7 +1110,7 @@ pub async fn mod_icon_edit(
     info: web::Path<(models::ids::ModId,)>,
     pool: web::Data<PgPool>,
     file_host: web::Data<Arc<dyn FileHost + Send + Sync>>,
-    mut payload: web::Payload,
+    payload: web::Payload,
 ) -> Result<HttpResponse, ApiError> {
     if let Some(content_type) = super::mod_creation::get_image_content_type(&*ext.ext) {
         let cdn_url = dotenv::var("CDN_URL")?;
@@ -940,40 +1139,40 @@ pub async fn mod_icon_edit(
             }
         }
 
-        if let Some(icon) = mod_item.icon_url {
-            let name = icon.split('/').next();
-
-            if let Some(icon_path) = name {
-                file_host.delete_file_version("", icon_path).await?;
-            }
-        }
-
-        let mut bytes = web::BytesMut::new();
-        while let Some(item) = payload.next().await {
-            bytes.extend_from_slice(&item.map_err(|_| {
-                ApiError::InvalidInputError("Unable to parse bytes in payload sent!".to_string())
-            })?);
-        }
-
-        if bytes.len() >= 262144 {
-            return Err(ApiError::InvalidInputError(String::from(
-                "Icons must be smaller than 256KiB",
-            )));
-        }
+        // Every icon gets a new name, so the old one is only deleted once the
+        // new one is stored and in use
+        let icon_name = format!(
+            "data/{}/icon-{}.{}",
+            id,
+            to_base62(random_base62(8)),
+            ext.ext
+        );
 
         let upload_data = file_host
             .upload_file(
                 content_type,
-                &format!("data/{}/icon.{}", id, ext.ext),
-                bytes.to_vec(),
+                &icon_name,
+                Box::pin(payload.map(|chunk| {
+                    chunk.map_err(|e| FileHostingError::PayloadError(e.to_string()))
+                })),
+                262144,
             )
-            .await?;
+            .await
+            .map_err(|e| match e {
+                FileHostingError::FileTooLarge(_) => ApiError::InvalidInputError(String::from(
+                    "Icons must be smaller than 256KiB",
+                )),
+                FileHostingError::PayloadError(_) => ApiError::InvalidInputError(
+                    "Unable to parse bytes in payload sent!".to_string(),
+                ),
+                e => ApiError::FileHostingError(e),
+            })?;
 
         let mod_id: database::models::ids::ModId = id.into();
         sqlx::query!(
             "
             UPDATE mods
-            SET icon_url = $1
+            SET icon_url = $1, edited = NOW()
             WHERE (id = $2)
             ",
             format!("{}/{}", cdn_url, upload_data.file_name),
@@ -983,6 +1182,12 @@ pub async fn mod_icon_edit(
         .await
         .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
+        cache::invalidate(Invalidation::Mod(mod_id.0), &**pool).await;
+
+        if let Some(icon) = mod_item.icon_url {
+            delete_cdn_file(&***file_host, &icon, &cdn_url).await;
+        }
+
         Ok(HttpResponse::Ok().body(""))
     } else {
         Err(ApiError::InvalidInputError(format!(
@@ -992,12 +1197,19 @@ pub async fn mod_icon_edit(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/mod/",
+    tag = "mods",
+    security(("token" = [])),
+    responses((status = 200, description = "The mod was deleted"), (status = 404, description = "The mod doesn't exist or is hidden")),
+)]
 #[delete("{id}")]
 pub async fn mod_delete(
     req: HttpRequest,
     info: web::Path<(models::ids::ModId,)>,
     pool: web::Data<PgPool>,
     config: web::Data<SearchConfig>,
+    file_host: web::Data<Arc<dyn FileHost + Send + Sync>>,
 ) -> Result<HttpResponse, ApiError> {
     let user = get_user_from_headers(req.headers(), &**pool).await?;
     let id = info.into_inner().0;
@@ -1018,10 +1230,36 @@ pub async fn mod_delete(
         }
     }
 
+    let versions = database::models::Version::get_mod_versions(id.into(), &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let icon_url = sqlx::query!(
+        "
+        SELECT icon_url FROM mods
+        WHERE id = $1
+        ",
+        id.0 as i64
+    )
+    .fetch_optional(&**pool)
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.into()))?
+    .and_then(|x| x.icon_url);
+
     let result = database::models::Mod::remove_full(id.into(), &**pool)
         .await
         .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
+    cache::invalidate(Invalidation::Mod(id.0 as i64), &**pool).await;
+    for version in versions {
+        cache::invalidate(Invalidation::Version(version.0), &**pool).await;
+    }
+
+    if let Some(icon_url) = icon_url {
+        let cdn_url = dotenv::var("CDN_URL")?;
+        delete_cdn_file(&***file_host, &icon_url, &cdn_url).await;
+    }
+
     delete_from_index(id, config).await?;
 
     if result.is_some() {
@@ -1031,6 +1269,12 @@ pub async fn mod_delete(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/mod/",
+    tag = "mods",
+    security(("token" = [])),
+    responses((status = 200, description = "The mod is now followed"), (status = 404, description = "The mod doesn't exist or is hidden")),
+)]
 #[post("{id}/follow")]
 pub async fn mod_follow(
     req: HttpRequest,
@@ -1094,6 +1338,12 @@ pub async fn mod_follow(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/mod/",
+    tag = "mods",
+    security(("token" = [])),
+    responses((status = 200, description = "The mod is no longer followed"), (status = 404, description = "The mod doesn't exist or isn't followed")),
+)]
 #[delete("{id}/follow")]
 pub async fn mod_unfollow(
     req: HttpRequest,
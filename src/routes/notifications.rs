@@ -1,17 +1,32 @@
 use crate::auth::get_user_from_headers;
 use crate::database;
-use crate::models::ids::NotificationId;
-use crate::models::notifications::{Notification, NotificationAction};
+use crate::models::ids::{NotificationId, UserId};
+use crate::models::notifications::{Notification, NotificationAction, NotificationType};
+use crate::notifications::stream::{NotificationStreams, StreamEvent};
+use crate::routes::batch::{in_request_order, BatchIds};
 use crate::routes::ApiError;
-use actix_web::{delete, get, web, HttpRequest, HttpResponse};
+use actix_web::{delete, get, http, patch, post, web, HttpRequest, HttpResponse};
+use askama::Template;
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct NotificationIds {
     pub ids: String,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "notifications",
+    security(("token" = [])),
+    params(NotificationIds),
+    responses((status = 200, description = "The notifications of the user that exist", body = Vec<Notification>)),
+)]
 #[get("notifications")]
 pub async fn notifications_get(
     req: HttpRequest,
@@ -41,6 +56,166 @@ pub async fn notifications_get(
     Ok(HttpResponse::Ok().json(notifications))
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "notifications",
+    security(("token" = [])),
+    request_body = BatchIds<NotificationId>,
+    responses((status = 200, description = "The notifications in the order they were requested, with `null` for notifications that don't exist or belong to another user", body = Vec<Option<Notification>>)),
+)]
+#[post("notifications")]
+pub async fn notifications_batch(
+    req: HttpRequest,
+    web::Json(batch): web::Json<BatchIds<NotificationId>>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+
+    batch.validate()?;
+
+    let notifications: HashMap<u64, Notification> =
+        database::models::notification_item::Notification::get_many(
+            batch.ids.iter().map(|x| (*x).into()).collect(),
+            &**pool,
+        )
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?
+        .into_iter()
+        .filter(|x| x.user_id == user.id.into() || user.role.is_mod())
+        .map(|x| (x.id.0 as u64, convert_notification(x)))
+        .collect();
+
+    let ids: Vec<u64> = batch.ids.iter().map(|x| x.0).collect();
+
+    Ok(HttpResponse::Ok().json(in_request_order(&ids, &notifications)))
+}
+
+/// How long a stream ticket can be used for, in seconds
+const STREAM_TICKET_EXPIRY: i64 = 60;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct StreamTicket {
+    /// Passed as the `ticket` query parameter of the notification stream
+    pub ticket: String,
+    pub expires: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamQuery {
+    /// A ticket from `POST /notifications/stream/ticket`, for clients that
+    /// can't send an `Authorization` header, like `EventSource` in browsers
+    pub ticket: Option<String>,
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "notifications",
+    security(("token" = [])),
+    responses((status = 200, description = "A ticket the notification stream can be opened with once, within a minute", body = StreamTicket)),
+)]
+#[post("notifications/stream/ticket")]
+pub async fn notifications_stream_ticket(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    use rand::Rng;
+
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+
+    let ticket = database::models::notification_item::StreamTicket {
+        ticket: rand::thread_rng()
+            .sample_iter(&rand::distributions::Alphanumeric)
+            .take(40)
+            .map(char::from)
+            .collect(),
+        user_id: user.id.into(),
+        expires: chrono::Utc::now() + chrono::Duration::seconds(STREAM_TICKET_EXPIRY),
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    ticket
+        .insert(&mut transaction)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(HttpResponse::Ok().json(StreamTicket {
+        ticket: ticket.ticket,
+        expires: ticket.expires,
+    }))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "notifications",
+    security(("token" = [])),
+    params(StreamQuery),
+    responses((status = 200, description = "A stream of server-sent events, sent when the user receives a notification. Authenticated with either a token or a ticket.", content_type = "text/event-stream")),
+)]
+#[get("notifications/stream")]
+pub async fn notifications_stream(
+    req: HttpRequest,
+    web::Query(query): web::Query<StreamQuery>,
+    pool: web::Data<PgPool>,
+    streams: web::Data<Arc<NotificationStreams>>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = match &query.ticket {
+        Some(ticket) => database::models::notification_item::StreamTicket::redeem(ticket, &**pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?
+            .ok_or(crate::auth::AuthenticationError::InvalidCredentialsError)?,
+        None => get_user_from_headers(req.headers(), &**pool)
+            .await?
+            .id
+            .into(),
+    };
+
+    let events = streams.subscribe(user_id).then(move |event| {
+        let pool = pool.clone();
+
+        async move {
+            let message = match event {
+                StreamEvent::Notification(id) => {
+                    let notification =
+                        database::models::notification_item::Notification::get(id, &**pool)
+                            .await
+                            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+                    match notification {
+                        Some(notification) => format!(
+                            "event: notification\ndata: {}\n\n",
+                            serde_json::to_string(&convert_notification(notification))?
+                        ),
+                        // The notification was deleted before it could be sent
+                        None => ": deleted\n\n".to_string(),
+                    }
+                }
+                StreamEvent::KeepAlive => ": keep-alive\n\n".to_string(),
+            };
+
+            Ok::<web::Bytes, ApiError>(web::Bytes::from(message))
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header(http::header::CACHE_CONTROL, "no-cache")
+        .streaming(Box::pin(events)))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/notification/",
+    tag = "notifications",
+    security(("token" = [])),
+    responses((status = 200, body = Notification), (status = 404, description = "The notification doesn't exist")),
+)]
 #[get("{id}")]
 pub async fn notification_get(
     req: HttpRequest,
@@ -73,11 +248,13 @@ pub fn convert_notification(
     Notification {
         id: notif.id.into(),
         user_id: notif.user_id.into(),
+        notification_type: NotificationType::from_str(&notif.notification_type),
         title: notif.title,
         text: notif.text,
         link: notif.link,
         read: notif.read,
         created: notif.created,
+        body: notif.body.and_then(|x| serde_json::from_value(x).ok()),
         actions: notif
             .actions
             .into_iter()
@@ -89,6 +266,12 @@ pub fn convert_notification(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/notification/",
+    tag = "notifications",
+    security(("token" = [])),
+    responses((status = 200, description = "The notification was deleted"), (status = 404, description = "The notification doesn't exist")),
+)]
 #[delete("{id}")]
 pub async fn notification_delete(
     req: HttpRequest,
@@ -120,3 +303,288 @@ pub async fn notification_delete(
         Ok(HttpResponse::NotFound().body(""))
     }
 }
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct EditNotification {
+    pub read: Option<bool>,
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/notification/",
+    tag = "notifications",
+    request_body = EditNotification,
+    security(("token" = [])),
+    responses((status = 200, description = "The notification was edited"), (status = 404, description = "The notification doesn't exist")),
+)]
+#[patch("{id}")]
+pub async fn notification_edit(
+    req: HttpRequest,
+    info: web::Path<(NotificationId,)>,
+    pool: web::Data<PgPool>,
+    edit_notification: web::Json<EditNotification>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+
+    let id = info.into_inner().0;
+
+    let notification_data =
+        database::models::notification_item::Notification::get(id.into(), &**pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    if let Some(data) = notification_data {
+        if data.user_id == user.id.into() || user.role.is_mod() {
+            let mut transaction = pool
+                .begin()
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+            if let Some(read) = edit_notification.read {
+                database::models::notification_item::Notification::set_read_many(
+                    vec![id.into()],
+                    read,
+                    &mut transaction,
+                )
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?;
+            }
+
+            transaction
+                .commit()
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+            Ok(HttpResponse::Ok().body(""))
+        } else {
+            Err(ApiError::CustomAuthenticationError(
+                "You are not authorized to edit this notification!".to_string(),
+            ))
+        }
+    } else {
+        Ok(HttpResponse::NotFound().body(""))
+    }
+}
+
+/// Fetches the notifications with the given IDs, failing if any of them
+/// belong to another user and the current user is not a moderator.
+async fn get_owned_notifications(
+    req: &HttpRequest,
+    ids: &NotificationIds,
+    pool: &PgPool,
+) -> Result<Vec<database::models::ids::NotificationId>, ApiError> {
+    let user = get_user_from_headers(req.headers(), pool).await?;
+
+    let notification_ids = serde_json::from_str::<Vec<NotificationId>>(&ids.ids)?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    let notifications_data =
+        database::models::notification_item::Notification::get_many(notification_ids, pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let mut owned_ids = Vec::new();
+
+    for notification in notifications_data {
+        if notification.user_id == user.id.into() || user.role.is_mod() {
+            owned_ids.push(notification.id);
+        } else {
+            return Err(ApiError::CustomAuthenticationError(
+                "You are not authorized to modify one or more of these notifications!"
+                    .to_string(),
+            ));
+        }
+    }
+
+    Ok(owned_ids)
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "notifications",
+    request_body = EditNotification,
+    security(("token" = [])),
+    params(NotificationIds),
+    responses((status = 200, description = "The notifications were edited")),
+)]
+#[patch("notifications")]
+pub async fn notifications_edit(
+    req: HttpRequest,
+    web::Query(ids): web::Query<NotificationIds>,
+    pool: web::Data<PgPool>,
+    edit_notification: web::Json<EditNotification>,
+) -> Result<HttpResponse, ApiError> {
+    let notification_ids = get_owned_notifications(&req, &ids, &pool).await?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    if let Some(read) = edit_notification.read {
+        database::models::notification_item::Notification::set_read_many(
+            notification_ids,
+            read,
+            &mut transaction,
+        )
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "notifications",
+    security(("token" = [])),
+    params(NotificationIds),
+    responses((status = 200, description = "The notifications were deleted")),
+)]
+#[delete("notifications")]
+pub async fn notifications_delete(
+    req: HttpRequest,
+    web::Query(ids): web::Query<NotificationIds>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let notification_ids = get_owned_notifications(&req, &ids, &pool).await?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    database::models::notification_item::Notification::remove_many(
+        notification_ids,
+        &mut transaction,
+    )
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Unsubscribe {
+    pub user: UserId,
+    #[serde(rename = "type")]
+    pub notification_type: NotificationType,
+    pub signature: String,
+}
+
+fn check_unsubscribe_link(info: &Unsubscribe) -> Result<(), ApiError> {
+    if !crate::notifications::email::verify_unsubscribe(
+        info.user.into(),
+        info.notification_type,
+        &info.signature,
+    )? {
+        return Err(ApiError::CustomAuthenticationError(
+            "Invalid unsubscribe link!".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(Template)]
+#[template(path = "unsubscribe.html")]
+struct UnsubscribePage<'a> {
+    notification_type: &'a str,
+    /// Where the confirmation is posted to, which is this page's own link
+    action: &'a str,
+}
+
+async fn unsubscribe(info: Unsubscribe, pool: &PgPool) -> Result<HttpResponse, ApiError> {
+    check_unsubscribe_link(&info)?;
+
+    let user_id = info.user.into();
+
+    let mut preference =
+        database::models::notification_item::NotificationPreference::get_many_user(user_id, pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?
+            .into_iter()
+            .find(|x| x.notification_type == info.notification_type.as_str())
+            .unwrap_or_else(|| {
+                database::models::notification_item::NotificationPreference::default_for(
+                    user_id,
+                    info.notification_type.as_str(),
+                )
+            });
+    preference.email = false;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    preference
+        .upsert(&mut transaction)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(HttpResponse::Ok().content_type("text/plain").body(
+        "You have been unsubscribed. You can change your email preferences at any time in your settings.",
+    ))
+}
+
+/// Asks to confirm the unsubscription.  Opening the link doesn't unsubscribe
+/// by itself, since mail scanners and link previews open links as well.
+#[utoipa::path(
+    context_path = "/api/v1/email/",
+    tag = "notifications",
+    params(Unsubscribe),
+    responses((status = 200, description = "A page asking to confirm the unsubscription, which posts to the same link", body = String, content_type = "text/html")),
+)]
+#[get("unsubscribe")]
+pub async fn email_unsubscribe_get(
+    req: HttpRequest,
+    web::Query(info): web::Query<Unsubscribe>,
+) -> Result<HttpResponse, ApiError> {
+    check_unsubscribe_link(&info)?;
+
+    let page = UnsubscribePage {
+        notification_type: info.notification_type.as_str(),
+        action: &format!("?{}", req.query_string()),
+    };
+
+    Ok(match page.render() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(body),
+        Err(_) => HttpResponse::InternalServerError().body(""),
+    })
+}
+
+/// Confirms an unsubscription, which is also how mail clients unsubscribe in
+/// one click, as described in RFC 8058
+#[utoipa::path(
+    context_path = "/api/v1/email/",
+    tag = "notifications",
+    params(Unsubscribe),
+    responses((status = 200, description = "The user was unsubscribed", body = String, content_type = "text/plain")),
+)]
+#[post("unsubscribe")]
+pub async fn email_unsubscribe_post(
+    web::Query(info): web::Query<Unsubscribe>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    unsubscribe(info, &pool).await
+}
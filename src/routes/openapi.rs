@@ -0,0 +1,363 @@
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{get, web, HttpResponse};
+use std::sync::Arc;
+use utoipa::openapi::path::Operation;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::openapi::{ContentBuilder, Ref, ResponseBuilder};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::Config;
+
+/// The OpenAPI document of the API.  Every handler is annotated with
+/// `#[utoipa::path]` and listed here; the tests below fail when a route is
+/// added without being documented.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "XivRepo API",
+        description = "The API of XivRepo.  Errors are returned as an `ApiError` object.",
+    ),
+    paths(
+        super::index::index_get,
+        super::mods::mod_search,
+        super::mods::mods_get,
+        super::mods::mods_batch,
+        super::mods::mod_slug_get,
+        super::mods::mod_get,
+        super::mods::mod_get_random,
+        super::mods::mod_edit,
+        super::mods::mod_icon_edit,
+        super::mods::mod_delete,
+        super::mods::mod_follow,
+        super::mods::mod_unfollow,
+        super::mod_creation::mod_create,
+        super::versions::version_list,
+        super::versions::versions_get,
+        super::versions::versions_batch,
+        super::versions::version_get,
+        super::versions::version_edit,
+        super::versions::version_delete,
+        super::versions::get_version_from_hash,
+        super::versions::download_version,
+        super::versions::delete_file,
+        super::version_creation::version_create,
+        super::version_creation::upload_file_to_version,
+        super::version_creation::version_file_presign,
+        super::version_creation::version_file_finalize,
+        super::users::user_auth_get,
+        super::users::users_get,
+        super::users::users_batch,
+        super::users::user_username_get,
+        super::users::user_get,
+        super::users::mods_list,
+        super::users::user_edit,
+        super::users::user_icon_edit,
+        super::users::user_delete,
+        super::users::user_follows,
+        super::users::user_notifications,
+        super::users::user_notification_preferences_get,
+        super::users::user_notification_preferences_edit,
+        super::users::user_storage_get,
+        super::users::user_storage_edit,
+        super::teams::team_members_get,
+        super::teams::join_team,
+        super::teams::add_team_member,
+        super::teams::edit_team_member,
+        super::teams::remove_team_member,
+        super::teams::team_storage_edit,
+        super::tags::category_list,
+        super::tags::category_create,
+        super::tags::category_delete,
+        super::tags::loader_list,
+        super::tags::loader_create,
+        super::tags::loader_delete,
+        super::tags::game_version_list,
+        super::tags::game_version_create,
+        super::tags::game_version_delete,
+        super::tags::license_list,
+        super::tags::license_create,
+        super::tags::license_delete,
+        super::tags::donation_platform_list,
+        super::tags::donation_platform_create,
+        super::tags::donation_platform_delete,
+        super::tags::report_type_list,
+        super::tags::report_type_create,
+        super::tags::report_type_delete,
+        super::auth::init,
+        super::auth::auth_callback,
+        super::moderation::mods,
+        super::admin::cache_stats,
+        super::reports::report_create,
+        super::reports::reports,
+        super::reports::delete_report,
+        super::notifications::notifications_get,
+        super::notifications::notifications_batch,
+        super::notifications::notifications_stream,
+        super::notifications::notifications_stream_ticket,
+        super::notifications::notification_get,
+        super::notifications::notification_delete,
+        super::notifications::notification_edit,
+        super::notifications::notifications_edit,
+        super::notifications::notifications_delete,
+        super::notifications::email_unsubscribe_get,
+        super::notifications::email_unsubscribe_post,
+        super::webhooks::webhook_create,
+        super::webhooks::webhooks_get,
+        super::webhooks::webhook_get,
+        super::webhooks::webhook_edit,
+        super::webhooks::webhook_delete,
+        super::webhooks::webhook_ping,
+        super::webhooks::webhook_deliveries,
+        super::uploads::upload_create,
+        super::uploads::upload_get,
+        super::uploads::upload_append,
+        super::uploads::upload_delete,
+        super::announcements::channel_list,
+        super::announcements::channel_create,
+        super::announcements::channel_edit,
+        super::announcements::channel_delete,
+        super::embeds::oembed,
+        super::embeds::mod_opengraph,
+        super::feeds::mod_feed,
+        super::feeds::user_feed,
+        super::feeds::category_feed,
+        super::feeds::approved_mods_feed,
+        super::badges::mod_badge,
+        super::sitemap::sitemap_index,
+        super::sitemap::sitemap_page,
+        super::files::file_get,
+    ),
+    components(schemas(crate::models::error::ApiError)),
+    modifiers(&SecurityAddon, &ErrorResponses),
+    tags(
+        (name = "mods", description = "Searching, reading and editing mods"),
+        (name = "versions", description = "Versions of mods and their files"),
+        (name = "users", description = "Users and their mods, follows and notifications"),
+        (name = "teams", description = "The members of the team behind a mod"),
+        (name = "tags", description = "Categories, loaders, game versions, licenses and other tags"),
+        (name = "auth", description = "Logging in with Discord"),
+        (name = "moderation", description = "Tools for moderators"),
+        (name = "admin", description = "Tools for administrators"),
+        (name = "reports", description = "Reports of mods, versions and users"),
+        (name = "notifications", description = "Notifications sent to users"),
+        (name = "webhooks", description = "Outgoing webhooks of mods and teams"),
+        (name = "uploads", description = "Resumable uploads of version files"),
+        (name = "announcements", description = "Discord channels new mods and versions are announced in"),
+        (name = "embeds", description = "Previews, badges and sitemaps for other sites"),
+        (name = "feeds", description = "Atom feeds"),
+        (name = "files", description = "Files stored on this server, when it doesn't use S3"),
+        (name = "misc"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Documents the tokens used by `security(("token" = []))`
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        components.add_security_scheme(
+            "token",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "Authorization",
+                "A token obtained by logging in through `/api/v1/auth/init`",
+            ))),
+        );
+    }
+}
+
+/// Adds the error responses every handler can return, so they don't have to
+/// be repeated in every annotation
+struct ErrorResponses;
+
+impl ErrorResponses {
+    fn add_response(operation: &mut Operation, status: &str, description: &str) {
+        operation
+            .responses
+            .responses
+            .entry(status.to_string())
+            .or_insert_with(|| {
+                ResponseBuilder::new()
+                    .description(description)
+                    .content(
+                        "application/json",
+                        ContentBuilder::new()
+                            .schema(Some(Ref::from_schema_name("ApiError")))
+                            .build(),
+                    )
+                    .build()
+                    .into()
+            });
+    }
+}
+
+impl Modify for ErrorResponses {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for item in openapi.paths.paths.values_mut() {
+            let operations = vec![
+                &mut item.get,
+                &mut item.put,
+                &mut item.post,
+                &mut item.delete,
+                &mut item.patch,
+            ];
+
+            for operation in operations.into_iter().flatten() {
+                Self::add_response(operation, "400", "The request was invalid");
+
+                if operation.security.is_some() {
+                    Self::add_response(operation, "401", "The token is missing or invalid");
+                }
+
+                Self::add_response(operation, "500", "An internal error occurred");
+            }
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref OPENAPI_JSON: String = ApiDoc::openapi()
+        .to_json()
+        .expect("The OpenAPI document could not be serialized");
+    static ref DOCS_CONFIG: Arc<Config<'static>> = Arc::new(Config::from("/api/v1/openapi.json"));
+}
+
+#[get("openapi.json")]
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .set(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(60 * 60),
+        ]))
+        .body(OPENAPI_JSON.as_str())
+}
+
+#[get("docs")]
+pub async fn docs_redirect() -> HttpResponse {
+    // The docs UI loads its assets relative to the page
+    HttpResponse::PermanentRedirect()
+        .header("Location", "docs/")
+        .finish()
+}
+
+/// The bundled docs UI, reading `/api/v1/openapi.json`
+#[get("docs/{tail:.*}")]
+pub async fn docs(tail: web::Path<(String,)>) -> HttpResponse {
+    match utoipa_swagger_ui::serve(&tail.into_inner().0, DOCS_CONFIG.clone()) {
+        Ok(Some(file)) => HttpResponse::Ok()
+            .content_type(file.content_type)
+            .body(file.bytes.into_owned()),
+        Ok(None) => HttpResponse::NotFound().body(""),
+        Err(_) => HttpResponse::InternalServerError().body(""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTE_FILES: &[(&str, &str)] = &[
+        ("admin.rs", include_str!("admin.rs")),
+        ("announcements.rs", include_str!("announcements.rs")),
+        ("auth.rs", include_str!("auth.rs")),
+        ("badges.rs", include_str!("badges.rs")),
+        ("embeds.rs", include_str!("embeds.rs")),
+        ("feeds.rs", include_str!("feeds.rs")),
+        ("files.rs", include_str!("files.rs")),
+        ("index.rs", include_str!("index.rs")),
+        ("mod_creation.rs", include_str!("mod_creation.rs")),
+        ("moderation.rs", include_str!("moderation.rs")),
+        ("mods.rs", include_str!("mods.rs")),
+        ("notifications.rs", include_str!("notifications.rs")),
+        ("reports.rs", include_str!("reports.rs")),
+        ("sitemap.rs", include_str!("sitemap.rs")),
+        ("tags.rs", include_str!("tags.rs")),
+        ("teams.rs", include_str!("teams.rs")),
+        ("uploads.rs", include_str!("uploads.rs")),
+        ("users.rs", include_str!("users.rs")),
+        ("version_creation.rs", include_str!("version_creation.rs")),
+        ("versions.rs", include_str!("versions.rs")),
+        ("webhooks.rs", include_str!("webhooks.rs")),
+    ];
+
+    fn count_routes(source: &str) -> usize {
+        source
+            .lines()
+            .filter(|line| {
+                ["#[get(", "#[post(", "#[put(", "#[patch(", "#[delete("]
+                    .iter()
+                    .any(|attribute| line.starts_with(attribute))
+            })
+            .count()
+    }
+
+    /// The `#[utoipa::path]` annotations of the routes in a file, each with
+    /// the name and parameters of the handler after it
+    fn documented_handlers(source: &str) -> Vec<(&str, &str)> {
+        source
+            .split("#[utoipa::path(")
+            .skip(1)
+            .filter_map(|chunk| {
+                let (annotation, handler) = chunk.split_at(chunk.find("\n)]")?);
+                let handler = &handler[..handler.find(") -> ")?];
+
+                Some((annotation, handler))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn json_bodies_are_documented() {
+        for (file, source) in ROUTE_FILES {
+            for (annotation, handler) in documented_handlers(source) {
+                if handler.contains("web::Json<") {
+                    assert!(
+                        annotation.contains("request_body"),
+                        "the route in {} taking a JSON body needs a request_body: {}",
+                        file,
+                        handler.trim()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let mut routes = 0;
+
+        for (file, source) in ROUTE_FILES {
+            let documented = source.matches("#[utoipa::path(").count();
+
+            assert_eq!(
+                count_routes(source),
+                documented,
+                "every route in {} needs a #[utoipa::path] annotation",
+                file
+            );
+
+            routes += documented;
+        }
+
+        let spec = ApiDoc::openapi();
+        let operations: usize = spec
+            .paths
+            .paths
+            .values()
+            .map(|item| {
+                [&item.get, &item.put, &item.post, &item.delete, &item.patch]
+                    .iter()
+                    .filter(|operation| operation.is_some())
+                    .count()
+            })
+            .sum();
+
+        assert_eq!(
+            operations, routes,
+            "every documented route needs to be listed in ApiDoc"
+        );
+        assert!(spec.to_json().is_ok());
+    }
+}
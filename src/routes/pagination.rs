@@ -0,0 +1,313 @@
+use super::ApiError;
+use crate::models::pagination::{Listing, Page, PageQuery};
+use chrono::{DateTime, TimeZone, Utc};
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 100;
+
+/// An order a list can be sorted in.  Lists are always sorted by a
+/// timestamp, with the ID as a tiebreaker.
+pub struct SortKey {
+    pub name: &'static str,
+    pub descending: bool,
+}
+
+pub const NEWEST: SortKey = SortKey {
+    name: "newest",
+    descending: true,
+};
+
+pub const OLDEST: SortKey = SortKey {
+    name: "oldest",
+    descending: false,
+};
+
+/// A validated `PageQuery`, ready to be passed to a keyset query
+pub struct Pagination {
+    pub sort: &'static SortKey,
+    /// The sort key and ID of the last item of the previous page
+    pub after: Option<(DateTime<Utc>, i64)>,
+    /// The size of a page, or how many items an unpaged list is cut off at
+    pub limit: Option<u32>,
+    /// Whether the list is returned as a `Page`.  Lists were plain arrays
+    /// before they could be paged, so clients have to opt into it.
+    pub paged: bool,
+}
+
+impl Pagination {
+    /// Validates the query against the sorts a list supports.  The first
+    /// sort is used when none is given.
+    pub fn from_query(
+        query: &PageQuery,
+        sorts: &'static [SortKey],
+    ) -> Result<Pagination, ApiError> {
+        let sort = match &query.sort {
+            Some(name) => sorts.iter().find(|x| x.name == name).ok_or_else(|| {
+                ApiError::InvalidInputError(format!(
+                    "Invalid sort, expected one of: {}",
+                    sorts.iter().map(|x| x.name).collect::<Vec<_>>().join(", ")
+                ))
+            })?,
+            None => &sorts[0],
+        };
+
+        let paged = query.cursor.is_some() || query.limit.is_some();
+
+        let limit = if paged {
+            let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+
+            if limit == 0 || limit > MAX_LIMIT {
+                return Err(ApiError::InvalidInputError(format!(
+                    "The limit must be between 1 and {}",
+                    MAX_LIMIT
+                )));
+            }
+
+            Some(limit)
+        } else {
+            query.count
+        };
+
+        let after = match &query.cursor {
+            Some(cursor) => Some(
+                decode_cursor(cursor, sort)
+                    .ok_or_else(|| ApiError::InvalidInputError("Invalid cursor".to_string()))?,
+            ),
+            None => None,
+        };
+
+        Ok(Pagination {
+            sort,
+            after,
+            limit,
+            paged,
+        })
+    }
+
+    /// Cuts the list off at `limit` when it isn't paged and no `count` was
+    /// given, for lists that were limited before they could be paged
+    pub fn unpaged_limit(mut self, limit: u32) -> Pagination {
+        if !self.paged && self.limit.is_none() {
+            self.limit = Some(limit);
+        }
+
+        self
+    }
+
+    /// The number of rows to fetch, if limited.  One more than the limit is
+    /// fetched to know whether there is a next page.
+    pub fn fetch_limit(&self) -> Option<i64> {
+        self.limit.map(|x| x as i64 + 1)
+    }
+
+    pub fn after_key(&self) -> Option<DateTime<Utc>> {
+        self.after.map(|x| x.0)
+    }
+
+    pub fn after_id(&self) -> Option<i64> {
+        self.after.map(|x| x.1)
+    }
+
+    /// Cuts the fetched `(sort key, id)` rows down to the page, returning
+    /// the IDs on it and the cursor of the next page
+    pub fn split(&self, mut rows: Vec<(DateTime<Utc>, i64)>) -> (Vec<i64>, Option<String>) {
+        let next = match self.limit {
+            Some(limit) if rows.len() > limit as usize => {
+                rows.truncate(limit as usize);
+                rows.last()
+                    .filter(|_| self.paged)
+                    .map(|(key, id)| encode_cursor(self.sort, key, *id))
+            }
+            _ => None,
+        };
+
+        (rows.into_iter().map(|x| x.1).collect(), next)
+    }
+
+    /// Builds the list, putting the items back in the order of the IDs
+    pub fn page<T>(
+        &self,
+        ids: &[i64],
+        mut items: Vec<T>,
+        id: impl Fn(&T) -> i64,
+        next: Option<String>,
+    ) -> Listing<T> {
+        items.sort_by_key(|x| ids.iter().position(|y| *y == id(x)));
+
+        if self.paged {
+            Listing::Page(Page { items, next })
+        } else {
+            Listing::All(items)
+        }
+    }
+}
+
+/// Cursors hold the seconds and nanoseconds of the sort key separately, so
+/// any timestamp can be encoded
+fn encode_cursor(sort: &SortKey, key: &DateTime<Utc>, id: i64) -> String {
+    base64::encode_config(
+        format!(
+            "{}:{}:{}:{}",
+            sort.name,
+            key.timestamp(),
+            key.timestamp_subsec_nanos(),
+            id
+        ),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+fn decode_cursor(cursor: &str, sort: &SortKey) -> Option<(DateTime<Utc>, i64)> {
+    let decoded = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+
+    let mut parts = decoded.split(':');
+
+    if parts.next()? != sort.name {
+        return None;
+    }
+
+    let key = Utc
+        .timestamp_opt(parts.next()?.parse().ok()?, parts.next()?.parse().ok()?)
+        .single()?;
+    let id = parts.next()?.parse().ok()?;
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some((key, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SORTS: &[SortKey] = &[NEWEST, OLDEST];
+
+    fn at(secs: i64, nanos: u32) -> DateTime<Utc> {
+        Utc.timestamp_opt(secs, nanos).unwrap()
+    }
+
+    fn query(cursor: Option<&str>, limit: Option<u32>, sort: Option<&str>) -> PageQuery {
+        PageQuery {
+            cursor: cursor.map(String::from),
+            limit,
+            count: None,
+            sort: sort.map(String::from),
+        }
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let key = at(1_626_000_000, 123_456_789);
+        let cursor = encode_cursor(&NEWEST, &key, 42);
+
+        assert_eq!(decode_cursor(&cursor, &NEWEST), Some((key, 42)));
+
+        let pagination = Pagination::from_query(&query(Some(&cursor), None, None), SORTS).unwrap();
+        assert_eq!(pagination.after, Some((key, 42)));
+        assert_eq!(pagination.limit, Some(DEFAULT_LIMIT));
+    }
+
+    #[test]
+    fn cursors_can_hold_any_date() {
+        for key in &[at(-1, 999_999_999), at(10_000_000_000, 1)] {
+            let cursor = encode_cursor(&OLDEST, key, 7);
+            assert_eq!(decode_cursor(&cursor, &OLDEST), Some((*key, 7)));
+        }
+    }
+
+    #[test]
+    fn cursors_only_work_with_their_sort() {
+        let cursor = encode_cursor(&NEWEST, &at(1_626_000_000, 0), 42);
+
+        assert_eq!(decode_cursor(&cursor, &OLDEST), None);
+        assert!(matches!(
+            Pagination::from_query(&query(Some(&cursor), None, Some("oldest")), SORTS),
+            Err(ApiError::InvalidInputError(_))
+        ));
+    }
+
+    #[test]
+    fn tampered_cursors_are_rejected() {
+        let cursor = encode_cursor(&NEWEST, &at(1_626_000_000, 0), 42);
+        let mut tampered = cursor.clone();
+        tampered.pop();
+        tampered.push('!');
+
+        for cursor in &[
+            tampered,
+            "not a cursor".to_string(),
+            base64::encode_config("newest:abc:42", base64::URL_SAFE_NO_PAD),
+            base64::encode_config("newest:1626000000", base64::URL_SAFE_NO_PAD),
+            base64::encode_config("newest:1626000000:x:42", base64::URL_SAFE_NO_PAD),
+            base64::encode_config("newest:1626000000:0:42:1", base64::URL_SAFE_NO_PAD),
+            base64::encode_config([0xff, 0xfe, 0xfd], base64::URL_SAFE_NO_PAD),
+        ] {
+            assert_eq!(decode_cursor(cursor, &NEWEST), None, "{}", cursor);
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        for cursor in &[
+            "newest:99999999999999999999:0:1",
+            "newest:9223372036854775807:0:1",
+            "newest:0:2000000000:1",
+            "newest:0:0:99999999999999999999",
+        ] {
+            let encoded = base64::encode_config(cursor, base64::URL_SAFE_NO_PAD);
+            assert_eq!(decode_cursor(&encoded, &NEWEST), None, "{}", cursor);
+        }
+
+        for limit in &[0, MAX_LIMIT + 1] {
+            assert!(Pagination::from_query(&query(None, Some(*limit), None), SORTS).is_err());
+        }
+        assert!(Pagination::from_query(&query(None, None, Some("oldest_first")), SORTS).is_err());
+    }
+
+    #[test]
+    fn pages_end_with_a_cursor_for_the_next() {
+        let pagination = Pagination::from_query(&query(None, Some(2), None), SORTS).unwrap();
+        assert_eq!(pagination.fetch_limit(), Some(3));
+
+        let mut rows = vec![(at(3, 0), 3), (at(2, 0), 2), (at(1, 0), 1)];
+        let (ids, next) = pagination.split(rows.clone());
+        assert_eq!(ids, vec![3, 2]);
+        assert_eq!(decode_cursor(&next.unwrap(), &NEWEST), Some((at(2, 0), 2)));
+
+        rows.truncate(2);
+        let (ids, next) = pagination.split(rows);
+        assert_eq!(ids, vec![3, 2]);
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn lists_are_only_paged_when_asked() {
+        let pagination = Pagination::from_query(&query(None, None, None), SORTS).unwrap();
+        assert!(!pagination.paged);
+        assert_eq!(pagination.fetch_limit(), None);
+
+        let rows = vec![(at(3, 0), 3), (at(2, 0), 2), (at(1, 0), 1)];
+        let (ids, next) = pagination.split(rows.clone());
+        assert_eq!(ids, vec![3, 2, 1]);
+        assert!(matches!(
+            pagination.page(&ids, ids.clone(), |x| *x, next),
+            Listing::All(items) if items == vec![3, 2, 1]
+        ));
+
+        let mut legacy = query(None, None, None);
+        legacy.count = Some(2);
+        let pagination = Pagination::from_query(&legacy, SORTS)
+            .unwrap()
+            .unpaged_limit(100);
+        assert!(!pagination.paged);
+        assert_eq!(pagination.split(rows), (vec![3, 2], None));
+
+        let pagination = Pagination::from_query(&query(None, None, None), SORTS)
+            .unwrap()
+            .unpaged_limit(100);
+        assert_eq!(pagination.fetch_limit(), Some(101));
+    }
+}
@@ -1,13 +1,19 @@
 use crate::auth::{check_is_moderator_from_headers, get_user_from_headers};
+use crate::database;
+use crate::database::models::notification_item::NotificationBuilder;
 use crate::models::ids::{ModId, UserId, VersionId};
+use crate::models::notifications::NotificationBody;
+use crate::models::pagination::{Listing, PageQuery};
 use crate::models::reports::{ItemType, Report};
+use crate::routes::pagination::{Pagination, SortKey, NEWEST, OLDEST};
 use crate::routes::ApiError;
 use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
 use futures::StreamExt;
 use serde::Deserialize;
 use sqlx::PgPool;
+use utoipa::ToSchema;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateReport {
     pub report_type: String,
     pub item_id: String,
@@ -15,6 +21,13 @@ pub struct CreateReport {
     pub body: String,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "reports",
+    security(("token" = [])),
+    request_body = CreateReport,
+    responses((status = 200, description = "The report was created", body = Report)),
+)]
 #[post("report")]
 pub async fn report_create(
     req: HttpRequest,
@@ -52,24 +65,59 @@ pub async fn report_create(
         version_id: None,
         user_id: None,
         body: new_report.body.clone(),
-        reporter: current_user.id.into(),
+        reporter: Some(current_user.id.into()),
         created: chrono::Utc::now(),
+        snapshot: None,
     };
 
     match new_report.item_type {
         ItemType::Mod => {
-            report.mod_id =
-                Some(serde_json::from_str::<ModId>(&*format!("\"{}\"", new_report.item_id))?.into())
+            let mod_id: database::models::ModId =
+                serde_json::from_str::<ModId>(&*format!("\"{}\"", new_report.item_id))?.into();
+
+            let mod_data = database::models::Mod::get_full(mod_id, &**pool)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?
+                .ok_or_else(|| {
+                    ApiError::InvalidInputError(format!("Invalid mod id: {}", new_report.item_id))
+                })?;
+
+            report.mod_id = Some(mod_id);
+            report.snapshot = Some(serde_json::to_value(super::mods::convert_mod(mod_data))?);
         }
         ItemType::Version => {
-            report.version_id = Some(
-                serde_json::from_str::<VersionId>(&*format!("\"{}\"", new_report.item_id))?.into(),
-            )
+            let version_id: database::models::VersionId =
+                serde_json::from_str::<VersionId>(&*format!("\"{}\"", new_report.item_id))?.into();
+
+            let version_data = database::models::Version::get_many_full(vec![version_id], &**pool)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?
+                .pop()
+                .ok_or_else(|| {
+                    ApiError::InvalidInputError(format!(
+                        "Invalid version id: {}",
+                        new_report.item_id
+                    ))
+                })?;
+
+            report.version_id = Some(version_id);
+            report.snapshot = Some(serde_json::to_value(super::versions::convert_version(
+                version_data,
+            ))?);
         }
         ItemType::User => {
-            report.user_id = Some(
-                serde_json::from_str::<UserId>(&*format!("\"{}\"", new_report.item_id))?.into(),
-            )
+            let user_id: database::models::UserId =
+                serde_json::from_str::<UserId>(&*format!("\"{}\"", new_report.item_id))?.into();
+
+            let user_data = database::models::User::get(user_id, &**pool)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?
+                .ok_or_else(|| {
+                    ApiError::InvalidInputError(format!("Invalid user id: {}", new_report.item_id))
+                })?;
+
+            report.user_id = Some(user_id);
+            report.snapshot = Some(serde_json::to_value(super::users::convert_user(user_data))?);
         }
         ItemType::Unknown => {
             return Err(ApiError::InvalidInputError(format!(
@@ -93,49 +141,71 @@ pub async fn report_create(
         report_type: new_report.report_type.clone(),
         item_id: new_report.item_id.clone(),
         item_type: new_report.item_type.clone(),
-        reporter: current_user.id,
+        reporter: Some(current_user.id),
         body: new_report.body.clone(),
-        created: chrono::Utc::now(),
+        created: report.created,
+        // The snapshot can show what the reporter isn't allowed to see, like
+        // hidden mods
+        snapshot: if current_user.role.is_mod() {
+            report.snapshot
+        } else {
+            None
+        },
     }))
 }
 
-#[derive(Deserialize)]
-pub struct ResultCount {
-    #[serde(default = "default_count")]
-    count: i16,
-}
-
-fn default_count() -> i16 {
-    100
-}
+const REPORTS_SORTS: &[SortKey] = &[OLDEST, NEWEST];
 
+/// Sorted by creation date, with the keys `oldest` and `newest`
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "reports",
+    security(("token" = [])),
+    params(PageQuery),
+    responses((status = 200, description = "The open reports. Only available to moderators. Reports raised by the site itself have no `reporter`.", body = Listing<Report>)),
+)]
 #[get("report")]
 pub async fn reports(
     req: HttpRequest,
     pool: web::Data<PgPool>,
-    count: web::Query<ResultCount>,
+    web::Query(page): web::Query<PageQuery>,
 ) -> Result<HttpResponse, ApiError> {
     check_is_moderator_from_headers(req.headers(), &**pool).await?;
 
+    let pagination = Pagination::from_query(&page, REPORTS_SORTS)?.unpaged_limit(100);
+
     use futures::stream::TryStreamExt;
 
-    let report_ids = sqlx::query!(
+    let rows = sqlx::query!(
         "
-        SELECT id FROM reports
-        ORDER BY created ASC
-        LIMIT $1;
+        SELECT id, created FROM reports
+        WHERE ($1::timestamptz IS NULL OR CASE WHEN $3
+            THEN (created, id) < ($1::timestamptz, $2::bigint)
+            ELSE (created, id) > ($1::timestamptz, $2::bigint)
+        END)
+        ORDER BY
+            CASE WHEN $3 THEN created END DESC,
+            CASE WHEN $3 THEN id END DESC,
+            created ASC, id ASC
+        LIMIT $4;
         ",
-        count.count as i64
+        pagination.after_key(),
+        pagination.after_id(),
+        pagination.sort.descending,
+        pagination.fetch_limit(),
     )
     .fetch_many(&**pool)
-    .try_filter_map(|e| async {
-        Ok(e.right()
-            .map(|m| crate::database::models::ids::ReportId(m.id)))
-    })
-    .try_collect::<Vec<crate::database::models::ids::ReportId>>()
+    .try_filter_map(|e| async { Ok(e.right().map(|m| (m.created, m.id))) })
+    .try_collect::<Vec<_>>()
     .await
     .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
+    let (ids, next) = pagination.split(rows);
+    let report_ids = ids
+        .iter()
+        .map(|x| crate::database::models::ids::ReportId(*x))
+        .collect();
+
     let query_reports = crate::database::models::report_item::Report::get_many(report_ids, &**pool)
         .await
         .map_err(|e| ApiError::DatabaseError(e.into()))?;
@@ -162,15 +232,27 @@ pub async fn reports(
             report_type: x.report_type,
             item_id,
             item_type,
-            reporter: x.reporter.into(),
+            reporter: x.reporter.map(|x| x.into()),
             body: x.body,
             created: x.created,
+            snapshot: x.snapshot,
         })
     }
 
-    Ok(HttpResponse::Ok().json(reports))
+    Ok(HttpResponse::Ok().json(pagination.page(
+        &ids,
+        reports,
+        |x| crate::database::models::ids::ReportId::from(x.id).0,
+        next,
+    )))
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "reports",
+    security(("token" = [])),
+    responses((status = 200, description = "The report was resolved"), (status = 404, description = "The report doesn't exist")),
+)]
 #[delete("report/{id}")]
 pub async fn delete_report(
     req: HttpRequest,
@@ -179,14 +261,69 @@ pub async fn delete_report(
 ) -> Result<HttpResponse, ApiError> {
     check_is_moderator_from_headers(req.headers(), &**pool).await?;
 
-    let result = crate::database::models::report_item::Report::remove_full(
-        info.into_inner().0.into(),
-        &**pool,
-    )
-    .await
-    .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    let report_id = info.into_inner().0;
+
+    let report = crate::database::models::report_item::Report::get(report_id.into(), &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let result =
+        crate::database::models::report_item::Report::remove_full(report_id.into(), &**pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    if let (Some(_), Some(report)) = (result, report) {
+        // Reports the site raised itself have nobody to tell
+        if let Some(reporter) = report.reporter {
+            let (item_type, item_id, link) = if let Some(mod_id) = report.mod_id {
+                let mod_id: ModId = mod_id.into();
+                (ItemType::Mod, mod_id.to_string(), format!("mod/{}", mod_id))
+            } else if let Some(version_id) = report.version_id {
+                let version_id: VersionId = version_id.into();
+                (
+                    ItemType::Version,
+                    version_id.to_string(),
+                    format!("version/{}", version_id),
+                )
+            } else if let Some(user_id) = report.user_id {
+                let user_id: UserId = user_id.into();
+                (
+                    ItemType::User,
+                    user_id.to_string(),
+                    format!("user/{}", user_id),
+                )
+            } else {
+                (ItemType::Unknown, "".to_string(), "".to_string())
+            };
+
+            let mut transaction = pool
+                .begin()
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+            NotificationBuilder {
+                title: "Your report has been resolved".to_string(),
+                text: format!(
+                    "A moderator has reviewed and closed your {} report",
+                    item_type.as_str()
+                ),
+                link,
+                body: NotificationBody::ReportResolved {
+                    report_id,
+                    item_type,
+                    item_id,
+                },
+                actions: vec![],
+            }
+            .insert(reporter, &mut transaction)
+            .await?;
+
+            transaction
+                .commit()
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?;
+        }
 
-    if result.is_some() {
         Ok(HttpResponse::Ok().body(""))
     } else {
         Ok(HttpResponse::NotFound().body(""))
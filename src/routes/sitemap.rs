@@ -0,0 +1,89 @@
+use crate::routes::ApiError;
+use crate::sitemap::{write_index, Sitemaps};
+use actix_web::http::header::{CacheControl, CacheDirective, HttpDate, LastModified};
+use actix_web::{get, web, HttpResponse};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use utoipa::IntoParams;
+
+/// How long crawlers and proxies may cache the sitemap, in seconds
+const SITEMAP_MAX_AGE: u32 = 60 * 60;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SitemapQuery {
+    /// Whether NSFW mods, and users with only NSFW mods, are included
+    #[serde(default)]
+    pub nsfw: bool,
+}
+
+fn sitemap_response(body: Vec<u8>, generated: chrono::DateTime<chrono::Utc>) -> HttpResponse {
+    let last_modified =
+        SystemTime::UNIX_EPOCH + Duration::from_secs(generated.timestamp().max(0) as u64);
+
+    HttpResponse::Ok()
+        .content_type("application/xml; charset=utf-8")
+        .set(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(SITEMAP_MAX_AGE),
+        ]))
+        .set(LastModified(HttpDate::from(last_modified)))
+        .body(body)
+}
+
+/// The sitemap index, linking to every page of the sitemap
+#[utoipa::path(
+    tag = "embeds",
+    params(SitemapQuery),
+    responses((status = 200, description = "The sitemap index", content_type = "application/xml", body = String), (status = 503, description = "The sitemap hasn't been generated yet")),
+)]
+#[get("/sitemap.xml")]
+pub async fn sitemap_index(
+    query: web::Query<SitemapQuery>,
+    sitemaps: web::Data<Arc<Sitemaps>>,
+) -> Result<HttpResponse, ApiError> {
+    let sitemap = match sitemaps.current(query.nsfw) {
+        Some(sitemap) => sitemap,
+        // The sitemap is generated shortly after startup
+        None => return Ok(HttpResponse::ServiceUnavailable().body("")),
+    };
+
+    // Pages are linked with the configured URL, since the Host header is
+    // up to the client
+    let base_url = dotenv::var("API_URL")?;
+    let page_query = if query.nsfw { "?nsfw=true" } else { "" };
+
+    let body = write_index(&sitemap, &base_url, page_query)
+        .map_err(|e| ApiError::XmlError(e.to_string()))?;
+
+    Ok(sitemap_response(body, sitemap.generated))
+}
+
+#[utoipa::path(
+    tag = "embeds",
+    params(("page" = usize, Path, description = "The page of the sitemap, starting at 1"), SitemapQuery),
+    responses((status = 200, description = "A page of the sitemap", content_type = "application/xml", body = String), (status = 404, description = "The page doesn't exist"), (status = 503, description = "The sitemap hasn't been generated yet")),
+)]
+#[get("/sitemap/{page}.xml")]
+pub async fn sitemap_page(
+    info: web::Path<(usize,)>,
+    query: web::Query<SitemapQuery>,
+    sitemaps: web::Data<Arc<Sitemaps>>,
+) -> Result<HttpResponse, ApiError> {
+    let sitemap = match sitemaps.current(query.nsfw) {
+        Some(sitemap) => sitemap,
+        None => return Ok(HttpResponse::ServiceUnavailable().body("")),
+    };
+
+    // Pages are numbered from 1
+    let page = match info.into_inner().0.checked_sub(1) {
+        Some(index) => sitemap.pages.get(index),
+        None => None,
+    };
+
+    match page {
+        Some(page) => Ok(sitemap_response(page.body.clone(), sitemap.generated)),
+        None => Ok(HttpResponse::NotFound().body("")),
+    }
+}
@@ -1,10 +1,13 @@
+use super::conditional::{json_response, CacheScope};
 use super::ApiError;
 use crate::auth::check_is_admin_from_headers;
 use crate::database::models;
+use crate::database::models::cache::{self, Invalidation};
 use crate::database::models::categories::{DonationPlatform, License, ReportType};
 use actix_web::{delete, get, put, web, HttpRequest, HttpResponse};
 use models::categories::{Category, GameVersion, Loader};
 use sqlx::PgPool;
+use utoipa::{IntoParams, ToSchema};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
@@ -32,12 +35,26 @@ pub fn config(cfg: &mut web::ServiceConfig) {
 
 // TODO: searching / filtering? Could be used to implement a live
 // searching category list
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    responses((status = 200, description = "Every category", body = Vec<String>), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("category")]
-pub async fn category_list(pool: web::Data<PgPool>) -> Result<HttpResponse, ApiError> {
+pub async fn category_list(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
     let results = Category::list(&**pool).await?;
-    Ok(HttpResponse::Ok().json(results))
+    json_response(&req, &results, CacheScope::Public)
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    security(("token" = [])),
+    responses((status = 200, description = "The category was created")),
+)]
 #[put("category/{name}")]
 pub async fn category_create(
     req: HttpRequest,
@@ -50,9 +67,17 @@ pub async fn category_create(
 
     let _id = Category::builder().name(&name)?.insert(&**pool).await?;
 
+    cache::invalidate(Invalidation::Categories, &**pool).await;
+
     Ok(HttpResponse::Ok().body(""))
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    security(("token" = [])),
+    responses((status = 200, description = "The category was deleted"), (status = 404, description = "The category doesn't exist")),
+)]
 #[delete("category/{name}")]
 pub async fn category_delete(
     req: HttpRequest,
@@ -71,6 +96,8 @@ pub async fn category_delete(
         .await
         .map_err(models::DatabaseError::from)?;
 
+    cache::invalidate(Invalidation::Categories, &**pool).await;
+
     if result.is_some() {
         Ok(HttpResponse::Ok().body(""))
     } else {
@@ -78,12 +105,26 @@ pub async fn category_delete(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    responses((status = 200, description = "Every loader", body = Vec<String>), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("loader")]
-pub async fn loader_list(pool: web::Data<PgPool>) -> Result<HttpResponse, ApiError> {
+pub async fn loader_list(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
     let results = Loader::list(&**pool).await?;
-    Ok(HttpResponse::Ok().json(results))
+    json_response(&req, &results, CacheScope::Public)
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    security(("token" = [])),
+    responses((status = 200, description = "The loader was created")),
+)]
 #[put("loader/{name}")]
 pub async fn loader_create(
     req: HttpRequest,
@@ -96,9 +137,17 @@ pub async fn loader_create(
 
     let _id = Loader::builder().name(&name)?.insert(&**pool).await?;
 
+    cache::invalidate(Invalidation::Loaders, &**pool).await;
+
     Ok(HttpResponse::Ok().body(""))
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    security(("token" = [])),
+    responses((status = 200, description = "The loader was deleted"), (status = 404, description = "The loader doesn't exist")),
+)]
 #[delete("loader/{name}")]
 pub async fn loader_delete(
     req: HttpRequest,
@@ -117,6 +166,8 @@ pub async fn loader_delete(
         .await
         .map_err(models::DatabaseError::from)?;
 
+    cache::invalidate(Invalidation::Loaders, &**pool).await;
+
     if result.is_some() {
         Ok(HttpResponse::Ok().body(""))
     } else {
@@ -124,35 +175,50 @@ pub async fn loader_delete(
     }
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct GameVersionQueryData {
     #[serde(rename = "type")]
     type_: Option<String>,
     major: Option<bool>,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    params(GameVersionQueryData),
+    responses((status = 200, description = "Every game version matching the filters", body = Vec<String>), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("game_version")]
 pub async fn game_version_list(
+    req: HttpRequest,
     pool: web::Data<PgPool>,
     query: web::Query<GameVersionQueryData>,
 ) -> Result<HttpResponse, ApiError> {
     if query.type_.is_some() || query.major.is_some() {
         let results =
             GameVersion::list_filter(query.type_.as_deref(), query.major, &**pool).await?;
-        Ok(HttpResponse::Ok().json(results))
+        json_response(&req, &results, CacheScope::Public)
     } else {
         let results = GameVersion::list(&**pool).await?;
-        Ok(HttpResponse::Ok().json(results))
+        json_response(&req, &results, CacheScope::Public)
     }
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, ToSchema)]
 pub struct GameVersionData {
     #[serde(rename = "type")]
     type_: String,
     date: Option<chrono::DateTime<chrono::Utc>>,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    request_body = GameVersionData,
+    security(("token" = [])),
+    responses((status = 200, description = "The game version was created")),
+)]
 #[put("game_version/{name}")]
 pub async fn game_version_create(
     req: HttpRequest,
@@ -177,9 +243,17 @@ pub async fn game_version_create(
 
     let _id = builder.insert(&**pool).await?;
 
+    cache::invalidate(Invalidation::GameVersions, &**pool).await;
+
     Ok(HttpResponse::Ok().body(""))
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    security(("token" = [])),
+    responses((status = 200, description = "The game version was deleted"), (status = 404, description = "The game version doesn't exist")),
+)]
 #[delete("game_version/{name}")]
 pub async fn game_version_delete(
     req: HttpRequest,
@@ -198,6 +272,8 @@ pub async fn game_version_delete(
         .await
         .map_err(models::DatabaseError::from)?;
 
+    cache::invalidate(Invalidation::GameVersions, &**pool).await;
+
     if result.is_some() {
         Ok(HttpResponse::Ok().body(""))
     } else {
@@ -205,14 +281,22 @@ pub async fn game_version_delete(
     }
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, ToSchema)]
 pub struct LicenseQueryData {
     short: String,
     name: String,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    responses((status = 200, description = "Every license", body = Vec<LicenseQueryData>), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("license")]
-pub async fn license_list(pool: web::Data<PgPool>) -> Result<HttpResponse, ApiError> {
+pub async fn license_list(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
     let results: Vec<LicenseQueryData> = License::list(&**pool)
         .await?
         .into_iter()
@@ -221,14 +305,21 @@ pub async fn license_list(pool: web::Data<PgPool>) -> Result<HttpResponse, ApiEr
             name: x.name,
         })
         .collect();
-    Ok(HttpResponse::Ok().json(results))
+    json_response(&req, &results, CacheScope::Public)
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, ToSchema)]
 pub struct LicenseData {
     name: String,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    request_body = LicenseData,
+    security(("token" = [])),
+    responses((status = 200, description = "The license was created")),
+)]
 #[put("license/{name}")]
 pub async fn license_create(
     req: HttpRequest,
@@ -246,9 +337,17 @@ pub async fn license_create(
         .insert(&**pool)
         .await?;
 
+    cache::invalidate(Invalidation::Licenses, &**pool).await;
+
     Ok(HttpResponse::Ok().body(""))
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    security(("token" = [])),
+    responses((status = 200, description = "The license was deleted"), (status = 404, description = "The license doesn't exist")),
+)]
 #[delete("license/{name}")]
 pub async fn license_delete(
     req: HttpRequest,
@@ -267,6 +366,8 @@ pub async fn license_delete(
         .await
         .map_err(models::DatabaseError::from)?;
 
+    cache::invalidate(Invalidation::Licenses, &**pool).await;
+
     if result.is_some() {
         Ok(HttpResponse::Ok().body(""))
     } else {
@@ -274,14 +375,22 @@ pub async fn license_delete(
     }
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, ToSchema)]
 pub struct DonationPlatformQueryData {
     short: String,
     name: String,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    responses((status = 200, description = "Every donation platform", body = Vec<DonationPlatformQueryData>), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("donation_platform")]
-pub async fn donation_platform_list(pool: web::Data<PgPool>) -> Result<HttpResponse, ApiError> {
+pub async fn donation_platform_list(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
     let results: Vec<DonationPlatformQueryData> = DonationPlatform::list(&**pool)
         .await?
         .into_iter()
@@ -290,14 +399,21 @@ pub async fn donation_platform_list(pool: web::Data<PgPool>) -> Result<HttpRespo
             name: x.name,
         })
         .collect();
-    Ok(HttpResponse::Ok().json(results))
+    json_response(&req, &results, CacheScope::Public)
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, ToSchema)]
 pub struct DonationPlatformData {
     name: String,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    request_body = DonationPlatformData,
+    security(("token" = [])),
+    responses((status = 200, description = "The donation platform was created")),
+)]
 #[put("donation_platform/{name}")]
 pub async fn donation_platform_create(
     req: HttpRequest,
@@ -315,9 +431,17 @@ pub async fn donation_platform_create(
         .insert(&**pool)
         .await?;
 
+    cache::invalidate(Invalidation::DonationPlatforms, &**pool).await;
+
     Ok(HttpResponse::Ok().body(""))
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    security(("token" = [])),
+    responses((status = 200, description = "The donation platform was deleted"), (status = 404, description = "The donation platform doesn't exist")),
+)]
 #[delete("donation_platform/{name}")]
 pub async fn donation_platform_delete(
     req: HttpRequest,
@@ -336,6 +460,8 @@ pub async fn donation_platform_delete(
         .await
         .map_err(models::DatabaseError::from)?;
 
+    cache::invalidate(Invalidation::DonationPlatforms, &**pool).await;
+
     if result.is_some() {
         Ok(HttpResponse::Ok().body(""))
     } else {
@@ -343,12 +469,26 @@ pub async fn donation_platform_delete(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    responses((status = 200, description = "Every report type", body = Vec<String>), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("report_type")]
-pub async fn report_type_list(pool: web::Data<PgPool>) -> Result<HttpResponse, ApiError> {
+pub async fn report_type_list(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
     let results = ReportType::list(&**pool).await?;
-    Ok(HttpResponse::Ok().json(results))
+    json_response(&req, &results, CacheScope::Public)
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    security(("token" = [])),
+    responses((status = 200, description = "The report type was created")),
+)]
 #[put("report_type/{name}")]
 pub async fn report_type_create(
     req: HttpRequest,
@@ -361,9 +501,17 @@ pub async fn report_type_create(
 
     let _id = ReportType::builder().name(&name)?.insert(&**pool).await?;
 
+    cache::invalidate(Invalidation::ReportTypes, &**pool).await;
+
     Ok(HttpResponse::Ok().body(""))
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/tag/",
+    tag = "tags",
+    security(("token" = [])),
+    responses((status = 200, description = "The report type was deleted"), (status = 404, description = "The report type doesn't exist")),
+)]
 #[delete("report_type/{name}")]
 pub async fn report_type_delete(
     req: HttpRequest,
@@ -382,6 +530,8 @@ pub async fn report_type_delete(
         .await
         .map_err(models::DatabaseError::from)?;
 
+    cache::invalidate(Invalidation::ReportTypes, &**pool).await;
+
     if result.is_some() {
         Ok(HttpResponse::Ok().body(""))
     } else {
@@ -1,14 +1,25 @@
 use crate::auth::get_user_from_headers;
 use crate::database::models::notification_item::{NotificationActionBuilder, NotificationBuilder};
+use crate::database::models::quota_item::StorageLimits;
 use crate::database::models::TeamMember;
 use crate::models::ids::ModId;
+use crate::models::notifications::NotificationBody;
+use crate::models::storage::EditStorageLimits;
 use crate::models::teams::{Permissions, TeamId};
 use crate::models::users::UserId;
+use crate::routes::conditional::{json_response, CacheScope};
+use crate::routes::users::edit_storage_limits;
 use crate::routes::ApiError;
 use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use utoipa::ToSchema;
 
+#[utoipa::path(
+    context_path = "/api/v1/team/",
+    tag = "teams",
+    responses((status = 200, description = "The members of the team. Members of the team also see pending invites and permissions.", body = Vec<crate::models::teams::TeamMember>), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("{id}/members")]
 pub async fn team_members_get(
     req: HttpRequest,
@@ -37,7 +48,8 @@ pub async fn team_members_get(
                 })
                 .collect();
 
-            return Ok(HttpResponse::Ok().json(team_members));
+            // Members see pending invites and permissions, so this can't be shared
+            return json_response(&req, &team_members, CacheScope::Private);
         }
     }
 
@@ -55,9 +67,15 @@ pub async fn team_members_get(
         }
     }
 
-    Ok(HttpResponse::Ok().json(team_members))
+    json_response(&req, &team_members, CacheScope::Public)
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/team/",
+    tag = "teams",
+    security(("token" = [])),
+    responses((status = 200, description = "The invite was accepted")),
+)]
 #[post("{id}/join")]
 pub async fn join_team(
     req: HttpRequest,
@@ -109,7 +127,7 @@ fn default_role() -> String {
     "Member".to_string()
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct NewTeamMember {
     pub user_id: UserId,
     #[serde(default = "default_role")]
@@ -118,6 +136,13 @@ pub struct NewTeamMember {
     pub permissions: Permissions,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/team/",
+    tag = "teams",
+    request_body = NewTeamMember,
+    security(("token" = [])),
+    responses((status = 200, description = "The user was invited")),
+)]
 #[post("{id}/members")]
 pub async fn add_team_member(
     req: HttpRequest,
@@ -217,6 +242,12 @@ pub async fn add_team_member(
             current_user.username, result.title
         ),
         link: format!("mod/{}", ModId(result.id as u64)),
+        body: NotificationBody::TeamInvite {
+            mod_id: ModId(result.id as u64),
+            team_id: team,
+            invited_by: current_user.id,
+            role: new_member.role.clone(),
+        },
         actions: vec![
             NotificationActionBuilder {
                 title: "Accept".to_string(),
@@ -242,12 +273,19 @@ pub async fn add_team_member(
     Ok(HttpResponse::Ok().body(""))
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct EditTeamMember {
     pub permissions: Option<Permissions>,
     pub role: Option<String>,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/team/",
+    tag = "teams",
+    request_body = EditTeamMember,
+    security(("token" = [])),
+    responses((status = 200, description = "The member was edited")),
+)]
 #[patch("{id}/members/{user_id}")]
 pub async fn edit_team_member(
     req: HttpRequest,
@@ -314,6 +352,12 @@ pub async fn edit_team_member(
     Ok(HttpResponse::Ok().body(""))
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/team/",
+    tag = "teams",
+    security(("token" = [])),
+    responses((status = 200, description = "The member was removed"), (status = 404, description = "The user isn't a member of the team")),
+)]
 #[delete("{id}/members/{user_id}")]
 pub async fn remove_team_member(
     req: HttpRequest,
@@ -376,3 +420,54 @@ pub async fn remove_team_member(
         Ok(HttpResponse::NotFound().body(""))
     }
 }
+
+#[utoipa::path(
+    context_path = "/api/v1/team/",
+    tag = "moderation",
+    security(("token" = [])),
+    request_body = crate::models::storage::EditStorageLimits,
+    responses((status = 204, description = "The limits were changed"), (status = 404, description = "The team doesn't exist")),
+)]
+#[patch("{id}/storage")]
+pub async fn team_storage_edit(
+    req: HttpRequest,
+    info: web::Path<(TeamId,)>,
+    pool: web::Data<PgPool>,
+    edit: web::Json<EditStorageLimits>,
+) -> Result<HttpResponse, ApiError> {
+    let current_user = get_user_from_headers(req.headers(), &**pool).await?;
+    let id = info.into_inner().0;
+
+    if !current_user.role.is_mod() {
+        return Err(ApiError::CustomAuthenticationError(
+            "You do not have permission to change storage limits!".to_string(),
+        ));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let mut limits = match StorageLimits::get_team(id.into(), &mut transaction)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?
+    {
+        Some(limits) => limits,
+        None => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    edit_storage_limits(&mut limits, edit.into_inner())?;
+
+    limits
+        .set_team(id.into(), &mut transaction)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(HttpResponse::NoContent().body(""))
+}
@@ -0,0 +1,333 @@
+use crate::auth::get_user_from_headers;
+use crate::database::models::generate_upload_id;
+use crate::database::models::quota_item::{format_size, get_largest_file_size_limit};
+use crate::database::models::upload_item::{UploadChunk, UploadSession};
+use crate::file_hosting::uploads::{self, Pieces};
+use crate::file_hosting::{FileHost, FileHostingError};
+use crate::models::ids::UploadId;
+use crate::models::uploads::Upload;
+use crate::models::users::User;
+use crate::routes::version_creation::{file_extension, mod_file_type};
+use crate::routes::ApiError;
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// The number of unfinished or unused uploads a user can have at once
+const MAX_OPEN_UPLOADS: i64 = 10;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateUpload {
+    /// The name the file will have in the version it's added to
+    pub file_name: String,
+    /// The size of the whole file, in bytes
+    pub size: u64,
+}
+
+impl From<UploadSession> for Upload {
+    fn from(data: UploadSession) -> Self {
+        Self {
+            id: data.id.into(),
+            file_name: data.file_name,
+            size: data.size as u64,
+            offset: data.received as u64,
+            created: data.created,
+            expires: data.updated + uploads::abandoned_after(),
+        }
+    }
+}
+
+/// Gets an upload, making sure it belongs to the user.  Uploads of other
+/// users are treated as if they didn't exist.
+async fn get_own_upload(
+    id: UploadId,
+    user: &User,
+    pool: &PgPool,
+) -> Result<Option<UploadSession>, ApiError> {
+    let upload = UploadSession::get(id.into(), pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(upload.filter(|x| x.user_id == user.id.into() && !x.used))
+}
+
+fn upload_response(
+    upload: UploadSession,
+    mut response: actix_web::dev::HttpResponseBuilder,
+) -> HttpResponse {
+    let upload = Upload::from(upload);
+
+    response
+        .header("Upload-Offset", upload.offset.to_string())
+        .header("Upload-Length", upload.size.to_string())
+        .header(actix_web::http::header::CACHE_CONTROL, "no-store")
+        .json(upload)
+}
+
+fn conflict(offset: i64) -> HttpResponse {
+    HttpResponse::Conflict()
+        .header("Upload-Offset", offset.to_string())
+        .json(crate::models::error::ApiError {
+            error: "upload_conflict",
+            description: &format!("The upload continues at offset {}", offset),
+        })
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "uploads",
+    request_body = CreateUpload,
+    security(("token" = [])),
+    responses((status = 201, description = "The upload was started. Its bytes are sent with `PATCH /upload/{id}`.", body = Upload)),
+)]
+#[post("upload")]
+pub async fn upload_create(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    new_upload: web::Json<CreateUpload>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+
+    if new_upload.file_name.is_empty() || new_upload.file_name.len() > 256 {
+        return Err(ApiError::InvalidInputError(
+            "The file name must be between 1 and 256 characters long".to_string(),
+        ));
+    }
+
+    if new_upload.file_name.contains('/') {
+        return Err(ApiError::InvalidInputError(
+            "The file name must not contain slashes".to_string(),
+        ));
+    }
+
+    let file_extension = file_extension(&new_upload.file_name).unwrap_or("");
+    if mod_file_type(file_extension).is_none() {
+        return Err(ApiError::InvalidInputError(format!(
+            "Invalid file type for version file: {}",
+            file_extension
+        )));
+    }
+
+    if new_upload.size == 0 {
+        return Err(ApiError::InvalidInputError("The file is empty".to_string()));
+    }
+
+    // Storage quotas are checked once the upload is added to a version,
+    // since they depend on its mod
+    let file_size_limit = get_largest_file_size_limit(user.id.into(), &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    if new_upload.size > file_size_limit {
+        return Err(ApiError::InvalidInputError(format!(
+            "Mod file exceeds the maximum of {}. Contact a moderator or admin to request permission to upload larger files.",
+            format_size(file_size_limit)
+        )));
+    }
+
+    let open_uploads = UploadSession::count_unused(user.id.into(), &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    if open_uploads >= MAX_OPEN_UPLOADS {
+        return Err(ApiError::InvalidInputError(format!(
+            "You can't have more than {} unused uploads at once",
+            MAX_OPEN_UPLOADS
+        )));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let id = generate_upload_id(&mut transaction).await?;
+    let now = chrono::Utc::now();
+
+    let upload = UploadSession {
+        id,
+        user_id: user.id.into(),
+        file_name: new_upload.file_name.clone(),
+        size: new_upload.size as i64,
+        received: 0,
+        created: now,
+        updated: now,
+        used: false,
+    };
+
+    upload
+        .insert(&mut transaction)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let mut response = HttpResponse::Created();
+    response.header("Location", format!("/api/v1/upload/{}", UploadId::from(id)));
+
+    Ok(upload_response(upload, response))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/upload/",
+    tag = "uploads",
+    security(("token" = [])),
+    responses((status = 200, description = "The upload, with the offset the next piece starts at", body = Upload), (status = 404, description = "The upload doesn't exist")),
+)]
+#[get("{id}")]
+pub async fn upload_get(
+    req: HttpRequest,
+    info: web::Path<(UploadId,)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+
+    match get_own_upload(info.into_inner().0, &user, &pool).await? {
+        Some(upload) => Ok(upload_response(upload, HttpResponse::Ok())),
+        None => Ok(HttpResponse::NotFound().body("")),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/upload/",
+    tag = "uploads",
+    request_body(content_type = "application/offset+octet-stream", description = "The next bytes of the file, starting at the offset in the `Upload-Offset` header"),
+    security(("token" = [])),
+    responses((status = 204, description = "The bytes were stored. `Upload-Offset` holds the offset the next piece starts at."), (status = 404, description = "The upload doesn't exist"), (status = 409, description = "`Upload-Offset` doesn't match the bytes received so far, which are in the `Upload-Offset` header of the response")),
+)]
+#[patch("{id}")]
+pub async fn upload_append(
+    req: HttpRequest,
+    info: web::Path<(UploadId,)>,
+    pool: web::Data<PgPool>,
+    file_host: web::Data<Arc<dyn FileHost + Send + Sync>>,
+    payload: web::Payload,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+    let id = info.into_inner().0;
+
+    let upload = match get_own_upload(id, &user, &pool).await? {
+        Some(upload) => upload,
+        None => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    let mut offset = req
+        .headers()
+        .get("Upload-Offset")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<i64>().ok())
+        .ok_or_else(|| {
+            ApiError::InvalidInputError("A valid Upload-Offset header is required".to_string())
+        })?;
+
+    if offset != upload.received {
+        return Ok(conflict(upload.received));
+    }
+
+    let mut pieces =
+        Pieces::new(Box::pin(payload.map(|chunk| {
+            chunk.map_err(|e| FileHostingError::PayloadError(e.to_string()))
+        })));
+
+    // Each piece is recorded as soon as it's stored, so an interrupted
+    // request keeps the pieces that made it
+    while let Some(piece) = pieces.next().await.map_err(piece_error)? {
+        let file_name = format!("uploads/{}/{}-{:016x}", id, offset, rand::random::<u64>());
+
+        let upload_data = file_host
+            .upload_file(
+                "application/octet-stream",
+                &file_name,
+                piece,
+                (upload.size - offset) as u64 + 1,
+            )
+            .await
+            .map_err(piece_error)?;
+
+        let chunk = UploadChunk {
+            upload_offset: offset,
+            length: upload_data.content_length as i64,
+            file_name,
+        };
+
+        let mut transaction = pool
+            .begin()
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+        let added = UploadSession::add_chunk(upload.id, &chunk, &mut transaction)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+        transaction
+            .commit()
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+        if !added {
+            // Another request stored this part of the upload first
+            uploads::delete_chunks(&***file_host, &[chunk.file_name]).await;
+
+            let received = UploadSession::get(upload.id, &**pool)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?
+                .map(|x| x.received)
+                .unwrap_or(offset);
+
+            return Ok(conflict(received));
+        }
+
+        offset += chunk.length;
+    }
+
+    Ok(HttpResponse::NoContent()
+        .header("Upload-Offset", offset.to_string())
+        .body(""))
+}
+
+fn piece_error(error: FileHostingError) -> ApiError {
+    match error {
+        FileHostingError::FileTooLarge(_) => {
+            ApiError::InvalidInputError("The upload is larger than its declared size".to_string())
+        }
+        FileHostingError::PayloadError(_) => {
+            ApiError::InvalidInputError("Unable to parse bytes in payload sent!".to_string())
+        }
+        e => ApiError::FileHostingError(e),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/upload/",
+    tag = "uploads",
+    security(("token" = [])),
+    responses((status = 204, description = "The upload and the bytes received for it were removed"), (status = 404, description = "The upload doesn't exist")),
+)]
+#[delete("{id}")]
+pub async fn upload_delete(
+    req: HttpRequest,
+    info: web::Path<(UploadId,)>,
+    pool: web::Data<PgPool>,
+    file_host: web::Data<Arc<dyn FileHost + Send + Sync>>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+
+    let upload = match get_own_upload(info.into_inner().0, &user, &pool).await? {
+        Some(upload) => upload,
+        None => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    let chunks = UploadSession::remove(upload.id, &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    uploads::delete_chunks(&***file_host, &chunks).await;
+
+    Ok(HttpResponse::NoContent().body(""))
+}
@@ -1,18 +1,34 @@
 use crate::auth::get_user_from_headers;
+use crate::database::models::notification_item::NotificationPreference;
+use crate::database::models::quota_item::{self, StorageLimits, StorageUsage};
 use crate::database::models::User;
-use crate::file_hosting::FileHost;
-use crate::models::ids::ModId;
+use crate::file_hosting::{delete_cdn_file, FileHost, FileHostingError};
+use crate::models::ids::base62_impl::to_base62;
+use crate::models::ids::{random_base62, ModId};
 use crate::models::mods::ModStatus;
-use crate::models::notifications::Notification;
+use crate::models::notifications::{Notification, NotificationType};
+use crate::models::pagination::{Listing, PageQuery};
+use crate::models::storage::{EditStorageLimits, ModStorage, UserStorage};
 use crate::models::users::{Role, UserId};
+use crate::routes::batch::{in_request_order, BatchIds};
+use crate::routes::conditional::{json_response, CacheScope};
 use crate::routes::notifications::convert_notification;
+use crate::routes::pagination::{Pagination, SortKey, NEWEST, OLDEST};
 use crate::routes::ApiError;
-use actix_web::{delete, get, patch, web, HttpRequest, HttpResponse};
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use std::collections::HashMap;
 use std::sync::Arc;
-
+use utoipa::{IntoParams, ToSchema};
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "users",
+    security(("token" = [])),
+    responses((status = 200, description = "The authenticated user", body = crate::models::users::User)),
+)]
 #[get("user")]
 pub async fn user_auth_get(
     req: HttpRequest,
@@ -30,11 +46,18 @@ pub async fn user_auth_get(
     ))
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct UserIds {
     pub ids: String,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "users",
+    params(UserIds),
+    responses((status = 200, description = "The users that exist", body = Vec<crate::models::users::User>)),
+)]
 #[get("users")]
 pub async fn users_get(
     web::Query(ids): web::Query<UserIds>,
@@ -54,8 +77,41 @@ pub async fn users_get(
     Ok(HttpResponse::Ok().json(users))
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "users",
+    request_body = BatchIds<UserId>,
+    responses((status = 200, description = "The users in the order they were requested, with `null` for users that don't exist", body = Vec<Option<crate::models::users::User>>)),
+)]
+#[post("users")]
+pub async fn users_batch(
+    web::Json(batch): web::Json<BatchIds<UserId>>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    batch.validate()?;
+
+    let users: HashMap<u64, crate::models::users::User> =
+        User::get_many(batch.ids.iter().map(|x| (*x).into()).collect(), &**pool)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.into()))?
+            .into_iter()
+            .map(|x| (x.id.0 as u64, convert_user(x)))
+            .collect();
+
+    let ids: Vec<u64> = batch.ids.iter().map(|x| x.0).collect();
+
+    Ok(HttpResponse::Ok().json(in_request_order(&ids, &users)))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/user/",
+    tag = "users",
+    params(("id" = String, Path, description = "The username of the user")),
+    responses((status = 200, body = crate::models::users::User), (status = 404, description = "The user doesn't exist"), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("@{id}")]
 pub async fn user_username_get(
+    req: HttpRequest,
     info: web::Path<(String,)>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ApiError> {
@@ -65,15 +121,21 @@ pub async fn user_username_get(
         .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
     if let Some(data) = user_data {
-        let response = convert_user(data);
-        Ok(HttpResponse::Ok().json(response))
+        json_response(&req, &convert_user(data), CacheScope::Public)
     } else {
         Ok(HttpResponse::NotFound().body(""))
     }
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/user/",
+    tag = "users",
+    params(("id" = String, Path, description = "The ID or username of the user")),
+    responses((status = 200, body = crate::models::users::User), (status = 404, description = "The user doesn't exist"), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("{id}")]
 pub async fn user_get(
+    req: HttpRequest,
     info: web::Path<(String,)>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ApiError> {
@@ -99,14 +161,13 @@ pub async fn user_get(
     }
 
     if let Some(data) = user_data {
-        let response = convert_user(data);
-        Ok(HttpResponse::Ok().json(response))
+        json_response(&req, &convert_user(data), CacheScope::Public)
     } else {
         Ok(HttpResponse::NotFound().body(""))
     }
 }
 
-fn convert_user(data: crate::database::models::user_item::User) -> crate::models::users::User {
+pub fn convert_user(data: crate::database::models::user_item::User) -> crate::models::users::User {
     crate::models::users::User {
         id: data.id.into(),
         discord_id: data.discord_id,
@@ -121,13 +182,32 @@ fn convert_user(data: crate::database::models::user_item::User) -> crate::models
     }
 }
 
+const USER_MODS_SORTS: &[SortKey] = &[
+    NEWEST,
+    OLDEST,
+    SortKey {
+        name: "updated",
+        descending: true,
+    },
+];
+
+/// Sorted by publication date with the keys `newest` and `oldest`, or by the
+/// last update with `updated`
+#[utoipa::path(
+    context_path = "/api/v1/user/",
+    tag = "users",
+    params(PageQuery),
+    responses((status = 200, description = "The IDs of the user's mods. Hidden mods are only listed for the user and moderators.", body = Listing<ModId>), (status = 404, description = "The user doesn't exist")),
+)]
 #[get("{user_id}/mods")]
 pub async fn mods_list(
     req: HttpRequest,
     info: web::Path<(UserId,)>,
+    web::Query(page): web::Query<PageQuery>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ApiError> {
     let user = get_user_from_headers(req.headers(), &**pool).await.ok();
+    let pagination = Pagination::from_query(&page, USER_MODS_SORTS)?;
 
     let id: crate::database::models::UserId = info.into_inner().0.into();
 
@@ -141,36 +221,58 @@ pub async fn mods_list(
     .exists;
 
     if user_exists.unwrap_or(false) {
+        use futures::TryStreamExt;
+
         let user_id: UserId = id.into();
 
-        let mod_data = if let Some(current_user) = user {
-            if current_user.role.is_mod() || current_user.id == user_id {
-                User::get_mods_private(id, &**pool)
-                    .await
-                    .map_err(|e| ApiError::DatabaseError(e.into()))?
-            } else {
-                User::get_mods(id, ModStatus::Approved.as_str(), &**pool)
-                    .await
-                    .map_err(|e| ApiError::DatabaseError(e.into()))?
-            }
-        } else {
-            User::get_mods(id, ModStatus::Approved.as_str(), &**pool)
-                .await
-                .map_err(|e| ApiError::DatabaseError(e.into()))?
+        // Hidden mods are only listed for the user and moderators
+        let status = match user {
+            Some(current_user) if current_user.role.is_mod() || current_user.id == user_id => None,
+            _ => Some(ModStatus::Approved.as_str()),
         };
 
-        let response = mod_data
-            .into_iter()
-            .map(|v| v.into())
-            .collect::<Vec<crate::models::ids::ModId>>();
+        let rows = sqlx::query!(
+            "
+            SELECT m.id, CASE WHEN $3 THEN m.updated ELSE m.published END \"key!\"
+            FROM mods m
+            INNER JOIN team_members tm ON tm.team_id = m.team_id
+            WHERE tm.user_id = $1
+            AND ($2::text IS NULL OR m.status = (SELECT s.id FROM statuses s WHERE s.status = $2))
+            AND ($4::timestamptz IS NULL OR CASE WHEN $6
+                THEN (CASE WHEN $3 THEN m.updated ELSE m.published END, m.id) < ($4::timestamptz, $5::bigint)
+                ELSE (CASE WHEN $3 THEN m.updated ELSE m.published END, m.id) > ($4::timestamptz, $5::bigint)
+            END)
+            ORDER BY
+                CASE WHEN $6 THEN CASE WHEN $3 THEN m.updated ELSE m.published END END DESC,
+                CASE WHEN $6 THEN m.id END DESC,
+                CASE WHEN $3 THEN m.updated ELSE m.published END ASC, m.id ASC
+            LIMIT $7
+            ",
+            id as crate::database::models::UserId,
+            status,
+            pagination.sort.name == "updated",
+            pagination.after_key(),
+            pagination.after_id(),
+            pagination.sort.descending,
+            pagination.fetch_limit(),
+        )
+        .fetch_many(&**pool)
+        .try_filter_map(|e| async { Ok(e.right().map(|m| (m.key, m.id))) })
+        .try_collect::<Vec<_>>()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+        let (ids, next) = pagination.split(rows);
+
+        let items = ids.iter().map(|x| ModId(*x as u64)).collect();
 
-        Ok(HttpResponse::Ok().json(response))
+        Ok(HttpResponse::Ok().json(pagination.page(&ids, items, |x| x.0 as i64, next)))
     } else {
         Ok(HttpResponse::NotFound().body(""))
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct EditUser {
     pub username: Option<String>,
     #[serde(
@@ -195,6 +297,13 @@ pub struct EditUser {
     pub show_nsfw: Option<bool>,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/user/",
+    tag = "users",
+    request_body = EditUser,
+    security(("token" = [])),
+    responses((status = 200, description = "The user was edited"), (status = 404, description = "The user doesn't exist")),
+)]
 #[patch("{id}")]
 pub async fn user_edit(
     req: HttpRequest,
@@ -323,11 +432,20 @@ pub async fn user_edit(
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Extension {
     pub ext: String,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/user/",
+    tag = "users",
+    security(("token" = [])),
+    params(Extension),
+    request_body(content = Vec<u8>, description = "The image data", content_type = "image/*"),
+    responses((status = 200, description = "The avatar was changed"), (status = 404, description = "The user doesn't exist")),
+)]
 #[patch("{id}/icon")]
 pub async fn user_icon_edit(
     web::Query(ext): web::Query<Extension>,
@@ -335,7 +453,7 @@ pub async fn user_icon_edit(
     info: web::Path<(UserId,)>,
     pool: web::Data<PgPool>,
     file_host: web::Data<Arc<dyn FileHost + Send + Sync>>,
-    mut payload: web::Payload,
+    payload: web::Payload,
 ) -> Result<HttpResponse, ApiError> {
     if let Some(content_type) = super::mod_creation::get_image_content_type(&*ext.ext) {
         let cdn_url = dotenv::var("CDN_URL")?;
@@ -362,36 +480,34 @@ pub async fn user_icon_edit(
             }
         }
 
-        if let Some(icon) = icon_url {
-            if icon.starts_with(&cdn_url) {
-                let name = icon.split('/').next();
-
-                if let Some(icon_path) = name {
-                    file_host.delete_file_version("", icon_path).await?;
-                }
-            }
-        }
-
-        let mut bytes = web::BytesMut::new();
-        while let Some(item) = payload.next().await {
-            bytes.extend_from_slice(&item.map_err(|_| {
-                ApiError::InvalidInputError("Unable to parse bytes in payload sent!".to_string())
-            })?);
-        }
-
-        if bytes.len() >= 262144 {
-            return Err(ApiError::InvalidInputError(String::from(
-                "Icons must be smaller than 256KiB",
-            )));
-        }
+        // Every icon gets a new name, so the old one is only deleted once the
+        // new one is stored and in use
+        let icon_name = format!(
+            "user/{}/icon-{}.{}",
+            id,
+            to_base62(random_base62(8)),
+            ext.ext
+        );
 
         let upload_data = file_host
             .upload_file(
                 content_type,
-                &format!("user/{}/icon.{}", id, ext.ext),
-                bytes.to_vec(),
+                &icon_name,
+                Box::pin(payload.map(|chunk| {
+                    chunk.map_err(|e| FileHostingError::PayloadError(e.to_string()))
+                })),
+                262144,
             )
-            .await?;
+            .await
+            .map_err(|e| match e {
+                FileHostingError::FileTooLarge(_) => ApiError::InvalidInputError(String::from(
+                    "Icons must be smaller than 256KiB",
+                )),
+                FileHostingError::PayloadError(_) => ApiError::InvalidInputError(
+                    "Unable to parse bytes in payload sent!".to_string(),
+                ),
+                e => ApiError::FileHostingError(e),
+            })?;
 
         let mod_id: crate::database::models::ids::UserId = id.into();
         sqlx::query!(
@@ -406,6 +522,11 @@ pub async fn user_icon_edit(
         .execute(&**pool)
         .await
         .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+        if let Some(icon) = icon_url {
+            delete_cdn_file(&***file_host, &icon, &cdn_url).await;
+        }
+
         Ok(HttpResponse::Ok().body(""))
     } else {
         Err(ApiError::InvalidInputError(format!(
@@ -415,7 +536,8 @@ pub async fn user_icon_edit(
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct RemovalType {
     #[serde(default = "default_removal")]
     removal_type: String,
@@ -425,12 +547,20 @@ fn default_removal() -> String {
     "partial".into()
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/user/",
+    tag = "users",
+    security(("token" = [])),
+    params(RemovalType),
+    responses((status = 200, description = "The user was deleted"), (status = 404, description = "The user doesn't exist")),
+)]
 #[delete("{id}")]
 pub async fn user_delete(
     req: HttpRequest,
     info: web::Path<(UserId,)>,
     pool: web::Data<PgPool>,
     removal_type: web::Query<RemovalType>,
+    file_host: web::Data<Arc<dyn FileHost + Send + Sync>>,
 ) -> Result<HttpResponse, ApiError> {
     let user = get_user_from_headers(req.headers(), &**pool).await?;
     let id = info.into_inner().0;
@@ -441,6 +571,18 @@ pub async fn user_delete(
         ));
     }
 
+    let avatar_url = sqlx::query!(
+        "
+        SELECT avatar_url FROM users
+        WHERE id = $1
+        ",
+        id.0 as i64
+    )
+    .fetch_optional(&**pool)
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.into()))?
+    .and_then(|x| x.avatar_url);
+
     let result;
     if &*removal_type.removal_type == "full" {
         result = crate::database::models::User::remove_full(id.into(), &**pool)
@@ -452,6 +594,12 @@ pub async fn user_delete(
             .map_err(|e| ApiError::DatabaseError(e.into()))?;
     };
 
+    // Icons of mods removed along with the user are left to the storage check
+    if let Some(avatar_url) = avatar_url {
+        let cdn_url = dotenv::var("CDN_URL")?;
+        delete_cdn_file(&***file_host, &avatar_url, &cdn_url).await;
+    }
+
     if result.is_some() {
         Ok(HttpResponse::Ok().body(""))
     } else {
@@ -459,10 +607,21 @@ pub async fn user_delete(
     }
 }
 
+const FOLLOWS_SORTS: &[SortKey] = &[NEWEST, OLDEST];
+
+/// Sorted by when the mods were followed, with the keys `newest` and `oldest`
+#[utoipa::path(
+    context_path = "/api/v1/user/",
+    tag = "users",
+    security(("token" = [])),
+    params(PageQuery),
+    responses((status = 200, description = "The IDs of the mods the user follows", body = Listing<ModId>)),
+)]
 #[get("{id}/follows")]
 pub async fn user_follows(
     req: HttpRequest,
     info: web::Path<(UserId,)>,
+    web::Query(page): web::Query<PageQuery>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ApiError> {
     let user = get_user_from_headers(req.headers(), &**pool).await?;
@@ -474,29 +633,59 @@ pub async fn user_follows(
         ));
     }
 
+    let pagination = Pagination::from_query(&page, FOLLOWS_SORTS)?;
+
     use futures::TryStreamExt;
 
     let user_id: crate::database::models::UserId = id.into();
-    let mods: Vec<ModId> = sqlx::query!(
+    let rows = sqlx::query!(
         "
-        SELECT mf.mod_id FROM mod_follows mf
+        SELECT mf.mod_id, mf.created FROM mod_follows mf
         WHERE mf.follower_id = $1
+        AND ($2::timestamptz IS NULL OR CASE WHEN $4
+            THEN (mf.created, mf.mod_id) < ($2::timestamptz, $3::bigint)
+            ELSE (mf.created, mf.mod_id) > ($2::timestamptz, $3::bigint)
+        END)
+        ORDER BY
+            CASE WHEN $4 THEN mf.created END DESC,
+            CASE WHEN $4 THEN mf.mod_id END DESC,
+            mf.created ASC, mf.mod_id ASC
+        LIMIT $5
         ",
         user_id as crate::database::models::ids::UserId,
+        pagination.after_key(),
+        pagination.after_id(),
+        pagination.sort.descending,
+        pagination.fetch_limit(),
     )
     .fetch_many(&**pool)
-    .try_filter_map(|e| async { Ok(e.right().map(|m| ModId(m.mod_id as u64))) })
-    .try_collect::<Vec<ModId>>()
+    .try_filter_map(|e| async { Ok(e.right().map(|m| (m.created, m.mod_id))) })
+    .try_collect::<Vec<_>>()
     .await
     .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
-    Ok(HttpResponse::Ok().json(mods))
+    let (ids, next) = pagination.split(rows);
+
+    let items = ids.iter().map(|x| ModId(*x as u64)).collect();
+
+    Ok(HttpResponse::Ok().json(pagination.page(&ids, items, |x| x.0 as i64, next)))
 }
 
+const NOTIFICATIONS_SORTS: &[SortKey] = &[NEWEST, OLDEST];
+
+/// Sorted by creation date, with the keys `newest` and `oldest`
+#[utoipa::path(
+    context_path = "/api/v1/user/",
+    tag = "notifications",
+    security(("token" = [])),
+    params(PageQuery),
+    responses((status = 200, body = Listing<Notification>, headers(("X-Unread-Count" = u64, description = "The number of unread notifications")))),
+)]
 #[get("{id}/notifications")]
 pub async fn user_notifications(
     req: HttpRequest,
     info: web::Path<(UserId,)>,
+    web::Query(page): web::Query<PageQuery>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ApiError> {
     let user = get_user_from_headers(req.headers(), &**pool).await?;
@@ -508,13 +697,309 @@ pub async fn user_notifications(
         ));
     }
 
+    let pagination = Pagination::from_query(&page, NOTIFICATIONS_SORTS)?;
+
+    use futures::TryStreamExt;
+
+    let user_id: crate::database::models::UserId = id.into();
+    let rows = sqlx::query!(
+        "
+        SELECT n.id, n.created FROM notifications n
+        WHERE n.user_id = $1
+        AND ($2::timestamptz IS NULL OR CASE WHEN $4
+            THEN (n.created, n.id) < ($2::timestamptz, $3::bigint)
+            ELSE (n.created, n.id) > ($2::timestamptz, $3::bigint)
+        END)
+        ORDER BY
+            CASE WHEN $4 THEN n.created END DESC,
+            CASE WHEN $4 THEN n.id END DESC,
+            n.created ASC, n.id ASC
+        LIMIT $5
+        ",
+        user_id as crate::database::models::ids::UserId,
+        pagination.after_key(),
+        pagination.after_id(),
+        pagination.sort.descending,
+        pagination.fetch_limit(),
+    )
+    .fetch_many(&**pool)
+    .try_filter_map(|e| async { Ok(e.right().map(|n| (n.created, n.id))) })
+    .try_collect::<Vec<_>>()
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let (ids, next) = pagination.split(rows);
+
     let notifications: Vec<Notification> =
-        crate::database::models::notification_item::Notification::get_many_user(id.into(), &**pool)
-            .await
-            .map_err(|e| ApiError::DatabaseError(e.into()))?
-            .into_iter()
-            .map(convert_notification)
+        crate::database::models::notification_item::Notification::get_many(
+            ids.iter()
+                .map(|x| crate::database::models::NotificationId(*x))
+                .collect(),
+            &**pool,
+        )
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?
+        .into_iter()
+        .map(convert_notification)
+        .collect();
+
+    let unread_count =
+        crate::database::models::notification_item::Notification::get_unread_count_user(
+            id.into(),
+            &**pool,
+        )
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(HttpResponse::Ok()
+        .header("X-Unread-Count", unread_count.to_string())
+        .json(pagination.page(
+            &ids,
+            notifications,
+            |x| crate::database::models::NotificationId::from(x.id).0,
+            next,
+        )))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/user/",
+    tag = "notifications",
+    security(("token" = [])),
+    responses((status = 200, description = "How the user receives each type of notification", body = Vec<crate::models::notifications::NotificationPreference>)),
+)]
+#[get("{id}/notifications/preferences")]
+pub async fn user_notification_preferences_get(
+    req: HttpRequest,
+    info: web::Path<(UserId,)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+    let id = info.into_inner().0;
+
+    if !user.role.is_mod() && user.id != id {
+        return Err(ApiError::CustomAuthenticationError(
+            "You do not have permission to see the notification preferences of this user!"
+                .to_string(),
+        ));
+    }
+
+    let configured = NotificationPreference::get_many_user(id.into(), &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let preferences: Vec<crate::models::notifications::NotificationPreference> =
+        NotificationType::ALL
+            .iter()
+            .map(|notification_type| {
+                let preference = configured
+                    .iter()
+                    .find(|x| x.notification_type == notification_type.as_str())
+                    .cloned()
+                    .unwrap_or_else(|| {
+                        NotificationPreference::default_for(id.into(), notification_type.as_str())
+                    });
+
+                crate::models::notifications::NotificationPreference {
+                    notification_type: *notification_type,
+                    in_app: preference.in_app,
+                    email: preference.email,
+                    webhook: preference.webhook,
+                }
+            })
             .collect();
 
-    Ok(HttpResponse::Ok().json(notifications))
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/user/",
+    tag = "notifications",
+    request_body = [crate::models::notifications::NotificationPreference],
+    security(("token" = [])),
+    responses((status = 200, description = "The preferences were changed")),
+)]
+#[patch("{id}/notifications/preferences")]
+pub async fn user_notification_preferences_edit(
+    req: HttpRequest,
+    info: web::Path<(UserId,)>,
+    pool: web::Data<PgPool>,
+    new_preferences: web::Json<Vec<crate::models::notifications::NotificationPreference>>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+    let id = info.into_inner().0;
+
+    if user.id != id {
+        return Err(ApiError::CustomAuthenticationError(
+            "You do not have permission to edit the notification preferences of this user!"
+                .to_string(),
+        ));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    for preference in new_preferences.into_inner() {
+        if preference.notification_type == NotificationType::Unknown {
+            return Err(ApiError::InvalidInputError(
+                "Invalid notification type!".to_string(),
+            ));
+        }
+
+        NotificationPreference {
+            user_id: id.into(),
+            notification_type: preference.notification_type.as_str().to_string(),
+            in_app: preference.in_app,
+            email: preference.email,
+            webhook: preference.webhook,
+        }
+        .upsert(&mut transaction)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/user/",
+    tag = "users",
+    security(("token" = [])),
+    responses((status = 200, description = "The storage used by the user's files, by the mod, and their limits", body = crate::models::storage::UserStorage), (status = 404, description = "The user doesn't exist")),
+)]
+#[get("{id}/storage")]
+pub async fn user_storage_get(
+    req: HttpRequest,
+    info: web::Path<(UserId,)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+    let id = info.into_inner().0;
+
+    if !user.role.is_mod() && user.id != id {
+        return Err(ApiError::CustomAuthenticationError(
+            "You do not have permission to see the storage of this user!".to_string(),
+        ));
+    }
+
+    let limits = match StorageLimits::get_user(id.into(), &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?
+    {
+        Some(limits) => limits,
+        None => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    let mods = StorageUsage::get_user_mods(id.into(), &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let storage = UserStorage {
+        used: mods.iter().map(|x| x.used as u64).sum(),
+        storage_limit: limits
+            .storage_limit
+            .map(|x| x.max(0) as u64)
+            .unwrap_or_else(quota_item::default_user_storage_limit),
+        file_size_limit: limits
+            .file_size_limit
+            .map(|x| x.max(0) as u64)
+            .unwrap_or_else(quota_item::default_file_size_limit),
+        limits_overridden: limits.storage_limit.is_some() || limits.file_size_limit.is_some(),
+        mods: mods
+            .into_iter()
+            .map(|x| ModStorage {
+                mod_id: x.mod_id.into(),
+                files: x.files as u64,
+                used: x.used as u64,
+            })
+            .collect(),
+    };
+
+    Ok(HttpResponse::Ok().json(storage))
+}
+
+/// Applies the changes a moderator made to storage limits
+pub fn edit_storage_limits(
+    limits: &mut StorageLimits,
+    edit: EditStorageLimits,
+) -> Result<(), ApiError> {
+    fn to_limit(limit: Option<u64>) -> Result<Option<i64>, ApiError> {
+        limit
+            .map(|x| {
+                if x <= i64::MAX as u64 {
+                    Ok(x as i64)
+                } else {
+                    Err(ApiError::InvalidInputError(
+                        "The storage limit is too large".to_string(),
+                    ))
+                }
+            })
+            .transpose()
+    }
+
+    if let Some(file_size_limit) = edit.file_size_limit {
+        limits.file_size_limit = to_limit(file_size_limit)?;
+    }
+    if let Some(storage_limit) = edit.storage_limit {
+        limits.storage_limit = to_limit(storage_limit)?;
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/user/",
+    tag = "moderation",
+    security(("token" = [])),
+    request_body = crate::models::storage::EditStorageLimits,
+    responses((status = 204, description = "The limits were changed"), (status = 404, description = "The user doesn't exist")),
+)]
+#[patch("{id}/storage")]
+pub async fn user_storage_edit(
+    req: HttpRequest,
+    info: web::Path<(UserId,)>,
+    pool: web::Data<PgPool>,
+    edit: web::Json<EditStorageLimits>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+    let id = info.into_inner().0;
+
+    if !user.role.is_mod() {
+        return Err(ApiError::CustomAuthenticationError(
+            "You do not have permission to change storage limits!".to_string(),
+        ));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let mut limits = match StorageLimits::get_user(id.into(), &mut transaction)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?
+    {
+        Some(limits) => limits,
+        None => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    edit_storage_limits(&mut limits, edit.into_inner())?;
+
+    limits
+        .set_user(id.into(), &mut transaction)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(HttpResponse::NoContent().body(""))
 }
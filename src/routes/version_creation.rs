@@ -1,19 +1,30 @@
 use crate::auth::get_user_from_headers;
 use crate::database::models;
+use crate::database::models::cache::{self, Invalidation};
 use crate::database::models::notification_item::NotificationBuilder;
+use crate::database::models::quota_item::StorageQuota;
+use crate::database::models::upload_item::UploadSession;
 use crate::database::models::version_item::{VersionBuilder, VersionFileBuilder};
-use crate::file_hosting::FileHost;
+use crate::file_hosting::archives::{self, ArchiveError};
+use crate::file_hosting::blobs::{self, StoredBlob};
+use crate::file_hosting::{uploads, ByteStream, FileHost, FileHostingError};
+use crate::models::ids::UploadId;
 use crate::models::mods::{
-    Dependency, ModId, Version, VersionFile, VersionId, VersionType
+    ArchiveEntry, Dependency, ModId, ModStatus, ScanStatus, Version, VersionFile, VersionId,
+    VersionType,
 };
+use crate::models::notifications::NotificationBody;
 use crate::models::teams::Permissions;
-use crate::routes::mod_creation::{CreateError, UploadedFile};
+use crate::models::webhooks::WebhookEvent;
+use crate::routes::mod_creation::{field_stream, upload_error, CreateError, UploadedFile};
 use actix_multipart::{Field, Multipart};
-use actix_web::web::Data;
+use actix_web::web::{Data, Json};
 use actix_web::{post, HttpRequest, HttpResponse};
 use futures::stream::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use utoipa::ToSchema;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct InitialVersionData {
@@ -27,11 +38,17 @@ pub struct InitialVersionData {
     pub release_channel: VersionType,
     pub featured: bool,
     pub hosting_location: String,
+    /// Finished resumable uploads to add to the version, after the files in
+    /// the request itself
+    #[serde(default)]
+    pub uploads: Vec<UploadId>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 struct InitialFileData {
     // TODO: hashes?
+    #[serde(default)]
+    pub uploads: Vec<UploadId>,
 }
 
 pub fn check_version(version: &InitialVersionData) -> Result<(), CreateError> {
@@ -65,6 +82,13 @@ pub fn check_version(version: &InitialVersionData) -> Result<(), CreateError> {
 }
 
 // under `/api/v1/version`
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "versions",
+    request_body(content_type = "multipart/form-data", description = "A `data` field holding the version as JSON, followed by its files"),
+    security(("token" = [])),
+    responses((status = 200, description = "The version was created", body = crate::models::mods::Version)),
+)]
 #[post("version")]
 pub async fn version_create(
     req: HttpRequest,
@@ -74,6 +98,7 @@ pub async fn version_create(
 ) -> Result<HttpResponse, CreateError> {
     let mut transaction = client.begin().await?;
     let mut uploaded_files = Vec::new();
+    let mut invalidations = Vec::new();
 
     let result = version_create_inner(
         req,
@@ -81,6 +106,7 @@ pub async fn version_create(
         &mut transaction,
         &***file_host,
         &mut uploaded_files,
+        &mut invalidations,
     )
     .await;
 
@@ -96,6 +122,10 @@ pub async fn version_create(
         }
     } else {
         transaction.commit().await?;
+
+        for invalidation in invalidations {
+            cache::invalidate(invalidation, &**client).await;
+        }
     }
 
     result
@@ -107,11 +137,13 @@ async fn version_create_inner(
     transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     file_host: &dyn FileHost,
     uploaded_files: &mut Vec<UploadedFile>,
+    invalidations: &mut Vec<Invalidation>,
 ) -> Result<HttpResponse, CreateError> {
     let cdn_url = dotenv::var("CDN_URL")?;
 
     let mut initial_version_data = None;
     let mut version_builder = None;
+    let mut quota = None;
 
     let user = get_user_from_headers(req.headers(), &mut *transaction).await?;
 
@@ -191,6 +223,10 @@ async fn version_create_inner(
                 ));
             }
 
+            quota = Some(
+                StorageQuota::get(user.id.into(), Some(team_member.team_id), transaction).await?,
+            );
+
             let version_id: VersionId = models::generate_version_id(transaction).await?.into();
 
             let release_channel = models::ChannelId::get_id(
@@ -227,21 +263,27 @@ async fn version_create_inner(
             continue;
         }
 
-        let version = version_builder.as_mut().ok_or_else(|| {
-            CreateError::InvalidInput(String::from("`data` field must come before file fields"))
-        })?;
+        let (version, quota) = match (version_builder.as_mut(), quota.as_mut()) {
+            (Some(version), Some(quota)) => (version, quota),
+            _ => {
+                return Err(CreateError::InvalidInput(String::from(
+                    "`data` field must come before file fields",
+                )))
+            }
+        };
 
         if uploaded_files.len() > 0 {
-            let file_builder = upload_file(
+            let mut file_builder = upload_file(
                 &mut field,
                 file_host,
+                &mut *transaction,
                 uploaded_files,
                 &cdn_url,
                 &content_disposition,
-                version.mod_id.into(),
-                &version.version_number,
+                quota,
             )
             .await?;
+            file_builder.primary = version.files.is_empty();
 
             // Add the newly uploaded file to the existing or new version
             version.files.push(file_builder);
@@ -250,13 +292,32 @@ async fn version_create_inner(
 
     let version_data = initial_version_data
         .ok_or_else(|| CreateError::InvalidInput("`data` field is required".to_string()))?;
-    let builder = version_builder
+    let mut builder = version_builder
         .ok_or_else(|| CreateError::InvalidInput("`data` field is required".to_string()))?;
+    let mut quota =
+        quota.ok_or_else(|| CreateError::InvalidInput("`data` field is required".to_string()))?;
+
+    for upload_id in &version_data.uploads {
+        let mut file_builder = use_upload(
+            *upload_id,
+            user.id.into(),
+            transaction,
+            file_host,
+            uploaded_files,
+            &cdn_url,
+            &mut quota,
+        )
+        .await?;
+        file_builder.primary = builder.files.is_empty();
+
+        builder.files.push(file_builder);
+    }
 
     let result = sqlx::query!(
         "
-        SELECT m.title FROM mods m
-        WHERE id = $1
+        SELECT m.title, m.team_id, s.status status_name FROM mods m
+        INNER JOIN statuses s ON s.id = m.status
+        WHERE m.id = $1
         ",
         builder.mod_id as crate::database::models::ids::ModId
     )
@@ -291,6 +352,10 @@ async fn version_create_inner(
             version_data.version_number.clone()
         ),
         link: format!("mod/{}/version/{}", mod_id, version_id),
+        body: NotificationBody::VersionReleased {
+            mod_id,
+            version_id,
+        },
         actions: vec![],
     }
     .insert_many(users, &mut *transaction)
@@ -301,6 +366,7 @@ async fn version_create_inner(
         mod_id: builder.mod_id.into(),
         author_id: user.id,
         featured: builder.featured,
+        quarantined: false,
         name: builder.name.clone(),
         version_number: builder.version_number.clone(),
         changelog: builder.changelog.clone(),
@@ -331,11 +397,26 @@ async fn version_create_inner(
                 url: file.url.clone(),
                 filename: file.filename.clone(),
                 primary: file.primary,
+                contents: file.contents.clone(),
+                scan_status: file.scan_status,
             })
             .collect::<Vec<_>>(),
         dependencies: version_data.dependencies
     };
 
+    crate::notifications::webhooks::dispatch_mod_event(
+        WebhookEvent::VersionCreated,
+        builder.mod_id,
+        models::TeamId(result.team_id),
+        !ModStatus::from_str(&result.status_name).is_hidden(),
+        &response,
+        &mut *transaction,
+    )
+    .await?;
+
+    crate::notifications::discord::announce_version(&response, &mut *transaction).await?;
+
+    invalidations.push(Invalidation::Mod(builder.mod_id.0));
     builder.insert(transaction).await?;
 
     Ok(HttpResponse::Ok().json(response))
@@ -344,6 +425,13 @@ async fn version_create_inner(
 // TODO: file deletion, listing, etc
 
 // under /api/v1/version/{version_id}
+#[utoipa::path(
+    context_path = "/api/v1/version/",
+    tag = "versions",
+    request_body(content_type = "multipart/form-data", description = "A `data` field holding the upload as JSON, followed by the files to add"),
+    security(("token" = [])),
+    responses((status = 200, description = "The files were added to the version"), (status = 404, description = "The version doesn't exist")),
+)]
 #[post("{version_id}/file")]
 pub async fn upload_file_to_version(
     req: HttpRequest,
@@ -354,6 +442,7 @@ pub async fn upload_file_to_version(
 ) -> Result<HttpResponse, CreateError> {
     let mut transaction = client.begin().await?;
     let mut uploaded_files = Vec::new();
+    let mut invalidations = Vec::new();
 
     let version_id = models::VersionId::from(url_data.into_inner().0);
 
@@ -363,6 +452,7 @@ pub async fn upload_file_to_version(
         &mut transaction,
         &***file_host,
         &mut uploaded_files,
+        &mut invalidations,
         version_id,
     )
     .await;
@@ -379,6 +469,10 @@ pub async fn upload_file_to_version(
         }
     } else {
         transaction.commit().await?;
+
+        for invalidation in invalidations {
+            cache::invalidate(invalidation, &**client).await;
+        }
     }
 
     result
@@ -390,6 +484,7 @@ async fn upload_file_to_version_inner(
     transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     file_host: &dyn FileHost,
     uploaded_files: &mut Vec<UploadedFile>,
+    invalidations: &mut Vec<Invalidation>,
     version_id: models::VersionId,
 ) -> Result<HttpResponse, CreateError> {
     let cdn_url = dotenv::var("CDN_URL")?;
@@ -399,9 +494,92 @@ async fn upload_file_to_version_inner(
 
     let user = get_user_from_headers(req.headers(), &mut *transaction).await?;
 
+    let (_, _, team_id) = get_upload_version(version_id, user.id.into(), &mut *transaction).await?;
+    let mut quota = StorageQuota::get(user.id.into(), Some(team_id), transaction).await?;
+
+    while let Some(item) = payload.next().await {
+        let mut field: Field = item.map_err(CreateError::MultipartError)?;
+        let content_disposition = field.content_disposition().ok_or_else(|| {
+            CreateError::MissingValueError("Missing content disposition".to_string())
+        })?;
+        let name = content_disposition
+            .get_name()
+            .ok_or_else(|| CreateError::MissingValueError("Missing content name".to_string()))?;
+
+        if name == "data" {
+            let mut data = Vec::new();
+            while let Some(chunk) = field.next().await {
+                data.extend_from_slice(&chunk.map_err(CreateError::MultipartError)?);
+            }
+            let file_data: InitialFileData = serde_json::from_slice(&data)?;
+            // TODO: currently no data here, but still required
+
+            initial_file_data = Some(file_data);
+            continue;
+        }
+
+        let _file_data = initial_file_data.as_ref().ok_or_else(|| {
+            CreateError::InvalidInput(String::from("`data` field must come before file fields"))
+        })?;
+
+        let mut file_builder = upload_file(
+            &mut field,
+            file_host,
+            &mut *transaction,
+            uploaded_files,
+            &cdn_url,
+            &content_disposition,
+            &mut quota,
+        )
+        .await?;
+        file_builder.primary = file_builders.is_empty();
+
+        file_builders.push(file_builder);
+    }
+
+    if let Some(file_data) = &initial_file_data {
+        for upload_id in &file_data.uploads {
+            let mut file_builder = use_upload(
+                *upload_id,
+                user.id.into(),
+                transaction,
+                file_host,
+                uploaded_files,
+                &cdn_url,
+                &mut quota,
+            )
+            .await?;
+            file_builder.primary = file_builders.is_empty();
+
+            file_builders.push(file_builder);
+        }
+    }
+
+    if file_builders.is_empty() {
+        return Err(CreateError::InvalidInput(
+            "At least one file must be specified".to_string(),
+        ));
+    } else {
+        for file_builder in file_builders {
+            file_builder.insert(version_id, &mut *transaction).await?;
+        }
+    }
+
+    invalidations.push(Invalidation::Version(version_id.0));
+
+    Ok(HttpResponse::Ok().into())
+}
+
+/// Makes sure a user can add files to a version, returning the mod it
+/// belongs to, its version number and the mod's team
+async fn get_upload_version(
+    version_id: models::VersionId,
+    user_id: models::UserId,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(ModId, String, models::TeamId), CreateError> {
     let result = sqlx::query!(
         "
-        SELECT mod_id, version_number, author_id
+        SELECT mod_id, version_number
         FROM versions
         WHERE id = $1
         ",
@@ -420,7 +598,7 @@ async fn upload_file_to_version_inner(
     };
 
     let team_member =
-        models::TeamMember::get_from_user_id_version(version_id, user.id.into(), &mut *transaction)
+        models::TeamMember::get_from_user_id_version(version_id, user_id, &mut *transaction)
             .await?
             .ok_or_else(|| {
                 CreateError::CustomAuthenticationError(
@@ -437,60 +615,260 @@ async fn upload_file_to_version_inner(
         ));
     }
 
-    let mod_id = ModId(version.mod_id as u64);
-    let version_number = version.version_number;
+    Ok((
+        ModId(version.mod_id as u64),
+        version.version_number,
+        team_member.team_id,
+    ))
+}
 
-    while let Some(item) = payload.next().await {
-        let mut field: Field = item.map_err(CreateError::MultipartError)?;
-        let content_disposition = field.content_disposition().ok_or_else(|| {
-            CreateError::MissingValueError("Missing content disposition".to_string())
-        })?;
-        let name = content_disposition
-            .get_name()
-            .ok_or_else(|| CreateError::MissingValueError("Missing content name".to_string()))?;
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct PresignFile {
+    /// The name of the file in the version
+    pub file_name: String,
+    /// The size of the file, in bytes
+    pub size: u64,
+}
 
-        if name == "data" {
-            let mut data = Vec::new();
-            while let Some(chunk) = field.next().await {
-                data.extend_from_slice(&chunk.map_err(CreateError::MultipartError)?);
-            }
-            let file_data: InitialFileData = serde_json::from_slice(&data)?;
-            // TODO: currently no data here, but still required
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct PresignedFile {
+    /// The URL to upload the file to with a `PUT` request
+    pub url: String,
+    /// The headers the `PUT` request has to be sent with
+    pub headers: HashMap<String, String>,
+    pub expires: chrono::DateTime<chrono::Utc>,
+}
 
-            initial_file_data = Some(file_data);
-            continue;
-        }
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct FinalizeFile {
+    /// The name of the file in the version, as it was presigned
+    pub file_name: String,
+    /// The size of the file, in bytes
+    pub size: u64,
+    /// The SHA-512 hash of the file, as hex
+    pub sha512: String,
+}
 
-        let _file_data = initial_file_data.as_ref().ok_or_else(|| {
-            CreateError::InvalidInput(String::from("`data` field must come before file fields"))
-        })?;
+/// How long presigned upload URLs are valid for, in seconds
+const PRESIGN_EXPIRY: u32 = 60 * 60;
 
-        let file_builder = upload_file(
-            &mut field,
-            file_host,
-            uploaded_files,
-            &cdn_url,
-            &content_disposition,
-            mod_id,
-            &version_number,
-        )
-        .await?;
+/// Checks a file that is uploaded directly to the file host, returning its
+/// content type
+fn check_direct_upload(
+    file_name: &str,
+    size: u64,
+    quota: &StorageQuota,
+) -> Result<&'static str, CreateError> {
+    super::mod_creation::check_length(1..=256, "file name", file_name)?;
 
-        // TODO: Malware scan + file validation
-        file_builders.push(file_builder);
+    if file_name.contains('/') {
+        return Err(CreateError::InvalidInput(
+            "The file name must not contain slashes".to_string(),
+        ));
     }
 
-    if file_builders.is_empty() {
+    let file_extension = file_extension(file_name).unwrap_or("");
+    let content_type = mod_file_type(file_extension)
+        .ok_or_else(|| CreateError::InvalidFileType(file_extension.to_string()))?;
+
+    if size == 0 {
+        return Err(CreateError::InvalidInput("The file is empty".to_string()));
+    }
+
+    if size > quota.max_file_size() {
+        return Err(CreateError::InvalidInput(quota.exceeded_error()));
+    }
+
+    Ok(content_type)
+}
+
+// under /api/v1/version/{version_id}
+#[utoipa::path(
+    context_path = "/api/v1/version/",
+    tag = "versions",
+    security(("token" = [])),
+    responses((status = 200, description = "A URL the file can be uploaded to directly, after which it's added to the version with `POST /version/{version_id}/file/finalize`", body = PresignedFile), (status = 400, description = "The storage backend doesn't support direct uploads, or the file is invalid")),
+)]
+#[post("{version_id}/file/presign")]
+pub async fn version_file_presign(
+    req: HttpRequest,
+    url_data: actix_web::web::Path<(VersionId,)>,
+    file_data: Json<PresignFile>,
+    client: Data<PgPool>,
+    file_host: Data<std::sync::Arc<dyn FileHost + Send + Sync>>,
+) -> Result<HttpResponse, CreateError> {
+    let version_id = models::VersionId::from(url_data.into_inner().0);
+    let mut transaction = client.begin().await?;
+
+    let user = get_user_from_headers(req.headers(), &mut *transaction).await?;
+    let (mod_id, version_number, team_id) =
+        get_upload_version(version_id, user.id.into(), &mut transaction).await?;
+    let quota = StorageQuota::get(user.id.into(), Some(team_id), &mut transaction).await?;
+    let content_type = check_direct_upload(&file_data.file_name, file_data.size, &quota)?;
+
+    check_file_name_free(version_id, &file_data.file_name, &mut transaction).await?;
+
+    let presigned = file_host
+        .presign_upload(
+            content_type,
+            &format!(
+                "data/{}/versions/{}/{}",
+                mod_id, version_number, file_data.file_name
+            ),
+            PRESIGN_EXPIRY,
+        )?
+        .ok_or_else(|| {
+            CreateError::InvalidInput(
+                "The storage backend doesn't support direct uploads".to_string(),
+            )
+        })?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(PresignedFile {
+        url: presigned.url,
+        headers: presigned.headers.into_iter().collect(),
+        expires: chrono::Utc::now() + chrono::Duration::seconds(PRESIGN_EXPIRY as i64),
+    }))
+}
+
+async fn check_file_name_free(
+    version_id: models::VersionId,
+    file_name: &str,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), CreateError> {
+    let results = sqlx::query!(
+        "SELECT EXISTS(SELECT 1 FROM files WHERE version_id = $1 AND filename = $2)",
+        version_id as models::VersionId,
+        file_name
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    if results.exists.unwrap_or(true) {
         return Err(CreateError::InvalidInput(
-            "At least one file must be specified".to_string(),
+            "A file with that name already exists in this version".to_string(),
         ));
-    } else {
-        for file_builder in file_builders {
-            file_builder.insert(version_id, &mut *transaction).await?;
+    }
+
+    Ok(())
+}
+
+// under /api/v1/version/{version_id}
+#[utoipa::path(
+    context_path = "/api/v1/version/",
+    tag = "versions",
+    security(("token" = [])),
+    responses((status = 200, description = "The uploaded file matched its size and hash, and was added to the version", body = VersionFile), (status = 400, description = "The file wasn't uploaded, or doesn't match its size or hash. Files that don't match are deleted.")),
+)]
+#[post("{version_id}/file/finalize")]
+pub async fn version_file_finalize(
+    req: HttpRequest,
+    url_data: actix_web::web::Path<(VersionId,)>,
+    file_data: Json<FinalizeFile>,
+    client: Data<PgPool>,
+    file_host: Data<std::sync::Arc<dyn FileHost + Send + Sync>>,
+) -> Result<HttpResponse, CreateError> {
+    let version_id = models::VersionId::from(url_data.into_inner().0);
+    let cdn_url = dotenv::var("CDN_URL")?;
+    let mut transaction = client.begin().await?;
+
+    let user = get_user_from_headers(req.headers(), &mut *transaction).await?;
+    let (mod_id, version_number, team_id) =
+        get_upload_version(version_id, user.id.into(), &mut transaction).await?;
+    let quota = StorageQuota::get(user.id.into(), Some(team_id), &mut transaction).await?;
+    let content_type = check_direct_upload(&file_data.file_name, file_data.size, &quota)?;
+
+    check_file_name_free(version_id, &file_data.file_name, &mut transaction).await?;
+
+    let file_path = format!(
+        "data/{}/versions/{}/{}",
+        mod_id, version_number, file_data.file_name
+    );
+
+    // The hashes are computed from what was actually stored, so the
+    // declared ones only need to match them
+    let upload_data = match file_host
+        .inspect_file(
+            content_type,
+            &file_path,
+            quota.max_file_size().saturating_add(1),
+        )
+        .await
+    {
+        Ok(upload_data) => upload_data,
+        Err(FileHostingError::NotFound(_)) => {
+            return Err(CreateError::InvalidInput(
+                "The file hasn't been uploaded yet".to_string(),
+            ));
+        }
+        Err(e) => {
+            let _ = file_host.delete_file_version("", &file_path).await;
+            return Err(upload_error(e, &quota.exceeded_error()));
         }
+    };
+
+    if upload_data.content_length != file_data.size
+        || !upload_data
+            .content_sha512
+            .eq_ignore_ascii_case(&file_data.sha512)
+    {
+        let _ = file_host.delete_file_version("", &file_path).await;
+        return Err(CreateError::InvalidInput(
+            "The uploaded file doesn't match its declared size and hash, and was deleted"
+                .to_string(),
+        ));
     }
 
-    Ok(HttpResponse::Ok().into())
+    let results = sqlx::query!(
+        "SELECT EXISTS(SELECT 1 FROM files WHERE version_id = $1)",
+        version_id as models::VersionId
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    let contents = inspect_upload(&***file_host, &file_path, &file_data.file_name).await?;
+    let blob = blobs::adopt(&***file_host, &file_path, upload_data, &mut transaction).await?;
+    check_not_flagged(&blob)?;
+
+    let mut uploaded_files = Vec::new();
+    let mut file_builder = blob_file(
+        &mut uploaded_files,
+        &cdn_url,
+        &file_data.file_name,
+        blob,
+        contents,
+    );
+    file_builder.primary = !results.exists.unwrap_or(false);
+
+    let response = VersionFile {
+        hashes: file_builder
+            .hashes
+            .iter()
+            .map(|hash| {
+                (
+                    hash.algorithm.clone(),
+                    String::from_utf8(hash.hash.clone()).unwrap(),
+                )
+            })
+            .collect(),
+        url: file_builder.url.clone(),
+        filename: file_builder.filename.clone(),
+        primary: file_builder.primary,
+        contents: file_builder.contents.clone(),
+        scan_status: file_builder.scan_status,
+    };
+
+    if let Err(e) = file_builder.insert(version_id, &mut transaction).await {
+        super::mod_creation::undo_uploads(&***file_host, &uploaded_files).await?;
+        return Err(e.into());
+    }
+
+    transaction.commit().await?;
+
+    cache::invalidate(Invalidation::Version(version_id.0), &**client).await;
+
+    Ok(HttpResponse::Ok().json(response))
 }
 
 // This function is used for adding a file to a version, uploading the initial
@@ -498,72 +876,186 @@ async fn upload_file_to_version_inner(
 pub async fn upload_file(
     field: &mut Field,
     file_host: &dyn FileHost,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     uploaded_files: &mut Vec<UploadedFile>,
     cdn_url: &str,
     content_disposition: &actix_web::http::header::ContentDisposition,
-    mod_id: crate::models::ids::ModId,
-    version_number: &str,
+    quota: &mut StorageQuota,
 ) -> Result<models::version_item::VersionFileBuilder, CreateError> {
     let (file_name, file_extension) = get_name_ext(content_disposition)?;
 
     let content_type = mod_file_type(file_extension)
         .ok_or_else(|| CreateError::InvalidFileType(file_extension.to_string()))?;
 
-    let mut data = Vec::new();
-    while let Some(chunk) = field.next().await {
-        data.extend_from_slice(&chunk.map_err(CreateError::MultipartError)?);
-    }
+    let (blob, contents) = store_file(
+        file_host,
+        transaction,
+        file_name,
+        content_type,
+        field_stream(field),
+        quota,
+    )
+    .await?;
+
+    Ok(blob_file(uploaded_files, cdn_url, file_name, blob, contents))
+}
+
+/// Adds a finished resumable upload to a version, copying it from its
+/// pieces to where the files of the version are stored
+async fn use_upload(
+    upload_id: UploadId,
+    user_id: models::UserId,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    file_host: &dyn FileHost,
+    uploaded_files: &mut Vec<UploadedFile>,
+    cdn_url: &str,
+    quota: &mut StorageQuota,
+) -> Result<models::version_item::VersionFileBuilder, CreateError> {
+    // Taking the upload locks it until the transaction ends, so it can't be
+    // used twice or removed while it's copied
+    let session = UploadSession::take(upload_id.into(), user_id, &mut *transaction)
+        .await?
+        .ok_or_else(|| {
+            CreateError::InvalidInput(format!(
+                "Upload {} doesn't exist, isn't complete or was used already",
+                upload_id
+            ))
+        })?;
+
+    let file_extension = file_extension(&session.file_name).unwrap_or("");
+    let content_type = mod_file_type(file_extension)
+        .ok_or_else(|| CreateError::InvalidFileType(file_extension.to_string()))?;
+
+    let chunks = UploadSession::get_chunks(session.id, &mut *transaction).await?;
+
+    let (blob, contents) = store_file(
+        file_host,
+        transaction,
+        &session.file_name,
+        content_type,
+        uploads::assemble(file_host, chunks),
+        quota,
+    )
+    .await?;
+
+    Ok(blob_file(
+        uploaded_files,
+        cdn_url,
+        &session.file_name,
+        blob,
+        contents,
+    ))
+}
+
+/// Stores the content of a file once it's been checked, counting it towards
+/// the uploader's quota.  Returns the files in it if it's an archive.
+async fn store_file(
+    file_host: &dyn FileHost,
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    file_name: &str,
+    content_type: &str,
+    file_stream: ByteStream<'_>,
+    quota: &mut StorageQuota,
+) -> Result<(StoredBlob, Option<Vec<ArchiveEntry>>), CreateError> {
+    let (incoming, data) = blobs::upload(
+        file_host,
+        content_type,
+        file_stream,
+        quota.max_file_size().saturating_add(1),
+    )
+    .await
+    .map_err(|e| upload_error(e, &quota.exceeded_error()))?;
+
+    let contents = inspect_upload(file_host, &incoming, file_name).await?;
+    let blob = blobs::adopt(file_host, &incoming, data, transaction).await?;
+    check_not_flagged(&blob)?;
+
+    quota.add(blob.data.content_length);
+
+    Ok((blob, contents))
+}
 
-    // Mod file size limit of 25MiB
-    const FILE_SIZE_CAP: usize = 25 * (2 << 30);
+/// Checks what's in a file before it's stored as a blob, deleting it if it
+/// isn't allowed
+async fn inspect_upload(
+    file_host: &dyn FileHost,
+    stored_name: &str,
+    file_name: &str,
+) -> Result<Option<Vec<ArchiveEntry>>, CreateError> {
+    match archives::inspect(file_host, stored_name, file_name).await {
+        Ok(contents) => Ok(contents),
+        Err(e) => {
+            let _ = file_host.delete_file_version("", stored_name).await;
+
+            Err(match e {
+                ArchiveError::Rejected(reason) => CreateError::InvalidInput(reason),
+                ArchiveError::FileHostingError(e) => CreateError::FileHostingError(e),
+            })
+        }
+    }
+}
 
-    // TODO: override file size cap for authorized users or mods
-    if data.len() >= FILE_SIZE_CAP {
+/// Content the malware scanner flagged before isn't taken again.  New
+/// content is scanned after it's stored.
+fn check_not_flagged(blob: &StoredBlob) -> Result<(), CreateError> {
+    if blob.scan_status == ScanStatus::Infected {
         return Err(CreateError::InvalidInput(
-            String::from("Mod file exceeds the maximum of 25MiB. Contact a moderator or admin to request permission to upload larger files.")
+            "The file was flagged by the malware scanner".to_string(),
         ));
     }
 
-    let upload_data = file_host
-        .upload_file(
-            content_type,
-            &format!("data/{}/versions/{}/{}", mod_id, version_number, file_name),
-            data.to_vec(),
-        )
-        .await?;
+    Ok(())
+}
 
-    uploaded_files.push(UploadedFile {
-        file_id: upload_data.file_id,
-        file_name: upload_data.file_name.clone(),
-    });
+/// A file of a version, with its content stored in a blob
+fn blob_file(
+    uploaded_files: &mut Vec<UploadedFile>,
+    cdn_url: &str,
+    file_name: &str,
+    blob: StoredBlob,
+    contents: Option<Vec<ArchiveEntry>>,
+) -> models::version_item::VersionFileBuilder {
+    // Blobs that existed before are shared with other files, so they're kept
+    // if the version isn't created after all
+    if blob.created {
+        uploaded_files.push(UploadedFile {
+            file_id: blob.data.file_id,
+            file_name: blob.data.file_name.clone(),
+        });
+    }
 
-    // TODO: Malware scan + file validation
-    Ok(models::version_item::VersionFileBuilder {
+    models::version_item::VersionFileBuilder {
         filename: file_name.to_string(),
-        url: format!("{}/{}", cdn_url, upload_data.file_name),
+        url: format!(
+            "{}/{}",
+            cdn_url,
+            blobs::blob_path(&blob.data.content_sha512)
+        ),
         hashes: vec![
             models::version_item::HashBuilder {
                 algorithm: "sha1".to_string(),
                 // This is an invalid cast - the database expects the hash's
                 // bytes, but this is the string version.
-                hash: upload_data.content_sha1.into_bytes(),
+                hash: blob.data.content_sha1.into_bytes(),
             },
             models::version_item::HashBuilder {
                 algorithm: "sha512".to_string(),
                 // This is an invalid cast - the database expects the hash's
                 // bytes, but this is the string version.
-                hash: upload_data.content_sha512.into_bytes(),
+                hash: blob.data.content_sha512.clone().into_bytes(),
             },
         ],
-        primary: uploaded_files.len() == 1,
-    })
+        primary: false,
+        blob_sha512: Some(blob.data.content_sha512),
+        contents,
+        scan_status: blob.scan_status,
+    }
 }
 
-fn mod_file_type(ext: &str) -> Option<&str> {
+pub fn mod_file_type(ext: &str) -> Option<&'static str> {
     match ext {
         "zip"       => Some("application/zip"),
         "tar.gz"    => Some("application/gzip"),
-        "rar"       => Some("application/vnd.rar"),
         "7z"        => Some("application/x-7z-compressed"),
         "ttmp2"     => Some("application/zip"),
         "cmp"       => Some("text/plain"),
@@ -578,12 +1070,19 @@ pub fn get_name_ext(
     let file_name = content_disposition
         .get_filename()
         .ok_or_else(|| CreateError::MissingValueError("Missing content file name".to_string()))?;
-    let file_extension = if let Some(last_period) = file_name.rfind('.') {
-        file_name.get((last_period + 1)..).unwrap_or("")
-    } else {
-        return Err(CreateError::MissingValueError(
-            "Missing content file extension".to_string(),
-        ));
-    };
+    let file_extension = file_extension(file_name).ok_or_else(|| {
+        CreateError::MissingValueError("Missing content file extension".to_string())
+    })?;
     Ok((file_name, file_extension))
 }
+
+pub fn file_extension(file_name: &str) -> Option<&str> {
+    // The only extension with more than one part
+    if file_name.len() > ".tar.gz".len() && file_name.ends_with(".tar.gz") {
+        return Some("tar.gz");
+    }
+
+    file_name
+        .rfind('.')
+        .map(|last_period| file_name.get((last_period + 1)..).unwrap_or(""))
+}
@@ -1,79 +1,176 @@
+use super::batch::{in_request_order, BatchIds};
+use super::conditional::{json_response, CacheScope};
+use super::mods::is_visible;
+use super::pagination::{Pagination, SortKey, NEWEST, OLDEST};
 use super::ApiError;
 use crate::auth::get_user_from_headers;
+use crate::database::models::cache::{self, Invalidation};
 use crate::file_hosting::FileHost;
 use crate::models;
 use crate::models::mods::{Dependency, DependencyType};
+use crate::models::pagination::{Listing, PageQuery};
 use crate::models::teams::Permissions;
 use crate::{database, Pepper};
 use actix_web::http::header;
-use actix_web::{delete, get, patch, web, HttpRequest, HttpResponse};
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
 use log::warn;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct VersionListFilters {
+    /// A JSON array of game versions, matching versions that support any of them
     pub game_versions: Option<String>,
+    /// A JSON array of loaders, matching versions that support any of them
     pub loaders: Option<String>,
     pub featured: Option<bool>,
 }
 
+const VERSION_SORTS: &[SortKey] = &[NEWEST, OLDEST];
+
+/// Sorted by publication date, with the keys `newest` and `oldest`
+#[utoipa::path(
+    context_path = "/api/v1/mod/{mod_id}/",
+    tag = "versions",
+    params(("mod_id" = models::ids::ModId, Path, description = "The ID of the mod"), VersionListFilters, PageQuery),
+    responses((status = 200, body = Listing<models::mods::Version>), (status = 404, description = "The mod doesn't exist or is hidden"), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("version")]
 pub async fn version_list(
+    req: HttpRequest,
     info: web::Path<(models::ids::ModId,)>,
     web::Query(filters): web::Query<VersionListFilters>,
+    web::Query(page): web::Query<PageQuery>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ApiError> {
-    let id = info.into_inner().0.into();
-
-    let mod_exists = sqlx::query!(
-        "SELECT EXISTS(SELECT 1 FROM mods WHERE id = $1)",
+    let id: database::models::ModId = info.into_inner().0.into();
+    let pagination = Pagination::from_query(&page, VERSION_SORTS)?;
+
+    let game_versions = filters
+        .game_versions
+        .as_deref()
+        .map(serde_json::from_str::<Vec<String>>)
+        .transpose()?;
+    let loaders = filters
+        .loaders
+        .as_deref()
+        .map(serde_json::from_str::<Vec<String>>)
+        .transpose()?;
+
+    let mod_data = sqlx::query!(
+        "
+        SELECT m.team_id, s.status FROM mods m
+        INNER JOIN statuses s ON s.id = m.status
+        WHERE m.id = $1
+        ",
         id as database::models::ModId,
     )
-    .fetch_one(&**pool)
+    .fetch_optional(&**pool)
     .await
-    .map_err(|e| ApiError::DatabaseError(e.into()))?
-    .exists;
+    .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let (status, team_id) = match mod_data {
+        Some(data) => (
+            models::mods::ModStatus::from_str(&data.status),
+            database::models::TeamId(data.team_id),
+        ),
+        None => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    let user_option = get_user_from_headers(req.headers(), &**pool).await.ok();
+
+    if is_visible(&status, team_id, user_option.as_ref(), &pool).await? {
+        use futures::stream::TryStreamExt;
 
-    if mod_exists.unwrap_or(false) {
-        let version_ids = database::models::Version::get_mod_versions(id,&**pool)
+        let rows = sqlx::query!(
+            "
+            SELECT v.id, v.date_published FROM versions v
+            WHERE v.mod_id = $1 AND ($2::bool IS NULL OR v.featured = $2)
+            AND ($3::timestamptz IS NULL OR CASE WHEN $5
+                THEN (v.date_published, v.id) < ($3::timestamptz, $4::bigint)
+                ELSE (v.date_published, v.id) > ($3::timestamptz, $4::bigint)
+            END)
+            AND ($7::varchar[] IS NULL OR EXISTS(
+                SELECT 1 FROM game_versions_versions gvv
+                INNER JOIN game_versions gv ON gv.id = gvv.game_version_id
+                WHERE gvv.joining_version_id = v.id AND gv.version = ANY($7)
+            ))
+            AND ($8::varchar[] IS NULL OR EXISTS(
+                SELECT 1 FROM loaders_versions lv
+                INNER JOIN loaders l ON l.id = lv.loader_id
+                WHERE lv.version_id = v.id AND l.loader = ANY($8)
+            ))
+            ORDER BY
+                CASE WHEN $5 THEN v.date_published END DESC,
+                CASE WHEN $5 THEN v.id END DESC,
+                v.date_published ASC, v.id ASC
+            LIMIT $6
+            ",
+            id as database::models::ModId,
+            filters.featured,
+            pagination.after_key(),
+            pagination.after_id(),
+            pagination.sort.descending,
+            pagination.fetch_limit(),
+            game_versions.as_deref(),
+            loaders.as_deref(),
+        )
+        .fetch_many(&**pool)
+        .try_filter_map(|e| async { Ok(e.right().map(|v| (v.date_published, v.id))) })
+        .try_collect::<Vec<_>>()
         .await
         .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
-        let mut versions = database::models::Version::get_many_full(version_ids, &**pool)
-            .await
-            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+        let (ids, next) = pagination.split(rows);
 
-        let mut response = versions
-            .iter()
-            .cloned()
-            .filter(|version| {
-                filters
-                    .featured
-                    .map(|featured| featured == version.featured)
-                    .unwrap_or(true)
-            })
-            .map(convert_version)
-            .collect::<Vec<_>>();
-
-        versions.sort_by(|a, b| b.date_published.cmp(&a.date_published));
+        let versions = database::models::Version::get_many_full(
+            ids.iter().map(|x| database::models::VersionId(*x)).collect(),
+            &**pool,
+        )
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?
+        .into_iter()
+        .map(convert_version)
+        .collect();
 
-        response.sort_by(|a, b| b.date_published.cmp(&a.date_published));
-        response.dedup_by(|a, b| a.id == b.id);
+        let page = pagination.page(
+            &ids,
+            versions,
+            |x| database::models::VersionId::from(x.id).0,
+            next,
+        );
+
+        // Hidden mods are only shown to some users, so their versions can't
+        // be cached for everyone
+        let scope = if status.is_hidden() {
+            CacheScope::Private
+        } else {
+            CacheScope::Public
+        };
 
-        Ok(HttpResponse::Ok().json(response))
+        json_response(&req, &page, scope)
     } else {
         Ok(HttpResponse::NotFound().body(""))
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct VersionIds {
     pub ids: String,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "versions",
+    params(VersionIds),
+    responses((status = 200, description = "The versions that exist", body = Vec<models::mods::Version>)),
+)]
 #[get("versions")]
 pub async fn versions_get(
     web::Query(ids): web::Query<VersionIds>,
@@ -96,8 +193,83 @@ pub async fn versions_get(
     Ok(HttpResponse::Ok().json(versions))
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "versions",
+    request_body = BatchIds<models::ids::VersionId>,
+    responses((status = 200, description = "The versions in the order they were requested, with `null` for versions that don't exist or belong to hidden mods", body = Vec<Option<models::mods::Version>>)),
+)]
+#[post("versions")]
+pub async fn versions_batch(
+    req: HttpRequest,
+    web::Json(batch): web::Json<BatchIds<models::ids::VersionId>>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    batch.validate()?;
+
+    let versions_data = database::models::Version::get_many_full(
+        batch.ids.iter().map(|x| (*x).into()).collect(),
+        &**pool,
+    )
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    use futures::stream::TryStreamExt;
+
+    let mod_ids: Vec<i64> = versions_data.iter().map(|x| x.mod_id.0).collect();
+    let parent_mods = sqlx::query!(
+        "
+        SELECT m.id, m.team_id, s.status FROM mods m
+        INNER JOIN statuses s ON s.id = m.status
+        WHERE m.id = ANY($1)
+        ",
+        &mod_ids[..]
+    )
+    .fetch_many(&**pool)
+    .try_filter_map(|e| async {
+        Ok(e.right().map(|m| {
+            (
+                m.id,
+                (
+                    models::mods::ModStatus::from_str(&m.status),
+                    database::models::TeamId(m.team_id),
+                ),
+            )
+        }))
+    })
+    .try_collect::<HashMap<_, _>>()
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let user_option = get_user_from_headers(req.headers(), &**pool).await.ok();
+
+    // Versions are visible if the mod they belong to is
+    let mut visible_mods = HashMap::new();
+
+    for (id, (status, team_id)) in parent_mods {
+        let visible = is_visible(&status, team_id, user_option.as_ref(), &pool).await?;
+        visible_mods.insert(id, visible);
+    }
+
+    let versions: HashMap<u64, models::mods::Version> = versions_data
+        .into_iter()
+        .filter(|x| visible_mods.get(&x.mod_id.0).copied().unwrap_or(false))
+        .map(|x| (x.id.0 as u64, convert_version(x)))
+        .collect();
+
+    let ids: Vec<u64> = batch.ids.iter().map(|x| x.0).collect();
+
+    Ok(HttpResponse::Ok().json(in_request_order(&ids, &versions)))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/version/",
+    tag = "versions",
+    responses((status = 200, body = models::mods::Version), (status = 404, description = "The version doesn't exist"), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("{version_id}")]
 pub async fn version_get(
+    req: HttpRequest,
     info: web::Path<(models::ids::VersionId,)>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ApiError> {
@@ -107,20 +279,23 @@ pub async fn version_get(
         .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
     if let Some(data) = version_data {
-        Ok(HttpResponse::Ok().json(convert_version(data)))
+        json_response(&req, &convert_version(data), CacheScope::Public)
     } else {
         Ok(HttpResponse::NotFound().body(""))
     }
 }
 
-fn convert_version(data: database::models::version_item::QueryVersion) -> models::mods::Version {
+pub fn convert_version(data: database::models::version_item::QueryVersion) -> models::mods::Version {
     use models::mods::VersionType;
 
+    let quarantined = data.quarantined;
+
     models::mods::Version {
         id: data.id.into(),
         mod_id: data.mod_id.into(),
         author_id: data.author_id.into(),
         featured: data.featured,
+        quarantined,
         name: data.name,
         version_number: data.version_number,
         changelog: data.changelog,
@@ -136,9 +311,12 @@ fn convert_version(data: database::models::version_item::QueryVersion) -> models
             _ => VersionType::Release,
         },
 
+        // Files can't be downloaded until they're scanned, or while their
+        // version is quarantined
         files: data
             .files
             .into_iter()
+            .filter(|f| !quarantined && f.scan_status == models::mods::ScanStatus::Clean)
             .map(|f| {
                 models::mods::VersionFile {
                     url: f.url,
@@ -152,6 +330,8 @@ fn convert_version(data: database::models::version_item::QueryVersion) -> models
                         .collect::<Option<_>>()
                         .unwrap_or_else(Default::default),
                     primary: f.primary,
+                    contents: f.contents,
+                    scan_status: f.scan_status,
                 }
             })
             .collect(),
@@ -166,7 +346,7 @@ fn convert_version(data: database::models::version_item::QueryVersion) -> models
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct EditVersion {
     pub name: Option<String>,
     pub version_number: Option<String>,
@@ -175,14 +355,26 @@ pub struct EditVersion {
     pub dependencies: Option<Vec<Dependency>>,
     pub featured: Option<bool>,
     pub primary_file: Option<(String, String)>,
+    /// Only moderators can quarantine a version, or lift its quarantine once
+    /// they checked the files the malware scanner flagged.  Lifting it
+    /// clears the flagged files, which can be downloaded again afterwards.
+    pub quarantined: Option<bool>,
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/version/",
+    tag = "versions",
+    request_body = EditVersion,
+    security(("token" = [])),
+    responses((status = 200, description = "The version was edited"), (status = 404, description = "The version doesn't exist")),
+)]
 #[patch("{id}")]
 pub async fn version_edit(
     req: HttpRequest,
     info: web::Path<(models::ids::VersionId,)>,
     pool: web::Data<PgPool>,
     new_version: web::Json<EditVersion>,
+    file_host: web::Data<Arc<dyn FileHost + Send + Sync>>,
 ) -> Result<HttpResponse, ApiError> {
     let user = get_user_from_headers(req.headers(), &**pool).await?;
 
@@ -335,6 +527,33 @@ pub async fn version_edit(
                 .map_err(|e| ApiError::DatabaseError(e.into()))?;
             }
 
+            if let Some(quarantined) = new_version.quarantined {
+                if !user.role.is_mod() {
+                    return Err(ApiError::CustomAuthenticationError(
+                        "You do not have the permissions to change the quarantine of this version!"
+                            .to_string(),
+                    ));
+                }
+
+                database::models::Version::set_quarantined(id, quarantined, &mut *transaction)
+                    .await
+                    .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+                // Moderators overrule the scanner, otherwise the flagged
+                // files would stay hidden.  They're published before the
+                // change is committed, so it's rolled back if that fails.
+                if !quarantined {
+                    let cleared =
+                        database::models::blob_item::FileBlob::clear_infected(id, &mut transaction)
+                            .await
+                            .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+                    for sha512 in &cleared {
+                        crate::file_hosting::blobs::publish(&***file_host, sha512).await?;
+                    }
+                }
+            }
+
             if let Some(primary_file) = &new_version.primary_file {
                 let result = sqlx::query!(
                     "
@@ -406,6 +625,9 @@ pub async fn version_edit(
                 .commit()
                 .await
                 .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+            cache::invalidate(Invalidation::Version(id.0), &**pool).await;
+
             Ok(HttpResponse::Ok().body(""))
         } else {
             Err(ApiError::CustomAuthenticationError(
@@ -417,6 +639,12 @@ pub async fn version_edit(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api/v1/version/",
+    tag = "versions",
+    security(("token" = [])),
+    responses((status = 200, description = "The version was deleted"), (status = 404, description = "The version doesn't exist")),
+)]
 #[delete("{version_id}")]
 pub async fn version_delete(
     req: HttpRequest,
@@ -450,10 +678,19 @@ pub async fn version_delete(
         }
     }
 
+    let version = database::models::Version::get(id.into(), &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
     let result = database::models::Version::remove_full(id.into(), &**pool)
         .await
         .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
+    if let Some(version) = version {
+        cache::invalidate(Invalidation::Version(version.id.0), &**pool).await;
+        cache::invalidate(Invalidation::Mod(version.mod_id.0), &**pool).await;
+    }
+
     if result.is_some() {
         Ok(HttpResponse::Ok().body(""))
     } else {
@@ -461,7 +698,8 @@ pub async fn version_delete(
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Algorithm {
     #[serde(default = "default_algorithm")]
     algorithm: String,
@@ -472,8 +710,15 @@ fn default_algorithm() -> String {
 }
 
 // under /api/v1/version_file/{hash}
+#[utoipa::path(
+    context_path = "/api/v1/version_file/",
+    tag = "versions",
+    params(("version_id" = String, Path, description = "The hash of the file"), Algorithm),
+    responses((status = 200, description = "The version the file belongs to", body = models::mods::Version), (status = 404, description = "No file has this hash"), (status = 304, description = "The client's copy is still current")),
+)]
 #[get("{version_id}")]
 pub async fn get_version_from_hash(
+    req: HttpRequest,
     info: web::Path<(String,)>,
     pool: web::Data<PgPool>,
     algorithm: web::Query<Algorithm>,
@@ -502,7 +747,7 @@ pub async fn get_version_from_hash(
         .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
         if let Some(data) = version_data {
-            Ok(HttpResponse::Ok().json(convert_version(data)))
+            json_response(&req, &convert_version(data), CacheScope::Public)
         } else {
             Ok(HttpResponse::NotFound().body(""))
         }
@@ -511,13 +756,19 @@ pub async fn get_version_from_hash(
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct DownloadRedirect {
     pub url: String,
 }
 
 // under /api/v1/version_file/{hash}/download
 #[allow(clippy::await_holding_refcell_ref)]
+#[utoipa::path(
+    context_path = "/api/v1/version_file/",
+    tag = "versions",
+    params(("version_id" = String, Path, description = "The hash of the file"), Algorithm),
+    responses((status = 307, description = "Redirects to the file", body = DownloadRedirect), (status = 404, description = "No file has this hash, or it can't be downloaded because it wasn't scanned for malware yet or its version is quarantined")),
+)]
 #[get("{version_id}/download")]
 pub async fn download_version(
     req: HttpRequest,
@@ -533,7 +784,9 @@ pub async fn download_version(
         SELECT f.url url, f.id id, f.version_id version_id, v.mod_id mod_id, f.filename filename FROM hashes h
         INNER JOIN files f ON h.file_id = f.id
         INNER JOIN versions v ON v.id = f.version_id
+        LEFT OUTER JOIN file_blobs b ON b.sha512 = f.blob_sha512
         WHERE h.algorithm = $2 AND h.hash = $1
+        AND NOT v.quarantined AND (b.scan_status IS NULL OR b.scan_status = 'clean')
         ",
         hash.as_bytes(),
         algorithm.algorithm
@@ -617,6 +870,13 @@ pub async fn download_version(
 }
 
 // under /api/v1/version_file/{hash}
+#[utoipa::path(
+    context_path = "/api/v1/version_file/",
+    tag = "versions",
+    security(("token" = [])),
+    params(("version_id" = String, Path, description = "The hash of the file"), Algorithm),
+    responses((status = 200, description = "The file was deleted"), (status = 404, description = "No file has this hash")),
+)]
 #[delete("{version_id}")]
 pub async fn delete_file(
     req: HttpRequest,
@@ -631,7 +891,7 @@ pub async fn delete_file(
 
     let result = sqlx::query!(
         "
-        SELECT f.id id, f.version_id version_id, f.filename filename, v.version_number version_number, v.mod_id mod_id FROM hashes h
+        SELECT f.id id, f.version_id version_id, f.filename filename, f.blob_sha512 blob_sha512, v.version_number version_number, v.mod_id mod_id FROM hashes h
         INNER JOIN files f ON h.file_id = f.id
         INNER JOIN versions v ON v.id = f.version_id
         WHERE h.algorithm = $2 AND h.hash = $1
@@ -695,22 +955,29 @@ pub async fn delete_file(
         .await
         .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
-        let mod_id: models::mods::ModId = database::models::ids::ModId(row.mod_id).into();
-        file_host
-            .delete_file_version(
-                "",
-                &format!(
-                    "data/{}/versions/{}/{}",
-                    mod_id, row.version_number, row.filename
-                ),
-            )
-            .await?;
+        if let Some(blob_sha512) = &row.blob_sha512 {
+            // The blob is deleted once nothing refers to it anymore
+            crate::file_hosting::blobs::release(blob_sha512, &mut transaction).await?;
+        } else {
+            let mod_id: models::mods::ModId = database::models::ids::ModId(row.mod_id).into();
+            file_host
+                .delete_file_version(
+                    "",
+                    &format!(
+                        "data/{}/versions/{}/{}",
+                        mod_id, row.version_number, row.filename
+                    ),
+                )
+                .await?;
+        }
 
         transaction
             .commit()
             .await
             .map_err(|e| ApiError::DatabaseError(e.into()))?;
 
+        cache::invalidate(Invalidation::Version(row.version_id), &**pool).await;
+
         Ok(HttpResponse::Ok().body(""))
     } else {
         Ok(HttpResponse::NotFound().body(""))
@@ -0,0 +1,442 @@
+use crate::auth::get_user_from_headers;
+use crate::database;
+use crate::database::models::webhook_item::{Webhook as DBWebhook, WebhookDelivery as DBDelivery};
+use crate::models::ids::{ModId, TeamId, WebhookId};
+use crate::models::teams::Permissions;
+use crate::models::users::User;
+use crate::models::webhooks::{DeliveryStatus, Webhook, WebhookDelivery, WebhookEvent};
+use crate::notifications::webhooks::{generate_secret, ping, validate_url};
+use crate::routes::ApiError;
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+/// The number of deliveries returned by the delivery log
+const DELIVERY_LOG_SIZE: i64 = 100;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateWebhook {
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    /// Registers the webhook for a team instead of the current user
+    pub team_id: Option<TeamId>,
+    /// Limits the webhook to one mod
+    pub mod_id: Option<ModId>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct EditWebhook {
+    pub url: Option<String>,
+    pub events: Option<Vec<WebhookEvent>>,
+}
+
+fn validate_events(events: &[WebhookEvent]) -> Result<Vec<String>, String> {
+    if events.is_empty() {
+        return Err("A webhook must subscribe to at least one event!".to_string());
+    }
+
+    let mut names: Vec<String> = Vec::new();
+
+    for event in events {
+        if !event.is_subscribable() {
+            return Err(format!("Invalid webhook event: {}", event));
+        }
+
+        if !names.iter().any(|x| x == event.as_str()) {
+            names.push(event.as_str().to_string());
+        }
+    }
+
+    Ok(names)
+}
+
+/// Whether a user is allowed to manage the webhooks of a team
+async fn can_manage_team(
+    team_id: database::models::TeamId,
+    user: &User,
+    pool: &PgPool,
+) -> Result<bool, ApiError> {
+    if user.role.is_mod() {
+        return Ok(true);
+    }
+
+    let member =
+        database::models::TeamMember::get_from_user_id(team_id, user.id.into(), pool).await?;
+
+    Ok(member
+        .map(|x| x.permissions.contains(Permissions::EDIT_DETAILS))
+        .unwrap_or(false))
+}
+
+/// Gets a webhook, making sure the user is allowed to manage it
+async fn get_managed_webhook(
+    id: WebhookId,
+    user: &User,
+    pool: &PgPool,
+) -> Result<Option<DBWebhook>, ApiError> {
+    let webhook = DBWebhook::get(id.into(), pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    if let Some(webhook) = webhook {
+        let allowed = if let Some(team_id) = webhook.team_id {
+            can_manage_team(team_id, user, pool).await?
+        } else {
+            webhook.user_id == Some(user.id.into()) || user.role.is_mod()
+        };
+
+        if allowed {
+            Ok(Some(webhook))
+        } else {
+            Err(ApiError::CustomAuthenticationError(
+                "You do not have permission to manage this webhook!".to_string(),
+            ))
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "webhooks",
+    request_body = CreateWebhook,
+    security(("token" = [])),
+    responses((status = 200, description = "The webhook was created. This is the only response that includes its secret.", body = Webhook)),
+)]
+#[post("webhook")]
+pub async fn webhook_create(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    new_webhook: web::Json<CreateWebhook>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+
+    validate_url(&new_webhook.url)
+        .await
+        .map_err(ApiError::InvalidInputError)?;
+    let events = validate_events(&new_webhook.events).map_err(ApiError::InvalidInputError)?;
+
+    let mod_data = if let Some(mod_id) = new_webhook.mod_id {
+        Some(
+            database::models::Mod::get_full(mod_id.into(), &**pool)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?
+                .ok_or_else(|| {
+                    ApiError::InvalidInputError(format!("Invalid mod id: {}", mod_id))
+                })?,
+        )
+    } else {
+        None
+    };
+
+    if let Some(team_id) = new_webhook.team_id {
+        if !can_manage_team(team_id.into(), &user, &pool).await? {
+            return Err(ApiError::CustomAuthenticationError(
+                "You do not have permission to manage the webhooks of this team!".to_string(),
+            ));
+        }
+
+        if let Some(mod_data) = &mod_data {
+            if mod_data.inner.team_id.0 as u64 != team_id.0 {
+                return Err(ApiError::InvalidInputError(
+                    "This mod does not belong to the team!".to_string(),
+                ));
+            }
+        }
+    } else if let (Some(mod_id), Some(mod_data)) = (new_webhook.mod_id, &mod_data) {
+        if mod_data.status.is_hidden() {
+            let member = database::models::TeamMember::get_from_user_id(
+                mod_data.inner.team_id,
+                user.id.into(),
+                &**pool,
+            )
+            .await?;
+
+            if member.is_none() && !user.role.is_mod() {
+                return Err(ApiError::InvalidInputError(format!(
+                    "Invalid mod id: {}",
+                    mod_id
+                )));
+            }
+        }
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let webhook = DBWebhook {
+        id: database::models::generate_webhook_id(&mut transaction).await?,
+        user_id: if new_webhook.team_id.is_none() {
+            Some(user.id.into())
+        } else {
+            None
+        },
+        team_id: new_webhook.team_id.map(|x| x.into()),
+        mod_id: new_webhook.mod_id.map(|x| x.into()),
+        url: new_webhook.url.clone(),
+        secret: generate_secret(),
+        events,
+        created: chrono::Utc::now(),
+    };
+
+    webhook
+        .insert(&mut transaction)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(HttpResponse::Ok().json(convert_webhook(webhook, true)))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/",
+    tag = "webhooks",
+    security(("token" = [])),
+    responses((status = 200, description = "The webhooks of the user and their teams", body = Vec<Webhook>)),
+)]
+#[get("webhooks")]
+pub async fn webhooks_get(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+
+    let webhooks = DBWebhook::get_many_user(user.id.into(), &**pool)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    let mut response = Vec::new();
+
+    for webhook in webhooks {
+        if let Some(team_id) = webhook.team_id {
+            if !can_manage_team(team_id, &user, &pool).await? {
+                continue;
+            }
+        }
+
+        response.push(convert_webhook(webhook, false));
+    }
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/webhook/",
+    tag = "webhooks",
+    security(("token" = [])),
+    responses((status = 200, body = Webhook), (status = 404, description = "The webhook doesn't exist")),
+)]
+#[get("{id}")]
+pub async fn webhook_get(
+    req: HttpRequest,
+    info: web::Path<(WebhookId,)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+
+    match get_managed_webhook(info.into_inner().0, &user, &pool).await? {
+        Some(webhook) => Ok(HttpResponse::Ok().json(convert_webhook(webhook, false))),
+        None => Ok(HttpResponse::NotFound().body("")),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/webhook/",
+    tag = "webhooks",
+    request_body = EditWebhook,
+    security(("token" = [])),
+    responses((status = 200, description = "The webhook was edited"), (status = 404, description = "The webhook doesn't exist")),
+)]
+#[patch("{id}")]
+pub async fn webhook_edit(
+    req: HttpRequest,
+    info: web::Path<(WebhookId,)>,
+    pool: web::Data<PgPool>,
+    edit_webhook: web::Json<EditWebhook>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+
+    let mut webhook = match get_managed_webhook(info.into_inner().0, &user, &pool).await? {
+        Some(webhook) => webhook,
+        None => return Ok(HttpResponse::NotFound().body("")),
+    };
+
+    if let Some(url) = &edit_webhook.url {
+        validate_url(url)
+            .await
+            .map_err(ApiError::InvalidInputError)?;
+        webhook.url = url.clone();
+    }
+
+    if let Some(events) = &edit_webhook.events {
+        webhook.events = validate_events(events).map_err(ApiError::InvalidInputError)?;
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    webhook
+        .edit(&mut transaction)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/webhook/",
+    tag = "webhooks",
+    security(("token" = [])),
+    responses((status = 200, description = "The webhook was deleted"), (status = 404, description = "The webhook doesn't exist")),
+)]
+#[delete("{id}")]
+pub async fn webhook_delete(
+    req: HttpRequest,
+    info: web::Path<(WebhookId,)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+
+    match get_managed_webhook(info.into_inner().0, &user, &pool).await? {
+        Some(webhook) => {
+            let mut transaction = pool
+                .begin()
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+            DBWebhook::remove(webhook.id, &mut transaction)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+            transaction
+                .commit()
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+            Ok(HttpResponse::Ok().body(""))
+        }
+        None => Ok(HttpResponse::NotFound().body("")),
+    }
+}
+
+/// Queues a `ping` delivery, to check that the receiver is reachable and verifies signatures
+#[utoipa::path(
+    context_path = "/api/v1/webhook/",
+    tag = "webhooks",
+    security(("token" = [])),
+    responses((status = 200, description = "A ping event was queued"), (status = 404, description = "The webhook doesn't exist")),
+)]
+#[post("{id}/ping")]
+pub async fn webhook_ping(
+    req: HttpRequest,
+    info: web::Path<(WebhookId,)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+
+    match get_managed_webhook(info.into_inner().0, &user, &pool).await? {
+        Some(webhook) => {
+            let mut transaction = pool
+                .begin()
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+            ping(webhook.id, &mut transaction).await?;
+
+            transaction
+                .commit()
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+            Ok(HttpResponse::Ok().body(""))
+        }
+        None => Ok(HttpResponse::NotFound().body("")),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/webhook/",
+    tag = "webhooks",
+    security(("token" = [])),
+    responses((status = 200, description = "The most recent deliveries to the webhook", body = Vec<WebhookDelivery>), (status = 404, description = "The webhook doesn't exist")),
+)]
+#[get("{id}/deliveries")]
+pub async fn webhook_deliveries(
+    req: HttpRequest,
+    info: web::Path<(WebhookId,)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user = get_user_from_headers(req.headers(), &**pool).await?;
+
+    match get_managed_webhook(info.into_inner().0, &user, &pool).await? {
+        Some(webhook) => {
+            let deliveries = DBDelivery::get_many_webhook(webhook.id, DELIVERY_LOG_SIZE, &**pool)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.into()))?;
+
+            Ok(HttpResponse::Ok().json(
+                deliveries
+                    .into_iter()
+                    .map(convert_delivery)
+                    .collect::<Vec<_>>(),
+            ))
+        }
+        None => Ok(HttpResponse::NotFound().body("")),
+    }
+}
+
+pub fn convert_webhook(data: DBWebhook, include_secret: bool) -> Webhook {
+    Webhook {
+        id: data.id.into(),
+        user_id: data.user_id.map(|x| x.into()),
+        team_id: data.team_id.map(|x| x.into()),
+        mod_id: data.mod_id.map(|x| x.into()),
+        url: data.url,
+        events: data
+            .events
+            .iter()
+            .map(|x| WebhookEvent::from_str(x))
+            .collect(),
+        created: data.created,
+        secret: if include_secret {
+            Some(data.secret)
+        } else {
+            None
+        },
+    }
+}
+
+pub fn convert_delivery(data: DBDelivery) -> WebhookDelivery {
+    let status = DeliveryStatus::from_str(&data.status);
+
+    WebhookDelivery {
+        id: data.id as u64,
+        event: WebhookEvent::from_str(&data.event),
+        status,
+        attempts: data.attempts as u32,
+        response_code: data.response_code.map(|x| x as u16),
+        error: data.error,
+        created: data.created,
+        next_attempt: if status == DeliveryStatus::Pending {
+            Some(data.next_attempt)
+        } else {
+            None
+        },
+        delivered: data.delivered,
+    }
+}
@@ -1,6 +1,8 @@
 use actix_rt::time;
 use actix_rt::Arbiter;
 use futures::StreamExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 pub struct Scheduler {
     arbiter: Arbiter,
@@ -21,6 +23,40 @@ impl Scheduler {
         let future = time::interval(interval).for_each_concurrent(2, move |_| task());
         self.arbiter.send(future);
     }
+
+    /// Runs a task whose futures can't be sent between threads on the
+    /// scheduler's own thread.  Ticks are skipped while the previous run is
+    /// still going, so long runs don't pile up.
+    pub fn run_local<F, R>(&mut self, interval: std::time::Duration, mut task: F)
+    where
+        F: FnMut() -> R + Send + 'static,
+        R: std::future::Future<Output = ()> + 'static,
+    {
+        let running = Arc::new(AtomicBool::new(false));
+
+        self.run(interval, move || {
+            if !running.swap(true, Ordering::AcqRel) {
+                let guard = RunningGuard(running.clone());
+                let run = task();
+
+                actix_rt::spawn(async move {
+                    run.await;
+                    drop(guard);
+                });
+            }
+
+            futures::future::ready(())
+        });
+    }
+}
+
+/// Marks a task as finished when dropped, even if it panicked
+struct RunningGuard(Arc<AtomicBool>);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
 }
 
 impl Drop for Scheduler {
@@ -11,6 +11,7 @@ use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::cmp::min;
 use thiserror::Error;
+use utoipa::ToSchema;
 
 pub mod indexing;
 
@@ -92,7 +93,7 @@ pub struct UploadSearchMod {
     pub host: Cow<'static, str>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct SearchResults {
     pub hits: Vec<ResultSearchMod>,
     pub offset: usize,
@@ -100,7 +101,7 @@ pub struct SearchResults {
     pub total_hits: usize,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct ResultSearchMod {
     pub mod_id: String,
     pub slug: Option<String>,
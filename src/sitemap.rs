@@ -0,0 +1,249 @@
+use crate::models::ids::ModId;
+use crate::models::mods::ModStatus;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use sqlx::PgPool;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+use xml::writer::{EmitterConfig, XmlEvent};
+
+/// The maximum number of URLs in one sitemap page.  The protocol allows
+/// 50,000, but smaller pages are cheaper for crawlers to refetch.
+const PAGE_SIZE: usize = 10_000;
+
+const SITEMAP_NAMESPACE: &str = "http://www.sitemaps.org/schemas/sitemap/0.9";
+
+#[derive(Error, Debug)]
+pub enum SitemapError {
+    #[error("Database error while generating the sitemap: {0}")]
+    DatabaseError(#[from] sqlx::error::Error),
+    #[error("Error while writing the sitemap: {0}")]
+    XmlError(#[from] xml::writer::Error),
+}
+
+struct SitemapUrl {
+    loc: String,
+    lastmod: DateTime<Utc>,
+    /// When the URL was last modified leaving NSFW mods out, or `None` if
+    /// it's only about NSFW mods
+    safe_lastmod: Option<DateTime<Utc>>,
+}
+
+/// A rendered page of the sitemap
+pub struct SitemapPage {
+    pub body: Vec<u8>,
+    /// When the most recently changed URL on this page was last modified
+    pub lastmod: DateTime<Utc>,
+}
+
+pub struct GeneratedSitemap {
+    pub pages: Vec<SitemapPage>,
+    pub generated: DateTime<Utc>,
+}
+
+/// The most recently generated sitemaps, shared between the scheduler and
+/// the routes.  NSFW mods are left out unless they're asked for, so there's
+/// a sitemap with them and one without.
+pub struct Sitemaps {
+    current: RwLock<Option<(Arc<GeneratedSitemap>, Arc<GeneratedSitemap>)>>,
+}
+
+impl Sitemaps {
+    pub fn new() -> Self {
+        Sitemaps {
+            current: RwLock::new(None),
+        }
+    }
+
+    /// The current sitemap, or `None` if it hasn't been generated yet
+    pub fn current(&self, include_nsfw: bool) -> Option<Arc<GeneratedSitemap>> {
+        // Can only panic if the lock is poisoned
+        let current = self.current.read().unwrap();
+
+        current.as_ref().map(|(safe, all)| {
+            if include_nsfw {
+                all.clone()
+            } else {
+                safe.clone()
+            }
+        })
+    }
+
+    /// Regenerates the sitemaps, returning the number of pages of the one
+    /// with NSFW mods
+    pub async fn refresh(&self, pool: &PgPool) -> Result<usize, SitemapError> {
+        let mut urls = get_mod_urls(pool).await?;
+        urls.extend(get_user_urls(pool).await?);
+
+        let generated = Utc::now();
+        let safe_urls = urls
+            .iter()
+            .filter_map(|x| {
+                x.safe_lastmod.map(|lastmod| SitemapUrl {
+                    loc: x.loc.clone(),
+                    lastmod,
+                    safe_lastmod: Some(lastmod),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let safe = GeneratedSitemap {
+            pages: paginate(&safe_urls)?,
+            generated,
+        };
+        let all = GeneratedSitemap {
+            pages: paginate(&urls)?,
+            generated,
+        };
+
+        let count = all.pages.len();
+
+        *self.current.write().unwrap() = Some((Arc::new(safe), Arc::new(all)));
+
+        Ok(count)
+    }
+}
+
+fn paginate(urls: &[SitemapUrl]) -> Result<Vec<SitemapPage>, SitemapError> {
+    urls.chunks(PAGE_SIZE)
+        .map(|chunk| {
+            Ok(SitemapPage {
+                body: write_page(chunk)?,
+                lastmod: chunk
+                    .iter()
+                    .map(|x| x.lastmod)
+                    .max()
+                    .unwrap_or_else(Utc::now),
+            })
+        })
+        .collect()
+}
+
+fn site_url() -> String {
+    dotenv::var("SITE_URL").unwrap_or_default()
+}
+
+/// The pages of every approved mod
+async fn get_mod_urls(pool: &PgPool) -> Result<Vec<SitemapUrl>, sqlx::error::Error> {
+    let site_url = site_url();
+
+    sqlx::query!(
+        "
+        SELECT m.id, m.slug, m.updated, m.is_nsfw
+        FROM mods m
+        INNER JOIN statuses s ON s.id = m.status
+        WHERE s.status = $1
+        ORDER BY m.id ASC
+        ",
+        ModStatus::Approved.as_str()
+    )
+    .fetch_many(pool)
+    .try_filter_map(|e| async {
+        Ok(e.right().map(|m| {
+            let id: ModId = crate::database::models::ModId(m.id).into();
+
+            SitemapUrl {
+                loc: format!(
+                    "{}/mod/{}",
+                    site_url,
+                    m.slug.unwrap_or_else(|| id.to_string())
+                ),
+                lastmod: m.updated,
+                safe_lastmod: if m.is_nsfw { None } else { Some(m.updated) },
+            }
+        }))
+    })
+    .try_collect::<Vec<SitemapUrl>>()
+    .await
+}
+
+/// The profiles of every user who is a member of an approved mod.  Other
+/// profiles are empty, so they aren't worth a crawler's time.  Profiles with
+/// only NSFW mods are left out of the sitemap without them.
+async fn get_user_urls(pool: &PgPool) -> Result<Vec<SitemapUrl>, sqlx::error::Error> {
+    let site_url = site_url();
+
+    sqlx::query!(
+        r#"
+        SELECT
+            u.username, MAX(m.updated) "updated!",
+            MAX(m.updated) FILTER (WHERE NOT m.is_nsfw) safe_updated
+        FROM users u
+        INNER JOIN team_members tm ON tm.user_id = u.id AND tm.accepted = TRUE
+        INNER JOIN mods m ON m.team_id = tm.team_id
+        INNER JOIN statuses s ON s.id = m.status
+        WHERE s.status = $1
+        GROUP BY u.id
+        ORDER BY u.id ASC
+        "#,
+        ModStatus::Approved.as_str()
+    )
+    .fetch_many(pool)
+    .try_filter_map(|e| async {
+        Ok(e.right().map(|u| SitemapUrl {
+            loc: format!("{}/user/{}", site_url, u.username),
+            lastmod: u.updated,
+            safe_lastmod: u.safe_updated,
+        }))
+    })
+    .try_collect::<Vec<SitemapUrl>>()
+    .await
+}
+
+fn write_element<W: std::io::Write>(
+    writer: &mut xml::EventWriter<W>,
+    name: &str,
+    text: &str,
+) -> xml::writer::Result<()> {
+    writer.write(XmlEvent::start_element(name))?;
+    writer.write(XmlEvent::characters(text))?;
+    writer.write(XmlEvent::end_element())
+}
+
+fn write_page(urls: &[SitemapUrl]) -> xml::writer::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let mut writer = EmitterConfig::new().create_writer(&mut buffer);
+
+    writer.write(XmlEvent::start_element("urlset").default_ns(SITEMAP_NAMESPACE))?;
+
+    for url in urls {
+        writer.write(XmlEvent::start_element("url"))?;
+        write_element(&mut writer, "loc", &url.loc)?;
+        write_element(&mut writer, "lastmod", &url.lastmod.to_rfc3339())?;
+        writer.write(XmlEvent::end_element())?;
+    }
+
+    writer.write(XmlEvent::end_element())?;
+
+    Ok(buffer)
+}
+
+/// Writes the sitemap index.  Pages are linked as `{base_url}/sitemap/{n}.xml`,
+/// counting from 1, followed by `query`.
+pub fn write_index(
+    sitemap: &GeneratedSitemap,
+    base_url: &str,
+    query: &str,
+) -> xml::writer::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .create_writer(&mut buffer);
+
+    writer.write(XmlEvent::start_element("sitemapindex").default_ns(SITEMAP_NAMESPACE))?;
+
+    for (index, page) in sitemap.pages.iter().enumerate() {
+        writer.write(XmlEvent::start_element("sitemap"))?;
+        write_element(
+            &mut writer,
+            "loc",
+            &format!("{}/sitemap/{}.xml{}", base_url, index + 1, query),
+        )?;
+        write_element(&mut writer, "lastmod", &page.lastmod.to_rfc3339())?;
+        writer.write(XmlEvent::end_element())?;
+    }
+
+    writer.write(XmlEvent::end_element())?;
+
+    Ok(buffer)
+}